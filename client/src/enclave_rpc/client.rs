@@ -25,7 +25,7 @@ use oasis_core_runtime::common::runtime::RuntimeId;
 use oasis_core_runtime::{
     common::{cbor, sgx::avr::EnclaveIdentity},
     enclave_rpc::{
-        session::{Builder, Session},
+        session::{Builder, Session, VerifiedStaticKey},
         types,
     },
     protocol::Protocol,
@@ -40,6 +40,8 @@ use crate::BoxFuture;
 
 /// Internal send queue backlog.
 const SENDQ_BACKLOG: usize = 10;
+/// Default maximum number of call retries.
+const MAX_RETRIES: usize = 3;
 
 /// RPC client error.
 #[derive(Error, Debug)]
@@ -50,15 +52,20 @@ pub enum RpcClientError {
     ExpectedResponseMessage(types::Message),
     #[error("expected close message, received: {0:?}")]
     ExpectedCloseMessage(types::Message),
+    #[error("invalid stream chunk")]
+    InvalidStreamChunk,
     #[error("transport error")]
     Transport,
     #[error("client dropped")]
     Dropped,
+    #[error("remote enclave identities not configured")]
+    NoRemoteEnclaves,
 }
 
 type SendqRequest = (
     Arc<Context>,
     types::Request,
+    Option<u32>,
     oneshot::Sender<Result<types::Response>>,
     usize,
 );
@@ -85,6 +92,40 @@ impl MultiplexedSession {
         self.id = types::SessionID::random();
         self.inner = self.builder.clone().build_initiator();
     }
+
+    /// Reset the session after a failure.
+    ///
+    /// The remote static key may be stale, for example because the remote
+    /// end has been restarted, so the next session uses a full handshake.
+    fn reset_after_failure(&mut self) {
+        if self.builder.get_remote_static_public_key().is_some()
+            || self.builder.get_verified_remote_static_key().is_some()
+        {
+            self.builder = self
+                .builder
+                .clone()
+                .remote_static_public_key(None)
+                .verified_remote_static_key(None);
+        }
+        self.reset();
+    }
+
+    /// Replace a working session with a new one.
+    ///
+    /// The remote static key of the current session is remembered, so the
+    /// new session can complete its handshake in a single round trip. If
+    /// the key has been verified against the remote enclave identities, the
+    /// new session can also send a request together with the handshake.
+    fn renegotiate(&mut self) {
+        if let Some(key) = self.inner.remote_static_public_key() {
+            self.builder = self
+                .builder
+                .clone()
+                .remote_static_public_key(Some(key.to_vec()))
+                .verified_remote_static_key(self.inner.verified_remote_static_key());
+        }
+        self.reset();
+    }
 }
 
 struct Inner {
@@ -101,6 +142,9 @@ struct Inner {
     has_controller: AtomicBool,
     /// Maximum number of call retries.
     max_retries: usize,
+    /// Methods which are idempotent and can thus be sent together with the
+    /// first handshake message, where they can be replayed by the host.
+    idempotent_methods: Mutex<HashSet<String>>,
 }
 
 /// RPC client.
@@ -110,6 +154,14 @@ pub struct RpcClient {
 
 impl RpcClient {
     fn new(transport: Box<dyn Transport>, builder: Builder) -> Self {
+        Self::with_max_retries(transport, builder, MAX_RETRIES)
+    }
+
+    fn with_max_retries(
+        transport: Box<dyn Transport>,
+        builder: Builder,
+        max_retries: usize,
+    ) -> Self {
         let (tx, rx) = mpsc::channel(SENDQ_BACKLOG);
 
         Self {
@@ -119,7 +171,8 @@ impl RpcClient {
                 recvq: Mutex::new(Some(rx)),
                 sendq: tx,
                 has_controller: AtomicBool::new(false),
-                max_retries: 3,
+                max_retries,
+                idempotent_methods: Mutex::new(HashSet::new()),
             }),
         }
     }
@@ -155,6 +208,38 @@ impl RpcClient {
 
     /// Call a remote method.
     pub fn call<C, O>(&self, ctx: Context, method: &'static str, args: C) -> BoxFuture<O>
+    where
+        C: Serialize,
+        O: DeserializeOwned + Send + 'static,
+    {
+        self.call_with(ctx, method, args, None)
+    }
+
+    /// Call a remote method with a streamed response.
+    ///
+    /// The response is pulled in chunks of at most `chunk_size` bytes, each
+    /// using a separate round trip, so that large results can be fetched.
+    pub fn call_streamed<C, O>(
+        &self,
+        ctx: Context,
+        method: &'static str,
+        args: C,
+        chunk_size: u32,
+    ) -> BoxFuture<O>
+    where
+        C: Serialize,
+        O: DeserializeOwned + Send + 'static,
+    {
+        self.call_with(ctx, method, args, Some(chunk_size))
+    }
+
+    fn call_with<C, O>(
+        &self,
+        ctx: Context,
+        method: &'static str,
+        args: C,
+        chunk_size: Option<u32>,
+    ) -> BoxFuture<O>
     where
         C: Serialize,
         O: DeserializeOwned + Send + 'static,
@@ -164,16 +249,20 @@ impl RpcClient {
             args: cbor::to_value(args),
         };
 
-        Box::new(
-            self.execute_call(ctx, request)
-                .and_then(|response| match response.body {
-                    types::Body::Success(value) => Ok(cbor::from_value(value)?),
-                    types::Body::Error(error) => Err(RpcClientError::CallFailed(error).into()),
-                }),
-        )
+        Box::new(self.execute_call(ctx, request, chunk_size).and_then(
+            |response| match response.body {
+                types::Body::Success(value) => Ok(cbor::from_value(value)?),
+                types::Body::Error(error) => Err(RpcClientError::CallFailed(error).into()),
+            },
+        ))
     }
 
-    fn execute_call(&self, ctx: Context, request: types::Request) -> BoxFuture<types::Response> {
+    fn execute_call(
+        &self,
+        ctx: Context,
+        request: types::Request,
+        chunk_size: Option<u32>,
+    ) -> BoxFuture<types::Response> {
         let inner = self.inner.clone();
         Box::new(future::lazy(move || {
             // Spawn a new controller if we haven't spawned one yet.
@@ -191,15 +280,22 @@ impl RpcClient {
                 let inner = inner.clone();
                 let inner2 = inner.clone();
                 spawn(
-                    rx.for_each(move |(ctx, request, rsp_tx, retries)| {
+                    rx.for_each(move |(ctx, request, chunk_size, rsp_tx, retries)| {
                         let inner = inner.clone();
                         let inner2 = inner.clone();
                         let request2 = request.clone();
                         let ctx2 = ctx.clone();
 
-                        Self::connect(inner.clone(), Context::create_child(&ctx))
+                        let early_msg = Self::early_message(&inner, &request, chunk_size);
+
+                        Self::connect(inner.clone(), Context::create_child(&ctx), early_msg)
                             .and_then(move |_| {
-                                Self::call_raw(inner.clone(), Context::create_child(&ctx), request)
+                                Self::call_raw(
+                                    inner.clone(),
+                                    Context::create_child(&ctx),
+                                    request,
+                                    chunk_size,
+                                )
                             })
                             .then(
                                 move |result| -> Box<dyn Future<Item = (), Error = ()> + Send> {
@@ -214,10 +310,16 @@ impl RpcClient {
                                                 inner2
                                                     .sendq
                                                     .clone()
-                                                    .send((ctx2, request2, rsp_tx, retries + 1))
+                                                    .send((
+                                                        ctx2,
+                                                        request2,
+                                                        chunk_size,
+                                                        rsp_tx,
+                                                        retries + 1,
+                                                    ))
                                                     .map(|_| ())
                                                     .or_else(|err| {
-                                                        let (_, _, rsp_tx, _) = err.into_inner();
+                                                        let (_, _, _, rsp_tx, _) = err.into_inner();
                                                         rsp_tx
                                                             .send(Err(
                                                                 RpcClientError::Dropped.into()
@@ -243,24 +345,78 @@ impl RpcClient {
             inner
                 .sendq
                 .clone()
-                .send((ctx.freeze(), request, rsp_tx, 0))
+                .send((ctx.freeze(), request, chunk_size, rsp_tx, 0))
                 .map_err(|err| err.into())
                 .and_then(move |_| rsp_rx.map_err(|err| err.into()).and_then(|result| result))
         }))
     }
 
-    fn connect(inner: Arc<Inner>, ctx: Context) -> BoxFuture<()> {
+    /// Message which may be sent together with the first handshake message.
+    fn early_message(
+        inner: &Inner,
+        request: &types::Request,
+        chunk_size: Option<u32>,
+    ) -> Option<types::Message> {
+        // Early messages can be replayed, so only idempotent requests are sent early.
+        if !inner
+            .idempotent_methods
+            .lock()
+            .unwrap()
+            .contains(&request.method)
+        {
+            return None;
+        }
+        Some(Self::request_message(request.clone(), chunk_size))
+    }
+
+    fn connect(
+        inner: Arc<Inner>,
+        ctx: Context,
+        early_msg: Option<types::Message>,
+    ) -> BoxFuture<()> {
         Box::new(future::lazy(move || -> BoxFuture<()> {
-            let mut session = inner.session.lock().unwrap();
-            if session.inner.is_connected() {
+            let session = inner.session.lock().unwrap();
+            if !session.inner.is_connected() {
+                drop(session);
+                return Self::handshake(inner, ctx, early_msg);
+            }
+            // Each call needs to send a request and receive a response and
+            // closing the session needs another two messages, so renegotiate
+            // the session before it reaches its message limit.
+            if session.inner.remaining_messages() >= 4 {
                 return Box::new(future::ok(()));
             }
+            drop(session);
+
+            // Close the current session first, so that the remote end does
+            // not need to keep it around until it becomes stale. In case this
+            // fails, the remote end eventually purges the stale session.
+            Box::new(Self::close(inner.clone()).then(move |_| {
+                inner.session.lock().unwrap().renegotiate();
+                Self::handshake(inner, ctx, early_msg)
+            }))
+        }))
+    }
+
+    fn handshake(
+        inner: Arc<Inner>,
+        ctx: Context,
+        early_msg: Option<types::Message>,
+    ) -> BoxFuture<()> {
+        Box::new(future::lazy(move || -> BoxFuture<()> {
+            let mut session = inner.session.lock().unwrap();
+            if let Some(ref msg) = early_msg {
+                if session.inner.can_write_early_message(msg) {
+                    // The message is sent together with the first handshake message.
+                    return Box::new(future::ok(()));
+                }
+            }
 
             let mut buffer = vec![];
             // Handshake1 -> Handshake2
             session
                 .inner
-                .process_data(vec![], &mut buffer)
+                .process_data(vec![], &[], &mut buffer)
                 .expect("initiation must always succeed");
             let session_id = session.id;
             drop(session);
@@ -277,9 +433,13 @@ impl RpcClient {
                         let mut session = inner.session.lock().unwrap();
                         let mut buffer = vec![];
                         // Handshake2 -> Transport
-                        if let Err(error) = session.inner.process_data(data, &mut buffer) {
+                        if let Err(error) = session.inner.process_data(data, &[], &mut buffer) {
                             return Box::new(future::err(error));
                         }
+                        // The IK pattern has no final handshake message.
+                        if buffer.is_empty() {
+                            return Box::new(future::ok(()));
+                        }
 
                         let ctx = Context::create_child(&fctx);
                         Box::new(
@@ -293,7 +453,7 @@ impl RpcClient {
                         // Failed to establish a session, we must reset it as otherwise
                         // it will always fail.
                         let mut session = inner2.session.lock().unwrap();
-                        session.reset();
+                        session.reset_after_failure();
 
                         Err(err)
                     }),
@@ -303,10 +463,15 @@ impl RpcClient {
 
     fn close(inner: Arc<Inner>) -> BoxFuture<()> {
         let mut session = inner.session.lock().unwrap();
+        if !session.inner.is_connected() {
+            // Avoid establishing a session just to close it.
+            return Box::new(future::ok(()));
+        }
         let mut buffer = vec![];
+        let ad = types::Frame::associated_data(&session.id, "");
         if let Err(error) = session
             .inner
-            .write_message(types::Message::Close, &mut buffer)
+            .write_message(types::Message::Close, &ad, &mut buffer)
         {
             return Box::new(future::err(error));
         }
@@ -322,7 +487,7 @@ impl RpcClient {
                     let mut session = inner.session.lock().unwrap();
                     let msg = session
                         .inner
-                        .process_data(data, vec![])?
+                        .process_data(data, &[], vec![])?
                         .expect("message must be decoded if there is no error");
 
                     match msg {
@@ -340,43 +505,154 @@ impl RpcClient {
         inner: Arc<Inner>,
         ctx: Context,
         request: types::Request,
+        chunk_size: Option<u32>,
     ) -> BoxFuture<types::Response> {
         let method = request.method.clone();
-        let msg = types::Message::Request(request);
+        let msg = Self::request_message(request, chunk_size);
+
+        let ctx = ctx.freeze();
+        let inner2 = inner.clone();
+        Box::new(
+            Self::round_trip(inner.clone(), Context::create_child(&ctx), msg, &method)
+                .and_then(move |msg| -> BoxFuture<types::Response> {
+                    match msg {
+                        types::Message::Response(rsp) => Box::new(future::ok(rsp)),
+                        types::Message::StreamChunk(chunk) => {
+                            Self::pull_stream(inner, ctx, method, chunk)
+                        }
+                        msg => Box::new(future::err(
+                            RpcClientError::ExpectedResponseMessage(msg).into(),
+                        )),
+                    }
+                })
+                .or_else(move |err| {
+                    // Failed to communicate, we must reset it as otherwise it will always fail.
+                    let mut session = inner2.session.lock().unwrap();
+                    session.reset_after_failure();
+
+                    Err(err)
+                }),
+        )
+    }
+
+    fn request_message(request: types::Request, chunk_size: Option<u32>) -> types::Message {
+        match chunk_size {
+            Some(chunk_size) => types::Message::StreamRequest(types::StreamRequest {
+                request,
+                chunk_size,
+            }),
+            None => types::Message::Request(request),
+        }
+    }
+
+    /// Send a message and receive the reply.
+    fn round_trip(
+        inner: Arc<Inner>,
+        ctx: Context,
+        msg: types::Message,
+        method: &str,
+    ) -> BoxFuture<types::Message> {
         let mut session = inner.session.lock().unwrap();
         let mut buffer = vec![];
-        if let Err(error) = session.inner.write_message(msg, &mut buffer) {
+        let ad = types::Frame::associated_data(&session.id, method);
+        if let Err(error) = session.inner.write_message(msg, &ad, &mut buffer) {
             return Box::new(future::err(error));
         }
 
         let inner = inner.clone();
-        let inner2 = inner.clone();
         Box::new(
             inner
                 .transport
-                .write_message(ctx, session.id, buffer, method)
+                .write_message(ctx, session.id, buffer, method.to_owned())
                 .and_then(move |data| {
                     let mut session = inner.session.lock().unwrap();
                     let msg = session
                         .inner
-                        .process_data(data, vec![])?
+                        .process_data(data, &[], vec![])?
                         .expect("message must be decoded if there is no error");
 
-                    match msg {
-                        types::Message::Response(rsp) => Ok(rsp),
-                        msg => Err(RpcClientError::ExpectedResponseMessage(msg).into()),
+                    Ok(msg)
+                }),
+        )
+    }
+
+    /// Pull the remaining chunks of a streamed response.
+    fn pull_stream(
+        inner: Arc<Inner>,
+        ctx: Arc<Context>,
+        method: String,
+        first: types::StreamChunk,
+    ) -> BoxFuture<types::Response> {
+        // Loop state is the data received so far and the number of remaining bytes.
+        type Pull = future::Loop<Vec<u8>, (Vec<u8>, u64)>;
+
+        Box::new(
+            future::loop_fn(
+                (first.data, first.remaining),
+                move |(mut data, remaining)| -> BoxFuture<Pull> {
+                    if remaining == 0 {
+                        return Box::new(future::ok(future::Loop::Break(data)));
                     }
-                })
-                .or_else(move |err| {
-                    // Failed to communicate, we must reset it as otherwise it will always fail.
-                    let mut session = inner2.session.lock().unwrap();
-                    session.reset();
 
-                    Err(err)
-                }),
+                    // Pulls use the method of the stream, so hosts can route them
+                    // in the same way.
+                    Box::new(
+                        Self::round_trip(
+                            inner.clone(),
+                            Context::create_child(&ctx),
+                            types::Message::StreamPull,
+                            &method,
+                        )
+                        .and_then(move |msg| match msg {
+                            types::Message::StreamChunk(chunk) => {
+                                // Each chunk must make progress.
+                                if chunk.data.is_empty() || chunk.remaining >= remaining {
+                                    return Err(RpcClientError::InvalidStreamChunk.into());
+                                }
+                                data.extend(chunk.data);
+                                Ok(future::Loop::Continue((data, chunk.remaining)))
+                            }
+                            msg => Err(RpcClientError::ExpectedResponseMessage(msg).into()),
+                        }),
+                    )
+                },
+            )
+            .and_then(|data: Vec<u8>| Ok(cbor::from_slice(&data)?)),
         )
     }
 
+    /// Configure methods which are idempotent, so that requests for them can
+    /// be sent together with the first handshake message of a session.
+    ///
+    /// Such requests can be replayed by the host, so this must only include
+    /// methods without side effects.
+    pub fn set_idempotent_methods(&self, methods: HashSet<String>) {
+        *self.inner.idempotent_methods.lock().unwrap() = methods;
+    }
+
+    /// Use the given remote static public key after verifying its RAK binding
+    /// against the configured remote enclave identities.
+    ///
+    /// The key and its binding can be obtained from an untrusted source, for
+    /// example the registry. Requests to idempotent methods are then sent
+    /// together with the first handshake message, see `set_idempotent_methods`.
+    pub fn set_remote_static_key(&self, key: Vec<u8>, rak_binding: &[u8]) -> Result<()> {
+        let mut session = self.inner.session.lock().unwrap();
+        let key = match session.builder.get_remote_enclaves() {
+            Some(enclaves) => VerifiedStaticKey::verify(key, rak_binding, enclaves)?,
+            None => return Err(RpcClientError::NoRemoteEnclaves.into()),
+        };
+        session.builder = session
+            .builder
+            .clone()
+            .verified_remote_static_key(Some(key));
+        if !session.inner.is_connected() {
+            session.reset();
+        }
+
+        Ok(())
+    }
+
     /// Update session enclaves if changed.
     pub fn update_enclaves(&self, enclaves: Option<HashSet<EnclaveIdentity>>) {
         let mut session = self.inner.session.lock().unwrap();
@@ -389,9 +665,12 @@ impl RpcClient {
 
 #[cfg(test)]
 mod test {
-    use std::sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
+    use std::{
+        collections::HashSet,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
     };
 
     use anyhow::anyhow;
@@ -400,6 +679,7 @@ mod test {
     use tokio::runtime::Runtime;
 
     use oasis_core_runtime::{
+        common::cbor,
         enclave_rpc::{demux::Demux, session, types},
         rak::RAK,
     };
@@ -412,6 +692,9 @@ mod test {
         rak: Arc<RAK>,
         demux: Arc<Mutex<Demux>>,
         next_error: Arc<AtomicBool>,
+        next_tamper: Arc<AtomicBool>,
+        round_trips: Arc<AtomicUsize>,
+        closes: Arc<AtomicUsize>,
     }
 
     impl MockTransport {
@@ -422,6 +705,9 @@ mod test {
                 rak: rak.clone(),
                 demux: Arc::new(Mutex::new(Demux::new(rak))),
                 next_error: Arc::new(AtomicBool::new(false)),
+                next_tamper: Arc::new(AtomicBool::new(false)),
+                round_trips: Arc::new(AtomicUsize::new(0)),
+                closes: Arc::new(AtomicUsize::new(0)),
             }
         }
 
@@ -433,10 +719,28 @@ mod test {
         fn induce_transport_error(&self) {
             self.next_error.store(true, Ordering::SeqCst);
         }
+
+        fn induce_method_tampering(&self) {
+            self.next_tamper.store(true, Ordering::SeqCst);
+        }
+
+        fn static_public_key(&self) -> Vec<u8> {
+            self.demux.lock().unwrap().static_public_key().to_vec()
+        }
+
+        fn round_trips(&self) -> usize {
+            self.round_trips.load(Ordering::SeqCst)
+        }
+
+        fn closes(&self) -> usize {
+            self.closes.load(Ordering::SeqCst)
+        }
     }
 
     impl Transport for MockTransport {
         fn write_message_impl(&self, _ctx: Context, data: Vec<u8>) -> BoxFuture<Vec<u8>> {
+            self.round_trips.fetch_add(1, Ordering::SeqCst);
+
             if self
                 .next_error
                 .compare_and_swap(true, false, Ordering::SeqCst)
@@ -444,19 +748,39 @@ mod test {
                 return Box::new(future::err(anyhow!("transport error")));
             }
 
+            // Simulate a host which changes the method used for routing.
+            let mut data = data;
+            if self
+                .next_tamper
+                .compare_and_swap(true, false, Ordering::SeqCst)
+            {
+                let mut frame: types::Frame = cbor::from_slice(&data).unwrap();
+                frame.untrusted_plaintext = "tampered".to_owned();
+                data = cbor::to_vec(&frame);
+            }
+
             let mut demux = self.demux.lock().unwrap();
 
             // Deliver directly to the multiplexer.
             let mut buffer = Vec::new();
             match demux.process_frame(data, &mut buffer) {
                 Err(err) => Box::new(future::err(err)),
-                Ok(Some((session_id, _session_info, message, _untrusted_plaintext))) => {
+                Ok(Some((session_id, _session_info, message))) => {
                     // Message, process and write reply.
                     let body = match message {
                         types::Message::Request(rq) => {
                             // Just echo back what was given.
                             types::Body::Success(rq.args)
                         }
+                        types::Message::Close => {
+                            self.closes.fetch_add(1, Ordering::SeqCst);
+
+                            let mut buffer = Vec::new();
+                            return match demux.close(session_id, &mut buffer) {
+                                Ok(_) => Box::new(future::ok(buffer)),
+                                Err(error) => Box::new(future::err(error)),
+                            };
+                        }
                         _ => panic!("unhandled message type"),
                     };
                     let response = types::Message::Response(types::Response { body });
@@ -505,4 +829,161 @@ mod test {
             .unwrap();
         assert_eq!(result, 44, "call should work");
     }
+
+    #[test]
+    fn test_rpc_client_method_tampering() {
+        let mut rt = Runtime::new().unwrap();
+        let transport = MockTransport::new();
+        let builder = session::Builder::new();
+        // Disable retries as a retried call would succeed on a fresh session.
+        let client = RpcClient::with_max_retries(Box::new(transport.clone()), builder, 0);
+
+        let result: u64 = rt
+            .block_on(client.call(Context::background(), "test", 42))
+            .unwrap();
+        assert_eq!(result, 42, "call should work");
+
+        // Tampering with the plaintext method must be detected as it is authenticated
+        // together with the payload.
+        transport.induce_method_tampering();
+
+        let err = rt
+            .block_on(client.call::<_, u64>(Context::background(), "test", 43))
+            .expect_err("call with tampered method should fail");
+        assert!(
+            matches!(
+                err.downcast_ref::<session::SessionError>(),
+                Some(session::SessionError::MismatchedAssociatedData)
+            ),
+            "call with tampered method should fail with mismatched associated data: {}",
+            err
+        );
+
+        let result: u64 = rt
+            .block_on(client.call(Context::background(), "test", 44))
+            .unwrap();
+        assert_eq!(result, 44, "call should work");
+    }
+
+    #[test]
+    fn test_rpc_client_large_payloads() {
+        let mut rt = Runtime::new().unwrap();
+        let transport = MockTransport::new();
+        let builder = session::Builder::new();
+        let client = RpcClient::new(Box::new(transport.clone()), builder);
+        let large = "x".repeat(200_000);
+
+        // Messages larger than a single Noise message are fragmented.
+        let result: String = rt
+            .block_on(client.call(Context::background(), "test", large.clone()))
+            .unwrap();
+        assert_eq!(result, large, "fragmented call should work");
+
+        // Streamed responses are pulled in chunks.
+        let result: String = rt
+            .block_on(client.call_streamed(Context::background(), "test", large.clone(), 10_000))
+            .unwrap();
+        assert_eq!(result, large, "streamed call should work");
+
+        // Small streamed responses fit into a single chunk.
+        let result: u64 = rt
+            .block_on(client.call_streamed(Context::background(), "test", 42, 10_000))
+            .unwrap();
+        assert_eq!(result, 42, "streamed call should work");
+    }
+
+    #[test]
+    fn test_rpc_client_session_limits() {
+        let mut rt = Runtime::new().unwrap();
+        let transport = MockTransport::new();
+        let builder = session::Builder::new()
+            .rekey_messages(2)
+            .rekey_interval_secs(0)
+            .max_messages(6);
+        let client = RpcClient::new(Box::new(transport.clone()), builder);
+
+        // Calls must keep working across key rotations and session renegotiations.
+        for i in 0..20u64 {
+            let result: u64 = rt
+                .block_on(client.call(Context::background(), "test", i))
+                .unwrap();
+            assert_eq!(result, i, "call should work");
+
+            let session = client.inner.session.lock().unwrap();
+            assert!(
+                session.inner.message_count() <= 6,
+                "session should be renegotiated"
+            );
+            if let Some(info) = session.inner.session_info() {
+                assert_eq!(info.message_count(), session.inner.message_count());
+            }
+        }
+        // Sessions must be closed before they are renegotiated.
+        assert!(transport.closes() > 0, "sessions should be closed");
+    }
+
+    #[test]
+    fn test_rpc_client_ik() {
+        let mut rt = Runtime::new().unwrap();
+        let transport = MockTransport::new();
+        let builder =
+            session::Builder::new().remote_static_public_key(Some(transport.static_public_key()));
+        let client = RpcClient::new(Box::new(transport.clone()), builder);
+        let mut methods = HashSet::new();
+        methods.insert("test".to_owned());
+        client.set_idempotent_methods(methods);
+
+        // The key has not been verified, so the request must not be sent before the
+        // handshake has completed, even for idempotent methods.
+        let result: u64 = rt
+            .block_on(client.call(Context::background(), "test", 42))
+            .unwrap();
+        assert_eq!(result, 42, "call should work");
+        assert_eq!(
+            transport.round_trips(),
+            2,
+            "call should take two round trips"
+        );
+
+        // Sessions must fall back to a full handshake in case the remote static key changes.
+        transport.reset();
+
+        let result: u64 = rt
+            .block_on(client.call(Context::background(), "test", 43))
+            .unwrap();
+        assert_eq!(result, 43, "call should work");
+    }
+
+    #[test]
+    fn test_rpc_client_ik_renegotiation() {
+        let mut rt = Runtime::new().unwrap();
+        let transport = MockTransport::new();
+        let builder = session::Builder::new().max_messages(2);
+        let client = RpcClient::new(Box::new(transport.clone()), builder);
+
+        // The first session uses a full handshake.
+        let result: u64 = rt
+            .block_on(client.call(Context::background(), "test", 42))
+            .unwrap();
+        assert_eq!(result, 42, "call should work");
+        assert_eq!(
+            transport.round_trips(),
+            3,
+            "call should take three round trips"
+        );
+
+        // Renegotiated sessions use the remote static key of the previous session.
+        for i in 0..5u64 {
+            let before = transport.round_trips();
+            let result: u64 = rt
+                .block_on(client.call(Context::background(), "test", i))
+                .unwrap();
+            assert_eq!(result, i, "call should work");
+            assert_eq!(
+                transport.round_trips() - before,
+                2,
+                "call should take two round trips"
+            );
+        }
+    }
 }
@@ -3,7 +3,6 @@
 #[cfg(not(target_env = "sgx"))]
 mod api;
 pub mod client;
-pub mod macros;
 mod transport;
 
 // Re-exports.
@@ -3,7 +3,6 @@
 pub mod api;
 mod block_watcher;
 pub mod client;
-pub mod macros;
 pub mod snapshot;
 
 // Re-exports.
@@ -11,7 +11,7 @@ use oasis_core_runtime::{
         roothash::{Block, Namespace},
     },
     storage::{
-        mkvs::{sync::*, Prefix, Root, Tree, WriteLog},
+        mkvs::{self, sync::*, Prefix, Root, Tree, WriteLog},
         MKVS,
     },
     transaction::types::{TxnCall, TxnOutput},
@@ -100,10 +100,18 @@ impl BlockSnapshot {
             mkvs,
         }
     }
+
+    /// Compute the write log which transforms the state in this snapshot
+    /// into the state in the other snapshot.
+    ///
+    /// Only the parts of the state trees which differ are fetched.
+    pub fn diff(&self, ctx: Context, other: &BlockSnapshot) -> Result<WriteLog> {
+        self.mkvs.diff(ctx, &other.mkvs)
+    }
 }
 
 impl MKVS for BlockSnapshot {
-    fn get(&self, ctx: Context, key: &[u8]) -> Option<Vec<u8>> {
+    fn get(&self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
         MKVS::get(&self.mkvs, ctx, key)
     }
 
@@ -111,18 +119,31 @@ impl MKVS for BlockSnapshot {
         MKVS::cache_contains_key(&self.mkvs, ctx, key)
     }
 
-    fn insert(&mut self, _ctx: Context, _key: &[u8], _value: &[u8]) -> Option<Vec<u8>> {
+    fn insert(&mut self, _ctx: Context, _key: &[u8], _value: &[u8]) -> Result<Option<Vec<u8>>> {
         unimplemented!("block snapshot is read-only");
     }
 
-    fn remove(&mut self, _ctx: Context, _key: &[u8]) -> Option<Vec<u8>> {
+    fn remove(&mut self, _ctx: Context, _key: &[u8]) -> Result<Option<Vec<u8>>> {
         unimplemented!("block snapshot is read-only");
     }
 
-    fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16) {
+    fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16) -> Result<()> {
         MKVS::prefetch_prefixes(&self.mkvs, ctx, prefixes, limit)
     }
 
+    fn prefetch_all_prefixes(
+        &self,
+        ctx: Context,
+        prefixes: &Vec<Prefix>,
+        page_size: u16,
+    ) -> Result<()> {
+        MKVS::prefetch_all_prefixes(&self.mkvs, ctx, prefixes, page_size)
+    }
+
+    fn iter(&self, ctx: Context) -> Box<dyn mkvs::Iterator + '_> {
+        MKVS::iter(&self.mkvs, ctx)
+    }
+
     fn commit(
         &mut self,
         _ctx: Context,
@@ -103,6 +103,13 @@ type InitResponse struct {
 	IsSecure       bool   `json:"is_secure"`
 	Checksum       []byte `json:"checksum"`
 	PolicyChecksum []byte `json:"policy_checksum"`
+
+	// RPCStaticKey is the static public key used by all EnclaveRPC sessions.
+	RPCStaticKey []byte `json:"rpc_static_key,omitempty"`
+	// RPCStaticKeyBinding is the RAK binding of the static public key, which
+	// allows clients to send requests together with the first handshake
+	// message.
+	RPCStaticKeyBinding []byte `json:"rpc_static_key_binding,omitempty"`
 }
 
 // SignedInitResponse is the signed initialization RPC response, returned
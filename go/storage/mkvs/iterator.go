@@ -34,6 +34,7 @@ func (t *tree) SyncIterate(ctx context.Context, request *syncer.IterateRequest)
 		IteratorPrefetch(request.Prefetch),
 	)
 	defer it.Close()
+	it.GetProofBuilder().SetKnownNodes(request.KnownNodes)
 
 	it.Seek(request.Key)
 	if it.Err() != nil {
@@ -49,6 +49,7 @@ func (t *tree) SyncGet(ctx context.Context, request *syncer.GetRequest) (*syncer
 	t.cache.markPosition()
 
 	pb := syncer.NewProofBuilder(request.Tree.Position)
+	pb.SetKnownNodes(request.KnownNodes)
 	opts := doGetOptions{
 		proofBuilder:    pb,
 		includeSiblings: request.IncludeSiblings,
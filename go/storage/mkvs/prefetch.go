@@ -3,6 +3,7 @@ package mkvs
 import (
 	"bytes"
 	"context"
+	"sort"
 
 	"github.com/oasisprotocol/oasis-core/go/storage/mkvs/node"
 	"github.com/oasisprotocol/oasis-core/go/storage/mkvs/syncer"
@@ -21,10 +22,10 @@ func (t *tree) PrefetchPrefixes(ctx context.Context, prefixes [][]byte, limit ui
 		return nil
 	}
 
-	return t.doPrefetchPrefixes(ctx, prefixes, limit)
+	return t.doPrefetchPrefixes(ctx, prefixes, nil, limit)
 }
 
-func (t *tree) doPrefetchPrefixes(ctx context.Context, prefixes [][]byte, limit uint16) error {
+func (t *tree) doPrefetchPrefixes(ctx context.Context, prefixes [][]byte, startKey []byte, limit uint16) error {
 	// TODO: Can we avoid fetching items that we already have?
 
 	return t.cache.remoteSync(
@@ -38,6 +39,7 @@ func (t *tree) doPrefetchPrefixes(ctx context.Context, prefixes [][]byte, limit
 				},
 				Prefixes: prefixes,
 				Limit:    limit,
+				StartKey: startKey,
 			})
 			if err != nil {
 				return nil, err
@@ -66,7 +68,7 @@ func (t *tree) SyncGetPrefixes(ctx context.Context, request *syncer.GetPrefixesR
 	// is available. This is needed to ensure that the same optimization
 	// carries on to the next layer.
 	if t.cache.rs != syncer.NopReadSyncer {
-		err := t.doPrefetchPrefixes(ctx, request.Prefixes, request.Limit)
+		err := t.doPrefetchPrefixes(ctx, request.Prefixes, request.StartKey, request.Limit)
 		if err != nil {
 			return nil, err
 		}
@@ -74,21 +76,36 @@ func (t *tree) SyncGetPrefixes(ctx context.Context, request *syncer.GetPrefixesR
 
 	it := t.NewIterator(ctx, WithProof(request.Tree.Root.Hash))
 	defer it.Close()
+	it.GetProofBuilder().SetKnownNodes(request.KnownNodes)
 
-	var total int
+	var (
+		total   int
+		nextKey []byte
+	)
 prefixLoop:
-	for _, prefix := range request.Prefixes {
-		it.Seek(prefix)
+	for _, prefix := range normalizePrefixes(request.Prefixes) {
+		if request.StartKey != nil && bytes.Compare(prefix, request.StartKey) < 0 {
+			if !bytes.HasPrefix(request.StartKey, prefix) {
+				// All keys with this prefix were returned in earlier pages.
+				continue
+			}
+			it.Seek(request.StartKey)
+		} else {
+			it.Seek(prefix)
+		}
 		if it.Err() != nil {
 			return nil, it.Err()
 		}
 		for ; it.Valid(); total++ {
-			if total >= int(request.Limit) {
-				break prefixLoop
-			}
 			if !bytes.HasPrefix(it.Key(), prefix) {
 				break
 			}
+			if total >= int(request.Limit) {
+				// Report the first key which did not fit so that the caller
+				// can continue from it.
+				nextKey = append([]byte{}, it.Key()...)
+				break prefixLoop
+			}
 			it.Next()
 		}
 		if it.Err() != nil {
@@ -102,6 +119,26 @@ prefixLoop:
 	}
 
 	return &syncer.ProofResponse{
-		Proof: *proof,
+		Proof:   *proof,
+		NextKey: nextKey,
 	}, nil
 }
+
+// normalizePrefixes sorts the prefixes and drops those covered by another
+// prefix, so that all matching keys are visited exactly once and in order.
+func normalizePrefixes(prefixes [][]byte) [][]byte {
+	sorted := append([][]byte{}, prefixes...)
+	sort.Slice(sorted, func(i, j int) bool {
+		return bytes.Compare(sorted[i], sorted[j]) < 0
+	})
+
+	normalized := make([][]byte, 0, len(sorted))
+	for _, prefix := range sorted {
+		// A covering prefix sorts directly before the prefixes it covers.
+		if n := len(normalized); n > 0 && bytes.HasPrefix(prefix, normalized[n-1]) {
+			continue
+		}
+		normalized = append(normalized, prefix)
+	}
+	return normalized
+}
@@ -14,6 +14,16 @@ const (
 	proofEntryFull byte = 0x01
 	// proofEntryHash is the proof entry type for subtree hashes.
 	proofEntryHash byte = 0x02
+	// proofEntryKnown is the proof entry type for nodes already known to the
+	// verifier. The entry contains the node hash followed by a byte of child
+	// flags. Only the entries of the flagged children follow, all other
+	// children are taken from the known node.
+	proofEntryKnown byte = 0x03
+
+	// knownFlagLeft is set when the entries of the left child follow.
+	knownFlagLeft byte = 0x01
+	// knownFlagRight is set when the entries of the right child follow.
+	knownFlagRight byte = 0x02
 )
 
 // Proof is a Merkle proof for a subtree.
@@ -33,9 +43,10 @@ type proofNode struct {
 
 // ProofBuilder is a Merkle proof builder.
 type ProofBuilder struct {
-	root     hash.Hash
-	included map[hash.Hash]*proofNode
-	size     uint64
+	root       hash.Hash
+	included   map[hash.Hash]*proofNode
+	knownNodes map[hash.Hash]bool
+	size       uint64
 }
 
 // NewProofBuilder creates a new Merkle proof builder for the given root.
@@ -46,6 +57,19 @@ func NewProofBuilder(root hash.Hash) *ProofBuilder {
 	}
 }
 
+// SetKnownNodes sets the hashes of nodes which the verifier already holds.
+//
+// Such nodes are encoded as known node entries which only contain the node
+// hash. Their children are only encoded if they are included, so subtrees
+// below known nodes which are not part of the proof are skipped. Proofs with
+// known node entries can only be verified using VerifyProofWithKnownNodes.
+func (b *ProofBuilder) SetKnownNodes(knownNodes []hash.Hash) {
+	b.knownNodes = make(map[hash.Hash]bool, len(knownNodes))
+	for _, h := range knownNodes {
+		b.knownNodes[h] = true
+	}
+}
+
 // Include adds a node to the set of included nodes.
 //
 // The node must be clean.
@@ -141,6 +165,32 @@ func (b *ProofBuilder) build(ctx context.Context, proof *Proof, h hash.Hash) err
 		return nil
 	}
 
+	if b.knownNodes[h] {
+		// Only included children follow, the verifier takes the others from
+		// the known node.
+		var (
+			flags    byte
+			children []hash.Hash
+		)
+		childFlags := []byte{knownFlagLeft, knownFlagRight}
+		for i, childHash := range n.children {
+			if b.included[childHash] == nil {
+				continue
+			}
+			flags |= childFlags[i]
+			children = append(children, childHash)
+		}
+
+		entry := append([]byte{proofEntryKnown}, h[:]...)
+		proof.Entries = append(proof.Entries, append(entry, flags))
+		for _, childHash := range children {
+			if err := b.build(ctx, proof, childHash); err != nil {
+				return err
+			}
+		}
+		return nil
+	}
+
 	// Pre-order traversal, add visited node.
 	proof.Entries = append(proof.Entries, append([]byte{proofEntryFull}, n.serialized...))
 
@@ -154,6 +204,14 @@ func (b *ProofBuilder) build(ctx context.Context, proof *Proof, h hash.Hash) err
 	return nil
 }
 
+// KnownNodes is a source of nodes already held by a verifier, used to verify
+// proofs containing known node entries.
+type KnownNodes interface {
+	// GetKnownNode returns the node with the given hash or nil if the node
+	// is not known.
+	GetKnownNode(h hash.Hash) node.Node
+}
+
 // ProofVerifier enables verifying proofs returned by the ReadSyncer API.
 type ProofVerifier struct {
 }
@@ -161,6 +219,18 @@ type ProofVerifier struct {
 // VerifyProof verifies a proof and generates an in-memory subtree representing
 // the nodes which are included in the proof.
 func (pv *ProofVerifier) VerifyProof(ctx context.Context, root hash.Hash, proof *Proof) (*node.Pointer, error) {
+	return pv.VerifyProofWithKnownNodes(ctx, root, proof, nil)
+}
+
+// VerifyProofWithKnownNodes verifies a proof which may contain known node
+// entries, resolving them using the given known nodes, and generates an
+// in-memory subtree representing the nodes which are included in the proof.
+func (pv *ProofVerifier) VerifyProofWithKnownNodes(
+	ctx context.Context,
+	root hash.Hash,
+	proof *Proof,
+	known KnownNodes,
+) (*node.Pointer, error) {
 	// Sanity check that the proof is for the correct root (as otherwise it
 	// makes no sense to verify the proof).
 	if !proof.UntrustedRoot.Equal(&root) {
@@ -173,7 +243,7 @@ func (pv *ProofVerifier) VerifyProof(ctx context.Context, root hash.Hash, proof
 		return nil, errors.New("verifier: empty proof")
 	}
 
-	_, rootNode, err := pv.verifyProof(ctx, proof, 0)
+	_, rootNode, err := pv.verifyProof(ctx, proof, 0, known)
 	if err != nil {
 		return nil, err
 	}
@@ -193,7 +263,12 @@ func (pv *ProofVerifier) VerifyProof(ctx context.Context, root hash.Hash, proof
 	return rootNode, nil
 }
 
-func (pv *ProofVerifier) verifyProof(ctx context.Context, proof *Proof, idx int) (int, *node.Pointer, error) {
+func (pv *ProofVerifier) verifyProof(
+	ctx context.Context,
+	proof *Proof,
+	idx int,
+	known KnownNodes,
+) (int, *node.Pointer, error) {
 	if ctx.Err() != nil {
 		return -1, nil, ctx.Err()
 	}
@@ -221,12 +296,12 @@ func (pv *ProofVerifier) verifyProof(ctx context.Context, proof *Proof, idx int)
 		pos := idx + 1
 		if nd, ok := n.(*node.InternalNode); ok {
 			// Left.
-			pos, nd.Left, err = pv.verifyProof(ctx, proof, pos)
+			pos, nd.Left, err = pv.verifyProof(ctx, proof, pos, known)
 			if err != nil {
 				return -1, nil, err
 			}
 			// Right.
-			pos, nd.Right, err = pv.verifyProof(ctx, proof, pos)
+			pos, nd.Right, err = pv.verifyProof(ctx, proof, pos, known)
 			if err != nil {
 				return -1, nil, err
 			}
@@ -244,6 +319,65 @@ func (pv *ProofVerifier) verifyProof(ctx context.Context, proof *Proof, idx int)
 		}
 
 		return idx + 1, &node.Pointer{Clean: true, Hash: h}, nil
+	case proofEntryKnown:
+		// Node already known to the verifier.
+		if len(entry) != 1+hash.Size+1 {
+			return -1, nil, errors.New("verifier: malformed known node entry")
+		}
+		var h hash.Hash
+		if err := h.UnmarshalBinary(entry[1 : 1+hash.Size]); err != nil {
+			return -1, nil, err
+		}
+		flags := entry[1+hash.Size]
+
+		var knownNode node.Node
+		if known != nil {
+			knownNode = known.GetKnownNode(h)
+		}
+		if knownNode == nil {
+			return -1, nil, fmt.Errorf("verifier: unknown node in proof (%s)", h)
+		}
+
+		// Work on a copy as children are replaced below. Known nodes use the
+		// full encoding, so children which do not follow are hash pointers.
+		data, err := knownNode.MarshalBinary()
+		if err != nil {
+			return -1, nil, err
+		}
+		n, err := node.UnmarshalBinary(data)
+		if err != nil {
+			return -1, nil, err
+		}
+
+		pos := idx + 1
+		switch nd := n.(type) {
+		case *node.InternalNode:
+			if flags&^(knownFlagLeft|knownFlagRight) != 0 {
+				return -1, nil, errors.New("verifier: malformed known node entry")
+			}
+			if flags&knownFlagLeft != 0 {
+				if pos, nd.Left, err = pv.verifyProof(ctx, proof, pos, known); err != nil {
+					return -1, nil, err
+				}
+			}
+			if flags&knownFlagRight != 0 {
+				if pos, nd.Right, err = pv.verifyProof(ctx, proof, pos, known); err != nil {
+					return -1, nil, err
+				}
+			}
+			nd.UpdateHash()
+		default:
+			if flags != 0 {
+				return -1, nil, errors.New("verifier: malformed known node entry")
+			}
+		}
+
+		nh := n.GetHash()
+		if !nh.Equal(&h) {
+			return -1, nil, fmt.Errorf("verifier: known node hash mismatch (%s)", h)
+		}
+
+		return pos, &node.Pointer{Clean: true, Hash: nh, Node: n}, nil
 	default:
 		return -1, nil, fmt.Errorf("verifier: unexpected entry in proof (%x)", entry[0])
 	}
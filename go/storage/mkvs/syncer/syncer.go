@@ -34,6 +34,9 @@ type GetRequest struct {
 	Tree            TreeID `json:"tree"`
 	Key             []byte `json:"key"`
 	IncludeSiblings bool   `json:"include_siblings,omitempty"`
+	// KnownNodes are the hashes of nodes which the caller already holds.
+	// They may be replaced with known node entries in the returned proof.
+	KnownNodes []hash.Hash `json:"known_nodes,omitempty"`
 }
 
 // GetPrefixesRequest is a request for the SyncGetPrefixes operation.
@@ -41,6 +44,12 @@ type GetPrefixesRequest struct {
 	Tree     TreeID   `json:"tree"`
 	Prefixes [][]byte `json:"prefixes"`
 	Limit    uint16   `json:"limit"`
+	// StartKey is the key to continue from, skipping all keys before it. It
+	// is used to fetch the following page after a response with NextKey set.
+	StartKey []byte `json:"start_key,omitempty"`
+	// KnownNodes are the hashes of nodes which the caller already holds.
+	// They may be replaced with known node entries in the returned proof.
+	KnownNodes []hash.Hash `json:"known_nodes,omitempty"`
 }
 
 // IterateRequest is a request for the SyncIterate operation.
@@ -48,11 +57,18 @@ type IterateRequest struct {
 	Tree     TreeID `json:"tree"`
 	Key      []byte `json:"key"`
 	Prefetch uint16 `json:"prefetch"`
+	// KnownNodes are the hashes of nodes which the caller already holds.
+	// They may be replaced with known node entries in the returned proof.
+	KnownNodes []hash.Hash `json:"known_nodes,omitempty"`
 }
 
 // ProofResponse is a response for requests that produce proofs.
 type ProofResponse struct {
 	Proof Proof `json:"proof"`
+	// NextKey is, for SyncGetPrefixes, the first key which was not included
+	// because the limit was reached. Set it as StartKey to fetch the following
+	// page.
+	NextKey []byte `json:"next_key,omitempty"`
 }
 
 // ReadSyncer is the interface for synchronizing the in-memory cache
@@ -157,6 +157,78 @@ func TestProof(t *testing.T) {
 	require.Error(err, "VerifyProof should fail with invalid proof")
 }
 
+type testKnownNodes map[hash.Hash]node.Node
+
+func (k testKnownNodes) GetKnownNode(h hash.Hash) node.Node {
+	return k[h]
+}
+
+func TestProofKnownNodes(t *testing.T) {
+	require := require.New(t)
+
+	ctx := context.Background()
+	keys, values := generateKeyValuePairsEx("", 10)
+	var ns common.Namespace
+
+	tree := New(nil, nil).(*tree)
+	for i, key := range keys {
+		err := tree.Insert(ctx, key, values[i])
+		require.NoError(err, "Insert")
+	}
+	_, rootHash, err := tree.Commit(ctx, ns, 0)
+	require.NoError(err, "Commit")
+
+	rootNode := tree.cache.pendingRoot.Node
+	rootIntNode := rootNode.(*node.InternalNode)
+	leftNode1 := rootIntNode.Left.Node
+
+	// Include root and root.left, with the root known to the verifier.
+	builder := syncer.NewProofBuilder(rootHash)
+	builder.SetKnownNodes([]hash.Hash{rootHash})
+	builder.Include(rootNode)
+	builder.Include(leftNode1)
+
+	proof, err := builder.Build(ctx)
+	require.NoError(err, "Build should not fail")
+	// Pre-order: root(known), root.left(full), root.left.left(hash), root.left.right(hash)
+	require.Len(proof.Entries, 4, "proof should skip children of known nodes which are not included")
+	require.EqualValues(proof.Entries[0][0], 0x03, "first entry should be a known node")
+	require.EqualValues(rootHash[:], proof.Entries[0][1:1+hash.Size], "first entry hash should be correct (root)")
+	require.EqualValues(proof.Entries[0][1+hash.Size], 0x01, "first entry should only flag the left child")
+	require.EqualValues(proof.Entries[1][0], 0x01, "second entry should be a full node")
+
+	// Proof should verify with the known nodes.
+	var pv syncer.ProofVerifier
+	known := testKnownNodes{rootHash: rootNode}
+	ptr, err := pv.VerifyProofWithKnownNodes(ctx, rootHash, proof, known)
+	require.NoError(err, "VerifyProofWithKnownNodes should not fail with a valid proof")
+	verifiedRoot := ptr.Node.(*node.InternalNode)
+	require.NotNil(verifiedRoot.Left.Node, "left child should be included")
+	require.Nil(verifiedRoot.Right.Node, "right child should only be a hash")
+	require.EqualValues(rootIntNode.Right.Hash, verifiedRoot.Right.Hash, "right child hash should be correct")
+
+	// Proof should not verify without the known nodes.
+	_, err = pv.VerifyProof(ctx, rootHash, proof)
+	require.Error(err, "VerifyProof should fail without known nodes")
+
+	// Known node with a different hash.
+	bogusHash := hash.NewFromBytes([]byte("i am a bogus hash"))
+	_, err = pv.VerifyProofWithKnownNodes(ctx, rootHash, proof, testKnownNodes{rootHash: leftNode1})
+	require.Error(err, "VerifyProofWithKnownNodes should fail with a mismatched known node")
+
+	// Corrupted known node entry.
+	corrupted := copyProof(proof)
+	copy(corrupted.Entries[0][1:], bogusHash[:])
+	_, err = pv.VerifyProofWithKnownNodes(ctx, rootHash, corrupted, known)
+	require.Error(err, "VerifyProofWithKnownNodes should fail with an unknown node")
+
+	// Invalid flags.
+	corrupted = copyProof(proof)
+	corrupted.Entries[0][1+hash.Size] = 0x04
+	_, err = pv.VerifyProofWithKnownNodes(ctx, rootHash, corrupted, known)
+	require.Error(err, "VerifyProofWithKnownNodes should fail with invalid flags")
+}
+
 func copyProof(p *syncer.Proof) *syncer.Proof {
 	if p == nil {
 		return nil
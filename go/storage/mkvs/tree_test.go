@@ -9,6 +9,7 @@ import (
 	"io/ioutil"
 	"os"
 	"path/filepath"
+	"sort"
 	"testing"
 
 	"github.com/stretchr/testify/require"
@@ -812,6 +813,47 @@ func testSyncerPrefetchPrefixes(t *testing.T, ndb db.NodeDB, factory NodeDBFacto
 	require.EqualValues(t, 0, stats.SyncIterateCount, "SyncIterate should not be called")
 }
 
+func testSyncerPrefetchPrefixesPagination(t *testing.T, ndb db.NodeDB, factory NodeDBFactory) {
+	ctx := context.Background()
+	keys, _, root, tree := generatePopulatedTree(t, ndb)
+
+	sortedKeys := append([][]byte{}, keys...)
+	sort.Slice(sortedKeys, func(i, j int) bool {
+		return bytes.Compare(sortedKeys[i], sortedKeys[j]) < 0
+	})
+
+	// Overlapping prefixes are only returned once and a full page reports
+	// the key to continue from.
+	const limit = 300
+	var (
+		pv       syncer.ProofVerifier
+		startKey []byte
+		pages    int
+	)
+	for {
+		rsp, err := tree.SyncGetPrefixes(ctx, &syncer.GetPrefixesRequest{
+			Tree: syncer.TreeID{
+				Root:     root,
+				Position: root.Hash,
+			},
+			Prefixes: [][]byte{[]byte("key 1"), []byte("key")},
+			Limit:    limit,
+			StartKey: startKey,
+		})
+		require.NoError(t, err, "SyncGetPrefixes")
+		_, err = pv.VerifyProof(ctx, root.Hash, &rsp.Proof)
+		require.NoError(t, err, "VerifyProof")
+		pages++
+
+		if rsp.NextKey == nil {
+			break
+		}
+		require.EqualValues(t, sortedKeys[pages*limit], rsp.NextKey, "NextKey")
+		startKey = rsp.NextKey
+	}
+	require.Equal(t, 4, pages, "number of pages")
+}
+
 func testValueEviction(t *testing.T, ndb db.NodeDB, factory NodeDBFactory) {
 	ctx := context.Background()
 	tree := New(nil, ndb, Capacity(0, 512)).(*tree)
@@ -2049,6 +2091,7 @@ func testBackend(
 		{"SyncerInsert", testSyncerInsert},
 		{"SyncerNilNodes", testSyncerNilNodes},
 		{"SyncerPrefetchPrefixes", testSyncerPrefetchPrefixes},
+		{"SyncerPrefetchPrefixesPagination", testSyncerPrefetchPrefixesPagination},
 		{"ValueEviction", testValueEviction},
 		{"NodeEviction", testNodeEviction},
 		{"DoubleInsertWithEviction", testDoubleInsertWithEviction},
@@ -48,6 +48,14 @@ pub struct InitResponse {
     /// Checksum for identifying policy.
     #[serde(with = "serde_bytes")]
     pub policy_checksum: Vec<u8>,
+    /// Static public key used by all EnclaveRPC sessions.
+    #[serde(default, with = "serde_bytes", skip_serializing_if = "Vec::is_empty")]
+    pub rpc_static_key: Vec<u8>,
+    /// RAK binding of the static public key used by all EnclaveRPC sessions,
+    /// which allows clients to send requests together with the first
+    /// handshake message.
+    #[serde(default, with = "serde_bytes", skip_serializing_if = "Vec::is_empty")]
+    pub rpc_static_key_binding: Vec<u8>,
 }
 
 /// Context used for the init response signature.
@@ -249,10 +257,18 @@ impl Default for TrustedPolicySigners {
     }
 }
 
-runtime_api! {
-    pub fn get_or_create_keys(RequestIds) -> KeyPair;
+#[runtime_api(rpc, client = "KeyManagerRpcClient", client_feature = "client")]
+pub trait KeyManager {
+    /// Get or create keys for the given runtime and key pair identifier.
+    fn get_or_create_keys(request: RequestIds) -> KeyPair;
 
-    pub fn get_public_key(RequestIds) -> Option<SignedPublicKey>;
+    /// Get the public key for the given runtime and key pair identifier.
+    fn get_public_key(request: RequestIds) -> Option<SignedPublicKey>;
 
-    pub fn replicate_master_secret(ReplicateRequest) -> ReplicateResponse;
+    /// Replicate the key manager master secret.
+    fn replicate_master_secret(request: ReplicateRequest) -> ReplicateResponse;
+
+    /// Initialize the key manager.
+    #[local]
+    fn init(request: InitRequest) -> SignedInitResponse;
 }
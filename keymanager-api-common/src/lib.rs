@@ -7,7 +7,6 @@ use std::{
     sync::{Mutex, Once},
 };
 
-#[macro_use]
 pub mod api;
 
 // Re-exports.
@@ -12,7 +12,7 @@ use grpcio::Channel;
 use io_context::Context;
 use lru::LruCache;
 
-use oasis_core_client::{create_rpc_api_client, BoxFuture, RpcClient};
+use oasis_core_client::{BoxFuture, RpcClient};
 use oasis_core_keymanager_api_common::*;
 use oasis_core_runtime::{
     common::{cbor, runtime::RuntimeId, sgx::avr::EnclaveIdentity},
@@ -23,18 +23,17 @@ use oasis_core_runtime::{
 
 use super::KeyManagerClient;
 
-with_api! {
-    create_rpc_api_client!(Client, api);
-}
-
 /// Key manager RPC endpoint.
 const KEY_MANAGER_ENDPOINT: &'static str = "key-manager";
+/// Key manager methods without side effects, which can be sent together with
+/// the first handshake message.
+const IDEMPOTENT_METHODS: &[&str] = &["get_or_create_keys", "get_public_key"];
 
 struct Inner {
     /// Runtime Id for which we are going to request keys.
     runtime_id: RuntimeId,
     /// RPC client.
-    rpc_client: Client,
+    rpc_client: KeyManagerRpcClient,
     /// Local cache for the get_or_create_keys KeyManager endpoint.
     get_or_create_secret_keys_cache: RwLock<LruCache<KeyPairId, KeyPair>>,
     /// Local cache for the get_public_key KeyManager endpoint.
@@ -48,10 +47,12 @@ pub struct RemoteClient {
 
 impl RemoteClient {
     fn new(runtime_id: RuntimeId, client: RpcClient, keys_cache_sizes: usize) -> Self {
+        client.set_idempotent_methods(IDEMPOTENT_METHODS.iter().map(|m| m.to_string()).collect());
+
         Self {
             inner: Arc::new(Inner {
                 runtime_id,
-                rpc_client: Client::new(client),
+                rpc_client: KeyManagerRpcClient::new(client),
                 get_or_create_secret_keys_cache: RwLock::new(LruCache::new(keys_cache_sizes)),
                 get_public_key_cache: RwLock::new(LruCache::new(keys_cache_sizes)),
             }),
@@ -137,12 +138,29 @@ impl RemoteClient {
     pub fn set_policy(&self, signed_policy_raw: Vec<u8>) -> Result<()> {
         let untrusted_policy: SignedPolicySGX = cbor::from_slice(&signed_policy_raw)?;
         let policy = untrusted_policy.verify()?;
-        let client = &self.inner.rpc_client.rpc_client;
+        let client = self.inner.rpc_client.rpc_client();
         let policies: HashSet<EnclaveIdentity> =
             HashSet::from_iter(policy.enclaves.keys().cloned());
         client.update_enclaves(Some(policies));
         Ok(())
     }
+
+    /// Set the static key used by the key manager's EnclaveRPC sessions from
+    /// a key manager node's signed initialization response, as published in
+    /// the registry.
+    ///
+    /// The key is verified using its RAK binding against the allowed enclaves,
+    /// so the initialization response signature does not need to be checked.
+    /// Requests are then sent together with the first handshake message.
+    pub fn set_rpc_static_key(&self, signed_init_response_raw: Vec<u8>) -> Result<()> {
+        let untrusted_response: SignedInitResponse = cbor::from_slice(&signed_init_response_raw)?;
+        let init_response = untrusted_response.init_response;
+        let client = self.inner.rpc_client.rpc_client();
+        client.set_remote_static_key(
+            init_response.rpc_static_key,
+            &init_response.rpc_static_key_binding,
+        )
+    }
 }
 
 impl KeyManagerClient for RemoteClient {
@@ -6,4 +6,5 @@ use oasis_core_runtime::{common::runtime::RuntimeId, Protocol};
 pub struct Context {
     pub runtime_id: RuntimeId,
     pub protocol: Arc<Protocol>,
+    pub rpc_static_key: Vec<u8>,
 }
@@ -27,7 +27,7 @@ use oasis_core_runtime::{
         runtime::RuntimeId,
         sgx::egetkey::egetkey,
     },
-    enclave_rpc::Context as RpcContext,
+    enclave_rpc::{session, Context as RpcContext},
     executor::Executor,
     runtime_context,
     storage::StorageContext,
@@ -305,11 +305,20 @@ impl Kdf {
             inner.signer = Some(signer);
         }
 
+        // Publish the EnclaveRPC static key together with its RAK binding, so
+        // that clients can send requests together with the first handshake
+        // message.
+        let rctx = runtime_context!(ctx, KmContext);
+        let rpc_static_key = rctx.rpc_static_key.clone();
+        let rpc_static_key_binding = session::static_key_binding(&ctx.rak, &rpc_static_key);
+
         // Build the response and sign it with the RAK.
         let init_response = InitResponse {
             is_secure: BUILD_INFO.is_secure && !Policy::unsafe_skip(),
             checksum: inner.checksum.as_ref().unwrap().clone(),
             policy_checksum,
+            rpc_static_key,
+            rpc_static_key_binding,
         };
 
         let body = cbor::to_vec(&init_response);
@@ -0,0 +1,813 @@
+//! Runtime API definition parsing and code generation.
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{
+    parse_quote, Attribute, AttributeArgs, Error, FnArg, Ident, Index, ItemTrait, Lit, Meta,
+    NestedMeta, Pat, Result, ReturnType, TraitItem, Type,
+};
+
+/// Runtime API kind.
+#[derive(Clone, Copy, PartialEq)]
+enum ApiKind {
+    /// Transaction API, dispatched by the transaction method dispatcher.
+    Txn,
+    /// EnclaveRPC API, dispatched by the RPC dispatcher.
+    Rpc,
+}
+
+/// Arguments passed to the `runtime_api` attribute.
+struct ApiArgs {
+    kind: ApiKind,
+    client: Option<Ident>,
+    client_feature: Option<String>,
+}
+
+impl ApiArgs {
+    fn parse(args: AttributeArgs) -> Result<Self> {
+        let mut kind = None;
+        let mut client = None;
+        let mut client_feature = None;
+
+        for arg in args {
+            match arg {
+                NestedMeta::Meta(Meta::Path(ref path))
+                    if path.is_ident("txn") || path.is_ident("rpc") =>
+                {
+                    if kind.is_some() {
+                        return Err(Error::new_spanned(
+                            path,
+                            "runtime_api: API kind specified more than once",
+                        ));
+                    }
+                    kind = Some(if path.is_ident("txn") {
+                        ApiKind::Txn
+                    } else {
+                        ApiKind::Rpc
+                    });
+                }
+                NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("client") => {
+                    match nv.lit {
+                        Lit::Str(ref name) => client = Some(name.parse()?),
+                        ref lit => {
+                            return Err(Error::new_spanned(
+                                lit,
+                                "runtime_api: client name must be a string",
+                            ))
+                        }
+                    }
+                }
+                NestedMeta::Meta(Meta::NameValue(ref nv))
+                    if nv.path.is_ident("client_feature") =>
+                {
+                    match nv.lit {
+                        Lit::Str(ref feature) => client_feature = Some(feature.value()),
+                        ref lit => {
+                            return Err(Error::new_spanned(
+                                lit,
+                                "runtime_api: client feature must be a string",
+                            ))
+                        }
+                    }
+                }
+                arg => {
+                    return Err(Error::new_spanned(
+                        arg,
+                        "runtime_api: unsupported argument (expected `txn`, `rpc`, `client` or `client_feature`)",
+                    ))
+                }
+            }
+        }
+
+        let kind = kind.ok_or_else(|| {
+            Error::new(
+                Span::call_site(),
+                "runtime_api: API kind (`txn` or `rpc`) must be specified",
+            )
+        })?;
+
+        Ok(Self {
+            kind,
+            client,
+            client_feature,
+        })
+    }
+}
+
+/// A single runtime API method.
+struct ApiMethod {
+    name: Ident,
+    docs: Vec<Attribute>,
+    doc_text: String,
+    args: Vec<(Ident, Type)>,
+    output: Type,
+    read_only: bool,
+    local: bool,
+}
+
+impl ApiMethod {
+    fn parse(kind: ApiKind, item: TraitItem) -> Result<Self> {
+        let method = match item {
+            TraitItem::Method(method) => method,
+            item => {
+                return Err(Error::new_spanned(
+                    item,
+                    "runtime_api: only method declarations are supported",
+                ))
+            }
+        };
+        if let Some(ref body) = method.default {
+            return Err(Error::new_spanned(
+                body,
+                "runtime_api: method declarations must not have a body",
+            ));
+        }
+
+        let sig = method.sig;
+        if sig.constness.is_some()
+            || sig.asyncness.is_some()
+            || sig.unsafety.is_some()
+            || sig.abi.is_some()
+            || sig.variadic.is_some()
+            || !sig.generics.params.is_empty()
+            || sig.generics.where_clause.is_some()
+        {
+            return Err(Error::new_spanned(
+                &sig,
+                "runtime_api: methods must be declared as `fn name(arg: Type, ...) -> Type`",
+            ));
+        }
+
+        let mut docs = Vec::new();
+        let mut doc_lines = Vec::new();
+        let mut read_only = false;
+        let mut local = false;
+        for attr in method.attrs {
+            if attr.path.is_ident("doc") {
+                if let Meta::NameValue(nv) = attr.parse_meta()? {
+                    if let Lit::Str(line) = nv.lit {
+                        doc_lines.push(line.value().trim().to_owned());
+                    }
+                }
+                docs.push(attr);
+            } else if attr.path.is_ident("read_only") {
+                if kind != ApiKind::Txn {
+                    return Err(Error::new_spanned(
+                        attr,
+                        "runtime_api: `read_only` is only supported for transaction APIs",
+                    ));
+                }
+                read_only = true;
+            } else if attr.path.is_ident("local") {
+                if kind != ApiKind::Rpc {
+                    return Err(Error::new_spanned(
+                        attr,
+                        "runtime_api: `local` is only supported for RPC APIs",
+                    ));
+                }
+                local = true;
+            } else {
+                return Err(Error::new_spanned(
+                    attr,
+                    "runtime_api: unsupported method attribute",
+                ));
+            }
+        }
+
+        let mut args = Vec::new();
+        for input in sig.inputs {
+            match input {
+                FnArg::Receiver(receiver) => {
+                    return Err(Error::new_spanned(
+                        receiver,
+                        "runtime_api: methods must not take `self`",
+                    ))
+                }
+                FnArg::Typed(arg) => match *arg.pat {
+                    Pat::Ident(ref pat) if pat.by_ref.is_none() && pat.subpat.is_none() => {
+                        if pat.ident == "ctx" {
+                            return Err(Error::new_spanned(
+                                pat,
+                                "runtime_api: argument name `ctx` is reserved",
+                            ));
+                        }
+                        args.push((pat.ident.clone(), *arg.ty));
+                    }
+                    ref pat => {
+                        return Err(Error::new_spanned(
+                            pat,
+                            "runtime_api: arguments must be simple identifiers",
+                        ))
+                    }
+                },
+            }
+        }
+
+        let output = match sig.output {
+            ReturnType::Default => parse_quote!(()),
+            ReturnType::Type(_, ty) => *ty,
+        };
+
+        Ok(Self {
+            name: sig.ident,
+            docs,
+            doc_text: doc_lines.join("\n"),
+            args,
+            output,
+            read_only,
+            local,
+        })
+    }
+
+    /// Type the method arguments are encoded as on the wire.
+    fn call_type(&self) -> TokenStream {
+        match self.args.len() {
+            0 => quote!(()),
+            1 => {
+                let ty = &self.args[0].1;
+                quote!(#ty)
+            }
+            _ => {
+                let types = self.args.iter().map(|(_, ty)| ty);
+                quote!((#(#types,)*))
+            }
+        }
+    }
+
+    /// Expression encoding the method arguments for the wire.
+    fn call_value(&self) -> TokenStream {
+        match self.args.len() {
+            0 => quote!(()),
+            1 => {
+                let name = &self.args[0].0;
+                quote!(#name)
+            }
+            _ => {
+                let names = self.args.iter().map(|(name, _)| name);
+                quote!((#(#names,)*))
+            }
+        }
+    }
+}
+
+/// Expand a runtime API definition.
+pub fn expand(args: AttributeArgs, item: ItemTrait) -> Result<TokenStream> {
+    let ApiArgs {
+        kind,
+        client,
+        client_feature,
+    } = ApiArgs::parse(args)?;
+    if !item.generics.params.is_empty() || item.generics.where_clause.is_some() {
+        return Err(Error::new_spanned(
+            &item.generics,
+            "runtime_api: generic APIs are not supported",
+        ));
+    }
+
+    let methods = item
+        .items
+        .into_iter()
+        .map(|item| ApiMethod::parse(kind, item))
+        .collect::<Result<Vec<_>>>()?;
+
+    let attrs = &item.attrs;
+    let vis = &item.vis;
+    let ident = &item.ident;
+    let colon_token = &item.colon_token;
+    let supertraits = &item.supertraits;
+
+    let result = quote!(::oasis_core_runtime::api::__private::Result);
+    let (ctx_type, dispatcher_type, method_module) = match kind {
+        ApiKind::Txn => (
+            quote!(::oasis_core_runtime::transaction::Context),
+            quote!(::oasis_core_runtime::transaction::dispatcher::MethodDispatcher),
+            quote!(::oasis_core_runtime::transaction::dispatcher),
+        ),
+        ApiKind::Rpc => (
+            quote!(::oasis_core_runtime::enclave_rpc::Context),
+            quote!(::oasis_core_runtime::enclave_rpc::dispatcher::Dispatcher),
+            quote!(::oasis_core_runtime::enclave_rpc::dispatcher),
+        ),
+    };
+
+    // Trait implemented by the runtime.
+    let trait_methods = methods.iter().map(|m| {
+        let ApiMethod {
+            name, docs, output, ..
+        } = m;
+        let arg_names = m.args.iter().map(|(name, _)| name);
+        let arg_types = m.args.iter().map(|(_, ty)| ty);
+
+        quote! {
+            #(#docs)*
+            #[allow(clippy::ptr_arg)]
+            fn #name(&self, #(#arg_names: &#arg_types,)* ctx: &mut #ctx_type) -> #result<#output>;
+        }
+    });
+    let registrations = methods.iter().map(|m| {
+        let name = &m.name;
+        let name_str = name.to_string();
+        let output = &m.output;
+        let call_type = m.call_type();
+        let (args_ident, call_args) = match m.args.len() {
+            0 => (format_ident!("_args"), quote!()),
+            1 => (format_ident!("args"), quote!(args,)),
+            n => {
+                let indices = (0..n).map(Index::from);
+                (format_ident!("args"), quote!(#(&args.#indices,)*))
+            }
+        };
+        // Read-only methods run with the storage context in read-only mode, so
+        // that any updates fail the method.
+        let mut call = quote!(handler.#name(#call_args ctx));
+        if m.read_only {
+            call = quote!(::oasis_core_runtime::storage::StorageContext::read_only(|| #call));
+        }
+        let is_local = match kind {
+            ApiKind::Txn => quote!(),
+            ApiKind::Rpc => {
+                let local = m.local;
+                quote!(, #local)
+            }
+        };
+
+        quote! {
+            let handler = ::std::sync::Arc::clone(&self);
+            dispatcher.add_method(
+                #method_module::Method::new(
+                    #method_module::MethodDescriptor {
+                        name: #name_str.to_owned(),
+                    },
+                    move |#args_ident: &#call_type, ctx: &mut #ctx_type| -> #result<#output> {
+                        #call
+                    },
+                )
+                #is_local
+            );
+        }
+    });
+
+    // Method descriptors.
+    let infos_ident = format_ident!("{}_METHODS", to_screaming_snake_case(&ident.to_string()));
+    let infos_doc = format!("Descriptions of all methods of the `{}` API.", ident);
+    let infos = methods.iter().map(|m| {
+        let name_str = m.name.to_string();
+        let doc_text = &m.doc_text;
+        let read_only = m.read_only;
+        let local = m.local;
+
+        quote! {
+            ::oasis_core_runtime::api::MethodInfo {
+                name: #name_str,
+                docs: #doc_text,
+                read_only: #read_only,
+                local: #local,
+            }
+        }
+    });
+
+    // Typed client.
+    let client_ident = client.unwrap_or_else(|| format_ident!("{}Client", ident));
+    let client_doc = format!("Client for the `{}` API.", ident);
+    let client_methods = methods.iter().filter(|m| !m.local).map(|m| {
+        let ApiMethod {
+            name, docs, output, ..
+        } = m;
+        let name_str = name.to_string();
+        let arg_names = m.args.iter().map(|(name, _)| name);
+        let arg_types = m.args.iter().map(|(_, ty)| ty);
+        let call_value = m.call_value();
+
+        match kind {
+            ApiKind::Txn => quote! {
+                #(#docs)*
+                pub fn #name(
+                    &self,
+                    #(#arg_names: #arg_types,)*
+                ) -> ::oasis_core_client::BoxFuture<#output> {
+                    self.txn_client.call(#name_str, #call_value)
+                }
+            },
+            ApiKind::Rpc => quote! {
+                #(#docs)*
+                pub fn #name(
+                    &self,
+                    ctx: ::oasis_core_runtime::api::__private::IoContext,
+                    #(#arg_names: #arg_types,)*
+                ) -> ::oasis_core_client::BoxFuture<#output> {
+                    self.rpc_client.call(ctx, #name_str, #call_value)
+                }
+            },
+        }
+    });
+    // The transaction client is not available inside enclaves, while the RPC
+    // client is, so that enclaves can talk to other enclaves.
+    let client_cfg = match (kind, client_feature) {
+        (ApiKind::Txn, None) => quote!(#[cfg(not(target_env = "sgx"))]),
+        (ApiKind::Txn, Some(feature)) => {
+            quote!(#[cfg(all(not(target_env = "sgx"), feature = #feature))])
+        }
+        (ApiKind::Rpc, None) => quote!(),
+        (ApiKind::Rpc, Some(feature)) => quote!(#[cfg(feature = #feature)]),
+    };
+    let client = match kind {
+        ApiKind::Txn => quote! {
+            #[doc = #client_doc]
+            #client_cfg
+            #vis struct #client_ident {
+                txn_client: ::oasis_core_client::TxnClient,
+            }
+
+            #client_cfg
+            impl #client_ident {
+                /// Create new client instance.
+                pub fn new(txn_client: ::oasis_core_client::TxnClient) -> Self {
+                    Self { txn_client }
+                }
+
+                /// The underlying transaction client.
+                pub fn txn_client(&self) -> &::oasis_core_client::TxnClient {
+                    &self.txn_client
+                }
+
+                #(#client_methods)*
+            }
+        },
+        ApiKind::Rpc => quote! {
+            #[doc = #client_doc]
+            #client_cfg
+            #vis struct #client_ident {
+                rpc_client: ::oasis_core_client::RpcClient,
+            }
+
+            #client_cfg
+            impl #client_ident {
+                /// Create new client instance.
+                pub fn new(rpc_client: ::oasis_core_client::RpcClient) -> Self {
+                    Self { rpc_client }
+                }
+
+                /// The underlying RPC client.
+                pub fn rpc_client(&self) -> &::oasis_core_client::RpcClient {
+                    &self.rpc_client
+                }
+
+                #(#client_methods)*
+            }
+        },
+    };
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis trait #ident #colon_token #supertraits {
+            #(#trait_methods)*
+
+            /// Register all methods of this API with the given dispatcher.
+            #[allow(unused_variables)]
+            fn register_methods(
+                self: ::std::sync::Arc<Self>,
+                dispatcher: &mut #dispatcher_type,
+            ) where
+                Self: Sized + 'static,
+            {
+                #(#registrations)*
+            }
+        }
+
+        #[doc = #infos_doc]
+        #vis const #infos_ident: &[::oasis_core_runtime::api::MethodInfo] = &[
+            #(#infos,)*
+        ];
+
+        #client
+    })
+}
+
+/// Convert a `CamelCase` identifier into `SCREAMING_SNAKE_CASE`.
+fn to_screaming_snake_case(name: &str) -> String {
+    let mut result = String::with_capacity(name.len() + 4);
+    let mut prev_lower = false;
+    for ch in name.chars() {
+        if ch.is_uppercase() && prev_lower {
+            result.push('_');
+        }
+        prev_lower = ch.is_lowercase() || ch.is_numeric();
+        result.extend(ch.to_uppercase());
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use syn::{Expr, File, ImplItem, Item, TraitItem};
+
+    use super::*;
+
+    fn expand_api(args: TokenStream, item: TokenStream) -> Result<File> {
+        let args = syn::parse::Parser::parse2(
+            syn::punctuated::Punctuated::<NestedMeta, syn::Token![,]>::parse_terminated,
+            args,
+        )?
+        .into_iter()
+        .collect();
+        let item = syn::parse2(item)?;
+
+        syn::parse2(expand(args, item)?)
+    }
+
+    fn expand_error(args: TokenStream, item: TokenStream) -> String {
+        match expand_api(args, item) {
+            Ok(_) => panic!("expansion should fail"),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    fn trait_methods(file: &File) -> Vec<(String, usize)> {
+        file.items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Trait(item) => Some(item),
+                _ => None,
+            })
+            .flat_map(|item| item.items.iter())
+            .filter_map(|item| match item {
+                TraitItem::Method(method) if method.sig.ident != "register_methods" => {
+                    Some((method.sig.ident.to_string(), method.sig.inputs.len()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn client_methods(file: &File) -> Vec<(String, usize)> {
+        file.items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Impl(item) => Some(item),
+                _ => None,
+            })
+            .flat_map(|item| item.items.iter())
+            .filter_map(|item| match item {
+                ImplItem::Method(method) => {
+                    Some((method.sig.ident.to_string(), method.sig.inputs.len()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_expand_txn() {
+        let file = expand_api(
+            quote!(txn),
+            quote! {
+                pub trait KeyValue {
+                    /// Insert a key/value pair.
+                    fn insert(key: String, value: String) -> Option<String>;
+
+                    #[read_only]
+                    fn get(key: String) -> Option<String>;
+
+                    fn clear();
+                }
+            },
+        )
+        .unwrap();
+
+        // Trait methods take all arguments by reference, followed by the context.
+        assert_eq!(
+            trait_methods(&file),
+            vec![
+                ("insert".to_owned(), 4),
+                ("get".to_owned(), 3),
+                ("clear".to_owned(), 2),
+            ]
+        );
+        // Client methods take the arguments by value.
+        assert_eq!(
+            client_methods(&file),
+            vec![
+                ("new".to_owned(), 1),
+                ("txn_client".to_owned(), 1),
+                ("insert".to_owned(), 3),
+                ("get".to_owned(), 2),
+                ("clear".to_owned(), 1),
+            ]
+        );
+
+        let expanded = quote!(#file).to_string();
+        // Multiple arguments are encoded as a tuple and passed to the handler
+        // one by one.
+        assert!(expanded.contains(&quote!(args: &(String, String)).to_string()));
+        assert!(expanded.contains(&quote!(handler.insert(&args.0, &args.1, ctx)).to_string()));
+        assert!(expanded.contains(&quote!(handler.clear(ctx)).to_string()));
+        // Read-only methods run with a read-only storage context. The
+        // expected calls are parsed the same way as the expansion, so that
+        // both are printed alike.
+        let read_only_call = |call: Expr| -> String {
+            let expr: Expr =
+                parse_quote!(::oasis_core_runtime::storage::StorageContext::read_only(|| #call));
+            quote!(#expr).to_string()
+        };
+        assert!(expanded.contains(&read_only_call(parse_quote!(handler.get(args, ctx)))));
+        assert!(!expanded.contains(&read_only_call(parse_quote!(handler.clear(ctx)))));
+        assert!(expanded.contains(&quote!(read_only: true,).to_string()));
+        assert!(
+            expanded.contains(&quote!(self.txn_client.call("insert", (key, value,))).to_string())
+        );
+        // Method descriptors include the documentation.
+        assert!(expanded.contains("KEY_VALUE_METHODS"));
+        assert!(expanded.contains(&quote!(docs: "Insert a key/value pair.").to_string()));
+        // Transaction clients are not available in SGX.
+        assert!(expanded.contains(&quote!(#[cfg(not(target_env = "sgx"))]).to_string()));
+    }
+
+    #[test]
+    fn test_expand_rpc() {
+        let file = expand_api(
+            quote!(rpc, client = "MyClient", client_feature = "client"),
+            quote! {
+                pub trait KeyManager {
+                    fn get_key(id: u64) -> Vec<u8>;
+
+                    #[local]
+                    fn init(request: Vec<u8>) -> bool;
+                }
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            trait_methods(&file),
+            vec![("get_key".to_owned(), 3), ("init".to_owned(), 3)]
+        );
+        // Local methods are not part of the client and RPC client methods
+        // take an I/O context.
+        assert_eq!(
+            client_methods(&file),
+            vec![
+                ("new".to_owned(), 1),
+                ("rpc_client".to_owned(), 1),
+                ("get_key".to_owned(), 3),
+            ]
+        );
+
+        let expanded = quote!(#file).to_string();
+        assert!(expanded.contains(&quote!(pub struct MyClient).to_string()));
+        assert!(expanded.contains(&quote!(#[cfg(feature = "client")]).to_string()));
+        assert!(!expanded.contains("target_env"));
+        // Methods are registered as local or not.
+        let registrations: Vec<&str> = expanded
+            .split("add_method")
+            .skip(1)
+            .map(|registration| registration.split(';').next().unwrap().trim_end())
+            .collect();
+        assert_eq!(registrations.len(), 2);
+        assert!(registrations[0].contains(&quote!(handler.get_key(args, ctx)).to_string()));
+        assert!(registrations[0].ends_with("false)"));
+        assert!(registrations[1].contains(&quote!(handler.init(args, ctx)).to_string()));
+        assert!(registrations[1].ends_with("true)"));
+        assert!(expanded.contains(&quote!(local: true,).to_string()));
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        let api = quote! {
+            pub trait Api {
+                fn get(key: String) -> String;
+            }
+        };
+
+        assert_eq!(
+            expand_error(quote!(), api.clone()),
+            "runtime_api: API kind (`txn` or `rpc`) must be specified"
+        );
+        assert_eq!(
+            expand_error(quote!(txn, rpc), api.clone()),
+            "runtime_api: API kind specified more than once"
+        );
+        assert_eq!(
+            expand_error(quote!(txn, foo), api.clone()),
+            "runtime_api: unsupported argument (expected `txn`, `rpc`, `client` or `client_feature`)"
+        );
+        assert_eq!(
+            expand_error(quote!(txn, client = 42), api.clone()),
+            "runtime_api: client name must be a string"
+        );
+        assert_eq!(
+            expand_error(quote!(txn, client_feature = 42), api),
+            "runtime_api: client feature must be a string"
+        );
+
+        let cases = vec![
+            (
+                quote!(txn),
+                quote!(
+                    trait Api<T> {
+                        fn get(key: T) -> T;
+                    }
+                ),
+                "runtime_api: generic APIs are not supported",
+            ),
+            (
+                quote!(txn),
+                quote!(
+                    trait Api {
+                        const X: u64;
+                    }
+                ),
+                "runtime_api: only method declarations are supported",
+            ),
+            (
+                quote!(txn),
+                quote!(
+                    trait Api {
+                        fn get(key: String) -> String {
+                            key
+                        }
+                    }
+                ),
+                "runtime_api: method declarations must not have a body",
+            ),
+            (
+                quote!(txn),
+                quote!(
+                    trait Api {
+                        async fn get(key: String) -> String;
+                    }
+                ),
+                "runtime_api: methods must be declared as `fn name(arg: Type, ...) -> Type`",
+            ),
+            (
+                quote!(txn),
+                quote!(
+                    trait Api {
+                        fn get(&self, key: String) -> String;
+                    }
+                ),
+                "runtime_api: methods must not take `self`",
+            ),
+            (
+                quote!(txn),
+                quote!(
+                    trait Api {
+                        fn get(ctx: String) -> String;
+                    }
+                ),
+                "runtime_api: argument name `ctx` is reserved",
+            ),
+            (
+                quote!(txn),
+                quote!(
+                    trait Api {
+                        fn get((a, b): (u64, u64)) -> u64;
+                    }
+                ),
+                "runtime_api: arguments must be simple identifiers",
+            ),
+            (
+                quote!(txn),
+                quote!(
+                    trait Api {
+                        #[local]
+                        fn get(key: String) -> String;
+                    }
+                ),
+                "runtime_api: `local` is only supported for RPC APIs",
+            ),
+            (
+                quote!(rpc),
+                quote!(
+                    trait Api {
+                        #[read_only]
+                        fn get(key: String) -> String;
+                    }
+                ),
+                "runtime_api: `read_only` is only supported for transaction APIs",
+            ),
+            (
+                quote!(txn),
+                quote!(
+                    trait Api {
+                        #[payable]
+                        fn get(key: String) -> String;
+                    }
+                ),
+                "runtime_api: unsupported method attribute",
+            ),
+        ];
+        for (args, item, expected) in cases {
+            assert_eq!(expand_error(args, item), expected);
+        }
+    }
+
+    #[test]
+    fn test_screaming_snake_case() {
+        assert_eq!(to_screaming_snake_case("KeyManager"), "KEY_MANAGER");
+        assert_eq!(
+            to_screaming_snake_case("SimpleKeyValue"),
+            "SIMPLE_KEY_VALUE"
+        );
+        assert_eq!(to_screaming_snake_case("Api2Test"), "API2_TEST");
+        assert_eq!(to_screaming_snake_case("RPC"), "RPC");
+    }
+}
@@ -0,0 +1,64 @@
+//! Procedural macros for the Oasis Core runtime SDK.
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, AttributeArgs, ItemTrait};
+
+mod api;
+
+/// Define a runtime API from a trait definition.
+///
+/// The attribute must specify the API kind, which is either `txn` for
+/// transaction APIs or `rpc` for EnclaveRPC APIs. Each trait item declares
+/// one API method together with its (named) arguments and output type.
+///
+/// From the definition, the following items are generated:
+///
+/// * A trait with the same name that runtimes implement. Every method takes
+///   its arguments by reference, followed by the dispatcher context, and
+///   returns an `anyhow::Result`. The provided `register_methods` method
+///   registers all API methods with the corresponding dispatcher.
+/// * A `<NAME>_METHODS` constant holding a `MethodInfo` descriptor for each
+///   method, including its documentation and attributes.
+/// * A typed client called `<Name>Client` (can be overridden by passing
+///   `client = "OtherName"`), which is based on `TxnClient` or `RpcClient`.
+///   Crates using the attribute therefore need to depend on the client crate.
+///   Transaction clients are never generated inside SGX enclaves. Passing
+///   `client_feature = "name"` additionally gates the client behind the given
+///   cargo feature of the defining crate, so that the client crate can be an
+///   optional dependency.
+///
+/// Methods without arguments are called with `()`, methods with a single
+/// argument are called with that argument and methods with multiple
+/// arguments are called with a tuple of all arguments.
+///
+/// The following per-method attributes are supported:
+///
+/// * `#[read_only]` (transaction APIs only) marks a method that does not
+///   modify any state. The method runs with the storage context in read-only
+///   mode, so any MKVS updates it makes fail.
+/// * `#[local]` (RPC APIs only) registers the method as a local RPC method,
+///   which is only available to the host and not part of the client.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// #[runtime_api(txn)]
+/// pub trait KeyValue {
+///     /// Insert a key/value pair.
+///     fn insert(key: String, value: String) -> Option<String>;
+///
+///     /// Fetch the value for a key.
+///     #[read_only]
+///     fn get(key: String) -> Option<String>;
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn runtime_api(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let item = parse_macro_input!(input as ItemTrait);
+
+    api::expand(args, item)
+        .unwrap_or_else(|error| error.to_compile_error())
+        .into()
+}
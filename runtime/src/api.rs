@@ -0,0 +1,27 @@
+//! Runtime API definitions.
+//!
+//! Runtime APIs are defined using the `#[runtime_api]` attribute, which
+//! generates the dispatcher registration, method descriptors and a typed
+//! client from a single trait definition.
+
+/// Description of a runtime API method.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MethodInfo {
+    /// Method name.
+    pub name: &'static str,
+    /// Method documentation.
+    pub docs: &'static str,
+    /// True iff the method does not modify any state.
+    ///
+    /// Storage updates made by read-only methods fail.
+    pub read_only: bool,
+    /// True iff the method is a local RPC method, only available to the host.
+    pub local: bool,
+}
+
+// Dependencies used by code generated by `#[runtime_api]`.
+#[doc(hidden)]
+pub mod __private {
+    pub use anyhow::Result;
+    pub use io_context::Context as IoContext;
+}
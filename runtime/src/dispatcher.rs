@@ -22,10 +22,12 @@ use crate::{
             signature::{Signature, Signer},
         },
         logger::get_logger,
-        roothash::{Block, ComputeResultsHeader, COMPUTE_RESULTS_HEADER_CONTEXT},
+        roothash::{
+            Block, ComputeResultsHeader, Message as RoothashMessage, COMPUTE_RESULTS_HEADER_CONTEXT,
+        },
     },
     enclave_rpc::{
-        demux::Demux as RpcDemux,
+        demux::{Demux as RpcDemux, RateLimits as RpcRateLimits, StreamLimits as RpcStreamLimits},
         dispatcher::Dispatcher as RpcDispatcher,
         types::{Message as RpcMessage, Request as RpcRequest},
         Context as RpcContext,
@@ -37,10 +39,11 @@ use crate::{
             sync::{HostReadSyncer, NoopReadSyncer},
             Root, Tree,
         },
-        StorageContext,
+        KeyValue, StorageContext,
     },
     transaction::{
         dispatcher::{Dispatcher as TxnDispatcher, NoopDispatcher as TxnNoopDispatcher},
+        tags::Tags,
         tree::Tree as TxnTree,
         types::TxnBatch,
         Context as TxnContext,
@@ -61,6 +64,24 @@ pub trait Initializer: Send + Sync {
         rpc_demux: &mut RpcDemux,
         rpc_dispatcher: &mut RpcDispatcher,
     ) -> Option<Box<dyn TxnDispatcher>>;
+
+    /// Rate limits and quotas for incoming RPC frames.
+    ///
+    /// By default, frames are not limited. Runtimes exposing RPC methods to
+    /// remote clients should opt in, either by overriding this method or by
+    /// configuring the demultiplexer when initializing the dispatchers.
+    fn rpc_rate_limits(&self) -> RpcRateLimits {
+        RpcRateLimits::default()
+    }
+
+    /// Limits for streamed RPC responses held in enclave memory.
+    ///
+    /// By default, the memory held by the streamed responses of all sessions
+    /// together is bounded by a few MiB. Runtimes with large responses can
+    /// raise the limits by overriding this method.
+    fn rpc_stream_limits(&self) -> RpcStreamLimits {
+        RpcStreamLimits::default()
+    }
 }
 
 impl<F> Initializer for F
@@ -182,6 +203,8 @@ impl Dispatcher {
         // Create actual dispatchers for RPCs and transactions.
         info!(self.logger, "Starting the runtime dispatcher");
         let mut rpc_demux = RpcDemux::new(self.rak.clone());
+        rpc_demux.set_rate_limits(initializer.rpc_rate_limits());
+        rpc_demux.set_stream_limits(initializer.rpc_stream_limits());
         let mut rpc_dispatcher = RpcDispatcher::new();
         let mut txn_dispatcher: Box<dyn TxnDispatcher> = if let Some(txn) =
             initializer.init(&protocol, &self.rak, &mut rpc_demux, &mut rpc_dispatcher)
@@ -310,20 +333,18 @@ impl Dispatcher {
 
         // Create a new context and dispatch the batch.
         let ctx = ctx.freeze();
-        cache.maybe_replace(Root {
+        let root = Root {
             namespace: block.header.namespace,
             version: block.header.round,
             hash: block.header.state_root,
-        });
+        };
 
         let untrusted_local = Arc::new(ProtocolUntrustedLocalStorage::new(
             Context::create_child(&ctx),
             protocol.clone(),
         ));
         let txn_ctx = TxnContext::new(ctx.clone(), &block.header, check_only);
-        match StorageContext::enter(&mut cache.mkvs, untrusted_local.clone(), || {
-            txn_dispatcher.dispatch_batch(&inputs, txn_ctx)
-        }) {
+        match cache.dispatch_batch(root, &**txn_dispatcher, untrusted_local, &inputs, txn_ctx) {
             Err(error) => {
                 warn!(self.logger, "Dispatching batch error"; "err" => %error);
                 protocol
@@ -474,7 +495,7 @@ impl Dispatcher {
         };
 
         let protocol_response;
-        if let Some((session_id, session_info, message, untrusted_plaintext)) = result {
+        if let Some((session_id, session_info, message)) = result {
             // Dispatch request.
             assert!(
                 buffer.is_empty(),
@@ -483,23 +504,6 @@ impl Dispatcher {
 
             match message {
                 RpcMessage::Request(req) => {
-                    // First make sure that the untrusted_plaintext matches
-                    // the request's method!
-                    if untrusted_plaintext != req.method {
-                        error!(self.logger, "Request methods don't match!";
-                            "untrusted_plaintext" => ?untrusted_plaintext,
-                            "method" => ?req.method
-                        );
-                        let err_reponse = Body::Error {
-                            module: "".to_owned(), // XXX: Error codes.
-                            code: 0,               // XXX: Error codes.
-                            message: "Request's method doesn't match untrusted_plaintext copy."
-                                .to_string(),
-                        };
-                        protocol.send_response(id, err_reponse).unwrap();
-                        return;
-                    }
-
                     // Request, dispatch.
                     let ctx = ctx.freeze();
                     let mut mkvs = Tree::make().new(Box::new(NoopReadSyncer));
@@ -507,7 +511,9 @@ impl Dispatcher {
                         Context::create_child(&ctx),
                         protocol.clone(),
                     ));
-                    let rpc_ctx = RpcContext::new(ctx.clone(), self.rak.clone(), session_info);
+                    let message_count = rpc_demux.session_message_count(&session_id);
+                    let rpc_ctx =
+                        RpcContext::new(ctx.clone(), self.rak.clone(), session_info, message_count);
                     let response =
                         StorageContext::enter(&mut mkvs, untrusted_local.clone(), || {
                             rpc_dispatcher.dispatch(req, rpc_ctx)
@@ -562,7 +568,7 @@ impl Dispatcher {
                 }
             }
         } else {
-            // Send back any handshake frames.
+            // Send back any handshake frames or streamed response chunks.
             protocol_response = Body::RuntimeRPCCallResponse { response: buffer };
         }
 
@@ -588,7 +594,7 @@ impl Dispatcher {
             Context::create_child(&ctx),
             protocol.clone(),
         ));
-        let rpc_ctx = RpcContext::new(ctx.clone(), self.rak.clone(), None);
+        let rpc_ctx = RpcContext::new(ctx.clone(), self.rak.clone(), None, None);
         let response = StorageContext::enter(&mut mkvs, untrusted_local.clone(), || {
             rpc_dispatcher.dispatch_local(req, rpc_ctx)
         });
@@ -623,39 +629,182 @@ impl Dispatcher {
 }
 
 struct Cache {
-    protocol: Arc<Protocol>,
+    new_tree: Box<dyn Fn(Root) -> Tree>,
     mkvs: Tree,
     root: Root,
 }
 
 impl Cache {
     fn new(protocol: Arc<Protocol>) -> Self {
+        Self::with_tree_factory(move |root| {
+            let read_syncer = HostReadSyncer::new(protocol.clone());
+            Tree::make()
+                .with_capacity(100_000, 10_000_000)
+                .with_root(root)
+                .new(Box::new(read_syncer))
+        })
+    }
+
+    fn with_tree_factory<F>(new_tree: F) -> Self
+    where
+        F: Fn(Root) -> Tree + 'static,
+    {
         Self {
-            mkvs: Self::new_tree(&protocol, Default::default()),
+            mkvs: new_tree(Default::default()),
             root: Default::default(),
-            protocol,
+            new_tree: Box::new(new_tree),
         }
     }
 
-    fn new_tree(protocol: &Arc<Protocol>, root: Root) -> Tree {
-        let read_syncer = HostReadSyncer::new(protocol.clone());
-        Tree::make()
-            .with_capacity(100_000, 10_000_000)
-            .with_root(root)
-            .new(Box::new(read_syncer))
-    }
-
     fn maybe_replace(&mut self, root: Root) {
         if self.root == root {
             return;
         }
 
-        self.mkvs = Self::new_tree(&self.protocol, root);
+        self.mkvs = (self.new_tree)(root);
         self.root = root;
     }
 
+    /// Dispatch a batch on top of the given root.
+    ///
+    /// If dispatching fails, the updates made by the batch so far are rolled
+    /// back, so that the next batch on the same root does not see them.
+    fn dispatch_batch(
+        &mut self,
+        root: Root,
+        txn_dispatcher: &dyn TxnDispatcher,
+        untrusted_local: Arc<dyn KeyValue>,
+        inputs: &TxnBatch,
+        txn_ctx: TxnContext,
+    ) -> Result<(TxnBatch, Vec<Tags>, Vec<RoothashMessage>)> {
+        self.maybe_replace(root);
+
+        let result = StorageContext::enter(&mut self.mkvs, untrusted_local, || {
+            txn_dispatcher.dispatch_batch(inputs, txn_ctx)
+        });
+        if result.is_err() {
+            self.mkvs.rollback();
+        }
+        result
+    }
+
     fn commit(&mut self, version: u64, root_hash: Hash) {
         self.root.version = version;
         self.root.hash = root_hash;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context as AnyContext;
+    use io_context::Context as IoContext;
+
+    use super::*;
+    use crate::{
+        common::roothash::Header,
+        storage::mkvs::StorageError,
+        transaction::{
+            dispatcher::{Method, MethodDescriptor, MethodDispatcher},
+            types::TxnCall,
+        },
+    };
+
+    struct NoopKeyValue;
+
+    impl KeyValue for NoopKeyValue {
+        fn get(&self, _key: Vec<u8>) -> Result<Vec<u8>> {
+            Err(anyhow!("not supported"))
+        }
+
+        fn insert(&self, _key: Vec<u8>, _value: Vec<u8>) -> Result<()> {
+            Err(anyhow!("not supported"))
+        }
+    }
+
+    fn insert(key: &str) -> Result<()> {
+        StorageContext::with_current(|mkvs, _untrusted_local| {
+            mkvs.insert(IoContext::background(), key.as_bytes(), b"value")
+        })?;
+        Ok(())
+    }
+
+    fn batch(calls: &[(&str, &str)]) -> TxnBatch {
+        TxnBatch::new(
+            calls
+                .iter()
+                .map(|(method, key)| {
+                    cbor::to_vec(&TxnCall {
+                        method: method.to_string(),
+                        args: cbor::to_value(key.to_string()),
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_cache_rollback_failed_batch() {
+        let mut dispatcher = MethodDispatcher::new();
+        dispatcher.add_method(Method::new(
+            MethodDescriptor {
+                name: "insert".to_owned(),
+            },
+            |key: &String, _ctx: &mut TxnContext| -> Result<()> { insert(key) },
+        ));
+        dispatcher.add_method(Method::new(
+            MethodDescriptor {
+                name: "insert_and_fail".to_owned(),
+            },
+            |key: &String, _ctx: &mut TxnContext| -> Result<()> {
+                insert(key)?;
+                Err(anyhow!("sync failed")).context(StorageError)
+            },
+        ));
+
+        let root = Root {
+            hash: Hash::empty_hash(),
+            ..Default::default()
+        };
+        let header = Header::default();
+        let io_ctx = IoContext::background().freeze();
+        let mut cache = Cache::with_tree_factory(|root| {
+            Tree::make().with_root(root).new(Box::new(NoopReadSyncer))
+        });
+        let untrusted_local: Arc<dyn KeyValue> = Arc::new(NoopKeyValue);
+
+        // A batch failing with a storage error after some updates.
+        let result = cache.dispatch_batch(
+            root,
+            &dispatcher,
+            untrusted_local.clone(),
+            &batch(&[("insert", "first"), ("insert_and_fail", "second")]),
+            TxnContext::new(io_ctx.clone(), &header, false),
+        );
+        assert!(result.is_err(), "batch should fail on storage errors");
+
+        // The next batch on the same root must not see the failed updates.
+        cache
+            .dispatch_batch(
+                root,
+                &dispatcher,
+                untrusted_local,
+                &batch(&[("insert", "third")]),
+                TxnContext::new(io_ctx, &header, false),
+            )
+            .expect("dispatch batch");
+        let (write_log, hash) = cache
+            .mkvs
+            .commit(IoContext::background(), Default::default(), 1)
+            .expect("commit");
+
+        let mut expected = Tree::make().with_root(root).new(Box::new(NoopReadSyncer));
+        expected
+            .insert(IoContext::background(), b"third", b"value")
+            .expect("insert");
+        let (expected_write_log, expected_hash) = expected
+            .commit(IoContext::background(), Default::default(), 1)
+            .expect("commit");
+        assert_eq!(write_log, expected_write_log);
+        assert_eq!(hash, expected_hash);
+    }
+}
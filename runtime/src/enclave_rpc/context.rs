@@ -16,6 +16,10 @@ pub struct Context {
     pub rak: Arc<RAK>,
     /// Information about the session the RPC call was delivered over.
     pub session_info: Option<Arc<SessionInfo>>,
+    /// Number of messages sent and received over the session the RPC call
+    /// was delivered over, including the request. Unlike the session
+    /// information, this is available for all sessions.
+    pub session_message_count: Option<u64>,
     /// Runtime-specific context.
     pub runtime: Box<dyn Any>,
 }
@@ -26,11 +30,13 @@ impl Context {
         io_ctx: Arc<IoContext>,
         rak: Arc<RAK>,
         session_info: Option<Arc<SessionInfo>>,
+        session_message_count: Option<u64>,
     ) -> Self {
         Self {
             io_ctx,
             rak,
             session_info,
+            session_message_count,
             runtime: Box::new(NoRuntimeContext),
         }
     }
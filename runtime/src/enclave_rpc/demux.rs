@@ -1,15 +1,18 @@
 //! Session demultiplexer.
-use std::{collections::HashMap, io::Write, sync::Arc, time::SystemTime};
+use std::{cmp, collections::HashMap, io::Write, sync::Arc, time::SystemTime};
 
 use anyhow::Result;
 use thiserror::Error;
 
 use super::{
-    session::{Builder, Session, SessionInfo},
-    types::{Frame, Message, SessionID},
+    session::{self, Builder, Session, SessionInfo, StaticKeyPair},
+    types::{Frame, Message, SessionID, StreamChunk},
 };
 use crate::{
-    common::{cbor, time::insecure_posix_system_time},
+    common::{
+        cbor,
+        time::{insecure_posix_system_time, insecure_posix_time},
+    },
     rak::RAK,
 };
 
@@ -21,6 +24,28 @@ const DEFAULT_STALE_SESSION_TIMEOUT_SECS: u64 = 60;
 /// Stale session check will be performed on any new incoming connection with at minimum
 /// STALE_SESSIONS_CHECK_TIMEOUT_SECS seconds between checks.
 const STALE_SESSIONS_CHECK_TIMEOUT_SECS: u64 = 10;
+/// Maximum size of a chunk of a streamed response.
+const MAX_STREAM_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+/// Default maximum size of a streamed response.
+const DEFAULT_MAX_STREAM_RESPONSE_SIZE: usize = 1024 * 1024;
+/// Default maximum number of bytes held in pending streamed responses of all
+/// sessions together.
+const DEFAULT_MAX_PENDING_STREAM_BYTES: usize = 4 * 1024 * 1024;
+/// Default maximum number of streamed responses for each session.
+const DEFAULT_MAX_SESSION_STREAMS: u64 = 16;
+/// Recommended maximum number of frames per second for each session.
+const RECOMMENDED_SESSION_FRAMES_PER_SEC: u64 = 100;
+/// Recommended maximum number of bytes per second for each session.
+const RECOMMENDED_SESSION_BYTES_PER_SEC: u64 = 64 * 1024 * 1024;
+/// Recommended maximum number of frames per second of all sessions together.
+const RECOMMENDED_GLOBAL_FRAMES_PER_SEC: u64 = 1000;
+/// Recommended maximum number of bytes per second of all sessions together.
+const RECOMMENDED_GLOBAL_BYTES_PER_SEC: u64 = 128 * 1024 * 1024;
+/// Recommended maximum number of new sessions per second.
+const RECOMMENDED_NEW_SESSION_FRAMES_PER_SEC: u64 = 100;
+/// Recommended maximum number of bytes per second in frames establishing
+/// new sessions.
+const RECOMMENDED_NEW_SESSION_BYTES_PER_SEC: u64 = 1024 * 1024;
 
 /// Demux error.
 #[derive(Error, Debug)]
@@ -29,36 +54,355 @@ enum DemuxError {
     SessionNotFound { session: SessionID },
     #[error("max concurrent sessions reached")]
     MaxConcurrentSessions,
+    #[error("invalid stream chunk size")]
+    InvalidChunkSize,
+    #[error("no streamed response available")]
+    NoStream,
+    #[error("rate limit exceeded")]
+    RateLimited,
+    #[error("session quota exceeded")]
+    QuotaExceeded,
+    #[error("streamed response too large")]
+    StreamTooLarge,
+    #[error("pending streamed responses limit reached")]
+    PendingStreamsLimit,
+    #[error("session streamed responses limit reached")]
+    SessionStreamsLimit,
 }
 
-pub type SessionMessage = (SessionID, Option<Arc<SessionInfo>>, Message, String);
+pub type SessionMessage = (SessionID, Option<Arc<SessionInfo>>, Message);
+
+/// Rate limits and quotas for incoming frames.
+///
+/// A limit of 0 means that there is no limit, which is the default.
+///
+/// Quotas are tracked for each session, so a client can renew them by
+/// establishing a new session. They bound the resources used by a single
+/// session, while the new session rate limits bound the rate at which new
+/// sessions can be established. The global rate limits bound the rate of
+/// all frames of all sessions together. Frames only count towards the global
+/// rate limits once they are within their session or new session limits, so
+/// a single client flooding its session or a flood of handshakes does not
+/// use up the global limits.
+///
+/// Frames exceeding any limit are rejected before decryption, so the nonce
+/// of the session's transport no longer matches the one of the client and
+/// the session is dropped. Clients must establish a new session, which the
+/// RPC client does when retrying a failed call. Rejected frames establishing
+/// new sessions do not affect any existing session.
+///
+/// Rate limits are tracked using the enclave's insecure clock, which is
+/// controlled by the host. They bound the load caused by clients, but a
+/// malicious host can always reset or stretch the time windows.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimits {
+    /// Maximum number of frames per second for each session.
+    pub session_frames_per_sec: u64,
+    /// Maximum number of bytes per second for each session.
+    pub session_bytes_per_sec: u64,
+    /// Maximum number of frames per second of all sessions together.
+    pub global_frames_per_sec: u64,
+    /// Maximum number of bytes per second of all sessions together.
+    pub global_bytes_per_sec: u64,
+    /// Maximum number of frames per second establishing new sessions.
+    pub new_session_frames_per_sec: u64,
+    /// Maximum number of bytes per second in frames establishing new sessions.
+    pub new_session_bytes_per_sec: u64,
+    /// Maximum total number of frames for each session.
+    pub session_frame_quota: u64,
+    /// Maximum total number of bytes for each session.
+    pub session_byte_quota: u64,
+}
+
+impl RateLimits {
+    /// Recommended rate limits for runtimes exposing RPC methods to remote
+    /// clients. Quotas are not limited.
+    pub fn recommended() -> Self {
+        Self {
+            session_frames_per_sec: RECOMMENDED_SESSION_FRAMES_PER_SEC,
+            session_bytes_per_sec: RECOMMENDED_SESSION_BYTES_PER_SEC,
+            global_frames_per_sec: RECOMMENDED_GLOBAL_FRAMES_PER_SEC,
+            global_bytes_per_sec: RECOMMENDED_GLOBAL_BYTES_PER_SEC,
+            new_session_frames_per_sec: RECOMMENDED_NEW_SESSION_FRAMES_PER_SEC,
+            new_session_bytes_per_sec: RECOMMENDED_NEW_SESSION_BYTES_PER_SEC,
+            session_frame_quota: 0,
+            session_byte_quota: 0,
+        }
+    }
+}
+
+/// Limits for streamed responses, which are held in enclave memory until
+/// they have been pulled by the client.
+///
+/// A limit of 0 means that there is no limit. The defaults keep the memory
+/// held by the streamed responses of all sessions together within a few MiB.
+#[derive(Clone, Debug)]
+pub struct StreamLimits {
+    /// Maximum size of a streamed response.
+    pub max_response_size: usize,
+    /// Maximum number of bytes held in pending streamed responses of all
+    /// sessions together. New streamed responses which would exceed it are
+    /// rejected until pending ones have been pulled.
+    pub max_pending_bytes: usize,
+    /// Maximum total number of streamed responses for each session.
+    pub max_session_streams: u64,
+}
+
+impl Default for StreamLimits {
+    fn default() -> Self {
+        Self {
+            max_response_size: DEFAULT_MAX_STREAM_RESPONSE_SIZE,
+            max_pending_bytes: DEFAULT_MAX_PENDING_STREAM_BYTES,
+            max_session_streams: DEFAULT_MAX_SESSION_STREAMS,
+        }
+    }
+}
+
+/// Rate limiter counting frames and bytes within one second windows.
+#[derive(Default)]
+struct RateLimiter {
+    window: i64,
+    frames: u64,
+    bytes: u64,
+}
+
+impl RateLimiter {
+    /// Account for a frame of the given size received at time `now`,
+    /// returning false if this would exceed the given limits.
+    fn check(&mut self, now: i64, bytes: u64, frames_per_sec: u64, bytes_per_sec: u64) -> bool {
+        if now != self.window {
+            self.window = now;
+            self.frames = 0;
+            self.bytes = 0;
+        }
+        if (frames_per_sec != 0 && self.frames + 1 > frames_per_sec)
+            || (bytes_per_sec != 0 && self.bytes + bytes > bytes_per_sec)
+        {
+            return false;
+        }
+        self.frames += 1;
+        self.bytes += bytes;
+        true
+    }
+
+    /// Account for a frame towards the global rate limits, failing in case
+    /// this would exceed them.
+    fn check_global(&mut self, limits: &RateLimits, now: i64, bytes: u64) -> Result<()> {
+        if !self.check(
+            now,
+            bytes,
+            limits.global_frames_per_sec,
+            limits.global_bytes_per_sec,
+        ) {
+            return Err(DemuxError::RateLimited.into());
+        }
+        Ok(())
+    }
+}
 
 /// Session demultiplexer.
 pub struct Demux {
     rak: Arc<RAK>,
+    session_builder: Builder,
+    static_keypair: StaticKeyPair,
     sessions: HashMap<SessionID, EnrichedSession>,
     max_concurrent_sessions: usize,
     stale_session_timeout: u64,
     last_stale_sessions_purge: SystemTime,
+    rate_limits: RateLimits,
+    global_rate_limiter: RateLimiter,
+    new_session_rate_limiter: RateLimiter,
+    stream_limits: StreamLimits,
 }
 
 struct EnrichedSession {
     session: Session,
     last_process_frame_time: SystemTime,
+    stream: Option<Stream>,
+    total_streams: u64,
+    rate_limiter: RateLimiter,
+    total_frames: u64,
+    total_bytes: u64,
+}
+
+/// State of a streamed response.
+enum Stream {
+    /// A streamed response was requested but has not been produced yet.
+    Requested { chunk_size: usize },
+    /// A streamed response is being pulled by the client.
+    Pending {
+        chunk_size: usize,
+        data: Vec<u8>,
+        offset: usize,
+    },
+}
+
+impl EnrichedSession {
+    fn new(session: Session) -> Self {
+        Self {
+            session,
+            last_process_frame_time: insecure_posix_system_time(),
+            stream: None,
+            total_streams: 0,
+            rate_limiter: RateLimiter::default(),
+            total_frames: 0,
+            total_bytes: 0,
+        }
+    }
+
+    /// Account for an incoming frame of the given size, failing in case
+    /// this would exceed the session's rate limits or quotas.
+    fn check_limits(&mut self, limits: &RateLimits, now: i64, bytes: u64) -> Result<()> {
+        if (limits.session_frame_quota != 0 && self.total_frames + 1 > limits.session_frame_quota)
+            || (limits.session_byte_quota != 0
+                && self.total_bytes + bytes > limits.session_byte_quota)
+        {
+            return Err(DemuxError::QuotaExceeded.into());
+        }
+        if !self.rate_limiter.check(
+            now,
+            bytes,
+            limits.session_frames_per_sec,
+            limits.session_bytes_per_sec,
+        ) {
+            return Err(DemuxError::RateLimited.into());
+        }
+        self.total_frames += 1;
+        self.total_bytes += bytes;
+
+        Ok(())
+    }
+
+    /// Process incoming data.
+    ///
+    /// Stream requests are returned as plain requests, while pulls of the
+    /// following chunks are handled directly.
+    fn process_data<W: Write>(
+        &mut self,
+        data: Vec<u8>,
+        ad: &[u8],
+        mut writer: W,
+    ) -> Result<Option<Message>> {
+        let msg = match self.session.process_data(data, ad, &mut writer)? {
+            Some(msg) => msg,
+            None => return Ok(None),
+        };
+
+        match msg {
+            Message::Request(req) => {
+                self.stream = None;
+                Ok(Some(Message::Request(req)))
+            }
+            Message::StreamRequest(stream_req) => {
+                if stream_req.chunk_size == 0 {
+                    return Err(DemuxError::InvalidChunkSize.into());
+                }
+                self.stream = Some(Stream::Requested {
+                    chunk_size: cmp::min(stream_req.chunk_size as usize, MAX_STREAM_CHUNK_SIZE),
+                });
+                Ok(Some(Message::Request(stream_req.request)))
+            }
+            Message::StreamPull => {
+                self.write_chunk(writer)?;
+                Ok(None)
+            }
+            msg => Ok(Some(msg)),
+        }
+    }
+
+    /// Account for a streamed response held until it has been pulled, failing
+    /// in case this would exceed the session's byte quota or the maximum
+    /// number of streamed responses.
+    fn charge_stream(&mut self, limits: &RateLimits, max_streams: u64, bytes: u64) -> Result<()> {
+        if max_streams != 0 && self.total_streams + 1 > max_streams {
+            return Err(DemuxError::SessionStreamsLimit.into());
+        }
+        if limits.session_byte_quota != 0 && self.total_bytes + bytes > limits.session_byte_quota {
+            return Err(DemuxError::QuotaExceeded.into());
+        }
+        self.total_streams += 1;
+        self.total_bytes += bytes;
+
+        Ok(())
+    }
+
+    /// Number of bytes held in a pending streamed response.
+    fn pending_stream_bytes(&self) -> usize {
+        match self.stream {
+            Some(Stream::Pending { ref data, .. }) => data.len(),
+            _ => 0,
+        }
+    }
+
+    /// Write the next chunk of a pending streamed response.
+    fn write_chunk<W: Write>(&mut self, writer: W) -> Result<()> {
+        let (data, remaining) = match self.stream {
+            Some(Stream::Pending {
+                chunk_size,
+                ref data,
+                ref mut offset,
+            }) => {
+                let end = cmp::min(*offset + chunk_size, data.len());
+                let chunk = data[*offset..end].to_vec();
+                *offset = end;
+                (chunk, data.len() - end)
+            }
+            _ => return Err(DemuxError::NoStream.into()),
+        };
+        if remaining == 0 {
+            self.stream = None;
+        }
+
+        // Responses don't need framing as they are linked at the runtime IPC
+        // protocol, so there is no associated data.
+        let chunk = StreamChunk {
+            data,
+            remaining: remaining as u64,
+        };
+        self.session
+            .write_message(Message::StreamChunk(chunk), &[], writer)
+    }
 }
 
 impl Demux {
     /// Create new session demultiplexer.
     pub fn new(rak: Arc<RAK>) -> Self {
+        let static_keypair = StaticKeyPair::generate();
+
         Self {
-            rak: rak,
+            rak: rak.clone(),
+            session_builder: Builder::new()
+                .local_rak(rak)
+                .local_static_keypair(static_keypair.clone()),
+            static_keypair,
             sessions: HashMap::new(),
             max_concurrent_sessions: DEFAULT_MAX_CONCURRENT_SESSIONS,
             stale_session_timeout: DEFAULT_STALE_SESSION_TIMEOUT_SECS,
             last_stale_sessions_purge: insecure_posix_system_time(),
+            rate_limits: RateLimits::default(),
+            global_rate_limiter: RateLimiter::default(),
+            new_session_rate_limiter: RateLimiter::default(),
+            stream_limits: StreamLimits::default(),
         }
     }
 
+    /// Static public key used by all sessions.
+    ///
+    /// Clients which know this key can establish sessions in a single round
+    /// trip, so it can be shared with them through untrusted channels.
+    pub fn static_public_key(&self) -> &[u8] {
+        self.static_keypair.public_key()
+    }
+
+    /// Serialized RAK binding of the static public key, which allows clients
+    /// to verify the key and send requests together with the first handshake
+    /// message, see `VerifiedStaticKey::verify`.
+    ///
+    /// The binding is empty in case the RAK has not been initialized.
+    pub fn static_key_binding(&self) -> Vec<u8> {
+        session::static_key_binding(&self.rak, self.static_keypair.public_key())
+    }
+
     /// Configures max_concurrent_sessions.
     pub fn set_max_concurrent_sessions(&mut self, max_concurrent_sessions: usize) {
         self.max_concurrent_sessions = max_concurrent_sessions;
@@ -70,6 +414,52 @@ impl Demux {
         self.stale_session_timeout = stale_session_timeout;
     }
 
+    /// Configures the number of messages sent after which sessions rotate
+    /// their sending key.
+    /// If 0, keys are never rotated based on the number of messages.
+    pub fn set_session_rekey_messages(&mut self, messages: u64) {
+        self.session_builder = self.session_builder.clone().rekey_messages(messages);
+    }
+
+    /// Configures the number of seconds after which sessions rotate their
+    /// sending key.
+    /// If 0, keys are never rotated based on time.
+    ///
+    /// Time is provided by the untrusted host, so this can be delayed by the
+    /// host and only message-based rotation is guaranteed.
+    pub fn set_session_rekey_interval_secs(&mut self, secs: u64) {
+        self.session_builder = self.session_builder.clone().rekey_interval_secs(secs);
+    }
+
+    /// Configures the number of messages sent and received after which a
+    /// session is closed, so that the client must establish a new one.
+    /// If 0, the number of messages is not limited.
+    pub fn set_session_max_messages(&mut self, messages: u64) {
+        self.session_builder = self.session_builder.clone().max_messages(messages);
+    }
+
+    /// Configures rate limits and quotas for incoming frames.
+    ///
+    /// Frames exceeding the limits are rejected before decryption. Sessions
+    /// whose frames are rejected can no longer decrypt any following frames,
+    /// so they are dropped, see `RateLimits`.
+    pub fn set_rate_limits(&mut self, rate_limits: RateLimits) {
+        self.rate_limits = rate_limits;
+    }
+
+    /// Configures limits for streamed responses.
+    pub fn set_stream_limits(&mut self, stream_limits: StreamLimits) {
+        self.stream_limits = stream_limits;
+    }
+
+    /// Number of bytes held in pending streamed responses of all sessions.
+    fn pending_stream_bytes(&self) -> usize {
+        self.sessions
+            .values()
+            .map(|enriched_session| enriched_session.pending_stream_bytes())
+            .sum()
+    }
+
     fn purge_stale_sessions(&mut self) {
         let now = insecure_posix_system_time();
         let stale_session_timeout = self.stale_session_timeout;
@@ -87,29 +477,30 @@ impl Demux {
     }
 
     /// Process an incoming frame.
+    ///
+    /// Requests for the next chunk of a streamed response are handled
+    /// without returning a message, with the chunk written to `writer`.
     pub fn process_frame<W: Write>(
         &mut self,
         data: Vec<u8>,
         writer: W,
     ) -> Result<Option<SessionMessage>> {
+        // The insecure clock is controlled by the host, see RateLimits.
+        let frame_time = insecure_posix_time();
+        let frame_len = data.len() as u64;
         let frame: Frame = cbor::from_slice(&data)?;
         let id = frame.session.clone();
-        let untrusted_plaintext = frame.untrusted_plaintext.clone();
+        let ad = Frame::associated_data(&frame.session, &frame.untrusted_plaintext);
 
         if let Some(enriched_session) = self.sessions.get_mut(&id.into()) {
+            let rate_limits = &self.rate_limits;
+            let global_rate_limiter = &mut self.global_rate_limiter;
             match enriched_session
-                .session
-                .process_data(frame.payload, writer)
-                .map(|m| {
-                    m.map(|msg| {
-                        (
-                            id,
-                            enriched_session.session.session_info(),
-                            msg,
-                            untrusted_plaintext.clone(),
-                        )
-                    })
-                }) {
+                .check_limits(rate_limits, frame_time, frame_len)
+                .and_then(|_| global_rate_limiter.check_global(rate_limits, frame_time, frame_len))
+                .and_then(|_| enriched_session.process_data(frame.payload, &ad, writer))
+                .map(|m| m.map(|msg| (id, enriched_session.session.session_info(), msg)))
+            {
                 Ok(result) => {
                     enriched_session.last_process_frame_time = insecure_posix_system_time();
                     Ok(result)
@@ -135,23 +526,35 @@ impl Demux {
                 self.purge_stale_sessions()
             }
 
+            // Only frames establishing new sessions count towards the new
+            // session rate limits, so a flood of handshakes cannot use up the
+            // global rate limits. As no session is created, nothing needs to
+            // be dropped.
+            if !self.new_session_rate_limiter.check(
+                frame_time,
+                frame_len,
+                self.rate_limits.new_session_frames_per_sec,
+                self.rate_limits.new_session_bytes_per_sec,
+            ) {
+                return Err(DemuxError::RateLimited.into());
+            }
+            self.global_rate_limiter
+                .check_global(&self.rate_limits, frame_time, frame_len)?;
+
             // Create a new session.
             if self.sessions.len() < self.max_concurrent_sessions {
-                let mut session = Builder::new().local_rak(self.rak.clone()).build_responder();
-                let result = match session.process_data(frame.payload, writer).map(|m| {
-                    m.map(|msg| (id, session.session_info(), msg, untrusted_plaintext.clone()))
-                }) {
+                let mut enriched_session =
+                    EnrichedSession::new(self.session_builder.clone().build_responder());
+                let result = match enriched_session
+                    .check_limits(&self.rate_limits, frame_time, frame_len)
+                    .and_then(|_| enriched_session.process_data(frame.payload, &ad, writer))
+                    .map(|m| m.map(|msg| (id, enriched_session.session.session_info(), msg)))
+                {
                     Ok(result) => result,
                     // In case there is an error, drop the session.
                     Err(error) => return Err(error),
                 };
-                self.sessions.insert(
-                    id,
-                    EnrichedSession {
-                        session: session,
-                        last_process_frame_time: insecure_posix_system_time(),
-                    },
-                );
+                self.sessions.insert(id, enriched_session);
 
                 Ok(result)
             } else {
@@ -161,23 +564,65 @@ impl Demux {
     }
 
     /// Write message to session and generate a response.
+    ///
+    /// In case a streamed response was requested, only the first chunk of
+    /// the response is written.
     pub fn write_message<W: Write>(
         &mut self,
         id: SessionID,
         msg: Message,
         mut writer: W,
     ) -> Result<()> {
+        let pending_stream_bytes = self.pending_stream_bytes();
         match self.sessions.get_mut(&id) {
             Some(enriched_session) => {
+                if let Some(Stream::Requested { chunk_size }) = enriched_session.stream {
+                    if let Message::Response(ref rsp) = msg {
+                        enriched_session.stream = None;
+                        let data = cbor::to_vec(rsp);
+                        let limits = &self.stream_limits;
+                        if limits.max_response_size != 0 && data.len() > limits.max_response_size {
+                            return Err(DemuxError::StreamTooLarge.into());
+                        }
+                        if limits.max_pending_bytes != 0
+                            && pending_stream_bytes + data.len() > limits.max_pending_bytes
+                        {
+                            return Err(DemuxError::PendingStreamsLimit.into());
+                        }
+                        enriched_session.charge_stream(
+                            &self.rate_limits,
+                            limits.max_session_streams,
+                            data.len() as u64,
+                        )?;
+
+                        enriched_session.stream = Some(Stream::Pending {
+                            chunk_size,
+                            data,
+                            offset: 0,
+                        });
+                        return enriched_session.write_chunk(&mut writer);
+                    }
+                }
+
                 // Responses don't need framing as they are linked at the
-                // runtime IPC protocol.
-                enriched_session.session.write_message(msg, &mut writer)?;
+                // runtime IPC protocol, so there is no associated data.
+                enriched_session
+                    .session
+                    .write_message(msg, &[], &mut writer)?;
                 Ok(())
             }
             None => Err(DemuxError::SessionNotFound { session: id }.into()),
         }
     }
 
+    /// Number of messages sent and received in transport mode by the given
+    /// session, if it exists.
+    pub fn session_message_count(&self, id: &SessionID) -> Option<u64> {
+        self.sessions
+            .get(id)
+            .map(|enriched_session| enriched_session.session.message_count())
+    }
+
     /// Close the session and generate a response.
     pub fn close<W: Write>(&mut self, id: SessionID, mut writer: W) -> Result<()> {
         match self.sessions.remove(&id) {
@@ -186,10 +631,435 @@ impl Demux {
                 // runtime IPC protocol.
                 enriched_session
                     .session
-                    .write_message(Message::Close, &mut writer)?;
+                    .write_message(Message::Close, &[], &mut writer)?;
                 Ok(())
             }
             None => Err(DemuxError::SessionNotFound { session: id }.into()),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::{
+        super::{
+            session::VerifiedStaticKey,
+            types::{Body, Request, Response, StreamRequest},
+        },
+        *,
+    };
+    use crate::common::sgx::avr;
+
+    fn frame(id: &SessionID, payload: Vec<u8>) -> Vec<u8> {
+        cbor::to_vec(&Frame {
+            session: id.clone(),
+            untrusted_plaintext: "".to_owned(),
+            payload,
+        })
+    }
+
+    /// Establish a session with the demultiplexer, using two frames.
+    fn connect(demux: &mut Demux) -> (SessionID, Session) {
+        let id = SessionID::random();
+        let mut session = Builder::new().build_initiator();
+
+        let mut data = vec![];
+        session.process_data(vec![], &[], &mut data).unwrap();
+        let mut reply = vec![];
+        demux.process_frame(frame(&id, data), &mut reply).unwrap();
+        let mut data = vec![];
+        session.process_data(reply, &[], &mut data).unwrap();
+        demux.process_frame(frame(&id, data), vec![]).unwrap();
+        assert!(session.is_connected());
+
+        (id, session)
+    }
+
+    fn assert_rejected(result: Result<Option<SessionMessage>>, expected: DemuxError) {
+        let error = result.err().expect("frame should be rejected");
+        let error = error
+            .downcast_ref::<DemuxError>()
+            .expect("frame should be rejected before decryption");
+        assert_eq!(format!("{}", error), format!("{}", expected));
+    }
+
+    /// Send a message over an established session.
+    fn send(demux: &mut Demux, id: &SessionID, session: &mut Session, msg: Message, method: &str) {
+        let ad = Frame::associated_data(id, method);
+        let mut data = vec![];
+        session.write_message(msg, &ad, &mut data).unwrap();
+        let frame = cbor::to_vec(&Frame {
+            session: id.clone(),
+            untrusted_plaintext: method.to_owned(),
+            payload: data,
+        });
+        demux.process_frame(frame, vec![]).unwrap();
+    }
+
+    /// Request a streamed response and produce it, returning the result.
+    fn stream(demux: &mut Demux, id: &SessionID, session: &mut Session) -> Result<()> {
+        let request = Message::StreamRequest(StreamRequest {
+            request: Request {
+                method: "test".to_owned(),
+                args: cbor::to_value(42),
+            },
+            chunk_size: 10,
+        });
+        send(demux, id, session, request, "test");
+
+        let response = Message::Response(Response {
+            body: Body::Success(cbor::to_value("x".repeat(100))),
+        });
+        demux.write_message(id.clone(), response, vec![])
+    }
+
+    fn assert_stream_rejected(result: Result<()>, expected: DemuxError) {
+        let error = result.expect_err("streamed response should be rejected");
+        let error = error
+            .downcast_ref::<DemuxError>()
+            .expect("streamed response should be rejected by the demultiplexer");
+        assert_eq!(format!("{}", error), format!("{}", expected));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::default();
+
+        // Frame limit.
+        assert!(limiter.check(1, 10, 2, 0));
+        assert!(limiter.check(1, 10, 2, 0));
+        assert!(
+            !limiter.check(1, 10, 2, 0),
+            "third frame should be rejected"
+        );
+
+        // Limits are reset in the next window.
+        assert!(limiter.check(2, 10, 2, 0));
+
+        // Byte limit.
+        assert!(limiter.check(3, 60, 0, 100));
+        assert!(
+            !limiter.check(3, 60, 0, 100),
+            "frame over byte limit should be rejected"
+        );
+        assert!(limiter.check(3, 40, 0, 100));
+
+        // No limits.
+        for _ in 0..1000 {
+            assert!(limiter.check(4, 1000, 0, 0));
+        }
+    }
+
+    #[test]
+    fn test_session_quotas() {
+        let limits = RateLimits {
+            session_frame_quota: 3,
+            session_byte_quota: 100,
+            ..Default::default()
+        };
+        let mut session = EnrichedSession::new(Builder::new().build_responder());
+
+        assert!(session.check_limits(&limits, 1, 10).is_ok());
+        assert!(session.check_limits(&limits, 2, 10).is_ok());
+        assert!(
+            session.check_limits(&limits, 3, 90).is_err(),
+            "frame over byte quota should be rejected"
+        );
+        assert!(session.check_limits(&limits, 4, 10).is_ok());
+        assert!(
+            session.check_limits(&limits, 5, 10).is_err(),
+            "frame over frame quota should be rejected"
+        );
+    }
+
+    #[test]
+    fn test_demux_rate_limits() {
+        let mut demux = Demux::new(Arc::new(RAK::new()));
+        let (id, mut session) = connect(&mut demux);
+
+        // Frames establishing new sessions are rejected before decryption, so
+        // the garbage payload must not cause a decryption error.
+        demux.set_rate_limits(RateLimits {
+            new_session_bytes_per_sec: 1,
+            ..Default::default()
+        });
+        let result = demux.process_frame(frame(&SessionID::random(), b"garbage".to_vec()), vec![]);
+        assert_rejected(result, DemuxError::RateLimited);
+        assert_eq!(demux.sessions.len(), 1, "no session should be created");
+
+        // Existing sessions are not subject to the new session rate limits.
+        let request = Message::Request(Request {
+            method: "test".to_owned(),
+            args: cbor::to_value(42),
+        });
+        send(&mut demux, &id, &mut session, request, "test");
+        assert!(
+            demux.sessions.contains_key(&id),
+            "existing session should not be dropped"
+        );
+    }
+
+    #[test]
+    fn test_demux_global_rate_limits() {
+        let mut demux = Demux::new(Arc::new(RAK::new()));
+        let (id_a, mut session_a) = connect(&mut demux);
+        let (id_b, mut session_b) = connect(&mut demux);
+
+        // The global rate limits apply to the frames of all sessions.
+        demux.set_rate_limits(RateLimits {
+            global_bytes_per_sec: 1,
+            ..Default::default()
+        });
+        let request = || {
+            Message::Request(Request {
+                method: "test".to_owned(),
+                args: cbor::to_value(42),
+            })
+        };
+        for (id, session) in vec![(&id_a, &mut session_a), (&id_b, &mut session_b)] {
+            let ad = Frame::associated_data(id, "test");
+            let mut data = vec![];
+            session.write_message(request(), &ad, &mut data).unwrap();
+            let frame = cbor::to_vec(&Frame {
+                session: id.clone(),
+                untrusted_plaintext: "test".to_owned(),
+                payload: data,
+            });
+            let result = demux.process_frame(frame, vec![]);
+            assert_rejected(result, DemuxError::RateLimited);
+
+            // The rejected frame was not decrypted, so the session can no
+            // longer decrypt any following frames and is dropped.
+            assert!(
+                !demux.sessions.contains_key(id),
+                "rate limited session should be dropped"
+            );
+        }
+
+        // Frames establishing new sessions are subject to the global rate
+        // limits as well.
+        let result = demux.process_frame(frame(&SessionID::random(), b"garbage".to_vec()), vec![]);
+        assert_rejected(result, DemuxError::RateLimited);
+        assert!(demux.sessions.is_empty(), "no session should be created");
+
+        // Clients can establish new sessions once the frames are within the
+        // limits again.
+        demux.set_rate_limits(RateLimits::default());
+        let (id, mut session) = connect(&mut demux);
+        send(&mut demux, &id, &mut session, request(), "test");
+    }
+
+    #[test]
+    fn test_demux_session_quotas() {
+        let mut demux = Demux::new(Arc::new(RAK::new()));
+        demux.set_rate_limits(RateLimits {
+            session_frame_quota: 2,
+            ..Default::default()
+        });
+        let (id, _) = connect(&mut demux);
+
+        let result = demux.process_frame(frame(&id, b"garbage".to_vec()), vec![]);
+        assert_rejected(result, DemuxError::QuotaExceeded);
+        assert!(
+            demux.sessions.is_empty(),
+            "session over quota should be dropped"
+        );
+    }
+
+    #[test]
+    fn test_demux_session_max_messages() {
+        let mut demux = Demux::new(Arc::new(RAK::new()));
+        demux.set_session_max_messages(2);
+        let (id, mut session) = connect(&mut demux);
+        let request = || {
+            Message::Request(Request {
+                method: "test".to_owned(),
+                args: cbor::to_value(42),
+            })
+        };
+        let write_request = |session: &mut Session| {
+            let ad = Frame::associated_data(&id, "test");
+            let mut data = vec![];
+            session.write_message(request(), &ad, &mut data).unwrap();
+            cbor::to_vec(&Frame {
+                session: id.clone(),
+                untrusted_plaintext: "test".to_owned(),
+                payload: data,
+            })
+        };
+
+        let data = write_request(&mut session);
+        let result = demux.process_frame(data, vec![]).unwrap();
+        assert!(result.is_some(), "request should be returned");
+        // The message counter is available without session information.
+        assert_eq!(demux.session_message_count(&id), Some(1));
+        demux
+            .write_message(id.clone(), request(), vec![])
+            .expect("response should be written");
+        assert_eq!(demux.session_message_count(&id), Some(2));
+
+        // The enclave must enforce the message limit even if the client does not.
+        let data = write_request(&mut session);
+        assert!(
+            demux.process_frame(data, vec![]).is_err(),
+            "request over the message limit should be rejected"
+        );
+        assert!(
+            demux.sessions.is_empty(),
+            "session over the message limit should be dropped"
+        );
+    }
+
+    #[test]
+    fn test_demux_stream_limits() {
+        let mut demux = Demux::new(Arc::new(RAK::new()));
+        demux.set_stream_limits(StreamLimits {
+            max_pending_bytes: 250,
+            max_session_streams: 1,
+            ..Default::default()
+        });
+        let mut sessions: Vec<_> = (0..3).map(|_| connect(&mut demux)).collect();
+
+        // Each session holds a single pending streamed response, but the
+        // budget is shared by all sessions.
+        for (id, session) in sessions.iter_mut().take(2) {
+            stream(&mut demux, id, session).expect("streamed response should fit into the budget");
+        }
+        let pending_stream_bytes = demux.pending_stream_bytes();
+        assert!(pending_stream_bytes > 200);
+
+        // Streamed responses are rejected while the budget is used up by
+        // pending ones of other sessions.
+        let (ref id_c, ref mut session_c) = sessions[2];
+        let result = stream(&mut demux, id_c, session_c);
+        assert_stream_rejected(result, DemuxError::PendingStreamsLimit);
+        assert_eq!(demux.pending_stream_bytes(), pending_stream_bytes);
+
+        // Pulling a pending response releases its part of the budget.
+        let (ref id_a, ref mut session_a) = sessions[0];
+        while demux.sessions[id_a].pending_stream_bytes() > 0 {
+            send(&mut demux, id_a, session_a, Message::StreamPull, "");
+        }
+        let (ref id_c, ref mut session_c) = sessions[2];
+        stream(&mut demux, id_c, session_c)
+            .expect("streamed response should fit into the released budget");
+
+        // Each session may only request a limited number of streamed responses.
+        demux.set_stream_limits(StreamLimits {
+            max_pending_bytes: 0,
+            max_session_streams: 1,
+            ..Default::default()
+        });
+        let (ref id_a, ref mut session_a) = sessions[0];
+        let result = stream(&mut demux, id_a, session_a);
+        assert_stream_rejected(result, DemuxError::SessionStreamsLimit);
+
+        // Responses over the size limit are rejected.
+        demux.set_stream_limits(StreamLimits {
+            max_response_size: 50,
+            max_pending_bytes: 0,
+            max_session_streams: 0,
+        });
+        let result = stream(&mut demux, id_a, session_a);
+        assert_stream_rejected(result, DemuxError::StreamTooLarge);
+    }
+
+    #[test]
+    fn test_demux_early_message() {
+        let identity = avr::EnclaveIdentity::fortanix_test(Default::default());
+        let mut enclaves = HashSet::new();
+        enclaves.insert(identity.clone());
+        let mut demux = Demux::new(Arc::new(RAK::new_test(&identity)));
+
+        // Keys can only be verified for the expected enclave identities.
+        let key = demux.static_public_key().to_vec();
+        let binding = demux.static_key_binding();
+        assert!(
+            VerifiedStaticKey::verify_with(
+                key.clone(),
+                &binding,
+                &HashSet::new(),
+                avr::verify_test
+            )
+            .is_err(),
+            "key bound to another enclave should be rejected"
+        );
+        assert!(
+            VerifiedStaticKey::verify_with(
+                StaticKeyPair::generate().public_key().to_vec(),
+                &binding,
+                &enclaves,
+                avr::verify_test,
+            )
+            .is_err(),
+            "binding of another key should be rejected"
+        );
+        if option_env!("OASIS_UNSAFE_SKIP_AVR_VERIFY").is_none() {
+            assert!(
+                VerifiedStaticKey::verify(key.clone(), &binding, &enclaves).is_err(),
+                "report not signed by IAS should be rejected"
+            );
+        }
+        let key = VerifiedStaticKey::verify_with(key, &binding, &enclaves, avr::verify_test)
+            .expect("key should be verified");
+        assert_eq!(key.enclave_identity(), &identity);
+
+        // The request is sent together with the first handshake message and the
+        // response together with the last one, so a single round trip is needed.
+        let id = SessionID::random();
+        let mut session = Builder::new()
+            .remote_enclaves(Some(enclaves))
+            .verified_remote_static_key(Some(key))
+            .avr_verifier(avr::verify_test)
+            .build_initiator();
+        let request = Message::Request(Request {
+            method: "test".to_owned(),
+            args: cbor::to_value(42),
+        });
+        assert!(session.can_write_early_message(&request));
+        let ad = Frame::associated_data(&id, "test");
+        let mut data = vec![];
+        session.write_message(request, &ad, &mut data).unwrap();
+        let frame = cbor::to_vec(&Frame {
+            session: id.clone(),
+            untrusted_plaintext: "test".to_owned(),
+            payload: data,
+        });
+
+        let mut reply = vec![];
+        let (_, _, msg) = demux
+            .process_frame(frame, &mut reply)
+            .unwrap()
+            .expect("early request should be returned");
+        match msg {
+            Message::Request(req) => assert_eq!(req.args, cbor::to_value(42)),
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+        let response = Message::Response(Response {
+            body: Body::Success(cbor::to_value(43)),
+        });
+        demux
+            .write_message(id.clone(), response, &mut reply)
+            .unwrap();
+
+        // The initiator verifies the responder's RAK binding before accepting
+        // the response.
+        let msg = session
+            .process_data(reply, &[], vec![])
+            .unwrap()
+            .expect("response should be returned");
+        match msg {
+            Message::Response(Response {
+                body: Body::Success(value),
+            }) => assert_eq!(value, cbor::to_value(43)),
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+        assert!(session.is_connected());
+        let info = session
+            .session_info()
+            .expect("responder should be verified");
+        assert_eq!(info.authenticated_avr.identity, identity);
+    }
+}
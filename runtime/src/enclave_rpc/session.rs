@@ -1,5 +1,13 @@
 //! Secure channel session.
-use std::{collections::HashSet, io::Write, mem, sync::Arc};
+use std::{
+    collections::HashSet,
+    io::Write,
+    mem,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
@@ -10,20 +18,53 @@ use super::types::Message;
 use crate::{
     common::{
         cbor,
-        crypto::signature::{PublicKey, Signature, Signer},
+        crypto::{
+            hash::Hash,
+            signature::{PublicKey, Signature, Signer},
+        },
         sgx::avr,
+        time::insecure_posix_time,
     },
     rak::RAK,
 };
 
 /// Noise protocol pattern.
 const NOISE_PATTERN: &'static str = "Noise_XX_25519_ChaChaPoly_SHA256";
+/// Noise protocol pattern used when the initiator knows the responder's
+/// static public key.
+const NOISE_PATTERN_IK: &'static str = "Noise_IK_25519_ChaChaPoly_SHA256";
 /// RAK signature session binding context.
 const RAK_SESSION_BINDING_CONTEXT: [u8; 8] = *b"EkRakRpc";
+/// Number of messages sent after which the sending key is rotated.
+const DEFAULT_REKEY_MESSAGES: u64 = 10_000;
+/// Number of seconds after which the sending key is rotated.
+const DEFAULT_REKEY_INTERVAL_SECS: u64 = 3600;
+/// Number of messages sent and received after which the session is closed.
+const DEFAULT_MAX_MESSAGES: u64 = 1_000_000;
+
+/// Maximum size of a Noise protocol message.
+const NOISE_MAX_MESSAGE_LEN: usize = 65535;
+/// Size of the authentication tag of a Noise transport message.
+const NOISE_TAG_LEN: usize = 16;
+/// Size of the header preceding each fragment, see `seal_fragment`.
+const FRAGMENT_HEADER_LEN: usize = 32 + 1;
+/// Maximum size of a message fragment carried by a single Noise message.
+const MAX_FRAGMENT_LEN: usize = NOISE_MAX_MESSAGE_LEN - NOISE_TAG_LEN - FRAGMENT_HEADER_LEN;
+/// Maximum size of a message after reassembly.
+pub const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+/// Maximum size of a message sent together with the first handshake message.
+pub const MAX_EARLY_MESSAGE_LEN: usize = 16 * 1024;
+/// Size of a Noise public key.
+const NOISE_DH_LEN: usize = 32;
+
+/// Flag set on fragments after which the sender has rotated its key.
+const FLAG_REKEY: u8 = 0x01;
+/// Flag set on all fragments of a message except the last one.
+const FLAG_MORE: u8 = 0x02;
 
 /// Session-related error.
 #[derive(Error, Debug)]
-enum SessionError {
+pub enum SessionError {
     #[error("invalid input")]
     InvalidInput,
     #[error("invalid state")]
@@ -32,17 +73,220 @@ enum SessionError {
     Closed,
     #[error("mismatched enclave identity")]
     MismatchedEnclaveIdentity,
+    #[error("mismatched associated data")]
+    MismatchedAssociatedData,
+    #[error("session message limit reached")]
+    MessageLimit,
+    #[error("message too large")]
+    MessageTooLarge,
 }
 
 /// Information about a session.
 pub struct SessionInfo {
     pub rak_binding: RAKBinding,
     pub authenticated_avr: avr::AuthenticatedAVR,
+    message_count: Arc<AtomicU64>,
+}
+
+impl SessionInfo {
+    /// Number of messages sent and received in transport mode.
+    pub fn message_count(&self) -> u64 {
+        self.message_count.load(Ordering::SeqCst)
+    }
+}
+
+/// Static Noise key pair of a session.
+///
+/// Responders which use the same key pair for all sessions can be connected
+/// to in a single round trip by initiators which know its public key.
+#[derive(Clone)]
+pub struct StaticKeyPair {
+    private: Vec<u8>,
+    public: Vec<u8>,
+}
+
+impl StaticKeyPair {
+    /// Generate a new random key pair.
+    pub fn generate() -> Self {
+        let keypair = snow::Builder::new(NOISE_PATTERN.parse().unwrap())
+            .generate_keypair()
+            .unwrap();
+
+        Self {
+            private: keypair.private,
+            public: keypair.public,
+        }
+    }
+
+    /// Public part of the key pair.
+    pub fn public_key(&self) -> &[u8] {
+        &self.public
+    }
+}
+
+/// Remote static public key whose RAK binding has been verified against
+/// the configured remote enclave identities in a previous session.
+///
+/// Only such keys can be used to send messages before the handshake has
+/// completed, as the early message is encrypted to the key before the
+/// remote end's RAK binding is checked.
+#[derive(Clone, Debug)]
+pub struct VerifiedStaticKey {
+    key: Vec<u8>,
+    identity: avr::EnclaveIdentity,
+}
+
+impl VerifiedStaticKey {
+    /// Verify a remote static public key using the serialized RAK binding
+    /// published by the remote enclave, see `static_key_binding`.
+    ///
+    /// The binding includes the remote enclave's attestation report, so it
+    /// can be obtained from an untrusted source such as the registry.
+    pub fn verify(
+        key: Vec<u8>,
+        rak_binding: &[u8],
+        remote_enclaves: &HashSet<avr::EnclaveIdentity>,
+    ) -> Result<Self> {
+        Self::verify_with(key, rak_binding, remote_enclaves, avr::verify)
+    }
+
+    /// Verify a remote static public key, using the given function to verify
+    /// the attestation report.
+    pub(crate) fn verify_with(
+        key: Vec<u8>,
+        rak_binding: &[u8],
+        remote_enclaves: &HashSet<avr::EnclaveIdentity>,
+        avr_verifier: AVRVerifier,
+    ) -> Result<Self> {
+        let rak_binding: RAKBinding = cbor::from_slice(rak_binding)?;
+        let authenticated_avr =
+            verify_static_key_binding(&rak_binding, &key, Some(remote_enclaves), avr_verifier)?;
+
+        Ok(Self {
+            key,
+            identity: authenticated_avr.identity,
+        })
+    }
+
+    /// Verified static public key.
+    pub fn public_key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Identity of the enclave the key is bound to.
+    pub fn enclave_identity(&self) -> &avr::EnclaveIdentity {
+        &self.identity
+    }
+}
+
+/// Serialized RAK binding of the given static public key, which allows
+/// remote ends to verify the key without establishing a session, see
+/// `VerifiedStaticKey::verify`.
+///
+/// The binding is empty in case the RAK has not been initialized.
+pub fn static_key_binding(rak: &RAK, static_pub: &[u8]) -> Vec<u8> {
+    if rak.public_key().is_none() || rak.avr().is_none() {
+        return vec![];
+    }
+
+    let rak_pub = rak.public_key().expect("rak is configured").clone();
+    let avr = rak.avr().expect("avr is configured").clone();
+    let rak_binding = RAKBinding {
+        avr: (*avr).clone(),
+        rak_pub,
+        binding: rak.sign(&RAK_SESSION_BINDING_CONTEXT, static_pub).unwrap(),
+    };
+
+    cbor::to_vec(&rak_binding)
+}
+
+/// Function used to verify attestation reports in RAK bindings.
+pub(crate) type AVRVerifier = fn(&avr::AVR) -> Result<avr::AuthenticatedAVR>;
+
+/// Verify that the given RAK binding binds the static public key to an
+/// enclave with one of the given identities, if any.
+fn verify_static_key_binding(
+    rak_binding: &RAKBinding,
+    remote_static: &[u8],
+    remote_enclaves: Option<&HashSet<avr::EnclaveIdentity>>,
+    avr_verifier: AVRVerifier,
+) -> Result<avr::AuthenticatedAVR> {
+    let authenticated_avr = avr_verifier(&rak_binding.avr)?;
+
+    // Verify MRENCLAVE/MRSIGNER.
+    if let Some(remote_enclaves) = remote_enclaves {
+        if !remote_enclaves.contains(&authenticated_avr.identity) {
+            return Err(SessionError::MismatchedEnclaveIdentity.into());
+        }
+    }
+
+    // Verify RAK binding.
+    RAK::verify_binding(&authenticated_avr, &rak_binding.rak_pub)?;
+
+    // Verify remote static key binding.
+    rak_binding.binding.verify(
+        &rak_binding.rak_pub,
+        &RAK_SESSION_BINDING_CONTEXT,
+        remote_static,
+    )?;
+
+    Ok(authenticated_avr)
+}
+
+/// Payload of the first handshake message of the IK pattern.
+#[derive(Serialize, Deserialize)]
+struct HandshakePayload {
+    #[serde(with = "serde_bytes")]
+    rak_binding: Vec<u8>,
+    /// Sealed message sent together with the handshake, see `seal_fragment`.
+    #[serde(default, with = "serde_bytes", skip_serializing_if = "Option::is_none")]
+    early_data: Option<Vec<u8>>,
+}
+
+/// Session key rotation and message limits.
+#[derive(Clone, Debug)]
+struct Limits {
+    /// Number of Noise messages sent after which the sending key is rotated.
+    /// If 0, keys are never rotated based on the number of messages.
+    rekey_messages: u64,
+    /// Number of seconds after which the sending key is rotated.
+    /// If 0, keys are never rotated based on time.
+    rekey_interval_secs: u64,
+    /// Number of messages sent and received after which the session is
+    /// closed. If 0, the number of messages is not limited.
+    max_messages: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            rekey_messages: DEFAULT_REKEY_MESSAGES,
+            rekey_interval_secs: DEFAULT_REKEY_INTERVAL_SECS,
+            max_messages: DEFAULT_MAX_MESSAGES,
+        }
+    }
+}
+
+/// Noise handshake pattern.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pattern {
+    /// Three message handshake where static keys are exchanged.
+    XX,
+    /// Two message handshake where the initiator knows the responder's
+    /// static key and can send a message together with the first handshake
+    /// message.
+    IK,
 }
 
 enum State {
+    /// Responder waiting for the first handshake message, which determines
+    /// the pattern to use.
+    Accept(snow::HandshakeState, snow::HandshakeState),
     Handshake1(snow::HandshakeState),
     Handshake2(snow::HandshakeState),
+    /// Responder which has received an early message and sends its last
+    /// handshake message together with the response.
+    HandshakeReply(snow::HandshakeState),
     Transport(snow::TransportState),
     Closed,
 }
@@ -50,43 +294,79 @@ enum State {
 /// An encrypted and authenticated RPC session.
 pub struct Session {
     local_static_pub: Vec<u8>,
+    remote_static_pub: Option<Vec<u8>>,
     rak: Option<Arc<RAK>>,
     remote_enclaves: Option<HashSet<avr::EnclaveIdentity>>,
+    avr_verifier: AVRVerifier,
     info: Option<Arc<SessionInfo>>,
+    pattern: Pattern,
     state: State,
+    early_data: bool,
+    early_message_sent: bool,
     buf: Vec<u8>,
+    limits: Limits,
+    message_count: Arc<AtomicU64>,
+    sent_since_rekey: u64,
+    last_rekey_time: i64,
 }
 
 impl Session {
     fn new(
-        handshake_state: snow::HandshakeState,
+        state: State,
+        pattern: Pattern,
+        early_data: bool,
         local_static_pub: Vec<u8>,
         rak: Option<Arc<RAK>>,
         remote_enclaves: Option<HashSet<avr::EnclaveIdentity>>,
+        avr_verifier: AVRVerifier,
+        limits: Limits,
     ) -> Self {
         Self {
             local_static_pub,
+            remote_static_pub: None,
             rak,
             remote_enclaves,
+            avr_verifier,
             info: None,
-            state: State::Handshake1(handshake_state),
+            pattern,
+            state,
+            early_data,
+            early_message_sent: false,
             buf: vec![0u8; 65535],
+            limits,
+            message_count: Arc::new(AtomicU64::new(0)),
+            sent_since_rekey: 0,
+            last_rekey_time: 0,
         }
     }
 
     /// Process incoming data.
     ///
     /// In case the session is in transport mode the returned result will
-    /// contained a parsed message. The `writer` will be used in case any
+    /// contained a parsed message, which must have been written with the
+    /// same associated data `ad`. The `writer` will be used in case any
     /// protocol replies need to be generated.
     pub fn process_data<W: Write>(
         &mut self,
         data: Vec<u8>,
+        ad: &[u8],
         mut writer: W,
     ) -> Result<Option<Message>> {
         // Replace the state with a closed state. In case processing fails for whatever
         // reason, this will cause the session to be torn down.
         match mem::replace(&mut self.state, State::Closed) {
+            State::Accept(xx_state, ik_state) => {
+                // The first message of the XX pattern only contains the
+                // initiator's ephemeral key, while the first message of the
+                // IK pattern also contains its encrypted static key.
+                if data.len() == NOISE_DH_LEN {
+                    self.state = State::Handshake1(xx_state);
+                } else {
+                    self.pattern = Pattern::IK;
+                    self.state = State::Handshake1(ik_state);
+                }
+                return self.process_data(data, ad, writer);
+            }
             State::Handshake1(mut state) => {
                 if state.is_initiator() {
                     // Initiator only sends in this state.
@@ -94,9 +374,37 @@ impl Session {
                         return Err(SessionError::InvalidInput.into());
                     }
 
-                    // -> e
-                    let len = state.write_message(&[], &mut self.buf)?;
+                    let payload = match self.pattern {
+                        // -> e
+                        Pattern::XX => vec![],
+                        // -> e, es, s, ss
+                        Pattern::IK => cbor::to_vec(&HandshakePayload {
+                            rak_binding: self.get_rak_binding(),
+                            early_data: None,
+                        }),
+                    };
+                    let len = state.write_message(&payload, &mut self.buf)?;
                     writer.write_all(&self.buf[..len])?;
+                } else if self.pattern == Pattern::IK {
+                    // <- e, es, s, ss
+                    let len = state.read_message(&data, &mut self.buf)?;
+                    let payload: HandshakePayload = cbor::from_slice(&self.buf[..len])?;
+                    let remote_static = state
+                        .get_remote_static()
+                        .expect("dh exchange just happened");
+                    self.info = self.verify_rak_binding(&payload.rak_binding, remote_static)?;
+                    self.remote_static_pub = Some(remote_static.to_vec());
+
+                    if let Some(early_data) = payload.early_data {
+                        // The handshake is completed once the response has
+                        // been written.
+                        let msg = self.read_early_message(&early_data, ad)?;
+                        self.state = State::HandshakeReply(state);
+                        return Ok(Some(msg));
+                    }
+
+                    self.write_handshake_reply(state, writer)?;
+                    return Ok(None);
                 } else {
                     // <- e
                     state.read_message(&data, &mut self.buf)?;
@@ -108,6 +416,37 @@ impl Session {
 
                 self.state = State::Handshake2(state);
             }
+            State::Handshake2(mut state) if self.pattern == Pattern::IK => {
+                // <- e, ee, se
+                let data = &data[..];
+                if data.len() < 2 {
+                    return Err(SessionError::InvalidInput.into());
+                }
+                let len = u16::from_be_bytes([data[0], data[1]]) as usize;
+                if data.len() < 2 + len {
+                    return Err(SessionError::InvalidInput.into());
+                }
+                let plaintext_len = state.read_message(&data[2..2 + len], &mut self.buf)?;
+                let remote_static = state
+                    .get_remote_static()
+                    .expect("dh exchange just happened");
+                self.info = self.verify_rak_binding(&self.buf[..plaintext_len], remote_static)?;
+                self.remote_static_pub = Some(remote_static.to_vec());
+
+                let mut state = state.into_transport_mode()?;
+                self.last_rekey_time = insecure_posix_time();
+
+                // The response to an early message follows the handshake message.
+                let data = &data[2 + len..];
+                let msg = match (self.early_message_sent, data.is_empty()) {
+                    (true, false) => Some(self.read_fragments(&mut state, data, ad)?),
+                    (false, true) => None,
+                    _ => return Err(SessionError::InvalidInput.into()),
+                };
+
+                self.state = State::Transport(state);
+                return Ok(msg);
+            }
             State::Handshake2(mut state) => {
                 if state.is_initiator() {
                     // <- e, ee, s, es
@@ -116,6 +455,7 @@ impl Session {
                         .get_remote_static()
                         .expect("dh exchange just happened");
                     self.info = self.verify_rak_binding(&self.buf[..len], remote_static)?;
+                    self.remote_static_pub = Some(remote_static.to_vec());
 
                     // -> s, se
                     let len = state.write_message(&self.get_rak_binding(), &mut self.buf)?;
@@ -127,19 +467,24 @@ impl Session {
                         .get_remote_static()
                         .expect("dh exchange just happened");
                     self.info = self.verify_rak_binding(&self.buf[..len], remote_static)?;
+                    self.remote_static_pub = Some(remote_static.to_vec());
                 }
 
                 // Move into transport mode.
                 self.state = State::Transport(state.into_transport_mode()?);
+                self.last_rekey_time = insecure_posix_time();
             }
             State::Transport(mut state) => {
                 // TODO: Restore session in case of errors.
-                let len = state.read_message(&data, &mut self.buf)?;
-                let msg = cbor::from_slice(&self.buf[..len])?;
+                let msg = self.read_fragments(&mut state, &data, ad)?;
 
                 self.state = State::Transport(state);
                 return Ok(Some(msg));
             }
+            State::HandshakeReply(_) => {
+                // Responder must write the response before receiving more data.
+                return Err(SessionError::InvalidState.into());
+            }
             State::Closed => {
                 return Err(SessionError::Closed.into());
             }
@@ -150,18 +495,210 @@ impl Session {
 
     /// Write message to session.
     ///
-    /// The `writer` will be used for protocol message output which should
-    /// be transmitted to the remote session counterpart.
-    pub fn write_message<W: Write>(&mut self, msg: Message, mut writer: W) -> Result<()> {
-        if let State::Transport(ref mut state) = self.state {
-            let msg = cbor::to_vec(&msg);
-            let len = state.write_message(&msg, &mut self.buf)?;
-            writer.write_all(&self.buf[..len])?;
-
-            Ok(())
-        } else {
-            Err(SessionError::InvalidState.into())
+    /// Messages larger than a single Noise message are split into multiple
+    /// fragments, which are all written together. The associated data `ad`
+    /// is authenticated together with the message, but is not included in
+    /// the output. The `writer` will be used for protocol message output
+    /// which should be transmitted to the remote session counterpart.
+    ///
+    /// In case the session uses the IK pattern, the initiator can write a
+    /// message before the handshake has started, see `can_write_early_message`.
+    pub fn write_message<W: Write>(
+        &mut self,
+        msg: Message,
+        ad: &[u8],
+        mut writer: W,
+    ) -> Result<()> {
+        let msg = cbor::to_vec(&msg);
+        if msg.len() > MAX_MESSAGE_LEN {
+            return Err(SessionError::MessageTooLarge.into());
+        }
+
+        match mem::replace(&mut self.state, State::Closed) {
+            State::Handshake1(mut state) if self.is_early_initiator(&state) => {
+                if msg.len() > MAX_EARLY_MESSAGE_LEN {
+                    self.state = State::Handshake1(state);
+                    return Err(SessionError::MessageTooLarge.into());
+                }
+                self.count_message()?;
+
+                // -> e, es, s, ss
+                let ad_hash = Hash::digest_bytes(ad);
+                let payload = HandshakePayload {
+                    rak_binding: self.get_rak_binding(),
+                    early_data: Some(Self::seal_fragment(&ad_hash, 0, &msg)),
+                };
+                let len = state.write_message(&cbor::to_vec(&payload), &mut self.buf)?;
+                writer.write_all(&self.buf[..len])?;
+
+                self.early_message_sent = true;
+                self.state = State::Handshake2(state);
+                return Ok(());
+            }
+            State::HandshakeReply(state) => {
+                self.write_handshake_reply(state, &mut writer)?;
+            }
+            state => self.state = state,
+        }
+
+        if !self.is_connected() {
+            return Err(SessionError::InvalidState.into());
+        }
+        self.count_message()?;
+
+        let ad_hash = Hash::digest_bytes(ad);
+        let fragments = (msg.len() + MAX_FRAGMENT_LEN - 1) / MAX_FRAGMENT_LEN;
+        for (index, fragment) in msg.chunks(MAX_FRAGMENT_LEN).enumerate() {
+            let rekey = self.should_rekey();
+            let mut flags = 0;
+            if rekey {
+                flags |= FLAG_REKEY;
+            }
+            if index + 1 < fragments {
+                flags |= FLAG_MORE;
+            }
+
+            let plaintext = Self::seal_fragment(&ad_hash, flags, fragment);
+            if let State::Transport(ref mut state) = self.state {
+                // Transport messages are length-prefixed so that multiple
+                // fragments can be transmitted together.
+                let len = state.write_message(&plaintext, &mut self.buf)?;
+                writer.write_all(&(len as u16).to_be_bytes())?;
+                writer.write_all(&self.buf[..len])?;
+
+                if rekey {
+                    state.rekey_outgoing();
+                    self.sent_since_rekey = 0;
+                    self.last_rekey_time = insecure_posix_time();
+                } else {
+                    self.sent_since_rekey += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read and reassemble all fragments of a message.
+    fn read_fragments(
+        &mut self,
+        state: &mut snow::TransportState,
+        mut data: &[u8],
+        ad: &[u8],
+    ) -> Result<Message> {
+        self.count_message()?;
+
+        let ad_hash = Hash::digest_bytes(ad);
+        let mut msg = Vec::new();
+        loop {
+            if data.len() < 2 {
+                return Err(SessionError::InvalidInput.into());
+            }
+            let len = u16::from_be_bytes([data[0], data[1]]) as usize;
+            if data.len() < 2 + len {
+                return Err(SessionError::InvalidInput.into());
+            }
+
+            let plaintext_len = state.read_message(&data[2..2 + len], &mut self.buf)?;
+            data = &data[2 + len..];
+            let flags = Self::open_fragment(&self.buf[..plaintext_len], &ad_hash, &mut msg)?;
+            if msg.len() > MAX_MESSAGE_LEN {
+                return Err(SessionError::MessageTooLarge.into());
+            }
+            if flags & FLAG_REKEY != 0 {
+                state.rekey_incoming();
+            }
+            if flags & FLAG_MORE == 0 {
+                break;
+            }
         }
+        if !data.is_empty() {
+            return Err(SessionError::InvalidInput.into());
+        }
+
+        Ok(cbor::from_slice(&msg)?)
+    }
+
+    /// Read a message sent together with the first handshake message.
+    fn read_early_message(&mut self, early_data: &[u8], ad: &[u8]) -> Result<Message> {
+        self.count_message()?;
+
+        let mut msg = Vec::new();
+        let flags = Self::open_fragment(early_data, &Hash::digest_bytes(ad), &mut msg)?;
+        if flags != 0 {
+            return Err(SessionError::InvalidInput.into());
+        }
+
+        Ok(cbor::from_slice(&msg)?)
+    }
+
+    /// Write the last handshake message of the IK pattern and move into
+    /// transport mode.
+    ///
+    /// The handshake message is length-prefixed as it may be followed by
+    /// the response to an early message.
+    fn write_handshake_reply<W: Write>(
+        &mut self,
+        mut state: snow::HandshakeState,
+        mut writer: W,
+    ) -> Result<()> {
+        // -> e, ee, se
+        let len = state.write_message(&self.get_rak_binding(), &mut self.buf)?;
+        writer.write_all(&(len as u16).to_be_bytes())?;
+        writer.write_all(&self.buf[..len])?;
+
+        self.state = State::Transport(state.into_transport_mode()?);
+        self.last_rekey_time = insecure_posix_time();
+
+        Ok(())
+    }
+
+    fn is_early_initiator(&self, state: &snow::HandshakeState) -> bool {
+        state.is_initiator()
+            && self.pattern == Pattern::IK
+            && self.early_data
+            && !self.early_message_sent
+    }
+
+    /// Return true if the given message can be written before the handshake
+    /// has started, so that the session is established in a single round trip.
+    ///
+    /// This requires the initiator to have been built with a verified remote
+    /// static key, see `Builder::verified_remote_static_key`. Note that an
+    /// early message can be replayed to the responder by the host, so only
+    /// idempotent requests should be sent early. It is also not forward
+    /// secret in case the responder's static key is compromised.
+    pub fn can_write_early_message(&self, msg: &Message) -> bool {
+        match self.state {
+            State::Handshake1(ref state) => {
+                self.is_early_initiator(state) && cbor::to_vec(msg).len() <= MAX_EARLY_MESSAGE_LEN
+            }
+            _ => false,
+        }
+    }
+
+    /// Count a message sent or received in transport mode, closing the
+    /// session in case the message limit has been reached.
+    fn count_message(&mut self) -> Result<()> {
+        let count = self.message_count.load(Ordering::SeqCst);
+        if self.limits.max_messages != 0 && count >= self.limits.max_messages {
+            self.close();
+            return Err(SessionError::MessageLimit.into());
+        }
+        self.message_count.store(count + 1, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Whether the sending key should be rotated after the next Noise message.
+    fn should_rekey(&self) -> bool {
+        let by_messages = self.limits.rekey_messages != 0
+            && self.sent_since_rekey + 1 >= self.limits.rekey_messages;
+        let by_time = self.limits.rekey_interval_secs != 0
+            && insecure_posix_time() - self.last_rekey_time
+                >= self.limits.rekey_interval_secs as i64;
+
+        by_messages || by_time
     }
 
     /// Mark the session as closed.
@@ -172,25 +709,34 @@ impl Session {
         self.state = State::Closed;
     }
 
-    fn get_rak_binding(&self) -> Vec<u8> {
-        match self.rak {
-            Some(ref rak) => {
-                if rak.public_key().is_none() || rak.avr().is_none() {
-                    return vec![];
-                }
+    /// Prepare a message fragment for encryption, binding it to the
+    /// associated data.
+    ///
+    /// The Noise transport does not support associated data, so the hash of
+    /// the associated data is encrypted together with the fragment instead.
+    /// It is followed by a byte of fragment flags.
+    fn seal_fragment(ad_hash: &Hash, flags: u8, fragment: &[u8]) -> Vec<u8> {
+        let mut plaintext = Vec::with_capacity(FRAGMENT_HEADER_LEN + fragment.len());
+        plaintext.extend_from_slice(ad_hash.as_ref());
+        plaintext.push(flags);
+        plaintext.extend_from_slice(fragment);
+        plaintext
+    }
 
-                let rak_pub = rak.public_key().expect("rak is configured").clone();
-                let avr = rak.avr().expect("avr is configured").clone();
-                let rak_binding = RAKBinding {
-                    avr: (*avr).clone(),
-                    rak_pub,
-                    binding: rak
-                        .sign(&RAK_SESSION_BINDING_CONTEXT, &self.local_static_pub)
-                        .unwrap(),
-                };
+    /// Verify that a decrypted fragment is bound to the associated data,
+    /// append its contents to `msg` and return its flags.
+    fn open_fragment(plaintext: &[u8], ad_hash: &Hash, msg: &mut Vec<u8>) -> Result<u8> {
+        if plaintext.len() < FRAGMENT_HEADER_LEN || !plaintext.starts_with(ad_hash.as_ref()) {
+            return Err(SessionError::MismatchedAssociatedData.into());
+        }
 
-                cbor::to_vec(&rak_binding)
-            }
+        msg.extend_from_slice(&plaintext[FRAGMENT_HEADER_LEN..]);
+        Ok(plaintext[FRAGMENT_HEADER_LEN - 1])
+    }
+
+    fn get_rak_binding(&self) -> Vec<u8> {
+        match self.rak {
+            Some(ref rak) => static_key_binding(rak, &self.local_static_pub),
             None => vec![],
         }
     }
@@ -210,28 +756,17 @@ impl Session {
         }
 
         let rak_binding: RAKBinding = cbor::from_slice(rak_binding)?;
-        let authenticated_avr = avr::verify(&rak_binding.avr)?;
-
-        // Verify MRENCLAVE/MRSIGNER.
-        if let Some(ref remote_enclaves) = self.remote_enclaves {
-            if !remote_enclaves.contains(&authenticated_avr.identity) {
-                return Err(SessionError::MismatchedEnclaveIdentity.into());
-            }
-        }
-
-        // Verify RAK binding.
-        RAK::verify_binding(&authenticated_avr, &rak_binding.rak_pub)?;
-
-        // Verify remote static key binding.
-        rak_binding.binding.verify(
-            &rak_binding.rak_pub,
-            &RAK_SESSION_BINDING_CONTEXT,
+        let authenticated_avr = verify_static_key_binding(
+            &rak_binding,
             remote_static,
+            self.remote_enclaves.as_ref(),
+            self.avr_verifier,
         )?;
 
         Ok(Some(Arc::new(SessionInfo {
             rak_binding,
             authenticated_avr,
+            message_count: self.message_count.clone(),
         })))
     }
 
@@ -240,6 +775,44 @@ impl Session {
         self.info.clone()
     }
 
+    /// Static public key of the remote session counterpart, available
+    /// after it has been received during the handshake.
+    pub fn remote_static_public_key(&self) -> Option<&[u8]> {
+        self.remote_static_pub.as_ref().map(|key| key.as_slice())
+    }
+
+    /// Static public key of the remote session counterpart in case its RAK
+    /// binding has been verified against the configured remote enclave
+    /// identities.
+    pub fn verified_remote_static_key(&self) -> Option<VerifiedStaticKey> {
+        if self.remote_enclaves.is_none() {
+            return None;
+        }
+        match (&self.remote_static_pub, &self.info) {
+            (Some(key), Some(info)) => Some(VerifiedStaticKey {
+                key: key.clone(),
+                identity: info.authenticated_avr.identity.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Number of messages sent and received in transport mode.
+    pub fn message_count(&self) -> u64 {
+        self.message_count.load(Ordering::SeqCst)
+    }
+
+    /// Number of messages which can still be sent or received before the
+    /// session reaches its message limit and must be renegotiated.
+    pub fn remaining_messages(&self) -> u64 {
+        if self.limits.max_messages == 0 {
+            return u64::max_value();
+        }
+        self.limits
+            .max_messages
+            .saturating_sub(self.message_count())
+    }
+
     /// Return true if session handshake has completed and the session
     /// is in transport mode.
     pub fn is_connected(&self) -> bool {
@@ -273,6 +846,11 @@ pub struct RAKBinding {
 pub struct Builder {
     rak: Option<Arc<RAK>>,
     remote_enclaves: Option<HashSet<avr::EnclaveIdentity>>,
+    avr_verifier: AVRVerifier,
+    limits: Limits,
+    local_static: Option<StaticKeyPair>,
+    remote_static_pub: Option<Vec<u8>>,
+    verified_remote_static: Option<VerifiedStaticKey>,
 }
 
 impl Builder {
@@ -281,6 +859,11 @@ impl Builder {
         Self {
             rak: None,
             remote_enclaves: None,
+            avr_verifier: avr::verify,
+            limits: Limits::default(),
+            local_static: None,
+            remote_static_pub: None,
+            verified_remote_static: None,
         }
     }
 
@@ -295,45 +878,285 @@ impl Builder {
         self
     }
 
+    /// Use the given function to verify attestation reports of remote ends,
+    /// instead of `avr::verify`.
+    #[cfg(test)]
+    pub(crate) fn avr_verifier(mut self, avr_verifier: AVRVerifier) -> Self {
+        self.avr_verifier = avr_verifier;
+        self
+    }
+
     /// Enable RAK binding.
     pub fn local_rak(mut self, rak: Arc<RAK>) -> Self {
         self.rak = Some(rak);
         self
     }
 
-    fn build<'a>(
+    /// Use the given static key pair instead of generating a new one for
+    /// each session.
+    pub fn local_static_keypair(mut self, keypair: StaticKeyPair) -> Self {
+        self.local_static = Some(keypair);
+        self
+    }
+
+    /// Return the remote static public key if configured in the builder.
+    pub fn get_remote_static_public_key(&self) -> &Option<Vec<u8>> {
+        &self.remote_static_pub
+    }
+
+    /// Use the IK handshake pattern with the given remote static public key,
+    /// which allows initiators to complete the handshake in a single round
+    /// trip.
+    ///
+    /// The first handshake message contains the initiator's static public
+    /// key and RAK binding, including its attestation report, encrypted to
+    /// the given key before the remote end's RAK binding has been verified.
+    /// With a key obtained from an untrusted source, these may thus be
+    /// revealed to a party other than the remote enclave. No requests are
+    /// sent before the remote end's RAK binding has been verified.
+    pub fn remote_static_public_key(mut self, key: Option<Vec<u8>>) -> Self {
+        self.remote_static_pub = key;
+        self
+    }
+
+    /// Return the verified remote static key if configured in the builder.
+    pub fn get_verified_remote_static_key(&self) -> &Option<VerifiedStaticKey> {
+        &self.verified_remote_static
+    }
+
+    /// Use the IK handshake pattern with a remote static key verified in a
+    /// previous session, which also allows initiators to send a message
+    /// together with the first handshake message.
+    ///
+    /// The key is only used if its enclave identity is one of the configured
+    /// remote enclave identities. It takes precedence over a key configured
+    /// through `remote_static_public_key`.
+    pub fn verified_remote_static_key(mut self, key: Option<VerifiedStaticKey>) -> Self {
+        self.verified_remote_static = key;
+        self
+    }
+
+    /// Rotate the sending key after the given number of messages.
+    /// If 0, keys are never rotated based on the number of messages.
+    pub fn rekey_messages(mut self, messages: u64) -> Self {
+        self.limits.rekey_messages = messages;
+        self
+    }
+
+    /// Rotate the sending key after the given number of seconds.
+    /// If 0, keys are never rotated based on time.
+    ///
+    /// Time is obtained through `insecure_posix_time`, which is controlled by
+    /// the untrusted host, so the host can delay rotation. Use `rekey_messages`
+    /// for a bound enforced by the enclave itself.
+    pub fn rekey_interval_secs(mut self, secs: u64) -> Self {
+        self.limits.rekey_interval_secs = secs;
+        self
+    }
+
+    /// Close the session after the given number of messages have been sent
+    /// and received, so that it must be renegotiated.
+    /// If 0, the number of messages is not limited.
+    pub fn max_messages(mut self, messages: u64) -> Self {
+        self.limits.max_messages = messages;
+        self
+    }
+
+    fn build(
         mut self,
     ) -> (
-        snow::Builder<'a>,
-        snow::Keypair,
+        StaticKeyPair,
         Option<Arc<RAK>>,
         Option<HashSet<avr::EnclaveIdentity>>,
+        AVRVerifier,
+        Limits,
     ) {
-        let noise_builder = snow::Builder::new(NOISE_PATTERN.parse().unwrap());
+        let keypair = self
+            .local_static
+            .take()
+            .unwrap_or_else(StaticKeyPair::generate);
         let rak = self.rak.take();
         let remote_enclaves = self.remote_enclaves.take();
-        let keypair = noise_builder.generate_keypair().unwrap();
 
-        (noise_builder, keypair, rak, remote_enclaves)
+        (
+            keypair,
+            rak,
+            remote_enclaves,
+            self.avr_verifier,
+            self.limits,
+        )
+    }
+
+    fn noise_builder<'a>(pattern: &str, keypair: &'a StaticKeyPair) -> snow::Builder<'a> {
+        snow::Builder::new(pattern.parse().unwrap()).local_private_key(&keypair.private)
     }
 
     /// Build initiator session.
-    pub fn build_initiator(self) -> Session {
-        let (builder, keypair, rak, enclaves) = self.build();
-        let session = builder
-            .local_private_key(&keypair.private)
-            .build_initiator()
-            .unwrap();
-        Session::new(session, keypair.public, rak, enclaves)
+    pub fn build_initiator(mut self) -> Session {
+        let remote_enclaves = &self.remote_enclaves;
+        let verified_remote_static = self.verified_remote_static.take().filter(|key| {
+            remote_enclaves
+                .as_ref()
+                .map_or(false, |enclaves| enclaves.contains(&key.identity))
+        });
+        let early_data = verified_remote_static.is_some();
+        // Fall back to the XX pattern in case the remote key is malformed.
+        let remote_static_pub = verified_remote_static
+            .map(|key| key.key)
+            .or_else(|| self.remote_static_pub.take())
+            .filter(|key| key.len() == NOISE_DH_LEN);
+        let (keypair, rak, enclaves, avr_verifier, limits) = self.build();
+        let (pattern, session) = match remote_static_pub {
+            Some(ref remote_static_pub) => (
+                Pattern::IK,
+                Self::noise_builder(NOISE_PATTERN_IK, &keypair)
+                    .remote_public_key(remote_static_pub)
+                    .build_initiator()
+                    .unwrap(),
+            ),
+            None => (
+                Pattern::XX,
+                Self::noise_builder(NOISE_PATTERN, &keypair)
+                    .build_initiator()
+                    .unwrap(),
+            ),
+        };
+        Session::new(
+            State::Handshake1(session),
+            pattern,
+            early_data && pattern == Pattern::IK,
+            keypair.public,
+            rak,
+            enclaves,
+            avr_verifier,
+            limits,
+        )
     }
 
     /// Build responder session.
+    ///
+    /// The responder accepts both the XX and the IK handshake patterns.
     pub fn build_responder(self) -> Session {
-        let (builder, keypair, rak, enclaves) = self.build();
-        let session = builder
-            .local_private_key(&keypair.private)
+        let (keypair, rak, enclaves, avr_verifier, limits) = self.build();
+        let xx_session = Self::noise_builder(NOISE_PATTERN, &keypair)
+            .build_responder()
+            .unwrap();
+        let ik_session = Self::noise_builder(NOISE_PATTERN_IK, &keypair)
             .build_responder()
             .unwrap();
-        Session::new(session, keypair.public, rak, enclaves)
+        Session::new(
+            State::Accept(xx_session, ik_session),
+            Pattern::XX,
+            false,
+            keypair.public,
+            rak,
+            enclaves,
+            avr_verifier,
+            limits,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{super::types, *};
+
+    fn request() -> Message {
+        Message::Request(types::Request {
+            method: "test".to_owned(),
+            args: cbor::to_value(42),
+        })
+    }
+
+    #[test]
+    fn test_early_message_requires_verified_key() {
+        let keypair = StaticKeyPair::generate();
+        let identity = avr::EnclaveIdentity::default();
+        let verified = VerifiedStaticKey {
+            key: keypair.public_key().to_vec(),
+            identity: identity.clone(),
+        };
+        let mut enclaves = HashSet::new();
+        enclaves.insert(identity);
+
+        // Keys which have not been verified can not be used for early messages.
+        let mut initiator = Builder::new()
+            .remote_static_public_key(Some(keypair.public_key().to_vec()))
+            .build_initiator();
+        assert!(!initiator.can_write_early_message(&request()));
+        assert!(
+            initiator.write_message(request(), b"ad", vec![]).is_err(),
+            "early message should be rejected"
+        );
+
+        // Verified keys can only be used for the configured enclave identities.
+        let initiator = Builder::new()
+            .verified_remote_static_key(Some(verified.clone()))
+            .build_initiator();
+        assert!(!initiator.can_write_early_message(&request()));
+
+        let mut initiator = Builder::new()
+            .remote_enclaves(Some(enclaves))
+            .verified_remote_static_key(Some(verified))
+            .build_initiator();
+        assert!(initiator.can_write_early_message(&request()));
+
+        let mut responder = Builder::new()
+            .local_static_keypair(keypair)
+            .build_responder();
+        let mut data = vec![];
+        initiator
+            .write_message(request(), b"ad", &mut data)
+            .expect("early message should be written");
+        let msg = responder
+            .process_data(data, b"ad", vec![])
+            .expect("early message should be read")
+            .expect("early message should be returned");
+        assert_eq!(cbor::to_vec(&msg), cbor::to_vec(&request()));
+
+        // The responder has no RAK binding, so the initiator must reject it.
+        let mut data = vec![];
+        responder
+            .write_message(request(), &[], &mut data)
+            .expect("response should be written");
+        assert!(
+            initiator.process_data(data, &[], vec![]).is_err(),
+            "responder without RAK binding should be rejected"
+        );
+    }
+
+    #[test]
+    fn test_ik_handshake_without_early_message() {
+        let keypair = StaticKeyPair::generate();
+        let mut initiator = Builder::new()
+            .remote_static_public_key(Some(keypair.public_key().to_vec()))
+            .build_initiator();
+        let mut responder = Builder::new()
+            .local_static_keypair(keypair)
+            .build_responder();
+
+        let mut data = vec![];
+        initiator.process_data(vec![], &[], &mut data).unwrap();
+        let mut reply = vec![];
+        assert!(responder
+            .process_data(data, &[], &mut reply)
+            .unwrap()
+            .is_none());
+        assert!(responder.is_connected());
+        assert!(initiator
+            .process_data(reply, &[], vec![])
+            .unwrap()
+            .is_none());
+        assert!(initiator.is_connected());
+
+        let mut data = vec![];
+        initiator
+            .write_message(request(), b"ad", &mut data)
+            .unwrap();
+        let msg = responder
+            .process_data(data, b"ad", vec![])
+            .unwrap()
+            .expect("message should be returned");
+        assert_eq!(cbor::to_vec(&msg), cbor::to_vec(&request()));
     }
 }
@@ -2,7 +2,7 @@
 use rand::{rngs::OsRng, Rng};
 use serde::{Deserialize, Serialize};
 
-use crate::common::cbor::Value;
+use crate::common::cbor::{self, Value};
 
 impl_bytes!(
     SessionID,
@@ -26,16 +26,27 @@ impl SessionID {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Frame {
     pub session: SessionID,
-    // The `untrusted_plaintext` field is only a temporary workaround until
-    // the snow library supports encrypting the payload with authenticated
-    // data.
-    // This field contains a plaintext copy of the Request's `method` field
-    // and is verified inside the enclave.  It is unused in other cases.
+    /// A plaintext copy of the Request's `method` field which hosts can use
+    /// for routing. It is empty for other messages.
+    ///
+    /// Despite its name, which is kept for wire compatibility, the field is
+    /// authenticated together with the session identifier as associated data
+    /// of the payload, see `Frame::associated_data`. Frames whose field was
+    /// changed in transit fail to decrypt, so the enclave does not need to
+    /// compare it against the method of the decrypted request.
     pub untrusted_plaintext: String,
     #[serde(with = "serde_bytes")]
     pub payload: Vec<u8>,
 }
 
+impl Frame {
+    /// Associated data which is authenticated together with the payload of
+    /// a frame with the given metadata.
+    pub fn associated_data(session: &SessionID, untrusted_plaintext: &str) -> Vec<u8> {
+        cbor::to_vec(&(session, untrusted_plaintext))
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Request {
     pub method: String,
@@ -58,10 +69,33 @@ pub struct Response {
     pub body: Body,
 }
 
+/// Request whose response is streamed in chunks.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StreamRequest {
+    pub request: Request,
+    /// Maximum size of each chunk of the CBOR-encoded response.
+    pub chunk_size: u32,
+}
+
+/// A chunk of a streamed response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StreamChunk {
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+    /// Number of bytes remaining after this chunk.
+    pub remaining: u64,
+}
+
 /// Protocol message.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Message {
     Request(Request),
     Response(Response),
     Close,
+    /// Request with a streamed response, which is returned in chunks. The
+    /// first chunk is returned immediately.
+    StreamRequest(StreamRequest),
+    /// Request for the next chunk of a streamed response.
+    StreamPull,
+    StreamChunk(StreamChunk),
 }
@@ -47,6 +47,7 @@ use lazy_static::lazy_static;
 #[cfg(target_env = "sgx")]
 use sgx_isa::{AttributesFlags, Report};
 
+pub mod api;
 #[macro_use]
 pub mod common;
 pub mod dispatcher;
@@ -119,6 +120,8 @@ pub struct BuildInfo {
 }
 
 // Re-exports.
+pub use oasis_core_runtime_macros::runtime_api;
+
 pub use self::{
     enclave_rpc::{demux::Demux as RpcDemux, dispatcher::Dispatcher as RpcDispatcher},
     init::start_runtime,
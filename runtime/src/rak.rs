@@ -283,6 +283,27 @@ impl RAK {
     }
 }
 
+#[cfg(test)]
+impl RAK {
+    /// Create an initialized runtime attestation key for an enclave with the
+    /// given identity, using an unsigned attestation report, for use in tests.
+    pub(crate) fn new_test(identity: &avr::EnclaveIdentity) -> Self {
+        let private_key = PrivateKey::generate();
+        let report_data = Self::report_body_for_rak(&private_key.public_key());
+        let avr = avr::AVR::new_test(identity, report_data.as_ref());
+
+        let rak = Self::new();
+        {
+            let mut inner = rak.inner.write().unwrap();
+            inner.private_key = Some(private_key);
+            inner.avr = Some(Arc::new(avr));
+            inner.avr_timestamp = Some(insecure_posix_time());
+            inner.enclave_identity = Some(identity.clone());
+        }
+        rak
+    }
+}
+
 impl Signer for RAK {
     /// Generate a RAK signature with the private key over the context and message.
     fn sign(&self, context: &[u8], message: &[u8]) -> Result<Signature> {
@@ -0,0 +1,645 @@
+//! Typed storage collections.
+//!
+//! Collections are stateless handles which store their entries in an MKVS
+//! under the prefix of a `KeyFormat`. Keys are encoded and decoded using the
+//! key format and values are stored CBOR-serialized.
+use std::marker::PhantomData;
+
+use anyhow::{Context as AnyContext, Result};
+use byteorder::{BigEndian, ByteOrder};
+use io_context::Context;
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+
+use super::MKVS;
+use crate::common::{cbor, key_format::KeyFormat};
+
+#[derive(Error, Debug)]
+pub enum CollectionError {
+    #[error("collections: malformed key")]
+    MalformedKey,
+    #[error("collections: malformed value")]
+    MalformedValue,
+    #[error("collections: counter overflow")]
+    CounterOverflow,
+    #[error("collections: counter underflow")]
+    CounterUnderflow,
+}
+
+/// Decode a key of the given key format, checking its prefix and size.
+fn decode_key<K: KeyFormat>(key: &[u8]) -> Result<K> {
+    if key.len() < 1 + K::size() || key[0] != K::prefix() {
+        return Err(CollectionError::MalformedKey.into());
+    }
+    Ok(K::decode_atoms(&key[1..]))
+}
+
+fn decode_value<V: DeserializeOwned>(value: &[u8]) -> Result<V> {
+    cbor::from_slice(value).context(CollectionError::MalformedValue)
+}
+
+fn decode_counter(value: &[u8]) -> Result<u64> {
+    if value.len() != 8 {
+        return Err(CollectionError::MalformedValue.into());
+    }
+    Ok(BigEndian::read_u64(value))
+}
+
+fn encode_counter(value: u64) -> Vec<u8> {
+    let mut data = vec![0; 8];
+    BigEndian::write_u64(&mut data, value);
+    data
+}
+
+/// Iterate over all entries under the prefix of the given key format.
+fn iter_prefix<'a, K: KeyFormat + 'a>(
+    ctx: Context,
+    mkvs: &'a dyn MKVS,
+) -> impl Iterator<Item = Result<(K, Vec<u8>)>> + 'a {
+    mkvs.prefix(ctx, &[K::prefix()])
+        .map(|item| item.and_then(|(key, value)| Ok((decode_key(&key)?, value))))
+}
+
+/// A map from keys of a key format to CBOR-serialized values.
+pub struct Map<K: KeyFormat, V> {
+    _type: PhantomData<(K, V)>,
+}
+
+impl<K: KeyFormat, V> Default for Map<K, V> {
+    fn default() -> Self {
+        Self { _type: PhantomData }
+    }
+}
+
+impl<K: KeyFormat, V: Serialize + DeserializeOwned> Map<K, V> {
+    /// Create a new map handle.
+    pub fn new() -> Self {
+        Self { _type: PhantomData }
+    }
+
+    /// Fetch the value for the given key.
+    pub fn get(&self, ctx: Context, mkvs: &dyn MKVS, key: K) -> Result<Option<V>> {
+        mkvs.get(ctx, &key.encode())?
+            .map(|value| decode_value(&value))
+            .transpose()
+    }
+
+    /// Check whether the map contains the given key.
+    pub fn contains_key(&self, ctx: Context, mkvs: &dyn MKVS, key: K) -> Result<bool> {
+        Ok(mkvs.get(ctx, &key.encode())?.is_some())
+    }
+
+    /// Insert a value, returning the previous value if any.
+    pub fn insert(
+        &self,
+        ctx: Context,
+        mkvs: &mut dyn MKVS,
+        key: K,
+        value: &V,
+    ) -> Result<Option<V>> {
+        mkvs.insert(ctx, &key.encode(), &cbor::to_vec(value))?
+            .map(|value| decode_value(&value))
+            .transpose()
+    }
+
+    /// Remove a value, returning the previous value if any.
+    pub fn remove(&self, ctx: Context, mkvs: &mut dyn MKVS, key: K) -> Result<Option<V>> {
+        mkvs.remove(ctx, &key.encode())?
+            .map(|value| decode_value(&value))
+            .transpose()
+    }
+
+    /// Iterate over all entries in key order.
+    pub fn iter<'a>(
+        &self,
+        ctx: Context,
+        mkvs: &'a dyn MKVS,
+    ) -> impl Iterator<Item = Result<(K, V)>> + 'a
+    where
+        K: 'a,
+        V: 'a,
+    {
+        iter_prefix(ctx, mkvs)
+            .map(|item| item.and_then(|(key, value)| Ok((key, decode_value(&value)?))))
+    }
+}
+
+/// A set of keys of a key format.
+pub struct Set<K: KeyFormat> {
+    _type: PhantomData<K>,
+}
+
+impl<K: KeyFormat> Default for Set<K> {
+    fn default() -> Self {
+        Self { _type: PhantomData }
+    }
+}
+
+impl<K: KeyFormat> Set<K> {
+    /// Create a new set handle.
+    pub fn new() -> Self {
+        Self { _type: PhantomData }
+    }
+
+    /// Check whether the set contains the given key.
+    pub fn contains(&self, ctx: Context, mkvs: &dyn MKVS, key: K) -> Result<bool> {
+        Ok(mkvs.get(ctx, &key.encode())?.is_some())
+    }
+
+    /// Add a key, returning `true` if it was not yet present.
+    pub fn insert(&self, ctx: Context, mkvs: &mut dyn MKVS, key: K) -> Result<bool> {
+        Ok(mkvs.insert(ctx, &key.encode(), &[])?.is_none())
+    }
+
+    /// Remove a key, returning `true` if it was present.
+    pub fn remove(&self, ctx: Context, mkvs: &mut dyn MKVS, key: K) -> Result<bool> {
+        Ok(mkvs.remove(ctx, &key.encode())?.is_some())
+    }
+
+    /// Iterate over all keys in order.
+    pub fn iter<'a>(&self, ctx: Context, mkvs: &'a dyn MKVS) -> impl Iterator<Item = Result<K>> + 'a
+    where
+        K: 'a,
+    {
+        iter_prefix(ctx, mkvs).map(|item| item.map(|(key, _)| key))
+    }
+}
+
+/// A map from keys of a key format to unsigned counters.
+///
+/// Counters which are not present are treated as zero and counters which
+/// reach zero are removed.
+pub struct Counter<K: KeyFormat> {
+    _type: PhantomData<K>,
+}
+
+impl<K: KeyFormat> Default for Counter<K> {
+    fn default() -> Self {
+        Self { _type: PhantomData }
+    }
+}
+
+impl<K: KeyFormat> Counter<K> {
+    /// Create a new counter handle.
+    pub fn new() -> Self {
+        Self { _type: PhantomData }
+    }
+
+    /// Fetch the value of the counter.
+    pub fn get(&self, ctx: Context, mkvs: &dyn MKVS, key: K) -> Result<u64> {
+        match mkvs.get(ctx, &key.encode())? {
+            Some(value) => decode_counter(&value),
+            None => Ok(0),
+        }
+    }
+
+    /// Increment the counter by the given amount and return the new value.
+    pub fn increment(&self, ctx: Context, mkvs: &mut dyn MKVS, key: K, by: u64) -> Result<u64> {
+        let key = key.encode();
+        let ctx = ctx.freeze();
+        let value = match mkvs.get(Context::create_child(&ctx), &key)? {
+            Some(value) => decode_counter(&value)?,
+            None => 0,
+        };
+        let value = value
+            .checked_add(by)
+            .ok_or(CollectionError::CounterOverflow)?;
+        self.set(Context::create_child(&ctx), mkvs, &key, value)?;
+
+        Ok(value)
+    }
+
+    /// Decrement the counter by the given amount and return the new value.
+    pub fn decrement(&self, ctx: Context, mkvs: &mut dyn MKVS, key: K, by: u64) -> Result<u64> {
+        let key = key.encode();
+        let ctx = ctx.freeze();
+        let value = match mkvs.get(Context::create_child(&ctx), &key)? {
+            Some(value) => decode_counter(&value)?,
+            None => 0,
+        };
+        let value = value
+            .checked_sub(by)
+            .ok_or(CollectionError::CounterUnderflow)?;
+        self.set(Context::create_child(&ctx), mkvs, &key, value)?;
+
+        Ok(value)
+    }
+
+    fn set(&self, ctx: Context, mkvs: &mut dyn MKVS, key: &[u8], value: u64) -> Result<()> {
+        if value == 0 {
+            mkvs.remove(ctx, key)?;
+        } else {
+            mkvs.insert(ctx, key, &encode_counter(value))?;
+        }
+        Ok(())
+    }
+
+    /// Iterate over all non-zero counters in key order.
+    pub fn iter<'a>(
+        &self,
+        ctx: Context,
+        mkvs: &'a dyn MKVS,
+    ) -> impl Iterator<Item = Result<(K, u64)>> + 'a
+    where
+        K: 'a,
+    {
+        iter_prefix(ctx, mkvs)
+            .map(|item| item.and_then(|(key, value)| Ok((key, decode_counter(&value)?))))
+    }
+}
+
+/// An append-only queue of CBOR-serialized values.
+///
+/// The key format is constructed from the index of each item and must
+/// encode it as at least one atom which sorts in index order (e.g., as a
+/// big-endian integer). The queue length is stored under the bare prefix.
+pub struct Queue<K: KeyFormat + From<u64>, V> {
+    _type: PhantomData<(K, V)>,
+}
+
+impl<K: KeyFormat + From<u64>, V> Default for Queue<K, V> {
+    fn default() -> Self {
+        Self { _type: PhantomData }
+    }
+}
+
+impl<K: KeyFormat + From<u64>, V: Serialize + DeserializeOwned> Queue<K, V> {
+    /// Create a new queue handle.
+    pub fn new() -> Self {
+        Self { _type: PhantomData }
+    }
+
+    fn length_key() -> Vec<u8> {
+        vec![K::prefix()]
+    }
+
+    /// Return the number of items in the queue.
+    pub fn len(&self, ctx: Context, mkvs: &dyn MKVS) -> Result<u64> {
+        match mkvs.get(ctx, &Self::length_key())? {
+            Some(value) => decode_counter(&value),
+            None => Ok(0),
+        }
+    }
+
+    /// Check whether the queue is empty.
+    pub fn is_empty(&self, ctx: Context, mkvs: &dyn MKVS) -> Result<bool> {
+        Ok(self.len(ctx, mkvs)? == 0)
+    }
+
+    /// Append an item to the queue and return its index.
+    pub fn push(&self, ctx: Context, mkvs: &mut dyn MKVS, value: &V) -> Result<u64> {
+        let ctx = ctx.freeze();
+        let index = self.len(Context::create_child(&ctx), mkvs)?;
+        let length = index
+            .checked_add(1)
+            .ok_or(CollectionError::CounterOverflow)?;
+        mkvs.insert(
+            Context::create_child(&ctx),
+            &K::from(index).encode(),
+            &cbor::to_vec(value),
+        )?;
+        mkvs.insert(
+            Context::create_child(&ctx),
+            &Self::length_key(),
+            &encode_counter(length),
+        )?;
+
+        Ok(index)
+    }
+
+    /// Fetch the item at the given index.
+    pub fn get(&self, ctx: Context, mkvs: &dyn MKVS, index: u64) -> Result<Option<V>> {
+        mkvs.get(ctx, &K::from(index).encode())?
+            .map(|value| decode_value(&value))
+            .transpose()
+    }
+
+    /// Iterate over all items in the order they were appended.
+    pub fn iter<'a>(&self, ctx: Context, mkvs: &'a dyn MKVS) -> impl Iterator<Item = Result<V>> + 'a
+    where
+        K: 'a,
+        V: 'a,
+    {
+        // Skip the queue length which is stored under the bare prefix.
+        mkvs.prefix(ctx, &Self::length_key())
+            .filter(|item| !matches!(item, Ok((key, _)) if key.len() == 1))
+            .map(|item| item.and_then(|(_, value)| decode_value(&value)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        common::crypto::hash::Hash,
+        storage::mkvs::{sync::NoopReadSyncer, Tree},
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct NameKeyFormat {
+        name: Vec<u8>,
+    }
+
+    impl KeyFormat for NameKeyFormat {
+        fn prefix() -> u8 {
+            'N' as u8
+        }
+
+        fn size() -> usize {
+            0
+        }
+
+        fn encode_atoms(self, atoms: &mut Vec<Vec<u8>>) {
+            atoms.push(self.name);
+        }
+
+        fn decode_atoms(data: &[u8]) -> Self {
+            Self {
+                name: data.to_vec(),
+            }
+        }
+    }
+
+    fn name(name: &str) -> NameKeyFormat {
+        NameKeyFormat {
+            name: name.as_bytes().to_vec(),
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct HashKeyFormat {
+        hash: Hash,
+    }
+
+    impl KeyFormat for HashKeyFormat {
+        fn prefix() -> u8 {
+            'H' as u8
+        }
+
+        fn size() -> usize {
+            32
+        }
+
+        fn encode_atoms(self, atoms: &mut Vec<Vec<u8>>) {
+            atoms.push(self.hash.as_ref().to_vec());
+        }
+
+        fn decode_atoms(data: &[u8]) -> Self {
+            Self {
+                hash: data[..32].into(),
+            }
+        }
+    }
+
+    struct IndexKeyFormat {
+        index: u64,
+    }
+
+    impl From<u64> for IndexKeyFormat {
+        fn from(index: u64) -> Self {
+            Self { index }
+        }
+    }
+
+    impl KeyFormat for IndexKeyFormat {
+        fn prefix() -> u8 {
+            'Q' as u8
+        }
+
+        fn size() -> usize {
+            8
+        }
+
+        fn encode_atoms(self, atoms: &mut Vec<Vec<u8>>) {
+            atoms.push(encode_counter(self.index));
+        }
+
+        fn decode_atoms(data: &[u8]) -> Self {
+            Self {
+                index: BigEndian::read_u64(&data[..8]),
+            }
+        }
+    }
+
+    fn make_tree() -> Tree {
+        Tree::make().new(Box::new(NoopReadSyncer))
+    }
+
+    #[test]
+    fn test_map() {
+        let mut tree = make_tree();
+        let map: Map<NameKeyFormat, (u64, String)> = Map::new();
+
+        assert_eq!(
+            map.insert(
+                Context::background(),
+                &mut tree,
+                name("b"),
+                &(2, "two".into())
+            )
+            .unwrap(),
+            None
+        );
+        map.insert(
+            Context::background(),
+            &mut tree,
+            name("a"),
+            &(1, "one".into()),
+        )
+        .unwrap();
+        map.insert(
+            Context::background(),
+            &mut tree,
+            name("c"),
+            &(3, "three".into()),
+        )
+        .unwrap();
+        assert_eq!(
+            map.insert(
+                Context::background(),
+                &mut tree,
+                name("b"),
+                &(4, "four".into())
+            )
+            .unwrap(),
+            Some((2, "two".into()))
+        );
+        assert_eq!(
+            map.get(Context::background(), &tree, name("b")).unwrap(),
+            Some((4, "four".into()))
+        );
+        assert!(!map
+            .contains_key(Context::background(), &tree, name("d"))
+            .unwrap());
+        assert_eq!(
+            map.remove(Context::background(), &mut tree, name("c"))
+                .unwrap(),
+            Some((3, "three".into()))
+        );
+
+        // Entries of other collections are not included.
+        let set: Set<HashKeyFormat> = Set::new();
+        set.insert(
+            Context::background(),
+            &mut tree,
+            HashKeyFormat {
+                hash: Hash::empty_hash(),
+            },
+        )
+        .unwrap();
+
+        let items: Vec<_> = map
+            .iter(Context::background(), &tree)
+            .map(|item| item.unwrap())
+            .collect();
+        assert_eq!(
+            items,
+            vec![
+                (name("a"), (1, "one".into())),
+                (name("b"), (4, "four".into()))
+            ]
+        );
+
+        // Values which cannot be decoded are reported as errors.
+        tree.insert(Context::background(), b"Nbad", b"\xff")
+            .unwrap();
+        let error = map
+            .get(Context::background(), &tree, name("bad"))
+            .expect_err("malformed value");
+        assert!(matches!(
+            error.downcast_ref::<CollectionError>(),
+            Some(CollectionError::MalformedValue)
+        ));
+    }
+
+    #[test]
+    fn test_set() {
+        let mut tree = make_tree();
+        let set: Set<HashKeyFormat> = Set::new();
+        let key = |data: &[u8]| HashKeyFormat {
+            hash: Hash::digest_bytes(data),
+        };
+
+        assert!(set
+            .insert(Context::background(), &mut tree, key(b"a"))
+            .unwrap());
+        assert!(set
+            .insert(Context::background(), &mut tree, key(b"b"))
+            .unwrap());
+        assert!(!set
+            .insert(Context::background(), &mut tree, key(b"a"))
+            .unwrap());
+        assert!(set
+            .contains(Context::background(), &tree, key(b"a"))
+            .unwrap());
+        assert!(set
+            .remove(Context::background(), &mut tree, key(b"a"))
+            .unwrap());
+        assert!(!set
+            .remove(Context::background(), &mut tree, key(b"a"))
+            .unwrap());
+        assert!(!set
+            .contains(Context::background(), &tree, key(b"a"))
+            .unwrap());
+
+        // Keys which are too short for the key format are reported as errors.
+        tree.insert(Context::background(), b"Hshort", b"").unwrap();
+        let items: Vec<_> = set.iter(Context::background(), &tree).collect();
+        assert_eq!(items.len(), 2);
+        assert!(items.iter().any(|item| item.is_err()));
+        assert!(items
+            .iter()
+            .any(|item| matches!(item, Ok(k) if *k == key(b"b"))));
+    }
+
+    #[test]
+    fn test_counter() {
+        let mut tree = make_tree();
+        let counter: Counter<NameKeyFormat> = Counter::new();
+
+        assert_eq!(
+            counter
+                .get(Context::background(), &tree, name("a"))
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            counter
+                .increment(Context::background(), &mut tree, name("a"), 5)
+                .unwrap(),
+            5
+        );
+        assert_eq!(
+            counter
+                .increment(Context::background(), &mut tree, name("b"), 1)
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            counter
+                .decrement(Context::background(), &mut tree, name("a"), 2)
+                .unwrap(),
+            3
+        );
+        let error = counter
+            .decrement(Context::background(), &mut tree, name("a"), 4)
+            .expect_err("underflow");
+        assert!(matches!(
+            error.downcast_ref::<CollectionError>(),
+            Some(CollectionError::CounterUnderflow)
+        ));
+        let error = counter
+            .increment(
+                Context::background(),
+                &mut tree,
+                name("a"),
+                u64::max_value(),
+            )
+            .expect_err("overflow");
+        assert!(matches!(
+            error.downcast_ref::<CollectionError>(),
+            Some(CollectionError::CounterOverflow)
+        ));
+
+        // Counters which reach zero are removed.
+        counter
+            .decrement(Context::background(), &mut tree, name("b"), 1)
+            .unwrap();
+        let items: Vec<_> = counter
+            .iter(Context::background(), &tree)
+            .map(|item| item.unwrap())
+            .collect();
+        assert_eq!(items, vec![(name("a"), 3)]);
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut tree = make_tree();
+        let queue: Queue<IndexKeyFormat, String> = Queue::new();
+
+        assert!(queue.is_empty(Context::background(), &tree).unwrap());
+        for idx in 0..300 {
+            assert_eq!(
+                queue
+                    .push(Context::background(), &mut tree, &format!("item {}", idx))
+                    .unwrap(),
+                idx
+            );
+        }
+        assert_eq!(queue.len(Context::background(), &tree).unwrap(), 300);
+        assert_eq!(
+            queue.get(Context::background(), &tree, 257).unwrap(),
+            Some("item 257".to_owned())
+        );
+        assert_eq!(queue.get(Context::background(), &tree, 300).unwrap(), None);
+
+        let items: Vec<_> = queue
+            .iter(Context::background(), &tree)
+            .map(|item| item.unwrap())
+            .collect();
+        assert_eq!(items.len(), 300);
+        for (idx, item) in items.iter().enumerate() {
+            assert_eq!(item, &format!("item {}", idx));
+        }
+    }
+}
@@ -0,0 +1,848 @@
+//! Confidential storage.
+//!
+//! A wrapper which transparently hashes all keys and encrypts all values
+//! stored in an underlying MKVS using Deoxys-II.
+use anyhow::Result;
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use hmac::{Hmac, Mac, NewMac};
+use io_context::Context;
+use sha2::Sha512Trunc256;
+use thiserror::Error;
+use zeroize::Zeroize;
+
+use super::mkvs::{Iterator as MKVSIterator, Prefix, Range, WriteLog, MKVS};
+use crate::common::{
+    crypto::{
+        hash::Hash,
+        mrae::deoxysii::{DeoxysII, KEY_SIZE, NONCE_SIZE, TAG_SIZE},
+    },
+    roothash::Namespace,
+};
+
+type Kdf = Hmac<Sha512Trunc256>;
+
+/// Size of the keyed hash of each key segment.
+const SEGMENT_HASH_SIZE: usize = 16;
+/// Size of the key length prefix in sealed values.
+const KEY_LENGTH_SIZE: usize = 4;
+
+#[derive(Error, Debug)]
+pub enum ConfidentialStoreError {
+    #[error("confidential store: malformed ciphertext")]
+    MalformedCiphertext,
+    #[error("confidential store: decryption failed")]
+    DecryptionFailed,
+    #[error("confidential store: range scans are not supported, use prefix scans")]
+    RangeNotSupported,
+    #[error("confidential store: prefix must consist of whole key segments")]
+    UnalignedPrefix,
+}
+
+/// Derive a subkey for the given purpose from the state key.
+fn derive_key(state_key: &[u8; KEY_SIZE], context: &[u8]) -> [u8; KEY_SIZE] {
+    let mut kdf = Kdf::new_varkey(state_key).expect("Hmac::new_varkey");
+    kdf.update(context);
+
+    let mut key = [0u8; KEY_SIZE];
+    key.copy_from_slice(&kdf.finalize().into_bytes()[..KEY_SIZE]);
+    key
+}
+
+/// An MKVS wrapper which encrypts all keys and values.
+///
+/// Keys are replaced by keyed hashes, so the underlying MKVS only learns
+/// whether two operations use the same key. By default the whole key is
+/// hashed at once. Use `with_segment_size` to instead hash each segment of
+/// the given size chained with the hash of the preceding segments, so that
+/// keys sharing a prefix of whole segments share a prefix of hashes and
+/// prefix scans remain possible. This reveals the number of segments in each
+/// key and which keys share leading segments. As the order of hashed keys is
+/// unrelated to the order of the keys, iteration returns entries in hashed
+/// key order and range scans with arbitrary bounds are not supported.
+///
+/// Values are sealed together with their key using Deoxys-II, with the
+/// hashed key as additional data so values cannot be moved between keys.
+/// Nonces are derived deterministically from the round, the hashed key and
+/// a counter, so all nodes executing the same operations produce the same
+/// ciphertexts.
+pub struct ConfidentialStore<M: MKVS> {
+    inner: M,
+    d2: DeoxysII,
+    key_mac: Kdf,
+    nonce_mac: Kdf,
+    segment_size: usize,
+    round: u64,
+    counter: u64,
+}
+
+impl<M: MKVS> ConfidentialStore<M> {
+    /// Create a new confidential store using the given state key.
+    ///
+    /// The round is used when deriving nonces and must be the round in which
+    /// the updates are committed. When executing a batch on top of the latest
+    /// block, this is the round following the one in the block's header. The
+    /// nonce counter must not repeat within a round, so when multiple stores
+    /// are used during the same round (e.g., one for each transaction in a
+    /// batch), each store must continue from the counter of the previous one
+    /// using `with_counter` and `counter`.
+    pub fn new(inner: M, state_key: &[u8; KEY_SIZE], round: u64) -> Self {
+        let mut value_key = derive_key(state_key, b"oasis-core/confidential-store: value");
+        let mut key_key = derive_key(state_key, b"oasis-core/confidential-store: key");
+        let mut nonce_key = derive_key(state_key, b"oasis-core/confidential-store: nonce");
+
+        let store = Self {
+            inner,
+            d2: DeoxysII::new(&value_key),
+            key_mac: Kdf::new_varkey(&key_key).expect("Hmac::new_varkey"),
+            nonce_mac: Kdf::new_varkey(&nonce_key).expect("Hmac::new_varkey"),
+            segment_size: 0,
+            round,
+            counter: 0,
+        };
+
+        value_key.zeroize();
+        key_key.zeroize();
+        nonce_key.zeroize();
+
+        store
+    }
+
+    /// Hash keys in segments of the given size, enabling scans over
+    /// prefixes which consist of whole segments.
+    ///
+    /// All stores accessing the same entries must use the same segment size.
+    pub fn with_segment_size(mut self, segment_size: usize) -> Self {
+        self.segment_size = segment_size;
+        self
+    }
+
+    /// Set the nonce counter to continue from.
+    pub fn with_counter(mut self, counter: u64) -> Self {
+        self.counter = counter;
+        self
+    }
+
+    /// Return the current nonce counter.
+    pub fn counter(&self) -> u64 {
+        self.counter
+    }
+
+    /// Return a reference to the underlying MKVS.
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    /// Return the underlying MKVS.
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Hash the given key segments, each chained with the hash of the
+    /// preceding segments.
+    fn hash_segments<'k, I: IntoIterator<Item = &'k [u8]>>(&self, segments: I) -> Vec<u8> {
+        let mut hashed = Vec::new();
+        let mut digest = [0u8; SEGMENT_HASH_SIZE];
+        for segment in segments {
+            let mut mac = self.key_mac.clone();
+            mac.update(&digest);
+            mac.update(segment);
+            digest.copy_from_slice(&mac.finalize().into_bytes()[..SEGMENT_HASH_SIZE]);
+            hashed.extend_from_slice(&digest);
+        }
+        hashed
+    }
+
+    /// Hash a key.
+    fn hash_key(&self, key: &[u8]) -> Vec<u8> {
+        if self.segment_size == 0 || key.is_empty() {
+            return self.hash_segments(vec![key]);
+        }
+        self.hash_segments(key.chunks(self.segment_size))
+    }
+
+    /// Hash a prefix, which must consist of whole key segments.
+    fn hash_prefix(&self, prefix: &[u8]) -> Result<Vec<u8>> {
+        if prefix.is_empty() {
+            return Ok(Vec::new());
+        }
+        if self.segment_size == 0 || prefix.len() % self.segment_size != 0 {
+            return Err(ConfidentialStoreError::UnalignedPrefix.into());
+        }
+        Ok(self.hash_segments(prefix.chunks(self.segment_size)))
+    }
+
+    /// Hash prefixes, which must all consist of whole key segments.
+    fn hash_prefixes(&self, prefixes: &[Prefix]) -> Result<Vec<Prefix>> {
+        prefixes
+            .iter()
+            .map(|prefix| Ok(Prefix::from(self.hash_prefix(prefix)?)))
+            .collect()
+    }
+
+    fn derive_nonce(&mut self, hashed_key: &[u8]) -> [u8; NONCE_SIZE] {
+        let mut mac = self.nonce_mac.clone();
+        let mut data = Vec::with_capacity(16 + hashed_key.len());
+        data.write_u64::<BigEndian>(self.round).unwrap();
+        data.write_u64::<BigEndian>(self.counter).unwrap();
+        data.extend_from_slice(hashed_key);
+        mac.update(&data);
+        self.counter += 1;
+
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce.copy_from_slice(&mac.finalize().into_bytes()[..NONCE_SIZE]);
+        nonce
+    }
+
+    /// Encrypt a key and value as `ciphertext || tag || nonce`.
+    fn seal(&mut self, hashed_key: &[u8], key: &[u8], value: &[u8]) -> Vec<u8> {
+        let mut plaintext = Vec::with_capacity(KEY_LENGTH_SIZE + key.len() + value.len());
+        plaintext.write_u32::<BigEndian>(key.len() as u32).unwrap();
+        plaintext.extend_from_slice(key);
+        plaintext.extend_from_slice(value);
+
+        let nonce = self.derive_nonce(hashed_key);
+        let mut ciphertext = self.d2.seal(&nonce, plaintext, hashed_key.to_vec());
+        ciphertext.extend_from_slice(&nonce);
+        ciphertext
+    }
+
+    /// Decrypt a key and value encrypted using `seal`.
+    fn open(&self, hashed_key: &[u8], ciphertext: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+        if ciphertext.len() < TAG_SIZE + NONCE_SIZE {
+            return Err(ConfidentialStoreError::MalformedCiphertext.into());
+        }
+
+        let nonce_offset = ciphertext.len() - NONCE_SIZE;
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce.copy_from_slice(&ciphertext[nonce_offset..]);
+
+        let mut plaintext = self
+            .d2
+            .open(
+                &nonce,
+                ciphertext[..nonce_offset].to_vec(),
+                hashed_key.to_vec(),
+            )
+            .map_err(|_| ConfidentialStoreError::DecryptionFailed)?;
+        if plaintext.len() < KEY_LENGTH_SIZE {
+            return Err(ConfidentialStoreError::MalformedCiphertext.into());
+        }
+        let key_length = BigEndian::read_u32(&plaintext[..KEY_LENGTH_SIZE]) as usize;
+        if plaintext.len() < KEY_LENGTH_SIZE + key_length {
+            return Err(ConfidentialStoreError::MalformedCiphertext.into());
+        }
+
+        let value = plaintext.split_off(KEY_LENGTH_SIZE + key_length);
+        let key = plaintext.split_off(KEY_LENGTH_SIZE);
+        Ok((key, value))
+    }
+
+    fn open_value(
+        &self,
+        hashed_key: &[u8],
+        ciphertext: Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>> {
+        ciphertext
+            .map(|ciphertext| Ok(self.open(hashed_key, &ciphertext)?.1))
+            .transpose()
+    }
+}
+
+impl<M: MKVS> MKVS for ConfidentialStore<M> {
+    fn get(&self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let hashed_key = self.hash_key(key);
+        let ciphertext = self.inner.get(ctx, &hashed_key)?;
+        self.open_value(&hashed_key, ciphertext)
+    }
+
+    fn cache_contains_key(&self, ctx: Context, key: &[u8]) -> bool {
+        self.inner.cache_contains_key(ctx, &self.hash_key(key))
+    }
+
+    fn insert(&mut self, ctx: Context, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        let hashed_key = self.hash_key(key);
+        let ciphertext = self.seal(&hashed_key, key, value);
+        let previous = self.inner.insert(ctx, &hashed_key, &ciphertext)?;
+        self.open_value(&hashed_key, previous)
+    }
+
+    fn remove(&mut self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let hashed_key = self.hash_key(key);
+        let previous = self.inner.remove(ctx, &hashed_key)?;
+        self.open_value(&hashed_key, previous)
+    }
+
+    /// All prefixes must consist of whole key segments, otherwise nothing is
+    /// prefetched and an error is returned.
+    fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16) -> Result<()> {
+        let prefixes = self.hash_prefixes(prefixes)?;
+        self.inner.prefetch_prefixes(ctx, &prefixes, limit)
+    }
+
+    /// All prefixes must consist of whole key segments, otherwise nothing is
+    /// prefetched and an error is returned.
+    fn prefetch_all_prefixes(
+        &self,
+        ctx: Context,
+        prefixes: &Vec<Prefix>,
+        page_size: u16,
+    ) -> Result<()> {
+        let prefixes = self.hash_prefixes(prefixes)?;
+        self.inner.prefetch_all_prefixes(ctx, &prefixes, page_size)
+    }
+
+    /// Returns an iterator over all entries in hashed key order.
+    fn iter(&self, ctx: Context) -> Box<dyn MKVSIterator + '_> {
+        Box::new(ConfidentialIterator::new(self, self.inner.iter(ctx), None))
+    }
+
+    /// Range scans are not supported as entries are not stored in key order.
+    ///
+    /// The returned range only yields an error.
+    fn range(&self, ctx: Context, start: &[u8], end: Option<&[u8]>) -> Range<'_> {
+        let mut it = ConfidentialIterator::new(self, self.inner.iter(ctx), None);
+        it.error = Some(ConfidentialStoreError::RangeNotSupported.into());
+        Range::new(Box::new(it), start, end)
+    }
+
+    /// Returns an iterator over entries with keys starting with the given
+    /// prefix, in hashed key order.
+    ///
+    /// The prefix must consist of whole key segments, otherwise the returned
+    /// range only yields an error.
+    fn prefix(&self, ctx: Context, prefix: &[u8]) -> Range<'_> {
+        let it = match self.hash_prefix(prefix) {
+            Ok(hashed_prefix) => {
+                ConfidentialIterator::new(self, self.inner.iter(ctx), Some(hashed_prefix))
+            }
+            Err(error) => {
+                let mut it = ConfidentialIterator::new(self, self.inner.iter(ctx), None);
+                it.error = Some(error);
+                it
+            }
+        };
+        Range::new_prefix(Box::new(it), prefix)
+    }
+
+    fn commit(
+        &mut self,
+        ctx: Context,
+        namespace: Namespace,
+        version: u64,
+    ) -> Result<(WriteLog, Hash)> {
+        self.inner.commit(ctx, namespace, version)
+    }
+
+    fn rollback(&mut self) {
+        self.inner.rollback()
+    }
+}
+
+/// An iterator which decrypts entries of the underlying MKVS.
+struct ConfidentialIterator<'a, M: MKVS> {
+    store: &'a ConfidentialStore<M>,
+    inner: Box<dyn MKVSIterator + 'a>,
+    /// Hashed prefix which all returned keys must start with.
+    prefix: Option<Vec<u8>>,
+    next: Option<(Vec<u8>, Vec<u8>)>,
+    error: Option<anyhow::Error>,
+}
+
+impl<'a, M: MKVS> ConfidentialIterator<'a, M> {
+    fn new(
+        store: &'a ConfidentialStore<M>,
+        inner: Box<dyn MKVSIterator + 'a>,
+        prefix: Option<Vec<u8>>,
+    ) -> Self {
+        Self {
+            store,
+            inner,
+            prefix,
+            next: None,
+            error: None,
+        }
+    }
+
+    fn advance(&mut self) {
+        self.next = None;
+        if self.error.is_some() {
+            return;
+        }
+
+        let (hashed_key, ciphertext) = match self.inner.next() {
+            Some(item) => item,
+            None => return,
+        };
+        if let Some(ref prefix) = self.prefix {
+            if !hashed_key.starts_with(prefix) {
+                return;
+            }
+        }
+        match self.store.open(&hashed_key, &ciphertext) {
+            Ok(item) => self.next = Some(item),
+            Err(error) => self.error = Some(error),
+        }
+    }
+}
+
+impl<'a, M: MKVS> Iterator for ConfidentialIterator<'a, M> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.next.take()?;
+        self.advance();
+        Some(item)
+    }
+}
+
+impl<'a, M: MKVS> MKVSIterator for ConfidentialIterator<'a, M> {
+    fn set_prefetch(&mut self, prefetch: usize) {
+        self.inner.set_prefetch(prefetch)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.next.is_some()
+    }
+
+    fn error(&self) -> &Option<anyhow::Error> {
+        match self.error {
+            Some(_) => &self.error,
+            None => self.inner.error(),
+        }
+    }
+
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        match self.error.take() {
+            Some(error) => Some(error),
+            None => self.inner.take_error(),
+        }
+    }
+
+    fn rewind(&mut self) {
+        match self.prefix {
+            Some(ref prefix) => self.inner.seek(prefix),
+            None => self.inner.rewind(),
+        }
+        self.advance();
+    }
+
+    /// As entries are not stored in key order, seeking within a prefix
+    /// moves to the start of the prefix and seeking to other keys moves to
+    /// the given key if it exists. If the key does not exist, the iterator
+    /// is invalidated as the following hashed keys are unrelated to it.
+    fn seek(&mut self, key: &[u8]) {
+        match self.prefix {
+            Some(ref prefix) => {
+                self.inner.seek(prefix);
+                self.advance();
+            }
+            None => {
+                self.inner.seek(&self.store.hash_key(key));
+                self.advance();
+                match self.next {
+                    Some((ref next_key, _)) if next_key.as_slice() == key => {}
+                    _ => self.next = None,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::storage::mkvs::{sync::NoopReadSyncer, Tree};
+
+    const STATE_KEY: [u8; KEY_SIZE] = [42u8; KEY_SIZE];
+
+    /// An MKVS which only records prefetched prefixes.
+    #[derive(Default)]
+    struct PrefetchRecorder {
+        prefixes: Mutex<Vec<Prefix>>,
+    }
+
+    impl MKVS for PrefetchRecorder {
+        fn get(&self, _ctx: Context, _key: &[u8]) -> Result<Option<Vec<u8>>> {
+            unimplemented!()
+        }
+
+        fn cache_contains_key(&self, _ctx: Context, _key: &[u8]) -> bool {
+            unimplemented!()
+        }
+
+        fn insert(&mut self, _ctx: Context, _key: &[u8], _value: &[u8]) -> Result<Option<Vec<u8>>> {
+            unimplemented!()
+        }
+
+        fn remove(&mut self, _ctx: Context, _key: &[u8]) -> Result<Option<Vec<u8>>> {
+            unimplemented!()
+        }
+
+        fn prefetch_prefixes(
+            &self,
+            _ctx: Context,
+            prefixes: &Vec<Prefix>,
+            _limit: u16,
+        ) -> Result<()> {
+            self.prefixes.lock().unwrap().extend_from_slice(prefixes);
+            Ok(())
+        }
+
+        fn prefetch_all_prefixes(
+            &self,
+            ctx: Context,
+            prefixes: &Vec<Prefix>,
+            page_size: u16,
+        ) -> Result<()> {
+            self.prefetch_prefixes(ctx, prefixes, page_size)
+        }
+
+        fn iter(&self, _ctx: Context) -> Box<dyn MKVSIterator + '_> {
+            unimplemented!()
+        }
+
+        fn commit(
+            &mut self,
+            _ctx: Context,
+            _namespace: Namespace,
+            _version: u64,
+        ) -> Result<(WriteLog, Hash)> {
+            unimplemented!()
+        }
+
+        fn rollback(&mut self) {
+            unimplemented!()
+        }
+    }
+
+    fn make_store() -> ConfidentialStore<Tree> {
+        ConfidentialStore::new(Tree::make().new(Box::new(NoopReadSyncer)), &STATE_KEY, 1)
+    }
+
+    #[test]
+    fn test_confidential_store() {
+        let mut store = make_store();
+        assert_eq!(
+            store.insert(Context::background(), b"foo", b"bar").unwrap(),
+            None
+        );
+        assert_eq!(
+            store.insert(Context::background(), b"foo", b"baz").unwrap(),
+            Some(b"bar".to_vec())
+        );
+        assert_eq!(
+            store.get(Context::background(), b"foo").unwrap(),
+            Some(b"baz".to_vec())
+        );
+        assert_eq!(store.get(Context::background(), b"moo").unwrap(), None);
+
+        // Neither keys nor values are stored in plain text.
+        let items: Vec<_> = store
+            .inner()
+            .prefix(Context::background(), b"")
+            .map(|item| item.unwrap())
+            .collect();
+        assert_eq!(items.len(), 1);
+        assert_ne!(items[0].0, b"foo".to_vec());
+        assert!(!items[0].1.windows(3).any(|window| window == &b"baz"[..]));
+
+        assert_eq!(
+            store.remove(Context::background(), b"foo").unwrap(),
+            Some(b"baz".to_vec())
+        );
+        assert_eq!(store.get(Context::background(), b"foo").unwrap(), None);
+    }
+
+    #[test]
+    fn test_confidential_store_deterministic() {
+        let mut a = make_store();
+        let mut b = make_store();
+        for store in vec![&mut a, &mut b] {
+            store.insert(Context::background(), b"foo", b"bar").unwrap();
+            store.insert(Context::background(), b"foo", b"bar").unwrap();
+            store.insert(Context::background(), b"moo", b"goo").unwrap();
+        }
+        let (_, hash_a) = a
+            .commit(Context::background(), Default::default(), 1)
+            .unwrap();
+        let (_, hash_b) = b
+            .commit(Context::background(), Default::default(), 1)
+            .unwrap();
+        assert_eq!(hash_a, hash_b, "same operations must produce the same root");
+
+        // Nonces differ between inserts of the same value.
+        let mut store = make_store();
+        store.insert(Context::background(), b"foo", b"bar").unwrap();
+        let first = store
+            .inner()
+            .get(Context::background(), &store.hash_key(b"foo"));
+        store.insert(Context::background(), b"foo", b"bar").unwrap();
+        let second = store
+            .inner()
+            .get(Context::background(), &store.hash_key(b"foo"));
+        assert_ne!(first.unwrap(), second.unwrap());
+
+        // A different state key produces different ciphertexts.
+        let other = ConfidentialStore::new(
+            Tree::make().new(Box::new(NoopReadSyncer)),
+            &[1u8; KEY_SIZE],
+            1,
+        );
+        assert_ne!(other.hash_key(b"foo"), a.hash_key(b"foo"));
+    }
+
+    #[test]
+    fn test_confidential_store_counter() {
+        // Stores used one after another in the same round continue the
+        // counter, so the same update does not repeat the nonce.
+        let mut store = make_store();
+        store.insert(Context::background(), b"foo", b"bar").unwrap();
+        let counter = store.counter();
+        let first = store.into_inner();
+
+        let mut store = make_store().with_counter(counter);
+        store.insert(Context::background(), b"foo", b"bar").unwrap();
+        assert_eq!(store.counter(), counter + 1);
+        let second = store.into_inner();
+
+        let key = make_store().hash_key(b"foo");
+        assert_ne!(
+            first.get(Context::background(), &key).unwrap(),
+            second.get(Context::background(), &key).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_confidential_store_keys() {
+        let store = make_store();
+        assert_eq!(store.hash_key(b"foo").len(), SEGMENT_HASH_SIZE);
+        assert_eq!(store.hash_key(b"").len(), SEGMENT_HASH_SIZE);
+        assert_ne!(store.hash_key(b""), store.hash_key(b"foo"));
+
+        // Without segments, keys with a common prefix are unrelated.
+        let a = store.hash_key(b"a/1");
+        let b = store.hash_key(b"a/2");
+        assert_ne!(a[..8], b[..8]);
+
+        // With segments, keys only share the hashes of whole common segments.
+        let store = make_store().with_segment_size(2);
+        let a = store.hash_key(b"a/10");
+        let b = store.hash_key(b"a/11");
+        let c = store.hash_key(b"a/1");
+        assert_eq!(a.len(), 2 * SEGMENT_HASH_SIZE);
+        assert_eq!(c.len(), 2 * SEGMENT_HASH_SIZE);
+        assert_eq!(a[..SEGMENT_HASH_SIZE], b[..SEGMENT_HASH_SIZE]);
+        assert_eq!(a[..SEGMENT_HASH_SIZE], c[..SEGMENT_HASH_SIZE]);
+        assert_ne!(a[SEGMENT_HASH_SIZE..], b[SEGMENT_HASH_SIZE..]);
+        assert_ne!(a[SEGMENT_HASH_SIZE..], c[SEGMENT_HASH_SIZE..]);
+        assert_ne!(
+            store.hash_key(b"b/10")[SEGMENT_HASH_SIZE..],
+            a[SEGMENT_HASH_SIZE..]
+        );
+    }
+
+    #[test]
+    fn test_confidential_store_prefix() {
+        let mut store = make_store().with_segment_size(2);
+        for idx in 0..50 {
+            store
+                .insert(
+                    Context::background(),
+                    format!("a/{}", idx).as_bytes(),
+                    format!("value {}", idx).as_bytes(),
+                )
+                .unwrap();
+            store
+                .insert(
+                    Context::background(),
+                    format!("b/{}", idx).as_bytes(),
+                    b"other",
+                )
+                .unwrap();
+        }
+
+        let mut items: Vec<_> = store
+            .prefix(Context::background(), b"a/")
+            .map(|item| item.expect("prefix scan"))
+            .collect();
+        items.sort();
+        let mut expected: Vec<_> = (0..50)
+            .map(|idx| {
+                (
+                    format!("a/{}", idx).into_bytes(),
+                    format!("value {}", idx).into_bytes(),
+                )
+            })
+            .collect();
+        expected.sort();
+        assert_eq!(items, expected);
+
+        let items: Vec<_> = store
+            .prefix(Context::background(), b"a/10")
+            .map(|item| item.expect("prefix scan"))
+            .collect();
+        assert_eq!(items, vec![(b"a/10".to_vec(), b"value 10".to_vec())]);
+        assert_eq!(store.prefix(Context::background(), b"").count(), 100);
+
+        assert_eq!(store.iter(Context::background()).count(), 0);
+        let mut it = store.iter(Context::background());
+        it.rewind();
+        assert_eq!(it.count(), 100);
+
+        let error = store
+            .prefix(Context::background(), b"a")
+            .next()
+            .expect("prefix should return an error")
+            .expect_err("unaligned prefixes are not supported");
+        assert!(matches!(
+            error.downcast_ref::<ConfidentialStoreError>(),
+            Some(ConfidentialStoreError::UnalignedPrefix)
+        ));
+
+        let mut range = store.range(Context::background(), b"a/", Some(b"b/"));
+        let error = range
+            .next()
+            .expect("range should return an error")
+            .expect_err("range scans are not supported");
+        assert!(matches!(
+            error.downcast_ref::<ConfidentialStoreError>(),
+            Some(ConfidentialStoreError::RangeNotSupported)
+        ));
+        drop(range);
+
+        // Without segments, only the empty prefix is supported.
+        let store = ConfidentialStore::new(store.into_inner(), &STATE_KEY, 1);
+        assert!(store
+            .prefix(Context::background(), b"a/")
+            .next()
+            .expect("prefix should return an error")
+            .is_err());
+    }
+
+    #[test]
+    fn test_confidential_store_seek() {
+        let mut store = make_store();
+        for i in 0..10 {
+            let key = format!("key {}", i);
+            store
+                .insert(Context::background(), key.as_bytes(), key.as_bytes())
+                .unwrap();
+        }
+
+        // Seeking to an existing key moves to that key.
+        let mut it = store.iter(Context::background());
+        it.seek(b"key 5");
+        assert!(it.is_valid());
+        assert_eq!(it.next(), Some((b"key 5".to_vec(), b"key 5".to_vec())));
+
+        // Seeking to a missing key returns no entries rather than unrelated
+        // entries following its hashed key.
+        it.seek(b"key 10");
+        assert!(!it.is_valid());
+        assert_eq!(it.next(), None);
+        assert!(it.error().is_none());
+
+        // The iterator can be reused after a failed seek.
+        it.seek(b"key 0");
+        assert_eq!(it.next(), Some((b"key 0".to_vec(), b"key 0".to_vec())));
+    }
+
+    #[test]
+    fn test_confidential_store_prefetch_prefixes() {
+        let store =
+            ConfidentialStore::new(PrefetchRecorder::default(), &STATE_KEY, 1).with_segment_size(2);
+        store
+            .prefetch_prefixes(
+                Context::background(),
+                &vec![Prefix::from(b"a/".to_vec()), Prefix::from(vec![])],
+                10,
+            )
+            .unwrap();
+        assert_eq!(
+            *store.inner().prefixes.lock().unwrap(),
+            vec![
+                Prefix::from(store.hash_prefix(b"a/").unwrap()),
+                Prefix::from(vec![])
+            ]
+        );
+
+        // Prefixes which do not consist of whole segments are rejected rather
+        // than widened to the whole segments they contain.
+        for prefixes in vec![
+            vec![Prefix::from(b"a".to_vec())],
+            vec![Prefix::from(b"a/".to_vec()), Prefix::from(b"a/b".to_vec())],
+        ] {
+            let error = store
+                .prefetch_prefixes(Context::background(), &prefixes, 10)
+                .expect_err("unaligned prefixes are not supported");
+            assert!(matches!(
+                error.downcast_ref::<ConfidentialStoreError>(),
+                Some(ConfidentialStoreError::UnalignedPrefix)
+            ));
+        }
+        assert_eq!(store.inner().prefixes.lock().unwrap().len(), 2);
+
+        // Without segments, only the empty prefix is supported.
+        let store = ConfidentialStore::new(store.into_inner(), &STATE_KEY, 1);
+        assert!(store
+            .prefetch_prefixes(
+                Context::background(),
+                &vec![Prefix::from(b"a/".to_vec())],
+                10
+            )
+            .is_err());
+        assert_eq!(store.inner().prefixes.lock().unwrap().len(), 2);
+
+        // Paged prefetches map prefixes in the same way.
+        let store = store.with_segment_size(2);
+        store
+            .prefetch_all_prefixes(
+                Context::background(),
+                &vec![Prefix::from(b"a/".to_vec())],
+                10,
+            )
+            .unwrap();
+        assert_eq!(
+            store.inner().prefixes.lock().unwrap()[2],
+            Prefix::from(store.hash_prefix(b"a/").unwrap())
+        );
+        assert!(store
+            .prefetch_all_prefixes(
+                Context::background(),
+                &vec![Prefix::from(b"a".to_vec())],
+                10
+            )
+            .is_err());
+        assert_eq!(store.inner().prefixes.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_confidential_store_tampered() {
+        let mut store = make_store();
+        store.insert(Context::background(), b"foo", b"bar").unwrap();
+        store.insert(Context::background(), b"moo", b"goo").unwrap();
+
+        // Values cannot be moved between keys.
+        let foo = store.hash_key(b"foo");
+        let moo = store.hash_key(b"moo");
+        let ciphertext = store
+            .inner()
+            .get(Context::background(), &foo)
+            .unwrap()
+            .unwrap();
+        let mut inner = store.into_inner();
+        inner
+            .insert(Context::background(), &moo, &ciphertext)
+            .unwrap();
+        let store = ConfidentialStore::new(inner, &STATE_KEY, 1);
+        let error = store
+            .get(Context::background(), b"moo")
+            .expect_err("tampered value");
+        assert!(matches!(
+            error.downcast_ref::<ConfidentialStoreError>(),
+            Some(ConfidentialStoreError::DecryptionFailed)
+        ));
+    }
+}
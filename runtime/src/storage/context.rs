@@ -2,13 +2,29 @@
 //!
 //! The storage context is a convenient way to share CAS and MKVS
 //! implementations across the current thread.
-use std::{cell::RefCell, sync::Arc};
+use std::{cell::RefCell, mem, sync::Arc};
 
-use super::{KeyValue, MKVS};
+use anyhow::Result;
+use io_context::Context;
+use thiserror::Error;
+
+use super::{
+    mkvs::{Iterator as MKVSIterator, Prefix, WriteLog},
+    KeyValue, MKVS,
+};
+use crate::common::{crypto::hash::Hash, roothash::Namespace};
+
+/// Storage context errors.
+#[derive(Error, Debug)]
+pub enum ContextError {
+    #[error("storage context: updates are not allowed in read-only mode")]
+    ReadOnly,
+}
 
 struct Ctx {
     mkvs: *mut dyn MKVS,
     untrusted_local: Arc<dyn KeyValue>,
+    read_only: bool,
 }
 
 thread_local! {
@@ -27,6 +43,7 @@ impl CtxGuard {
             ctx.borrow_mut().replace(Ctx {
                 mkvs,
                 untrusted_local,
+                read_only: false,
             });
         });
 
@@ -42,6 +59,70 @@ impl Drop for CtxGuard {
     }
 }
 
+/// Guard restoring the previous read-only mode of the storage context.
+struct ReadOnlyGuard(bool);
+
+impl Drop for ReadOnlyGuard {
+    fn drop(&mut self) {
+        CTX.with(|ctx| {
+            if let Some(ctx) = ctx.borrow_mut().as_mut() {
+                ctx.read_only = self.0;
+            }
+        });
+    }
+}
+
+/// MKVS wrapper which rejects all updates.
+struct ReadOnlyMKVS<'a>(&'a mut dyn MKVS);
+
+impl<'a> MKVS for ReadOnlyMKVS<'a> {
+    fn get(&self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.0.get(ctx, key)
+    }
+
+    fn cache_contains_key(&self, ctx: Context, key: &[u8]) -> bool {
+        self.0.cache_contains_key(ctx, key)
+    }
+
+    fn insert(&mut self, _ctx: Context, _key: &[u8], _value: &[u8]) -> Result<Option<Vec<u8>>> {
+        Err(ContextError::ReadOnly.into())
+    }
+
+    fn remove(&mut self, _ctx: Context, _key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Err(ContextError::ReadOnly.into())
+    }
+
+    fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16) -> Result<()> {
+        self.0.prefetch_prefixes(ctx, prefixes, limit)
+    }
+
+    fn prefetch_all_prefixes(
+        &self,
+        ctx: Context,
+        prefixes: &Vec<Prefix>,
+        page_size: u16,
+    ) -> Result<()> {
+        self.0.prefetch_all_prefixes(ctx, prefixes, page_size)
+    }
+
+    fn iter(&self, ctx: Context) -> Box<dyn MKVSIterator + '_> {
+        self.0.iter(ctx)
+    }
+
+    fn commit(
+        &mut self,
+        _ctx: Context,
+        _namespace: Namespace,
+        _version: u64,
+    ) -> Result<(WriteLog, Hash)> {
+        Err(ContextError::ReadOnly.into())
+    }
+
+    /// There are no updates to roll back, so this must not discard the
+    /// updates made outside of read-only mode.
+    fn rollback(&mut self) {}
+}
+
 /// Thread-local storage context.
 pub struct StorageContext;
 
@@ -56,8 +137,33 @@ impl StorageContext {
         f()
     }
 
+    /// Run a closure with the thread-local storage context in read-only mode.
+    ///
+    /// While the closure runs, the MKVS passed by `with_current` rejects all
+    /// updates with `ContextError::ReadOnly`.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if called outside `StorageContext::enter` or from within
+    /// `StorageContext::with_current`.
+    pub fn read_only<F, R>(f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let previous = CTX.with(|ctx| {
+            let mut ctx = ctx.borrow_mut();
+            let ctx_ref = ctx.as_mut().expect("must only be called while entered");
+            mem::replace(&mut ctx_ref.read_only, true)
+        });
+        let _guard = ReadOnlyGuard(previous);
+        f()
+    }
+
     /// Run a closure with the thread-local storage context.
     ///
+    /// The closure's result is returned unchanged, so MKVS errors can be
+    /// propagated to the caller by returning a `Result`.
+    ///
     /// # Panics
     ///
     /// Will panic if called outside `StorageContext::enter`.
@@ -70,7 +176,83 @@ impl StorageContext {
             let ctx_ref = ctx.as_ref().expect("must only be called while entered");
             let mkvs_ref = unsafe { ctx_ref.mkvs.as_mut().expect("pointer is never null") };
 
-            f(mkvs_ref, &ctx_ref.untrusted_local)
+            if ctx_ref.read_only {
+                f(&mut ReadOnlyMKVS(mkvs_ref), &ctx_ref.untrusted_local)
+            } else {
+                f(mkvs_ref, &ctx_ref.untrusted_local)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::anyhow;
+
+    use super::*;
+    use crate::storage::mkvs::{sync::NoopReadSyncer, Tree};
+
+    struct NoopKeyValue;
+
+    impl KeyValue for NoopKeyValue {
+        fn get(&self, _key: Vec<u8>) -> Result<Vec<u8>> {
+            Err(anyhow!("not supported"))
+        }
+
+        fn insert(&self, _key: Vec<u8>, _value: Vec<u8>) -> Result<()> {
+            Err(anyhow!("not supported"))
+        }
+    }
+
+    fn insert(key: &[u8]) -> Result<Option<Vec<u8>>> {
+        StorageContext::with_current(|mkvs, _untrusted_local| {
+            mkvs.insert(Context::background(), key, b"value")
         })
     }
+
+    fn get(key: &[u8]) -> Result<Option<Vec<u8>>> {
+        StorageContext::with_current(|mkvs, _untrusted_local| mkvs.get(Context::background(), key))
+    }
+
+    #[test]
+    fn test_read_only() {
+        let mut mkvs = Tree::make().new(Box::new(NoopReadSyncer));
+        StorageContext::enter(&mut mkvs, Arc::new(NoopKeyValue), || {
+            insert(b"foo").unwrap();
+
+            StorageContext::read_only(|| {
+                // Reads are allowed.
+                assert_eq!(get(b"foo").unwrap(), Some(b"value".to_vec()));
+
+                // Updates are rejected, also in nested read-only mode.
+                let err = insert(b"bar").unwrap_err();
+                assert!(matches!(
+                    err.downcast_ref::<ContextError>(),
+                    Some(ContextError::ReadOnly)
+                ));
+                StorageContext::read_only(|| assert!(insert(b"bar").is_err()));
+                assert!(insert(b"bar").is_err());
+                let err = StorageContext::with_current(|mkvs, _untrusted_local| {
+                    mkvs.remove(Context::background(), b"foo")
+                })
+                .unwrap_err();
+                assert!(matches!(
+                    err.downcast_ref::<ContextError>(),
+                    Some(ContextError::ReadOnly)
+                ));
+
+                // Rollback must not discard updates made before.
+                StorageContext::with_current(|mkvs, _untrusted_local| mkvs.rollback());
+            });
+
+            // Updates are allowed again after leaving read-only mode.
+            assert_eq!(get(b"foo").unwrap(), Some(b"value".to_vec()));
+            assert_eq!(get(b"bar").unwrap(), None);
+            insert(b"bar").unwrap();
+        });
+        assert_eq!(
+            mkvs.get(Context::background(), b"bar").unwrap(),
+            Some(b"value".to_vec())
+        );
+    }
 }
@@ -3,41 +3,99 @@ use std::{any::Any, ptr::NonNull, sync::Arc};
 use anyhow::Result;
 use io_context::Context;
 
-use crate::storage::mkvs::{cache::lru_cache::CacheItemBox, sync::*, tree::*};
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{cache::lru_cache::CacheItemBox, sync::*, tree::*},
+};
 
-/// Statistics about the contents of the cache.
-#[derive(Debug, Default)]
+/// Statistics about the contents and usage of the cache.
+#[derive(Clone, Debug, Default)]
 pub struct CacheStats {
     /// Count of internal nodes held by the cache.
     pub internal_node_count: usize,
     /// Total size of values held by the cache.
     pub leaf_value_size: usize,
+    /// Memory used by internal nodes held by the cache, in bytes.
+    pub internal_node_bytes: usize,
+    /// Memory used by leaf nodes held by the cache, in bytes.
+    pub leaf_node_bytes: usize,
+    /// Number of node dereferences served from the cache.
+    pub hits: u64,
+    /// Number of node dereferences which required a remote fetch.
+    pub misses: u64,
+    /// Number of proofs fetched using the read syncer.
+    pub remote_syncs: u64,
+    /// Number of nodes evicted from the cache.
+    pub evictions: u64,
+}
+
+impl CacheStats {
+    /// Return the fraction of node dereferences served from the cache.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / total as f64
+    }
+}
+
+/// Policy used to select nodes for eviction when the cache is full.
+///
+/// Nodes are kept in separate lists for internal and leaf nodes and each list
+/// evicts on its own. Nodes in use by an ongoing operation, including the one
+/// currently being dereferenced, are never evicted. If only such nodes are
+/// left in a list, the dereferenced node is not cached. Adaptive policies
+/// which also track recently evicted nodes (e.g. ARC) are not provided.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Evict the least recently used node.
+    LRU,
+    /// Evict nodes in insertion order, but give nodes which have been used
+    /// since they were last considered for eviction a second chance. This
+    /// is cheaper than LRU and resistant to a single large scan.
+    SecondChance,
+    /// Evict the largest of the eight least recently used nodes, keeping
+    /// more small nodes in memory. Sizes are memory estimates, so this only
+    /// makes a difference when the values or labels differ in size.
+    SizeAware,
+}
+
+impl Default for EvictionPolicy {
+    fn default() -> Self {
+        EvictionPolicy::LRU
+    }
 }
 
 /// Used to fetch proofs from a remote tree via the ReadSyncer interface.
 pub trait ReadSyncFetcher {
     /// Fetch proof.
+    ///
+    /// The known nodes are hashes of nodes held by the cache, which should
+    /// be passed to the read syncer so it can omit them from the proof.
     fn fetch(
         &self,
         ctx: Context,
         root: Root,
         ptr: NodePtrRef,
+        known_nodes: &[Hash],
         rs: &mut Box<dyn ReadSync>,
     ) -> Result<Proof>;
 }
 
 impl<F> ReadSyncFetcher for F
 where
-    F: Fn(Context, Root, NodePtrRef, &mut Box<dyn ReadSync>) -> Result<Proof>,
+    F: Fn(Context, Root, NodePtrRef, &[Hash], &mut Box<dyn ReadSync>) -> Result<Proof>,
 {
     fn fetch(
         &self,
         ctx: Context,
         root: Root,
         ptr: NodePtrRef,
+        known_nodes: &[Hash],
         rs: &mut Box<dyn ReadSync>,
     ) -> Result<Proof> {
-        (*self)(ctx, root, ptr, rs)
+        (*self)(ctx, root, ptr, known_nodes, rs)
     }
 }
 
@@ -133,6 +191,15 @@ where
     fn set_cache_extra(&mut self, new_val: CacheExtra<Item>);
     /// Return the size, in bytes, of the item when cached.
     fn get_cached_size(&self) -> usize;
+    /// Return the approximate memory, in bytes, used by the item.
+    fn get_memory_size(&self) -> usize {
+        self.get_cached_size()
+    }
+    /// Check whether the item is in use by an ongoing operation, so that
+    /// it must not be evicted.
+    fn is_in_use(&self) -> bool {
+        false
+    }
 }
 
 /// Callback type used for updating cache items after a commit.
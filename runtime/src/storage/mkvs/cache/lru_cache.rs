@@ -1,11 +1,31 @@
-use std::{any::Any, cell::RefCell, pin::Pin, ptr::NonNull, rc::Rc, sync::Arc};
+use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+    pin::Pin,
+    ptr::NonNull,
+    rc::Rc,
+    sync::Arc,
+};
 
 use anyhow::{anyhow, Result};
 use intrusive_collections::{IntrusivePointer, LinkedList, LinkedListLink};
 use io_context::Context;
 use thiserror::Error;
 
-use crate::storage::mkvs::{cache::*, sync::*, tree::*};
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{cache::*, sync::*, tree::*},
+};
+
+/// Maximum number of cached nodes passed as known nodes in sync requests.
+const MAX_KNOWN_NODES: usize = 32;
+
+/// Number of least recently used items considered by the size-aware policy.
+///
+/// If all items in the window are locked, the first unlocked item past the
+/// window is evicted instead.
+const SIZE_AWARE_WINDOW: usize = 8;
 
 #[derive(Error, Debug)]
 #[error("mkvs: tried to remove locked node")]
@@ -15,6 +35,16 @@ struct RemoveLockedError;
 pub struct CacheItemBox<Item: CacheItem + Default> {
     item: Rc<RefCell<Item>>,
     link: LinkedListLink,
+    /// Whether the item has been used since it was last considered for
+    /// eviction (only used by the second chance policy).
+    referenced: Cell<bool>,
+    /// Size accounted for the item when it was added to the list.
+    cached_size: usize,
+    /// Memory accounted for the item when it was added to the list.
+    ///
+    /// Removal uses the accounted sizes instead of recomputing them, so the
+    /// totals stay consistent even if the item changed or is borrowed.
+    memory_size: usize,
 }
 
 unsafe impl<T: CacheItem + Default> IntrusivePointer<CacheItemBox<T>>
@@ -43,6 +73,10 @@ where
     pub list: LinkedList<CacheItemAdapter<V>>,
     pub size: usize,
     pub capacity: usize,
+    pub bytes: usize,
+    pub byte_capacity: usize,
+    pub policy: EvictionPolicy,
+    pub evictions: u64,
     pub mark: CacheExtra<V>,
 }
 
@@ -55,6 +89,10 @@ where
             list: LinkedList::new(CacheItemAdapter::new()),
             size: 0,
             capacity: capacity,
+            bytes: 0,
+            byte_capacity: 0,
+            policy: EvictionPolicy::LRU,
+            evictions: 0,
             mark: None,
         }
     }
@@ -72,10 +110,16 @@ where
     fn add(&mut self, val: Rc<RefCell<V>>) {
         let mut val_ref = val.borrow_mut();
         if val_ref.get_cache_extra().is_none() {
-            self.size += val_ref.get_cached_size();
+            let cached_size = val_ref.get_cached_size();
+            let memory_size = val_ref.get_memory_size();
+            self.size += cached_size;
+            self.bytes += memory_size;
             let mut item_box = Box::pin(CacheItemBox {
                 item: val.clone(),
                 link: LinkedListLink::new(),
+                referenced: Cell::new(false),
+                cached_size,
+                memory_size,
             });
             val_ref.set_cache_extra(NonNull::new(&mut *item_box));
             if let Some(non_null_pos) = &self.mark {
@@ -95,6 +139,11 @@ where
         match val_ref.get_cache_extra() {
             None => false,
             Some(non_null) => {
+                if self.policy == EvictionPolicy::SecondChance {
+                    unsafe { non_null.as_ref() }.referenced.set(true);
+                    return true;
+                }
+
                 let mut item_cursor = unsafe { self.list.cursor_mut_from_ptr(non_null.as_ptr()) };
                 let removed_box = item_cursor.remove().unwrap();
                 self.list.push_front(removed_box);
@@ -118,9 +167,9 @@ where
                 match item_cursor.remove() {
                     None => false,
                     Some(item_box) => {
-                        let mut val = item_box.item.borrow_mut();
-                        val.set_cache_extra(None);
-                        self.size -= val.get_cached_size();
+                        item_box.item.borrow_mut().set_cache_extra(None);
+                        self.size -= item_box.cached_size;
+                        self.bytes -= item_box.memory_size;
                         true
                     }
                 }
@@ -134,21 +183,85 @@ where
         locked_val: Option<&Rc<RefCell<V>>>,
     ) -> Result<Vec<Rc<RefCell<V>>>, RemoveLockedError> {
         let mut evicted: Vec<Rc<RefCell<V>>> = Vec::new();
-        if self.capacity > 0 {
-            let target_size = val.borrow().get_cached_size();
-            while !self.list.is_empty() && self.size + target_size > self.capacity {
-                let back = (*self.list.back().get().unwrap()).item.clone();
-                if let Some(locked_val) = locked_val {
-                    if back.as_ptr() == locked_val.as_ptr() {
-                        return Err(RemoveLockedError);
+        let target_size = val.borrow().get_cached_size();
+        let target_bytes = val.borrow().get_memory_size();
+        while !self.list.is_empty() && self.is_over_capacity(target_size, target_bytes) {
+            let victim = self.select_victim(locked_val)?;
+            if self.remove(victim.clone()) {
+                self.evictions += 1;
+                evicted.push(victim);
+            }
+        }
+        Ok(evicted)
+    }
+
+    fn is_over_capacity(&self, target_size: usize, target_bytes: usize) -> bool {
+        (self.capacity > 0 && self.size + target_size > self.capacity)
+            || (self.byte_capacity > 0 && self.bytes + target_bytes > self.byte_capacity)
+    }
+
+    fn select_victim(
+        &mut self,
+        locked_val: Option<&Rc<RefCell<V>>>,
+    ) -> Result<Rc<RefCell<V>>, RemoveLockedError> {
+        // Items being updated by an ongoing operation are in use as well.
+        let is_locked = |val: &Rc<RefCell<V>>| {
+            locked_val.map_or(false, |locked_val| val.as_ptr() == locked_val.as_ptr())
+                || val.try_borrow().map_or(true, |val| val.is_in_use())
+        };
+
+        match self.policy {
+            EvictionPolicy::LRU => {
+                // Skip locked items, which are in use.
+                let mut cursor = self.list.back();
+                while let Some(item_box) = cursor.get() {
+                    if !is_locked(&item_box.item) {
+                        return Ok(item_box.item.clone());
+                    }
+                    cursor.move_prev();
+                }
+                Err(RemoveLockedError)
+            }
+            EvictionPolicy::SecondChance => {
+                // Each item is considered at most twice, as the first time
+                // clears its reference bit.
+                let count = self.list.iter().count();
+                for _ in 0..2 * count {
+                    let back = self.list.back().get().unwrap();
+                    // Skip locked items, which are in use.
+                    if !is_locked(&back.item) && !back.referenced.replace(false) {
+                        return Ok(back.item.clone());
                     }
+
+                    // Used since last considered, move to the front.
+                    let item_box = self.list.back_mut().remove().unwrap();
+                    self.list.push_front(item_box);
                 }
-                if self.remove(back.clone()) {
-                    evicted.push(back);
+                Err(RemoveLockedError)
+            }
+            EvictionPolicy::SizeAware => {
+                let mut victim: Option<(usize, Rc<RefCell<V>>)> = None;
+                let mut cursor = self.list.back();
+                let mut considered = 0;
+                while let Some(item_box) = cursor.get() {
+                    if considered >= SIZE_AWARE_WINDOW && victim.is_some() {
+                        break;
+                    }
+                    considered += 1;
+                    cursor.move_prev();
+                    if is_locked(&item_box.item) {
+                        continue;
+                    }
+
+                    let size = item_box.memory_size;
+                    if victim.as_ref().map_or(true, |(max, _)| size > *max) {
+                        victim = Some((size, item_box.item.clone()));
+                    }
                 }
+                // Only locked items are left.
+                victim.map(|(_, val)| val).ok_or(RemoveLockedError)
             }
         }
-        Ok(evicted)
     }
 }
 
@@ -161,6 +274,10 @@ pub struct LRUCache {
 
     lru_leaf: LRUList<NodePointer>,
     lru_internal: LRUList<NodePointer>,
+
+    hits: u64,
+    misses: u64,
+    remote_syncs: u64,
 }
 
 impl LRUCache {
@@ -187,9 +304,28 @@ impl LRUCache {
 
             lru_leaf: LRUList::new(value_capacity),
             lru_internal: LRUList::new(node_capacity),
+
+            hits: 0,
+            misses: 0,
+            remote_syncs: 0,
         })
     }
 
+    /// Set the policy used to select nodes for eviction.
+    pub fn set_eviction_policy(&mut self, policy: EvictionPolicy) {
+        self.lru_leaf.policy = policy;
+        self.lru_internal.policy = policy;
+    }
+
+    /// Set the maximum memory, in bytes, used by internal nodes held by the
+    /// cache before eviction. Zero means that only the node capacity applies.
+    ///
+    /// Leaf nodes, including leaves embedded in internal nodes, are not
+    /// covered and are only bounded by the value capacity.
+    pub fn set_node_memory_capacity(&mut self, capacity: usize) {
+        self.lru_internal.byte_capacity = capacity;
+    }
+
     fn new_internal_node_ptr(&mut self, node: Option<NodeRef>) -> NodePtrRef {
         Rc::new(RefCell::new(NodePointer {
             node: node,
@@ -316,6 +452,44 @@ impl LRUCache {
         Ok(())
     }
 
+    /// Collect the topmost clean nodes held by the cache, up to a limit.
+    ///
+    /// Proofs anchored at the root repeat the nodes above the fetched subtree,
+    /// which the read syncer can omit if they are known.
+    fn known_nodes(&self) -> HashMap<Hash, NodeRef> {
+        let mut known = HashMap::new();
+        let mut pending = VecDeque::new();
+        pending.push_back(self.pending_root.clone());
+        while let Some(ptr) = pending.pop_front() {
+            if known.len() >= MAX_KNOWN_NODES {
+                break;
+            }
+
+            // Pointers and nodes may be borrowed by the operation which
+            // triggered the sync, skip them as they are being changed.
+            let ptr = match ptr.try_borrow() {
+                Ok(ptr) => ptr,
+                Err(_) => continue,
+            };
+            let node_ref = match ptr.node {
+                Some(ref node_ref) if !ptr.is_null() => node_ref.clone(),
+                _ => continue,
+            };
+            let node = match node_ref.try_borrow() {
+                Ok(node) => node,
+                Err(_) => continue,
+            };
+            if ptr.clean && node.is_clean() {
+                known.insert(ptr.hash, node_ref.clone());
+            }
+            if let NodeBox::Internal(ref n) = *node {
+                pending.push_back(n.left.clone());
+                pending.push_back(n.right.clone());
+            }
+        }
+        known
+    }
+
     fn commit_merged_node(
         &mut self,
         ptr: NodePtrRef,
@@ -356,6 +530,12 @@ impl Cache for LRUCache {
         CacheStats {
             internal_node_count: self.lru_internal.size,
             leaf_value_size: self.lru_leaf.size,
+            internal_node_bytes: self.lru_internal.bytes,
+            leaf_node_bytes: self.lru_leaf.bytes,
+            hits: self.hits,
+            misses: self.misses,
+            remote_syncs: self.remote_syncs,
+            evictions: self.lru_internal.evictions + self.lru_leaf.evictions,
         }
     }
 
@@ -438,6 +618,7 @@ impl Cache for LRUCache {
                 drop(ptr);
                 self.remove_node(ptr_ref.clone());
             } else {
+                self.hits += 1;
                 return Ok(Some(node.clone()));
             }
         } else {
@@ -448,6 +629,7 @@ impl Cache for LRUCache {
         }
 
         // Node not available locally, fetch from read syncer.
+        self.misses += 1;
         if let Some(fetcher) = fetcher {
             self.remote_sync(ctx, ptr_ref.clone(), fetcher)?;
         } else {
@@ -471,10 +653,14 @@ impl Cache for LRUCache {
         ptr: NodePtrRef,
         fetcher: F,
     ) -> Result<()> {
+        self.remote_syncs += 1;
+        let known_nodes = self.known_nodes();
+        let known_hashes: Vec<Hash> = known_nodes.keys().cloned().collect();
         let proof = fetcher.fetch(
             Context::create_child(&ctx),
             self.sync_root,
             ptr.clone(),
+            &known_hashes,
             &mut self.read_syncer,
         )?;
 
@@ -495,7 +681,12 @@ impl Cache for LRUCache {
 
         // Verify proof.
         let pv = ProofVerifier;
-        let subtree = pv.verify_proof(Context::create_child(&ctx), expected_root, &proof)?;
+        let subtree = pv.verify_compact_proof(
+            Context::create_child(&ctx),
+            expected_root,
+            &proof,
+            &known_nodes,
+        )?;
 
         // Merge resulting nodes.
         let mut merged_nodes: Vec<NodePtrRef> = Vec::new();
@@ -550,3 +741,92 @@ impl Cache for LRUCache {
         self.lru_leaf.mark();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn leaf(value: &[u8]) -> NodePtrRef {
+        Rc::new(RefCell::new(NodePointer {
+            clean: true,
+            node: Some(Rc::new(RefCell::new(NodeBox::Leaf(LeafNode {
+                key: b"key".to_vec(),
+                value: value.to_vec(),
+                ..Default::default()
+            })))),
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn test_remove_accounted_size() {
+        let mut list: LRUList<NodePointer> = LRUList::new(0);
+        let ptr = leaf(b"value");
+        list.add(ptr.clone());
+        assert_eq!(list.bytes, ptr.borrow().get_memory_size());
+
+        // Removal must not depend on the current state of the node.
+        let node = ptr.borrow().get_node();
+        if let NodeBox::Leaf(ref mut n) = *node.borrow_mut() {
+            n.value = vec![0; 100];
+        }
+        let _guard = node.borrow_mut();
+        assert!(list.remove(ptr.clone()));
+        assert_eq!(list.size, 0);
+        assert_eq!(list.bytes, 0);
+    }
+
+    #[test]
+    fn test_select_victim_locked() {
+        for policy in vec![
+            EvictionPolicy::LRU,
+            EvictionPolicy::SecondChance,
+            EvictionPolicy::SizeAware,
+        ] {
+            let mut list: LRUList<NodePointer> = LRUList::new(0);
+            list.policy = policy;
+
+            // The locked node is skipped even if it would be selected.
+            let locked = leaf(&[0; 100]);
+            list.add(locked.clone());
+            for _ in 0..2 * SIZE_AWARE_WINDOW {
+                list.add(leaf(b"small"));
+            }
+            for _ in 0..2 * SIZE_AWARE_WINDOW {
+                let victim = list.select_victim(Some(&locked)).expect("select victim");
+                assert!(!Rc::ptr_eq(&victim, &locked), "policy: {:?}", policy);
+                assert!(list.remove(victim));
+            }
+
+            // Eviction only fails when the locked node is the only one left.
+            assert!(
+                list.select_victim(Some(&locked)).is_err(),
+                "policy: {:?}",
+                policy
+            );
+            let victim = list.select_victim(None).expect("select victim");
+            assert!(Rc::ptr_eq(&victim, &locked), "policy: {:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_size_aware_window() {
+        let mut list: LRUList<NodePointer> = LRUList::new(0);
+        list.policy = EvictionPolicy::SizeAware;
+
+        // Only the least recently used nodes are considered.
+        let largest = leaf(&[0; 100]);
+        let outside = leaf(&[0; 200]);
+        list.add(largest.clone());
+        for _ in 1..SIZE_AWARE_WINDOW {
+            list.add(leaf(b"small"));
+        }
+        list.add(outside.clone());
+
+        let victim = list.select_victim(None).expect("select victim");
+        assert!(Rc::ptr_eq(&victim, &largest));
+        assert!(list.remove(victim));
+        let victim = list.select_victim(None).expect("select victim");
+        assert!(Rc::ptr_eq(&victim, &outside));
+    }
+}
@@ -0,0 +1,305 @@
+//! Tree checkpoints.
+//!
+//! A checkpoint contains all nodes of a tree under a given root, serialized
+//! in pre-order and split into chunks of bounded size. The checkpoint
+//! metadata lists the hashes of all chunks so that each chunk can be verified
+//! independently when restoring.
+use std::{cell::RefCell, rc::Rc};
+
+use anyhow::Result;
+use io_context::Context;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{
+    marshal::Marshal,
+    tree::{Node, NodeBox, NodePointer, NodePtrRef, Root, Tree},
+};
+use crate::common::crypto::hash::Hash;
+
+/// Current checkpoint format version.
+pub const CHECKPOINT_VERSION: u16 = 1;
+
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("mkvs: unsupported checkpoint version {0}")]
+    UnsupportedVersion(u16),
+    #[error("mkvs: checkpoint chunk {0} hash mismatch")]
+    ChunkHashMismatch(usize),
+    #[error("mkvs: checkpoint chunk {0} contains an unexpected node")]
+    UnexpectedNode(usize),
+    #[error("mkvs: checkpoint chunk {0} contains a node with an unexpected hash")]
+    NodeHashMismatch(usize),
+    #[error("mkvs: all checkpoint chunks have already been restored")]
+    AlreadyRestored,
+    #[error("mkvs: checkpoint restoration is incomplete")]
+    Incomplete,
+}
+
+/// Checkpoint metadata.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// Checkpoint format version.
+    pub version: u16,
+    /// Root the checkpoint was created for.
+    pub root: Root,
+    /// Hashes of all chunks in the checkpoint, in order.
+    pub chunks: Vec<Hash>,
+}
+
+/// Create a checkpoint of the tree's current root.
+///
+/// Chunks are passed to `write_chunk` in order as soon as they are full.
+/// A chunk only exceeds `chunk_size` bytes when it contains a single node
+/// which is larger than that. The tree must not have any uncommitted changes.
+pub fn create_checkpoint<F>(
+    ctx: Context,
+    tree: &Tree,
+    chunk_size: usize,
+    mut write_chunk: F,
+) -> Result<Metadata>
+where
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let root = tree.visit_nodes(ctx, |node| {
+        let data = node.marshal_binary()?;
+        if !chunk.is_empty() && chunk.len() + data.len() > chunk_size {
+            chunks.push(Hash::digest_bytes(&chunk));
+            write_chunk(&chunk)?;
+            chunk.clear();
+        }
+        chunk.extend_from_slice(&data);
+        Ok(())
+    })?;
+    if !chunk.is_empty() {
+        chunks.push(Hash::digest_bytes(&chunk));
+        write_chunk(&chunk)?;
+    }
+
+    Ok(Metadata {
+        version: CHECKPOINT_VERSION,
+        root,
+        chunks,
+    })
+}
+
+/// Restores a tree from checkpoint chunks.
+///
+/// Chunks must be restored in order. Each chunk is verified against the
+/// metadata and each node is verified against the hash referenced by its
+/// parent (or the root hash), so a tampered chunk is rejected even if the
+/// metadata itself is not trusted.
+pub struct Restorer {
+    metadata: Metadata,
+    next_chunk: usize,
+    root_ptr: NodePtrRef,
+    pending: Vec<NodePtrRef>,
+}
+
+impl Restorer {
+    /// Create a new restorer for the checkpoint described by the metadata.
+    pub fn new(metadata: Metadata) -> Result<Self> {
+        if metadata.version != CHECKPOINT_VERSION {
+            return Err(CheckpointError::UnsupportedVersion(metadata.version).into());
+        }
+
+        let root_ptr = NodePointer::hash_ptr(metadata.root.hash);
+        let pending = if metadata.root.hash.is_empty() {
+            vec![]
+        } else {
+            vec![root_ptr.clone()]
+        };
+
+        Ok(Self {
+            metadata,
+            next_chunk: 0,
+            root_ptr,
+            pending,
+        })
+    }
+
+    /// Check whether all chunks have been restored.
+    pub fn is_done(&self) -> bool {
+        self.next_chunk == self.metadata.chunks.len()
+    }
+
+    /// Verify and restore the next chunk.
+    ///
+    /// Returns `true` when all chunks have been restored.
+    pub fn restore_chunk(&mut self, data: &[u8]) -> Result<bool> {
+        let index = self.next_chunk;
+        let expected = self
+            .metadata
+            .chunks
+            .get(index)
+            .ok_or(CheckpointError::AlreadyRestored)?;
+        if Hash::digest_bytes(data) != *expected {
+            return Err(CheckpointError::ChunkHashMismatch(index).into());
+        }
+
+        let mut pos = 0;
+        while pos < data.len() {
+            let mut node = NodeBox::default();
+            pos += node.unmarshal_binary(&data[pos..])?;
+
+            let ptr = self
+                .pending
+                .pop()
+                .ok_or(CheckpointError::UnexpectedNode(index))?;
+            if node.get_hash() != ptr.borrow().hash {
+                return Err(CheckpointError::NodeHashMismatch(index).into());
+            }
+
+            // Children are restored in pre-order, so the left child comes first.
+            if let NodeBox::Internal(ref n) = node {
+                for child in vec![n.right.clone(), n.left.clone()] {
+                    if !child.borrow().is_null() {
+                        self.pending.push(child);
+                    }
+                }
+            }
+            ptr.borrow_mut().node = Some(Rc::new(RefCell::new(node)));
+        }
+        self.next_chunk += 1;
+
+        if self.is_done() && !self.pending.is_empty() {
+            return Err(CheckpointError::Incomplete.into());
+        }
+        Ok(self.is_done())
+    }
+
+    /// Finish restoration and return the restored tree.
+    ///
+    /// The tree keeps all nodes in memory.
+    pub fn finish(self) -> Result<Tree> {
+        if !self.is_done() || !self.pending.is_empty() {
+            return Err(CheckpointError::Incomplete.into());
+        }
+
+        Ok(Tree::from_restored_root(self.metadata.root, self.root_ptr))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::storage::mkvs::{
+        db::{MemoryBackend, NodeDB},
+        tree::tree_test::{generate_key_value_pairs_ex, make_tree as make_uncommitted_tree},
+    };
+
+    fn make_tree(count: usize) -> Tree {
+        let mut tree = make_uncommitted_tree(count);
+        Tree::commit(&mut tree, Context::background(), Default::default(), 1).expect("commit");
+        tree
+    }
+
+    fn checkpoint(tree: &Tree, chunk_size: usize) -> (Metadata, Vec<Vec<u8>>) {
+        let mut chunks = Vec::new();
+        let metadata = create_checkpoint(Context::background(), tree, chunk_size, |chunk| {
+            chunks.push(chunk.to_vec());
+            Ok(())
+        })
+        .expect("create checkpoint");
+        (metadata, chunks)
+    }
+
+    fn restore(metadata: Metadata, chunks: &[Vec<u8>]) -> Result<Tree> {
+        let mut restorer = Restorer::new(metadata)?;
+        for chunk in chunks {
+            restorer.restore_chunk(chunk)?;
+        }
+        restorer.finish()
+    }
+
+    #[test]
+    fn test_checkpoint_restore() {
+        let tree = make_tree(1000);
+        let (metadata, chunks) = checkpoint(&tree, 4096);
+        assert_eq!(metadata.version, CHECKPOINT_VERSION);
+        assert_eq!(metadata.root.version, 1);
+        assert_eq!(metadata.chunks.len(), chunks.len());
+        assert!(chunks.len() > 1, "checkpoint should be split into chunks");
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 4096));
+
+        let root = metadata.root;
+        let chunk_hashes = metadata.chunks.clone();
+        let restored = restore(metadata, &chunks).expect("restore");
+        let (keys, values) = generate_key_value_pairs_ex("".to_string(), 1000);
+        for (key, value) in keys.iter().zip(values.into_iter()) {
+            assert_eq!(
+                restored.get(Context::background(), key).unwrap(),
+                Some(value)
+            );
+        }
+
+        // The restored tree can be stored in a node database.
+        let mut restored = restored;
+        let db = NodeDB::open(Arc::new(MemoryBackend::new()), Default::default()).unwrap();
+        let (write_log, db_root) = db
+            .commit(Context::background(), &mut restored, root.version)
+            .expect("commit restored tree");
+        assert!(write_log.is_empty());
+        assert_eq!(db_root, root);
+
+        // Checkpoints can also be created from trees which fetch nodes remotely.
+        let remote_tree = db.tree(root).expect("tree");
+        let (remote_metadata, remote_chunks) = checkpoint(&remote_tree, 4096);
+        assert_eq!(remote_metadata.chunks, chunk_hashes);
+        assert_eq!(remote_chunks, chunks);
+    }
+
+    #[test]
+    fn test_checkpoint_empty() {
+        let tree = make_tree(0);
+        let (metadata, chunks) = checkpoint(&tree, 4096);
+        assert!(chunks.is_empty());
+
+        let restored = restore(metadata, &chunks).expect("restore");
+        assert_eq!(restored.get(Context::background(), b"key 0").unwrap(), None);
+    }
+
+    #[test]
+    fn test_checkpoint_tampered() {
+        let tree = make_tree(100);
+        let (metadata, chunks) = checkpoint(&tree, 1024);
+
+        // Chunks which do not match the metadata.
+        let mut tampered = chunks.clone();
+        tampered[1][10] ^= 0xff;
+        let error = restore(metadata.clone(), &tampered).expect_err("tampered chunk");
+        assert!(matches!(
+            error.downcast_ref::<CheckpointError>(),
+            Some(CheckpointError::ChunkHashMismatch(1))
+        ));
+
+        // Metadata which does not match the root.
+        let other = make_tree(101);
+        let (mut other_metadata, other_chunks) = checkpoint(&other, 1024);
+        other_metadata.root = metadata.root;
+        let error = restore(other_metadata, &other_chunks).expect_err("tampered metadata");
+        assert!(matches!(
+            error.downcast_ref::<CheckpointError>(),
+            Some(CheckpointError::NodeHashMismatch(0))
+        ));
+
+        // Missing and extra chunks.
+        let error =
+            restore(metadata.clone(), &chunks[..chunks.len() - 1]).expect_err("missing chunk");
+        assert!(matches!(
+            error.downcast_ref::<CheckpointError>(),
+            Some(CheckpointError::Incomplete)
+        ));
+        let mut extra = chunks.clone();
+        extra.push(chunks[0].clone());
+        let error = restore(metadata, &extra).expect_err("extra chunk");
+        assert!(matches!(
+            error.downcast_ref::<CheckpointError>(),
+            Some(CheckpointError::AlreadyRestored)
+        ));
+    }
+}
@@ -0,0 +1,353 @@
+//! Key/value backends for the node database.
+use std::{collections::HashMap, sync::Mutex};
+#[cfg(not(target_env = "sgx"))]
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+#[cfg(not(target_env = "sgx"))]
+use anyhow::{anyhow, Context as AnyContext};
+#[cfg(not(target_env = "sgx"))]
+use byteorder::{ByteOrder, LittleEndian};
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(target_env = "sgx"))]
+use crate::common::{cbor, crypto::hash::Hash};
+
+/// A single update in a batch.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct BatchOp {
+    #[serde(with = "serde_bytes")]
+    key: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    value: Option<Vec<u8>>,
+}
+
+/// A set of updates which is applied to a backend atomically.
+#[derive(Clone, Debug, Default)]
+pub struct Batch {
+    ops: Vec<BatchOp>,
+}
+
+impl Batch {
+    /// Create a new empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store a value under the given key.
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.ops.push(BatchOp {
+            key,
+            value: Some(value),
+        });
+    }
+
+    /// Remove the value stored under the given key.
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.ops.push(BatchOp { key, value: None });
+    }
+
+    /// Check whether the batch contains any updates.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    fn apply_to(self, items: &mut HashMap<Vec<u8>, Vec<u8>>) {
+        for op in self.ops {
+            match op.value {
+                Some(value) => items.insert(op.key, value),
+                None => items.remove(&op.key),
+            };
+        }
+    }
+}
+
+/// A key/value store used by the node database.
+pub trait Backend: Send + Sync {
+    /// Fetch the value stored under the given key.
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Atomically apply a batch of updates.
+    fn apply(&self, batch: Batch) -> Result<()>;
+}
+
+/// A backend which keeps everything in memory.
+#[derive(Default)]
+pub struct MemoryBackend {
+    items: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl MemoryBackend {
+    /// Create a new empty in-memory backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.items.lock().unwrap().get(key).cloned())
+    }
+
+    fn apply(&self, batch: Batch) -> Result<()> {
+        batch.apply_to(&mut self.items.lock().unwrap());
+        Ok(())
+    }
+}
+
+/// Size of the record header (payload length).
+#[cfg(not(target_env = "sgx"))]
+const RECORD_LENGTH_SIZE: usize = 4;
+
+#[cfg(not(target_env = "sgx"))]
+struct FileState {
+    file: File,
+    items: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+/// A backend which persists batches in an append-only log file.
+///
+/// The whole store is kept in memory and the log is replayed when the
+/// backend is opened. Each record is checksummed so that a trailing batch
+/// which was only partially written (e.g., due to a crash) is discarded on
+/// open, while a corrupted record followed by other records is reported as
+/// an error instead of silently dropping the later batches. Use
+/// `compact` to rewrite the log so that it only contains the current state.
+#[cfg(not(target_env = "sgx"))]
+pub struct FileBackend {
+    path: PathBuf,
+    state: Mutex<FileState>,
+}
+
+#[cfg(not(target_env = "sgx"))]
+impl FileBackend {
+    /// Open the backend stored at the given path, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)
+            .with_context(|| format!("mkvs/db: failed to open {}", path.display()))?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        let mut items = HashMap::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            match Self::decode_record(&data[pos..]) {
+                Some((batch, length)) => {
+                    batch.apply_to(&mut items);
+                    pos += length;
+                }
+                None if Self::is_last_record(&data[pos..]) => {
+                    // Drop the torn trailing record so new records can be appended.
+                    file.set_len(pos as u64)?;
+                    break;
+                }
+                None => {
+                    return Err(anyhow!(
+                        "mkvs/db: corrupted record at offset {} in {}",
+                        pos,
+                        path.display()
+                    ))
+                }
+            }
+        }
+
+        Ok(Self {
+            path,
+            state: Mutex::new(FileState { file, items }),
+        })
+    }
+
+    /// Rewrite the log so that it only contains the current state.
+    pub fn compact(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        let mut batch = Batch::new();
+        for (key, value) in state.items.iter() {
+            batch.put(key.clone(), value.clone());
+        }
+
+        let mut tmp_path = OsString::from(&self.path);
+        tmp_path.push(".tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&Self::encode_record(&batch))?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        // Make sure the rename itself is durable.
+        if let Some(dir) = self.path.parent() {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            File::open(dir)?.sync_all()?;
+        }
+
+        state.file = OpenOptions::new().append(true).open(&self.path)?;
+
+        Ok(())
+    }
+
+    fn encode_record(batch: &Batch) -> Vec<u8> {
+        let payload = cbor::to_vec(&batch.ops);
+        let mut record = vec![0; RECORD_LENGTH_SIZE];
+        LittleEndian::write_u32(&mut record, payload.len() as u32);
+        record.extend_from_slice(Hash::digest_bytes(&payload).as_ref());
+        record.extend_from_slice(&payload);
+        record
+    }
+
+    /// Check whether the record at the start of the given data extends to
+    /// the end of the data, so that it could have been torn by a crash.
+    fn is_last_record(data: &[u8]) -> bool {
+        let header_size = RECORD_LENGTH_SIZE + Hash::len();
+        if data.len() < header_size {
+            return true;
+        }
+        let length = header_size + LittleEndian::read_u32(&data[..RECORD_LENGTH_SIZE]) as usize;
+        data.len() <= length
+    }
+
+    fn decode_record(data: &[u8]) -> Option<(Batch, usize)> {
+        let header_size = RECORD_LENGTH_SIZE + Hash::len();
+        if data.len() < header_size {
+            return None;
+        }
+        let length = header_size + LittleEndian::read_u32(&data[..RECORD_LENGTH_SIZE]) as usize;
+        if data.len() < length {
+            return None;
+        }
+
+        let checksum = Hash::from(&data[RECORD_LENGTH_SIZE..header_size]);
+        let payload = &data[header_size..length];
+        if Hash::digest_bytes(payload) != checksum {
+            return None;
+        }
+
+        let ops = cbor::from_slice(payload).ok()?;
+        Some((Batch { ops }, length))
+    }
+}
+
+#[cfg(not(target_env = "sgx"))]
+impl Backend for FileBackend {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.state.lock().unwrap().items.get(key).cloned())
+    }
+
+    fn apply(&self, batch: Batch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let offset = state.file.metadata()?.len();
+        let result = state
+            .file
+            .write_all(&Self::encode_record(&batch))
+            .context("mkvs/db: failed to write batch")
+            .and_then(|_| {
+                state
+                    .file
+                    .sync_data()
+                    .context("mkvs/db: failed to sync batch")
+            });
+        if let Err(err) = result {
+            // Remove what was written of the failed batch so that it is not
+            // replayed and later batches do not follow a corrupted record.
+            state.file.set_len(offset)?;
+            return Err(err);
+        }
+        batch.apply_to(&mut state.items);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_file_backend() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("nodes.db");
+
+        {
+            let backend = FileBackend::open(&path).expect("open");
+            let mut batch = Batch::new();
+            batch.put(b"foo".to_vec(), b"bar".to_vec());
+            batch.put(b"moo".to_vec(), b"goo".to_vec());
+            backend.apply(batch).expect("apply");
+            let mut batch = Batch::new();
+            batch.delete(b"moo".to_vec());
+            backend.apply(batch).expect("apply");
+        }
+
+        // Simulate a partially written record.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0xff, 0x00, 0x00]).unwrap();
+        drop(file);
+
+        let backend = FileBackend::open(&path).expect("reopen");
+        assert_eq!(backend.get(b"foo").unwrap(), Some(b"bar".to_vec()));
+        assert_eq!(backend.get(b"moo").unwrap(), None);
+
+        let mut batch = Batch::new();
+        batch.put(b"boo".to_vec(), b"zoo".to_vec());
+        backend.apply(batch).expect("apply after truncation");
+        backend.compact().expect("compact");
+        drop(backend);
+
+        let backend = FileBackend::open(&path).expect("reopen after compaction");
+        assert_eq!(backend.get(b"foo").unwrap(), Some(b"bar".to_vec()));
+        assert_eq!(backend.get(b"boo").unwrap(), Some(b"zoo".to_vec()));
+        assert_eq!(backend.get(b"moo").unwrap(), None);
+    }
+
+    #[test]
+    fn test_file_backend_corruption() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("nodes.db");
+
+        {
+            let backend = FileBackend::open(&path).expect("open");
+            for idx in 0..2u8 {
+                let mut batch = Batch::new();
+                batch.put(vec![idx], vec![idx]);
+                backend.apply(batch).expect("apply");
+            }
+        }
+
+        // Corrupt the payload of the first record.
+        let mut data = fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        let first_end = data.len() / 2;
+        data[first_end - 1] ^= 0xff;
+        fs::write(&path, &data).unwrap();
+
+        // Corruption followed by a valid record must not be discarded.
+        assert!(FileBackend::open(&path).is_err());
+        assert_eq!(fs::read(&path).unwrap().len(), data.len());
+
+        // Corruption in the last record is treated as a torn write.
+        data[first_end - 1] ^= 0xff;
+        data[last] ^= 0xff;
+        fs::write(&path, &data).unwrap();
+
+        let backend = FileBackend::open(&path).expect("reopen");
+        assert_eq!(backend.get(&[0]).unwrap(), Some(vec![0]));
+        assert_eq!(backend.get(&[1]).unwrap(), None);
+        assert_eq!(fs::read(&path).unwrap().len(), first_end);
+    }
+}
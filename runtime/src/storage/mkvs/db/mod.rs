@@ -0,0 +1,9 @@
+//! Persistent node database for MKVS trees.
+mod backend;
+mod nodedb;
+
+pub use backend::*;
+pub use nodedb::*;
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,558 @@
+//! Versioned node database.
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use io_context::Context;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    common::{cbor, crypto::hash::Hash, roothash::Namespace},
+    storage::mkvs::{
+        db::{Backend, Batch},
+        marshal::Marshal,
+        sync::*,
+        tree::{Node, NodeBox, NodePtrRef, Root, Tree},
+        TreeSnapshot, WriteLog,
+    },
+};
+
+/// Key prefix for nodes, followed by the node hash.
+const NODE_KEY_PREFIX: u8 = 0x00;
+/// Key prefix for write logs, followed by the root version and hash.
+const WRITE_LOG_KEY_PREFIX: u8 = 0x01;
+/// Key prefix for the list of roots in a version, followed by the version.
+const VERSION_KEY_PREFIX: u8 = 0x02;
+/// Key of the database metadata.
+const METADATA_KEY: &[u8] = &[0x03];
+
+/// Size of the reference count stored in front of each node.
+const REFCOUNT_SIZE: usize = 8;
+
+#[derive(Error, Debug)]
+pub enum NodeDBError {
+    #[error("mkvs: database namespace mismatch (expected: {expected:?} got: {got:?})")]
+    NamespaceMismatch { expected: Namespace, got: Namespace },
+    #[error("mkvs: root not found")]
+    RootNotFound,
+    #[error("mkvs: node not found")]
+    NodeNotFound,
+    #[error("mkvs: write log not found")]
+    WriteLogNotFound,
+    #[error("mkvs: version {0} is older than the earliest version")]
+    VersionPruned(u64),
+    #[error("mkvs: version {0} is already finalized")]
+    AlreadyFinalized(u64),
+    #[error("mkvs: version {0} is not finalized")]
+    NotFinalized(u64),
+    #[error("mkvs: version {0} is not the earliest version")]
+    NotEarliest(u64),
+    #[error("mkvs: malformed database entry")]
+    Malformed,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Metadata {
+    namespace: Namespace,
+    earliest_version: Option<u64>,
+    last_finalized_version: Option<u64>,
+}
+
+impl Metadata {
+    /// Make sure that new roots can still be added to the given version.
+    fn check_writable(&self, version: u64) -> Result<()> {
+        match self.earliest_version {
+            Some(earliest) if version < earliest => {
+                return Err(NodeDBError::VersionPruned(version).into())
+            }
+            _ => {}
+        }
+        match self.last_finalized_version {
+            Some(last) if version <= last => Err(NodeDBError::AlreadyFinalized(version).into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn node_key(hash: &Hash) -> Vec<u8> {
+    let mut key = Vec::with_capacity(1 + Hash::len());
+    key.push(NODE_KEY_PREFIX);
+    key.extend_from_slice(hash.as_ref());
+    key
+}
+
+fn write_log_key(version: u64, hash: &Hash) -> Vec<u8> {
+    let mut key = vec![0; 1 + 8];
+    key[0] = WRITE_LOG_KEY_PREFIX;
+    BigEndian::write_u64(&mut key[1..], version);
+    key.extend_from_slice(hash.as_ref());
+    key
+}
+
+fn version_key(version: u64) -> Vec<u8> {
+    let mut key = vec![0; 1 + 8];
+    key[0] = VERSION_KEY_PREFIX;
+    BigEndian::write_u64(&mut key[1..], version);
+    key
+}
+
+/// A stored node together with the number of references to it.
+struct NodeEntry {
+    refs: u64,
+    serialized: Vec<u8>,
+}
+
+impl NodeEntry {
+    fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < REFCOUNT_SIZE {
+            return Err(NodeDBError::Malformed.into());
+        }
+        Ok(Self {
+            refs: LittleEndian::read_u64(&data[..REFCOUNT_SIZE]),
+            serialized: data[REFCOUNT_SIZE..].to_vec(),
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut data = vec![0; REFCOUNT_SIZE];
+        LittleEndian::write_u64(&mut data, self.refs);
+        data.extend_from_slice(&self.serialized);
+        data
+    }
+}
+
+/// Reference count updates for nodes which are written in a single batch.
+///
+/// Each node is referenced once by every parent node and once by every
+/// root pointing at it. Nodes are removed when they are no longer referenced.
+struct NodeUpdates<'a> {
+    backend: &'a dyn Backend,
+    nodes: HashMap<Hash, Option<NodeEntry>>,
+}
+
+impl<'a> NodeUpdates<'a> {
+    fn new(backend: &'a dyn Backend) -> Self {
+        Self {
+            backend,
+            nodes: HashMap::new(),
+        }
+    }
+
+    fn entry(&mut self, hash: Hash) -> Result<&mut Option<NodeEntry>> {
+        if !self.nodes.contains_key(&hash) {
+            let entry = match self.backend.get(&node_key(&hash))? {
+                Some(data) => Some(NodeEntry::decode(&data)?),
+                None => None,
+            };
+            self.nodes.insert(hash, entry);
+        }
+        Ok(self.nodes.get_mut(&hash).unwrap())
+    }
+
+    /// Add a reference to the node behind the given pointer, storing the
+    /// node and referencing its children in case it is not yet stored.
+    fn add_ref(&mut self, ptr: &NodePtrRef) -> Result<()> {
+        let ptr = ptr.borrow();
+        if ptr.is_null() {
+            return Ok(());
+        }
+        if let Some(entry) = self.entry(ptr.hash)? {
+            entry.refs += 1;
+            return Ok(());
+        }
+
+        let node_ref = ptr.node.clone().ok_or(NodeDBError::NodeNotFound)?;
+        let node = node_ref.borrow();
+        *self.entry(ptr.hash)? = Some(NodeEntry {
+            refs: 1,
+            serialized: node.marshal_binary()?,
+        });
+        if let NodeBox::Internal(ref n) = *node {
+            self.add_ref(&n.left)?;
+            self.add_ref(&n.right)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove a reference to the given node, removing the node and the
+    /// references to its children in case it is no longer referenced.
+    fn release(&mut self, hash: Hash) -> Result<()> {
+        if hash.is_empty() {
+            return Ok(());
+        }
+        let entry = self.entry(hash)?;
+        let node_entry = entry.as_mut().ok_or(NodeDBError::NodeNotFound)?;
+        if node_entry.refs > 1 {
+            node_entry.refs -= 1;
+            return Ok(());
+        }
+
+        let mut node = NodeBox::default();
+        node.unmarshal_binary(&node_entry.serialized)?;
+        *entry = None;
+        if let NodeBox::Internal(ref n) = node {
+            self.release(n.left.borrow().hash)?;
+            self.release(n.right.borrow().hash)?;
+        }
+
+        Ok(())
+    }
+
+    fn write(self, batch: &mut Batch) {
+        for (hash, entry) in self.nodes {
+            match entry {
+                Some(entry) => batch.put(node_key(&hash), entry.encode()),
+                None => batch.delete(node_key(&hash)),
+            }
+        }
+    }
+}
+
+/// A versioned node database for a single namespace.
+///
+/// Nodes are stored by hash and shared between all roots that reference
+/// them. Every committed root is recorded under its version together with
+/// the write log that produced it. Once a version is finalized, all roots in
+/// that version which were not finalized are discarded, and finalized
+/// versions can later be pruned, starting with the earliest one.
+///
+/// The database also implements `ReadSync`, so it can serve as the read
+/// syncer for trees backed by any of its roots.
+#[derive(Clone)]
+pub struct NodeDB {
+    backend: Arc<dyn Backend>,
+    meta: Arc<Mutex<Metadata>>,
+}
+
+impl NodeDB {
+    /// Open a node database for the given namespace on top of a backend.
+    pub fn open(backend: Arc<dyn Backend>, namespace: Namespace) -> Result<Self> {
+        let meta = match backend.get(METADATA_KEY)? {
+            Some(data) => {
+                let meta: Metadata = cbor::from_slice(&data)?;
+                if meta.namespace != namespace {
+                    return Err(NodeDBError::NamespaceMismatch {
+                        expected: namespace,
+                        got: meta.namespace,
+                    }
+                    .into());
+                }
+                meta
+            }
+            None => Metadata {
+                namespace,
+                ..Default::default()
+            },
+        };
+
+        Ok(Self {
+            backend,
+            meta: Arc::new(Mutex::new(meta)),
+        })
+    }
+
+    /// Return the namespace of the database.
+    pub fn namespace(&self) -> Namespace {
+        self.meta.lock().unwrap().namespace
+    }
+
+    /// Return the earliest version in the database, if any.
+    pub fn earliest_version(&self) -> Option<u64> {
+        self.meta.lock().unwrap().earliest_version
+    }
+
+    /// Return the last finalized version, if any.
+    pub fn last_finalized_version(&self) -> Option<u64> {
+        self.meta.lock().unwrap().last_finalized_version
+    }
+
+    fn get_version_roots(&self, version: u64) -> Result<Vec<Hash>> {
+        match self.backend.get(&version_key(version))? {
+            Some(data) => Ok(cbor::from_slice(&data)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Return all roots stored under the given version.
+    pub fn get_roots(&self, version: u64) -> Result<Vec<Root>> {
+        let namespace = self.namespace();
+        Ok(self
+            .get_version_roots(version)?
+            .into_iter()
+            .map(|hash| Root {
+                namespace,
+                version,
+                hash,
+            })
+            .collect())
+    }
+
+    /// Check whether the given root is stored in the database.
+    pub fn has_root(&self, root: &Root) -> Result<bool> {
+        if root.namespace != self.namespace() {
+            return Ok(false);
+        }
+        Ok(self.get_version_roots(root.version)?.contains(&root.hash))
+    }
+
+    /// Return the write log which produced the given root.
+    pub fn get_write_log(&self, root: &Root) -> Result<WriteLog> {
+        if !self.has_root(root)? {
+            return Err(NodeDBError::RootNotFound.into());
+        }
+        match self.backend.get(&write_log_key(root.version, &root.hash))? {
+            Some(data) => Ok(cbor::from_slice(&data)?),
+            None => Err(NodeDBError::WriteLogNotFound.into()),
+        }
+    }
+
+    /// Fetch the node with the given hash.
+    pub fn get_node(&self, hash: &Hash) -> Result<NodeBox> {
+        let data = self
+            .backend
+            .get(&node_key(hash))?
+            .ok_or(NodeDBError::NodeNotFound)?;
+        let entry = NodeEntry::decode(&data)?;
+
+        let mut node = NodeBox::default();
+        node.unmarshal_binary(&entry.serialized)?;
+        if node.get_hash() != *hash {
+            return Err(NodeDBError::Malformed.into());
+        }
+
+        Ok(node)
+    }
+
+    /// Create a tree for the given root, fetching nodes from the database.
+    pub fn tree(&self, root: Root) -> Result<Tree> {
+        if !self.has_root(&root)? {
+            return Err(NodeDBError::RootNotFound.into());
+        }
+
+        Ok(Tree::make()
+            .with_root(root)
+            .new(Box::new(NodeFetcher { db: self.clone() })))
+    }
+
+    /// Create a thread-safe snapshot of the given root, fetching nodes from
+    /// the database as they are needed.
+    pub fn snapshot(&self, root: Root) -> Result<TreeSnapshot> {
+        if !self.has_root(&root)? {
+            return Err(NodeDBError::RootNotFound.into());
+        }
+
+        Ok(TreeSnapshot::new(root, Arc::new(self.clone())))
+    }
+
+    /// Commit the tree's pending updates and store the resulting root under
+    /// the given version.
+    ///
+    /// All nodes reachable from the new root which are not yet stored in the
+    /// database must be available in the tree, so the tree should either be
+    /// created using `tree` or be built from scratch.
+    pub fn commit(&self, ctx: Context, tree: &mut Tree, version: u64) -> Result<(WriteLog, Root)> {
+        let mut meta = self.meta.lock().unwrap();
+        meta.check_writable(version)?;
+
+        let namespace = meta.namespace;
+        let mut new_meta = meta.clone();
+        if new_meta.earliest_version.is_none() {
+            new_meta.earliest_version = Some(version);
+        }
+
+        let (write_log, hash) = tree.commit_with(ctx, namespace, version, |root_ptr, log| {
+            let hash = root_ptr.borrow().hash;
+            let mut batch = Batch::new();
+
+            let mut roots = self.get_version_roots(version)?;
+            if !roots.contains(&hash) {
+                let mut nodes = NodeUpdates::new(&*self.backend);
+                nodes.add_ref(root_ptr)?;
+                nodes.write(&mut batch);
+
+                roots.push(hash);
+                batch.put(version_key(version), cbor::to_vec(&roots));
+            }
+            batch.put(write_log_key(version, &hash), cbor::to_vec(log));
+            batch.put(METADATA_KEY.to_vec(), cbor::to_vec(&new_meta));
+
+            self.backend.apply(batch)
+        })?;
+        *meta = new_meta;
+
+        Ok((
+            write_log,
+            Root {
+                namespace,
+                version,
+                hash,
+            },
+        ))
+    }
+
+    /// Finalize the given version.
+    ///
+    /// The passed roots are the roots within the version that have been
+    /// finalized. All other roots in the version are discarded. Versions
+    /// must be finalized in order.
+    pub fn finalize(&self, version: u64, roots: &[Hash]) -> Result<()> {
+        let mut meta = self.meta.lock().unwrap();
+        meta.check_writable(version)?;
+        let next_version = match meta.last_finalized_version {
+            Some(last) => Some(last + 1),
+            None => meta.earliest_version,
+        };
+        if next_version.map_or(false, |next| version != next) {
+            return Err(NodeDBError::NotFinalized(version - 1).into());
+        }
+
+        let existing = self.get_version_roots(version)?;
+        if roots.iter().any(|hash| !existing.contains(hash)) {
+            return Err(NodeDBError::RootNotFound.into());
+        }
+
+        let mut batch = Batch::new();
+        let mut nodes = NodeUpdates::new(&*self.backend);
+        let mut finalized = Vec::new();
+        for hash in existing {
+            if roots.contains(&hash) {
+                finalized.push(hash);
+                continue;
+            }
+            nodes.release(hash)?;
+            batch.delete(write_log_key(version, &hash));
+        }
+        nodes.write(&mut batch);
+        batch.put(version_key(version), cbor::to_vec(&finalized));
+
+        let mut new_meta = meta.clone();
+        new_meta.last_finalized_version = Some(version);
+        if new_meta.earliest_version.is_none() {
+            new_meta.earliest_version = Some(version);
+        }
+        batch.put(METADATA_KEY.to_vec(), cbor::to_vec(&new_meta));
+
+        self.backend.apply(batch)?;
+        *meta = new_meta;
+
+        Ok(())
+    }
+
+    /// Remove all roots stored under the given version.
+    ///
+    /// Only the earliest version can be pruned and it must be finalized.
+    /// Nodes which are still referenced by later versions are kept.
+    pub fn prune(&self, version: u64) -> Result<()> {
+        let mut meta = self.meta.lock().unwrap();
+        if meta.earliest_version != Some(version) {
+            return Err(NodeDBError::NotEarliest(version).into());
+        }
+        match meta.last_finalized_version {
+            Some(last) if version <= last => {}
+            _ => return Err(NodeDBError::NotFinalized(version).into()),
+        }
+
+        let mut batch = Batch::new();
+        let mut nodes = NodeUpdates::new(&*self.backend);
+        for hash in self.get_version_roots(version)? {
+            nodes.release(hash)?;
+            batch.delete(write_log_key(version, &hash));
+        }
+        nodes.write(&mut batch);
+        batch.delete(version_key(version));
+
+        let mut new_meta = meta.clone();
+        new_meta.earliest_version = Some(version + 1);
+        batch.put(METADATA_KEY.to_vec(), cbor::to_vec(&new_meta));
+
+        self.backend.apply(batch)?;
+        *meta = new_meta;
+
+        Ok(())
+    }
+
+    fn sync_tree(&self, tree: &TreeID) -> Result<Tree> {
+        if !self.has_root(&tree.root)? {
+            return Err(anyhow!(
+                "mkvs: requested root not available (expected: {:?})",
+                tree.root.hash
+            ));
+        }
+
+        Ok(Tree::make()
+            .with_capacity(0, 0)
+            .with_root(tree.root)
+            .new(Box::new(NodeFetcher { db: self.clone() })))
+    }
+}
+
+/// Requests are served by traversing a tree rooted at the requested root,
+/// so the returned proofs are the same as the ones returned by a tree.
+impl ReadSync for NodeDB {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn sync_get(&mut self, ctx: Context, request: GetRequest) -> Result<ProofResponse> {
+        self.sync_tree(&request.tree)?.sync_get(ctx, request)
+    }
+
+    fn sync_get_prefixes(
+        &mut self,
+        ctx: Context,
+        request: GetPrefixesRequest,
+    ) -> Result<ProofResponse> {
+        self.sync_tree(&request.tree)?
+            .sync_get_prefixes(ctx, request)
+    }
+
+    fn sync_iterate(&mut self, ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+        self.sync_tree(&request.tree)?.sync_iterate(ctx, request)
+    }
+}
+
+/// A read syncer which returns the single node at the requested position.
+struct NodeFetcher {
+    db: NodeDB,
+}
+
+impl NodeFetcher {
+    fn fetch(&self, ctx: Context, tree: &TreeID) -> Result<ProofResponse> {
+        let node = self.db.get_node(&tree.position)?;
+        let mut pb = ProofBuilder::new(tree.position, tree.position);
+        pb.include(&node)?;
+
+        Ok(ProofResponse {
+            proof: pb.build(ctx)?,
+            next_key: None,
+        })
+    }
+}
+
+impl ReadSync for NodeFetcher {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn sync_get(&mut self, ctx: Context, request: GetRequest) -> Result<ProofResponse> {
+        self.fetch(ctx, &request.tree)
+    }
+
+    fn sync_get_prefixes(
+        &mut self,
+        ctx: Context,
+        request: GetPrefixesRequest,
+    ) -> Result<ProofResponse> {
+        self.fetch(ctx, &request.tree)
+    }
+
+    fn sync_iterate(&mut self, ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+        self.fetch(ctx, &request.tree)
+    }
+}
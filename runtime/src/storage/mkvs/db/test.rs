@@ -0,0 +1,210 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use anyhow::{anyhow, Result};
+use io_context::Context;
+
+use crate::{
+    common::{crypto::hash::Hash, roothash::Namespace},
+    storage::mkvs::{db::*, sync::*, tree::*, LogEntry, MKVS},
+};
+
+fn namespace() -> Namespace {
+    Namespace::from(Hash::digest_bytes(b"node db test").as_ref())
+}
+
+fn insert_items(tree: &mut Tree, prefix: &str, count: usize) {
+    for idx in 0..count {
+        let key = format!("{}key {}", prefix, idx);
+        let value = format!("{}value {}", prefix, idx);
+        tree.insert(Context::background(), key.as_bytes(), value.as_bytes())
+            .expect("insert");
+    }
+}
+
+fn check_items(tree: &Tree, prefix: &str, count: usize) {
+    for idx in 0..count {
+        let key = format!("{}key {}", prefix, idx);
+        let value = format!("{}value {}", prefix, idx);
+        assert_eq!(
+            tree.get(Context::background(), key.as_bytes())
+                .expect("get"),
+            Some(value.into_bytes()),
+        );
+    }
+}
+
+#[test]
+fn test_nodedb_commit() {
+    let db = NodeDB::open(Arc::new(MemoryBackend::new()), namespace()).expect("open");
+
+    let mut tree = Tree::make().new(Box::new(NoopReadSyncer));
+    insert_items(&mut tree, "", 100);
+    let (write_log, root) = db
+        .commit(Context::background(), &mut tree, 0)
+        .expect("commit");
+    assert_eq!(write_log.len(), 100);
+    assert_eq!(db.get_roots(0).unwrap(), vec![root]);
+    assert_eq!(db.get_write_log(&root).unwrap(), write_log);
+    assert_eq!(db.earliest_version(), Some(0));
+
+    let tree = db.tree(root).expect("tree");
+    check_items(&tree, "", 100);
+
+    // The database can also serve as a read syncer for other trees.
+    let remote_tree = Tree::make()
+        .with_capacity(0, 0)
+        .with_root(root)
+        .new(Box::new(db.clone()));
+    check_items(&remote_tree, "", 100);
+    remote_tree
+        .prefetch_prefixes(Context::background(), &vec![b"key 1".to_vec().into()], 10)
+        .expect("prefetch prefixes");
+    let items: Vec<_> = remote_tree
+        .prefix(Context::background(), b"key 5")
+        .map(|item| item.expect("range scan").0)
+        .collect();
+    assert_eq!(items.len(), 11);
+
+    let missing = Root { version: 1, ..root };
+    assert!(db.tree(missing).is_err());
+    assert!(db.get_write_log(&missing).is_err());
+}
+
+/// A backend which fails to apply batches while `fail` is set.
+#[derive(Default)]
+struct FailingBackend {
+    inner: MemoryBackend,
+    fail: AtomicBool,
+}
+
+impl Backend for FailingBackend {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn apply(&self, batch: Batch) -> Result<()> {
+        if self.fail.load(Ordering::SeqCst) {
+            return Err(anyhow!("injected failure"));
+        }
+        self.inner.apply(batch)
+    }
+}
+
+#[test]
+fn test_nodedb_commit_failure() {
+    let backend = Arc::new(FailingBackend::default());
+    let db = NodeDB::open(backend.clone(), namespace()).expect("open");
+
+    let mut tree = Tree::make()
+        .with_capacity(10, 0)
+        .new(Box::new(NoopReadSyncer));
+    insert_items(&mut tree, "", 100);
+
+    // A failed write must leave the updates pending in the tree.
+    backend.fail.store(true, Ordering::SeqCst);
+    assert!(db.commit(Context::background(), &mut tree, 0).is_err());
+    assert!(db.get_roots(0).unwrap().is_empty());
+    assert_eq!(db.earliest_version(), None);
+    check_items(&tree, "", 100);
+
+    backend.fail.store(false, Ordering::SeqCst);
+    let (write_log, root) = db
+        .commit(Context::background(), &mut tree, 0)
+        .expect("commit");
+    assert_eq!(write_log.len(), 100);
+
+    let tree = db.tree(root).expect("tree");
+    check_items(&tree, "", 100);
+}
+
+#[test]
+fn test_nodedb_finalize_prune() {
+    let db = NodeDB::open(Arc::new(MemoryBackend::new()), namespace()).expect("open");
+
+    let mut tree = Tree::make().new(Box::new(NoopReadSyncer));
+    insert_items(&mut tree, "", 50);
+    let (_, root_0) = db
+        .commit(Context::background(), &mut tree, 0)
+        .expect("commit");
+
+    // Create two alternative roots in the next version.
+    let mut tree_a = db.tree(root_0).expect("tree");
+    insert_items(&mut tree_a, "a/", 10);
+    let (_, root_a) = db
+        .commit(Context::background(), &mut tree_a, 1)
+        .expect("commit");
+    let mut tree_b = db.tree(root_0).expect("tree");
+    insert_items(&mut tree_b, "b/", 10);
+    tree_b.remove(Context::background(), b"key 0").unwrap();
+    let (write_log_b, root_b) = db
+        .commit(Context::background(), &mut tree_b, 1)
+        .expect("commit");
+    assert_eq!(write_log_b.len(), 11);
+    assert_eq!(db.get_roots(1).unwrap().len(), 2);
+
+    db.finalize(1, &[root_a.hash])
+        .expect_err("previous version must be finalized first");
+    db.finalize(0, &[root_0.hash]).expect("finalize");
+    db.finalize(1, &[root_a.hash]).expect("finalize");
+    assert_eq!(db.last_finalized_version(), Some(1));
+    assert_eq!(db.get_roots(1).unwrap(), vec![root_a]);
+    assert!(!db.has_root(&root_b).unwrap());
+    assert!(db.get_node(&root_b.hash).is_err());
+
+    // Finalized versions can no longer be changed.
+    let mut tree = db.tree(root_a).expect("tree");
+    insert_items(&mut tree, "c/", 1);
+    db.commit(Context::background(), &mut tree, 1)
+        .expect_err("commit to finalized version");
+
+    db.prune(1)
+        .expect_err("only the earliest version can be pruned");
+    db.prune(0).expect("prune");
+    assert_eq!(db.earliest_version(), Some(1));
+    assert!(!db.has_root(&root_0).unwrap());
+    assert!(db.tree(root_0).is_err());
+
+    // Nodes shared with later versions must be kept.
+    let tree = db.tree(root_a).expect("tree");
+    check_items(&tree, "", 50);
+    check_items(&tree, "a/", 10);
+
+    // Removing the last root removes all nodes.
+    db.prune(1).expect("prune");
+    assert!(db.get_node(&root_a.hash).is_err());
+}
+
+#[test]
+fn test_nodedb_persistence() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("nodes.db");
+
+    let root = {
+        let backend = Arc::new(FileBackend::open(&path).expect("open backend"));
+        let db = NodeDB::open(backend, namespace()).expect("open");
+
+        let mut tree = Tree::make().new(Box::new(NoopReadSyncer));
+        insert_items(&mut tree, "", 100);
+        let (_, root) = db
+            .commit(Context::background(), &mut tree, 0)
+            .expect("commit");
+        db.finalize(0, &[root.hash]).expect("finalize");
+
+        root
+    };
+
+    let backend = Arc::new(FileBackend::open(&path).expect("reopen backend"));
+    assert!(NodeDB::open(backend.clone(), Default::default()).is_err());
+
+    let db = NodeDB::open(backend, namespace()).expect("reopen");
+    assert_eq!(db.last_finalized_version(), Some(0));
+    assert_eq!(
+        db.get_write_log(&root).unwrap()[0],
+        LogEntry::new(b"key 0", b"value 0")
+    );
+    let tree = db.tree(root).expect("tree");
+    check_items(&tree, "", 100);
+}
@@ -6,20 +6,33 @@ use base64;
 use io_context::Context;
 use serde::{self, ser::SerializeSeq, Deserialize, Serialize, Serializer};
 use serde_bytes::Bytes;
+use thiserror::Error;
 
 use crate::common::{crypto::hash::Hash, roothash::Namespace};
 
 #[macro_use]
 mod tree;
 mod cache;
+pub mod checkpoint;
+pub mod db;
 #[cfg(test)]
 mod interop;
 pub mod marshal;
+mod overlay;
+mod range;
+mod snapshot;
 pub mod sync;
 #[cfg(test)]
 mod tests;
 
-pub use tree::{Depth, Key, NodeBox, Root, Tree};
+pub use cache::{CacheStats, EvictionPolicy};
+pub use overlay::OverlayTree;
+pub use range::Range;
+pub use snapshot::{
+    NodeSource, ReadSyncSource, SnapshotError, SnapshotIterator, TreeSnapshot,
+    DEFAULT_SNAPSHOT_NODE_CAPACITY,
+};
+pub use tree::{Depth, Key, NodeBox, NodeDump, ProofSize, Root, Tree, TreeStats};
 
 /// The type of entry in the log.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -114,10 +127,44 @@ impl From<Vec<u8>> for Prefix {
     }
 }
 
+/// Error indicating that an MKVS operation failed due to a storage issue.
+///
+/// MKVS implementations attach this as context to any error encountered
+/// while accessing storage (e.g., a failed sync with the host), so that
+/// callers can distinguish storage failures from other errors.
+#[derive(Error, Debug)]
+#[error("mkvs: storage operation failed")]
+pub struct StorageError;
+
+/// An MKVS iterator.
+///
+/// Iteration errors are not returned from `next` but instead stop the
+/// iteration and are made available via `error`.
+pub trait Iterator: std::iter::Iterator<Item = (Vec<u8>, Vec<u8>)> {
+    /// Sets the number of next elements to prefetch.
+    fn set_prefetch(&mut self, prefetch: usize);
+
+    /// Return whether the iterator is valid.
+    fn is_valid(&self) -> bool;
+
+    /// Return the error that occurred during iteration if any.
+    fn error(&self) -> &Option<anyhow::Error>;
+
+    /// Take the error that occurred during iteration if any.
+    fn take_error(&mut self) -> Option<anyhow::Error>;
+
+    /// Move the iterator to the first key in the tree.
+    fn rewind(&mut self);
+
+    /// Moves the iterator either at the given key or at the next larger
+    /// key.
+    fn seek(&mut self, key: &[u8]);
+}
+
 /// Merklized key-value store.
 pub trait MKVS: Send + Sync {
     /// Fetch entry with given key.
-    fn get(&self, ctx: Context, key: &[u8]) -> Option<Vec<u8>>;
+    fn get(&self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>>;
 
     /// Check if the local MKVS cache contains the given key.
     ///
@@ -134,14 +181,42 @@ pub trait MKVS: Send + Sync {
     /// returned.
     ///
     /// [`None`]: std::option::Option
-    fn insert(&mut self, ctx: Context, key: &[u8], value: &[u8]) -> Option<Vec<u8>>;
+    fn insert(&mut self, ctx: Context, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>>;
 
     /// Remove entry with given key, returning the value at the key if the key was previously
     /// in the database.
-    fn remove(&mut self, ctx: Context, key: &[u8]) -> Option<Vec<u8>>;
+    fn remove(&mut self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>>;
 
     /// Populate the in-memory tree with nodes for keys starting with given prefixes.
-    fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16);
+    ///
+    /// At most `limit` keys are fetched, use `prefetch_all_prefixes` to fetch
+    /// all of them.
+    fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16) -> Result<()>;
+
+    /// Populate the in-memory tree with nodes for all keys starting with given
+    /// prefixes, fetching at most `page_size` keys per request.
+    fn prefetch_all_prefixes(
+        &self,
+        ctx: Context,
+        prefixes: &Vec<Prefix>,
+        page_size: u16,
+    ) -> Result<()>;
+
+    /// Returns an iterator over the MKVS, including any uncommitted changes.
+    fn iter(&self, ctx: Context) -> Box<dyn Iterator + '_>;
+
+    /// Returns an iterator over entries with keys in the range `[start, end)`.
+    ///
+    /// If `end` is `None`, the range is unbounded. See `Range` for how to
+    /// configure prefetching and the iteration order.
+    fn range(&self, ctx: Context, start: &[u8], end: Option<&[u8]>) -> Range<'_> {
+        Range::new(self.iter(ctx), start, end)
+    }
+
+    /// Returns an iterator over entries with keys starting with the given prefix.
+    fn prefix(&self, ctx: Context, prefix: &[u8]) -> Range<'_> {
+        Range::new_prefix(self.iter(ctx), prefix)
+    }
 
     /// Commit all database changes to the underlying store.
     fn commit(
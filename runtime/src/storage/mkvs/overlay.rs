@@ -0,0 +1,462 @@
+//! Overlay trees.
+use std::{
+    cmp::Ordering,
+    collections::{btree_map, BTreeMap},
+    iter::Peekable,
+    ops::Bound,
+};
+
+use anyhow::{Error, Result};
+use io_context::Context;
+
+use super::{Iterator as MKVSIterator, LogEntry, Prefix, WriteLog, MKVS};
+use crate::common::{crypto::hash::Hash, roothash::Namespace};
+
+/// An overlay over another MKVS which buffers all updates in memory.
+///
+/// Reads fall through to the parent for keys which have not been updated in
+/// the overlay. Buffered updates can either be committed into the parent or
+/// discarded. As the overlay is itself an MKVS, overlays can be nested to
+/// create multiple savepoints.
+pub struct OverlayTree<T: MKVS> {
+    inner: T,
+    /// Buffered updates where `None` marks a removed key.
+    overlay: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<T: MKVS> OverlayTree<T> {
+    /// Create a new overlay over the given parent.
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            overlay: BTreeMap::new(),
+        }
+    }
+
+    /// Return a reference to the parent.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Discard any buffered updates and return the parent.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Check whether the overlay has any buffered updates.
+    pub fn is_dirty(&self) -> bool {
+        !self.overlay.is_empty()
+    }
+
+    /// Apply all buffered updates to the parent in key order and return
+    /// the applied updates.
+    ///
+    /// If applying an update fails, the updates which have not yet been
+    /// applied remain buffered in the overlay.
+    pub fn commit_into_parent(&mut self, ctx: Context) -> Result<WriteLog> {
+        let ctx = ctx.freeze();
+        let mut log = WriteLog::new();
+        while let Some(key) = self.overlay.keys().next().cloned() {
+            match self.overlay[&key] {
+                Some(ref value) => {
+                    self.inner
+                        .insert(Context::create_child(&ctx), &key, value)?;
+                }
+                None => {
+                    self.inner.remove(Context::create_child(&ctx), &key)?;
+                }
+            }
+            let value = self.overlay.remove(&key).unwrap();
+            log.push(LogEntry { key, value });
+        }
+
+        Ok(log)
+    }
+
+    /// Discard all buffered updates.
+    pub fn discard(&mut self) {
+        self.overlay.clear();
+    }
+}
+
+impl<T: MKVS> MKVS for OverlayTree<T> {
+    fn get(&self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.overlay.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => self.inner.get(ctx, key),
+        }
+    }
+
+    fn cache_contains_key(&self, ctx: Context, key: &[u8]) -> bool {
+        match self.overlay.get(key) {
+            Some(value) => value.is_some(),
+            None => self.inner.cache_contains_key(ctx, key),
+        }
+    }
+
+    fn insert(&mut self, ctx: Context, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        let previous = self.get(ctx, key)?;
+        self.overlay.insert(key.to_vec(), Some(value.to_vec()));
+
+        Ok(previous)
+    }
+
+    fn remove(&mut self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let previous = self.get(ctx, key)?;
+        self.overlay.insert(key.to_vec(), None);
+
+        Ok(previous)
+    }
+
+    fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16) -> Result<()> {
+        self.inner.prefetch_prefixes(ctx, prefixes, limit)
+    }
+
+    fn prefetch_all_prefixes(
+        &self,
+        ctx: Context,
+        prefixes: &Vec<Prefix>,
+        page_size: u16,
+    ) -> Result<()> {
+        self.inner.prefetch_all_prefixes(ctx, prefixes, page_size)
+    }
+
+    fn iter(&self, ctx: Context) -> Box<dyn MKVSIterator + '_> {
+        Box::new(OverlayIterator::new(self.inner.iter(ctx), &self.overlay))
+    }
+
+    /// Commit all buffered updates into the parent and then commit the parent.
+    fn commit(
+        &mut self,
+        ctx: Context,
+        namespace: Namespace,
+        version: u64,
+    ) -> Result<(WriteLog, Hash)> {
+        let ctx = ctx.freeze();
+        self.commit_into_parent(Context::create_child(&ctx))?;
+        self.inner
+            .commit(Context::create_child(&ctx), namespace, version)
+    }
+
+    /// Discard all buffered updates, leaving the parent unchanged.
+    fn rollback(&mut self) {
+        self.discard();
+    }
+}
+
+impl<T: MKVS + ?Sized> MKVS for &mut T {
+    fn get(&self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        (**self).get(ctx, key)
+    }
+
+    fn cache_contains_key(&self, ctx: Context, key: &[u8]) -> bool {
+        (**self).cache_contains_key(ctx, key)
+    }
+
+    fn insert(&mut self, ctx: Context, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        (**self).insert(ctx, key, value)
+    }
+
+    fn remove(&mut self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        (**self).remove(ctx, key)
+    }
+
+    fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16) -> Result<()> {
+        (**self).prefetch_prefixes(ctx, prefixes, limit)
+    }
+
+    fn prefetch_all_prefixes(
+        &self,
+        ctx: Context,
+        prefixes: &Vec<Prefix>,
+        page_size: u16,
+    ) -> Result<()> {
+        (**self).prefetch_all_prefixes(ctx, prefixes, page_size)
+    }
+
+    fn iter(&self, ctx: Context) -> Box<dyn MKVSIterator + '_> {
+        (**self).iter(ctx)
+    }
+
+    fn commit(
+        &mut self,
+        ctx: Context,
+        namespace: Namespace,
+        version: u64,
+    ) -> Result<(WriteLog, Hash)> {
+        (**self).commit(ctx, namespace, version)
+    }
+
+    fn rollback(&mut self) {
+        (**self).rollback()
+    }
+}
+
+type OverlayRange<'a> = Peekable<btree_map::Range<'a, Vec<u8>, Option<Vec<u8>>>>;
+
+/// An iterator which merges buffered updates with the parent's entries.
+struct OverlayIterator<'a> {
+    inner: Box<dyn MKVSIterator + 'a>,
+    /// Next entry of the parent iterator.
+    inner_next: Option<(Vec<u8>, Vec<u8>)>,
+    overlay: &'a BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    overlay_it: Option<OverlayRange<'a>>,
+    key: Option<Vec<u8>>,
+    value: Option<Vec<u8>>,
+}
+
+impl<'a> OverlayIterator<'a> {
+    fn new(
+        inner: Box<dyn MKVSIterator + 'a>,
+        overlay: &'a BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    ) -> Self {
+        Self {
+            inner,
+            inner_next: None,
+            overlay,
+            overlay_it: None,
+            key: None,
+            value: None,
+        }
+    }
+
+    fn reset(&mut self, key: &[u8]) {
+        self.inner_next = self.inner.next();
+        self.overlay_it = Some(
+            self.overlay
+                .range::<[u8], _>((Bound::Included(key), Bound::Unbounded))
+                .peekable(),
+        );
+        self.advance();
+    }
+
+    /// Move to the next entry which has not been removed in the overlay.
+    fn advance(&mut self) {
+        loop {
+            let overlay_next = self.overlay_it.as_mut().and_then(|it| it.peek().cloned());
+            let order = match (overlay_next, self.inner_next.as_ref()) {
+                (None, None) => {
+                    self.key = None;
+                    self.value = None;
+                    return;
+                }
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((key, _)), Some((inner_key, _))) => key.cmp(inner_key),
+            };
+
+            if order == Ordering::Greater {
+                let (key, value) = self.inner_next.take().unwrap();
+                self.inner_next = self.inner.next();
+                self.key = Some(key);
+                self.value = Some(value);
+                return;
+            }
+            if order == Ordering::Equal {
+                // The parent's entry is shadowed by the overlay.
+                self.inner_next = self.inner.next();
+            }
+
+            let (key, value) = overlay_next.unwrap();
+            self.overlay_it.as_mut().unwrap().next();
+            if let Some(value) = value {
+                self.key = Some(key.clone());
+                self.value = Some(value.clone());
+                return;
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for OverlayIterator<'a> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.key.take()?;
+        let value = self.value.take().expect("iterator is valid");
+        self.advance();
+
+        Some((key, value))
+    }
+}
+
+impl<'a> MKVSIterator for OverlayIterator<'a> {
+    fn set_prefetch(&mut self, prefetch: usize) {
+        self.inner.set_prefetch(prefetch)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.key.is_some()
+    }
+
+    fn error(&self) -> &Option<Error> {
+        self.inner.error()
+    }
+
+    fn take_error(&mut self) -> Option<Error> {
+        self.inner.take_error()
+    }
+
+    fn rewind(&mut self) {
+        self.inner.rewind();
+        self.reset(&[]);
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.inner.seek(key);
+        self.reset(key);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::mkvs::{sync::NoopReadSyncer, Tree};
+
+    fn make_tree() -> Tree {
+        let mut tree = Tree::make().new(Box::new(NoopReadSyncer));
+        for key in &[&b"a"[..], b"b", b"c", b"d"] {
+            tree.insert(Context::background(), key, b"parent").unwrap();
+        }
+        tree
+    }
+
+    fn items(mkvs: &dyn MKVS) -> Vec<(Vec<u8>, Vec<u8>)> {
+        mkvs.range(Context::background(), b"", None)
+            .map(|item| item.expect("range scan"))
+            .collect()
+    }
+
+    #[test]
+    fn test_overlay() {
+        let mut tree = make_tree();
+        let (_, hash) =
+            MKVS::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+
+        let mut overlay = OverlayTree::new(&mut tree);
+        assert_eq!(
+            overlay
+                .insert(Context::background(), b"b", b"overlay")
+                .unwrap(),
+            Some(b"parent".to_vec())
+        );
+        assert_eq!(
+            overlay.insert(Context::background(), b"e", b"new").unwrap(),
+            None
+        );
+        assert_eq!(
+            overlay.remove(Context::background(), b"c").unwrap(),
+            Some(b"parent".to_vec())
+        );
+        assert_eq!(overlay.remove(Context::background(), b"c").unwrap(), None);
+        assert_eq!(
+            overlay.get(Context::background(), b"b").unwrap(),
+            Some(b"overlay".to_vec())
+        );
+        assert_eq!(overlay.get(Context::background(), b"c").unwrap(), None);
+        assert_eq!(
+            overlay.get(Context::background(), b"d").unwrap(),
+            Some(b"parent".to_vec())
+        );
+        assert!(!overlay.cache_contains_key(Context::background(), b"c"));
+        assert!(overlay.is_dirty());
+
+        assert_eq!(
+            items(&overlay),
+            vec![
+                (b"a".to_vec(), b"parent".to_vec()),
+                (b"b".to_vec(), b"overlay".to_vec()),
+                (b"d".to_vec(), b"parent".to_vec()),
+                (b"e".to_vec(), b"new".to_vec()),
+            ]
+        );
+        let from_c: Vec<_> = overlay
+            .range(Context::background(), b"c", None)
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(from_c, vec![b"d".to_vec(), b"e".to_vec()]);
+
+        // The parent is unchanged until the overlay is committed.
+        assert_eq!(
+            overlay.inner().get(Context::background(), b"c").unwrap(),
+            Some(b"parent".to_vec())
+        );
+        let log = overlay
+            .commit_into_parent(Context::background())
+            .expect("commit into parent");
+        assert_eq!(
+            log,
+            vec![
+                LogEntry::new(b"b", b"overlay"),
+                LogEntry {
+                    key: b"c".to_vec(),
+                    value: None,
+                },
+                LogEntry::new(b"e", b"new"),
+            ]
+        );
+        assert!(!overlay.is_dirty());
+        drop(overlay);
+
+        assert_eq!(tree.get(Context::background(), b"c").unwrap(), None);
+        let (write_log, new_hash) =
+            MKVS::commit(&mut tree, Context::background(), Default::default(), 1).expect("commit");
+        assert_eq!(write_log.len(), 3);
+        assert_ne!(new_hash, hash);
+    }
+
+    #[test]
+    fn test_overlay_nested() {
+        let mut tree = make_tree();
+
+        let mut outer = OverlayTree::new(&mut tree);
+        outer.insert(Context::background(), b"a", b"outer").unwrap();
+
+        {
+            // A discarded savepoint does not affect the outer overlay.
+            let mut inner = OverlayTree::new(&mut outer);
+            inner.insert(Context::background(), b"a", b"inner").unwrap();
+            inner.remove(Context::background(), b"b").unwrap();
+            assert_eq!(
+                inner.get(Context::background(), b"a").unwrap(),
+                Some(b"inner".to_vec())
+            );
+            inner.rollback();
+            assert_eq!(
+                inner.get(Context::background(), b"a").unwrap(),
+                Some(b"outer".to_vec())
+            );
+        }
+        assert_eq!(
+            outer.get(Context::background(), b"b").unwrap(),
+            Some(b"parent".to_vec())
+        );
+
+        {
+            let mut inner = OverlayTree::new(&mut outer);
+            inner.remove(Context::background(), b"a").unwrap();
+            inner.insert(Context::background(), b"f", b"inner").unwrap();
+            inner
+                .commit_into_parent(Context::background())
+                .expect("commit into parent");
+        }
+        assert_eq!(
+            items(&outer),
+            vec![
+                (b"b".to_vec(), b"parent".to_vec()),
+                (b"c".to_vec(), b"parent".to_vec()),
+                (b"d".to_vec(), b"parent".to_vec()),
+                (b"f".to_vec(), b"inner".to_vec()),
+            ]
+        );
+
+        // Discarding the outer overlay leaves the tree unchanged.
+        outer.discard();
+        drop(outer);
+        assert_eq!(
+            tree.get(Context::background(), b"a").unwrap(),
+            Some(b"parent".to_vec())
+        );
+        assert_eq!(tree.get(Context::background(), b"f").unwrap(), None);
+    }
+}
@@ -0,0 +1,268 @@
+//! Range scans over an MKVS.
+use std::vec;
+
+use anyhow::Result;
+
+use super::{Iterator as MKVSIterator, StorageError};
+
+/// Default number of entries to prefetch when scanning a range.
+const DEFAULT_PREFETCH: usize = 10;
+
+enum State {
+    Initial,
+    Forward,
+    Reverse(vec::IntoIter<(Vec<u8>, Vec<u8>)>),
+    Done,
+}
+
+/// An iterator over MKVS entries with keys in a given range.
+///
+/// Entries are returned in ascending key order unless `reversed` is used.
+/// As the tree can only be traversed in ascending order, reverse scans
+/// buffer the whole range in memory before returning the first entry.
+///
+/// Any error encountered while iterating is returned as the last item, with
+/// the original error as its source.
+pub struct Range<'a> {
+    it: Box<dyn MKVSIterator + 'a>,
+    start: Vec<u8>,
+    end: Option<Vec<u8>>,
+    reverse: bool,
+    state: State,
+}
+
+impl<'a> Range<'a> {
+    /// Create a new range over `[start, end)` using the given iterator.
+    pub fn new(mut it: Box<dyn MKVSIterator + 'a>, start: &[u8], end: Option<&[u8]>) -> Self {
+        it.set_prefetch(DEFAULT_PREFETCH);
+
+        Self {
+            it,
+            start: start.to_vec(),
+            end: end.map(|end| end.to_vec()),
+            reverse: false,
+            state: State::Initial,
+        }
+    }
+
+    /// Create a new range over all keys starting with the given prefix.
+    pub fn new_prefix(it: Box<dyn MKVSIterator + 'a>, prefix: &[u8]) -> Self {
+        let end = prefix_end(prefix);
+        Self::new(it, prefix, end.as_ref().map(|end| end.as_slice()))
+    }
+
+    /// Set the number of entries to prefetch from remote storage at once.
+    pub fn prefetch(mut self, prefetch: usize) -> Self {
+        self.it.set_prefetch(prefetch);
+        self
+    }
+
+    /// Return entries in descending key order.
+    ///
+    /// Reverse iteration is eager: the whole range is read into memory (and
+    /// fetched from remote storage if needed) when the first entry is
+    /// requested, so it should only be used for ranges known to be small.
+    pub fn reversed(mut self) -> Self {
+        self.reverse = true;
+        self
+    }
+
+    fn next_forward(&mut self) -> Option<Result<(Vec<u8>, Vec<u8>)>> {
+        match self.it.next() {
+            Some((key, value)) => match self.end {
+                Some(ref end) if &key >= end => None,
+                _ => Some(Ok((key, value))),
+            },
+            None => self
+                .it
+                .take_error()
+                .map(|error| Err(error.context(StorageError))),
+        }
+    }
+}
+
+impl<'a> Iterator for Range<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.state {
+                State::Initial => {
+                    self.it.seek(&self.start);
+                    if !self.reverse {
+                        self.state = State::Forward;
+                        continue;
+                    }
+
+                    let mut items = Vec::new();
+                    while let Some(item) = self.next_forward() {
+                        match item {
+                            Ok(item) => items.push(item),
+                            Err(error) => {
+                                self.state = State::Done;
+                                return Some(Err(error));
+                            }
+                        }
+                    }
+                    items.reverse();
+                    self.state = State::Reverse(items.into_iter());
+                }
+                State::Forward => {
+                    let item = self.next_forward();
+                    if !matches!(item, Some(Ok(_))) {
+                        self.state = State::Done;
+                    }
+                    return item;
+                }
+                State::Reverse(ref mut items) => return items.next().map(Ok),
+                State::Done => return None,
+            }
+        }
+    }
+}
+
+/// Returns the smallest key that is larger than all keys starting with the
+/// given prefix or `None` if there is no such key.
+pub(crate) fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use io_context::Context;
+
+    use super::*;
+    use crate::{
+        common::crypto::hash::Hash,
+        storage::mkvs::{
+            cache::Cache,
+            interop::{Driver, ProtocolServer},
+            sync::{NoopReadSyncer, StatsCollector, SyncerError},
+            Root, Tree, MKVS,
+        },
+    };
+
+    fn collect(range: Range) -> Vec<Vec<u8>> {
+        range
+            .map(|item| item.expect("range scan should not fail").0)
+            .collect()
+    }
+
+    fn keys(keys: &[&str]) -> Vec<Vec<u8>> {
+        keys.iter().map(|key| key.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_prefix_end() {
+        assert_eq!(prefix_end(b""), None);
+        assert_eq!(prefix_end(b"abc"), Some(b"abd".to_vec()));
+        assert_eq!(prefix_end(b"ab\xff"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_end(b"\xff\xff"), None);
+    }
+
+    #[test]
+    fn test_range() {
+        let mut tree = Tree::make().new(Box::new(NoopReadSyncer));
+        for key in keys(&["a", "acc/1", "acc/2", "acc/3", "b", "queue/1"]) {
+            MKVS::insert(&mut tree, Context::background(), &key, b"value").unwrap();
+        }
+        MKVS::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+
+        // Uncommitted changes should be visible.
+        MKVS::insert(&mut tree, Context::background(), b"acc/4", b"value").unwrap();
+        MKVS::remove(&mut tree, Context::background(), b"acc/2").unwrap();
+
+        assert_eq!(
+            collect(tree.prefix(Context::background(), b"acc/")),
+            keys(&["acc/1", "acc/3", "acc/4"]),
+        );
+        assert_eq!(
+            collect(tree.prefix(Context::background(), b"acc/").reversed()),
+            keys(&["acc/4", "acc/3", "acc/1"]),
+        );
+        assert_eq!(
+            collect(tree.range(Context::background(), b"acc/3", Some(&b"queue/1"[..]))),
+            keys(&["acc/3", "acc/4", "b"]),
+        );
+        assert_eq!(
+            collect(tree.range(Context::background(), b"b", None).reversed()),
+            keys(&["queue/1", "b"]),
+        );
+        assert!(collect(tree.prefix(Context::background(), b"missing")).is_empty());
+        assert_eq!(collect(tree.prefix(Context::background(), b"")).len(), 6);
+    }
+
+    #[test]
+    fn test_range_error() {
+        // A tree that needs to fetch its root, but cannot.
+        let tree = Tree::make()
+            .with_root(Root {
+                hash: Hash::digest_bytes(b"missing"),
+                ..Default::default()
+            })
+            .new(Box::new(NoopReadSyncer));
+
+        for range in vec![
+            tree.prefix(Context::background(), b"acc/"),
+            tree.prefix(Context::background(), b"acc/").reversed(),
+        ] {
+            let items: Vec<_> = range.collect();
+            assert_eq!(items.len(), 1, "error should be the only item");
+            let error = items.into_iter().next().unwrap().unwrap_err();
+            assert!(error.downcast_ref::<StorageError>().is_some());
+            assert!(matches!(
+                error.downcast_ref::<SyncerError>(),
+                Some(SyncerError::Unsupported)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_range_remote() {
+        let server = ProtocolServer::new();
+
+        let mut tree = Tree::make().new(Box::new(NoopReadSyncer));
+        for idx in 0..20 {
+            let key = format!("acc/{:02}", idx);
+            tree.insert(Context::background(), key.as_bytes(), b"value")
+                .unwrap();
+        }
+        tree.insert(Context::background(), b"other", b"value")
+            .unwrap();
+        let (write_log, hash) =
+            Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+        server.apply(&write_log, hash, Default::default(), 0);
+
+        let stats = StatsCollector::new(server.read_sync());
+        let remote_tree = Tree::make()
+            .with_capacity(0, 0)
+            .with_root(Root {
+                hash,
+                ..Default::default()
+            })
+            .new(Box::new(stats));
+
+        let items = collect(
+            remote_tree
+                .prefix(Context::background(), b"acc/")
+                .prefetch(100),
+        );
+        assert_eq!(items.len(), 20);
+
+        let cache = remote_tree.cache.borrow();
+        let stats = cache
+            .get_read_syncer()
+            .as_any()
+            .downcast_ref::<StatsCollector>()
+            .expect("stats");
+        assert_eq!(0, stats.sync_get_count, "sync_get_count");
+        assert_eq!(1, stats.sync_iterate_count, "sync_iterate_count");
+    }
+}
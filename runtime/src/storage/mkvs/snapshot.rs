@@ -0,0 +1,849 @@
+//! Thread-safe snapshots of committed trees.
+//!
+//! A `Tree` uses single-threaded node pointers and serializes all access
+//! through a lock, which is not changed by snapshots. A snapshot is a
+//! separate read-only view of a committed root which holds its nodes in
+//! immutable, reference-counted nodes, so any number of threads can read it
+//! concurrently. Nodes are loaded on first use from a node source (e.g., the
+//! node database the root was committed to or a remote read syncer) and
+//! verified against the hash referenced by their parent. Loaded nodes are
+//! kept in a bounded cache shared by all clones of the snapshot.
+//!
+//! Snapshots implement `MKVS`, so they can be used wherever the runtime
+//! expects a (read-only) tree, e.g., with `StorageContext`.
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context as AnyContext, Error, Result};
+use io_context::Context;
+use thiserror::Error;
+
+use super::{
+    db::NodeDB,
+    sync::{GetRequest, ProofVerifier, ReadSync, TreeID},
+    tree::{Depth, Key, KeyTrait, Node, NodeBox, NodePtrRef, Root, Value},
+    Iterator as MKVSIterator, Prefix, StorageError, WriteLog, MKVS,
+};
+use crate::common::{crypto::hash::Hash, roothash::Namespace};
+
+/// The default maximum number of nodes held by a snapshot's cache.
+pub const DEFAULT_SNAPSHOT_NODE_CAPACITY: usize = 50_000;
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("mkvs: snapshot node not available")]
+    NodeNotAvailable,
+    #[error("mkvs: snapshot node has an unexpected hash")]
+    NodeHashMismatch,
+    #[error("mkvs: snapshot internal node has an invalid leaf")]
+    InvalidLeaf,
+    #[error("mkvs: snapshots are read-only")]
+    ReadOnly,
+}
+
+/// A source of nodes for lazily loaded snapshots.
+pub trait NodeSource: Send + Sync {
+    /// Fetch the node with the given hash from the tree with the given root.
+    ///
+    /// The lookup of `key` in the tree passes through the node, which allows
+    /// sources that can only look nodes up by key to fetch it. Sources which
+    /// can fetch nodes by hash alone may ignore it.
+    fn get_node(&self, root: &Root, hash: &Hash, key: &[u8]) -> Result<NodeBox>;
+}
+
+impl NodeSource for NodeDB {
+    fn get_node(&self, _root: &Root, hash: &Hash, _key: &[u8]) -> Result<NodeBox> {
+        NodeDB::get_node(self, hash)
+    }
+}
+
+/// A node source which fetches nodes from a (remote) read syncer.
+///
+/// Each node is fetched with a separate request and is verified against the
+/// returned proof. Requests are serialized.
+pub struct ReadSyncSource {
+    read_syncer: Mutex<Box<dyn ReadSync + Send>>,
+}
+
+impl ReadSyncSource {
+    /// Create a node source which fetches nodes using the given read syncer.
+    pub fn new(read_syncer: Box<dyn ReadSync + Send>) -> Self {
+        Self {
+            read_syncer: Mutex::new(read_syncer),
+        }
+    }
+}
+
+impl NodeSource for ReadSyncSource {
+    fn get_node(&self, root: &Root, hash: &Hash, key: &[u8]) -> Result<NodeBox> {
+        let rsp = self.read_syncer.lock().unwrap().sync_get(
+            Context::background(),
+            GetRequest {
+                tree: TreeID {
+                    root: *root,
+                    position: *hash,
+                },
+                key: key.to_vec(),
+                include_siblings: false,
+                known_nodes: Vec::new(),
+            },
+        )?;
+        let ptr = ProofVerifier.verify_proof(Context::background(), *hash, &rsp.proof)?;
+        let ptr = ptr.borrow();
+        match ptr.node {
+            Some(ref node) => Ok(node.borrow().clone()),
+            None => Err(SnapshotError::NodeNotAvailable.into()),
+        }
+    }
+}
+
+struct SnapshotLeaf {
+    key: Key,
+    value: Value,
+}
+
+struct SnapshotInternal {
+    label: Key,
+    label_bit_length: Depth,
+    leaf: Option<Arc<SnapshotLeaf>>,
+    left: Hash,
+    right: Hash,
+}
+
+enum SnapshotNode {
+    Internal(SnapshotInternal),
+    Leaf(Arc<SnapshotLeaf>),
+}
+
+fn hash_from_ptr(ptr: &NodePtrRef) -> Hash {
+    let ptr = ptr.borrow();
+    if ptr.is_null() {
+        Hash::empty_hash()
+    } else {
+        ptr.hash
+    }
+}
+
+fn leaf_from_ptr(ptr: &NodePtrRef) -> Result<Option<Arc<SnapshotLeaf>>> {
+    let ptr = ptr.borrow();
+    if ptr.is_null() {
+        return Ok(None);
+    }
+    match ptr.node {
+        Some(ref node) => match *node.borrow() {
+            NodeBox::Leaf(ref n) => Ok(Some(Arc::new(SnapshotLeaf {
+                key: n.key.clone(),
+                value: n.value.clone(),
+            }))),
+            NodeBox::Internal(..) => Err(SnapshotError::InvalidLeaf.into()),
+        },
+        None => Err(SnapshotError::InvalidLeaf.into()),
+    }
+}
+
+impl SnapshotNode {
+    fn from_node(node: &NodeBox) -> Result<Self> {
+        match *node {
+            NodeBox::Internal(ref n) => Ok(SnapshotNode::Internal(SnapshotInternal {
+                label: n.label.clone(),
+                label_bit_length: n.label_bit_length,
+                leaf: leaf_from_ptr(&n.leaf_node)?,
+                left: hash_from_ptr(&n.left),
+                right: hash_from_ptr(&n.right),
+            })),
+            NodeBox::Leaf(ref n) => Ok(SnapshotNode::Leaf(Arc::new(SnapshotLeaf {
+                key: n.key.clone(),
+                value: n.value.clone(),
+            }))),
+        }
+    }
+}
+
+/// A cache of loaded nodes which evicts the least recently used node once
+/// it holds more than `capacity` nodes.
+struct NodeCache {
+    capacity: usize,
+    nodes: HashMap<Hash, (Arc<SnapshotNode>, u64)>,
+    /// Cached node hashes ordered by their last use.
+    used: BTreeMap<u64, Hash>,
+    clock: u64,
+}
+
+impl NodeCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            nodes: HashMap::new(),
+            used: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn get(&mut self, hash: &Hash) -> Option<Arc<SnapshotNode>> {
+        self.clock += 1;
+        let (node, last_used) = self.nodes.get_mut(hash)?;
+        self.used.remove(&*last_used);
+        *last_used = self.clock;
+        self.used.insert(self.clock, *hash);
+        Some(node.clone())
+    }
+
+    fn insert(&mut self, hash: Hash, node: Arc<SnapshotNode>) {
+        self.clock += 1;
+        if let Some((_, last_used)) = self.nodes.insert(hash, (node, self.clock)) {
+            self.used.remove(&last_used);
+        }
+        self.used.insert(self.clock, hash);
+
+        while self.capacity > 0 && self.nodes.len() > self.capacity {
+            let oldest = *self.used.keys().next().expect("cache is not empty");
+            let hash = self.used.remove(&oldest).expect("entry exists");
+            self.nodes.remove(&hash);
+        }
+    }
+}
+
+/// Return true if the first `bit_length` bits of the key equal the path.
+fn has_prefix(key: &Key, path: &Key, bit_length: Depth) -> bool {
+    if key.bit_length() < bit_length {
+        return false;
+    }
+    let full = (bit_length / 8) as usize;
+    if key[..full] != path[..full] {
+        return false;
+    }
+    let rem = bit_length % 8;
+    rem == 0 || (key[full] ^ path[full]) & (0xff << (8 - rem)) == 0
+}
+
+/// An immutable, thread-safe snapshot of a committed tree root.
+///
+/// Cloning a snapshot is cheap and clones share the node cache.
+#[derive(Clone)]
+pub struct TreeSnapshot {
+    root: Root,
+    source: Arc<dyn NodeSource>,
+    cache: Arc<Mutex<NodeCache>>,
+}
+
+impl TreeSnapshot {
+    /// Create a snapshot of the given root which loads nodes from the
+    /// given source on demand.
+    ///
+    /// At most `DEFAULT_SNAPSHOT_NODE_CAPACITY` nodes are cached.
+    pub fn new(root: Root, source: Arc<dyn NodeSource>) -> Self {
+        Self::with_capacity(root, source, DEFAULT_SNAPSHOT_NODE_CAPACITY)
+    }
+
+    /// Create a snapshot of the given root which loads nodes from the
+    /// given source on demand and caches at most `node_capacity` nodes.
+    ///
+    /// If set to 0, the cache will have an unlimited capacity.
+    pub fn with_capacity(root: Root, source: Arc<dyn NodeSource>, node_capacity: usize) -> Self {
+        Self {
+            root,
+            source,
+            cache: Arc::new(Mutex::new(NodeCache::new(node_capacity))),
+        }
+    }
+
+    /// Return the root of the snapshot.
+    pub fn root(&self) -> Root {
+        self.root
+    }
+
+    /// Load the node with the given hash, whose lookup passes through the
+    /// lookup of `key`.
+    ///
+    /// If `cached_only` is set, nodes are not loaded from the source.
+    fn load(
+        &self,
+        hash: &Hash,
+        key: &[u8],
+        cached_only: bool,
+    ) -> Result<Option<Arc<SnapshotNode>>> {
+        if hash.is_empty() {
+            return Ok(None);
+        }
+        if let Some(node) = self.cache.lock().unwrap().get(hash) {
+            return Ok(Some(node));
+        }
+        if cached_only {
+            return Err(SnapshotError::NodeNotAvailable.into());
+        }
+
+        let node = self
+            .source
+            .get_node(&self.root, hash, key)
+            .map_err(|err| err.context(SnapshotError::NodeNotAvailable))?;
+        if node.get_hash() != *hash {
+            return Err(SnapshotError::NodeHashMismatch.into());
+        }
+        let node = Arc::new(SnapshotNode::from_node(&node)?);
+        // Concurrent loads of the same node produce equal nodes, so it does
+        // not matter which one is kept.
+        self.cache.lock().unwrap().insert(*hash, node.clone());
+
+        Ok(Some(node))
+    }
+
+    fn lookup(&self, key: &[u8], cached_only: bool) -> Result<Option<Vec<u8>>> {
+        let key = key.to_vec();
+        let mut bit_depth: Depth = 0;
+        let mut node = match self.load(&self.root.hash, &key, cached_only)? {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+
+        loop {
+            let next = match *node {
+                SnapshotNode::Leaf(ref leaf) => {
+                    return Ok(Some(leaf.value.clone()).filter(|_| leaf.key == key));
+                }
+                SnapshotNode::Internal(ref n) => {
+                    let depth = bit_depth + n.label_bit_length;
+                    if key.bit_length() == depth {
+                        return Ok(n
+                            .leaf
+                            .as_ref()
+                            .filter(|leaf| leaf.key == key)
+                            .map(|leaf| leaf.value.clone()));
+                    }
+                    if key.bit_length() < depth {
+                        return Ok(None);
+                    }
+
+                    bit_depth = depth;
+                    let child = if key.get_bit(depth) {
+                        &n.right
+                    } else {
+                        &n.left
+                    };
+                    match self.load(child, &key, cached_only)? {
+                        Some(next) => next,
+                        None => return Ok(None),
+                    }
+                }
+            };
+            node = next;
+        }
+    }
+
+    /// Fetch the value for the given key.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.lookup(key, false)
+    }
+
+    /// Returns an iterator over the entries of the snapshot.
+    ///
+    /// The iterator must be positioned using `rewind` or `seek` before use.
+    pub fn iter(&self) -> SnapshotIterator<'_> {
+        SnapshotIterator {
+            snapshot: self,
+            pending: Vec::new(),
+            start: Key::new(),
+            key: None,
+            value: None,
+            error: None,
+        }
+    }
+
+    /// Load the nodes for the entries with the given prefixes, at most
+    /// `limit` entries in total if set.
+    fn prefetch(&self, prefixes: &[Prefix], mut limit: Option<usize>) -> Result<()> {
+        let mut it = self.iter();
+        for prefix in prefixes {
+            it.seek(prefix);
+            while limit != Some(0) {
+                match Iterator::next(&mut it) {
+                    Some((key, _)) if key.starts_with(prefix) => {}
+                    _ => break,
+                }
+                limit = limit.map(|limit| limit - 1);
+            }
+        }
+
+        match it.take_error() {
+            Some(err) => Err(err.context(StorageError)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl MKVS for TreeSnapshot {
+    fn get(&self, _ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get(key).context(StorageError)
+    }
+
+    fn cache_contains_key(&self, _ctx: Context, key: &[u8]) -> bool {
+        self.lookup(key, true)
+            .map_or(false, |value| value.is_some())
+    }
+
+    fn insert(&mut self, _ctx: Context, _key: &[u8], _value: &[u8]) -> Result<Option<Vec<u8>>> {
+        Err(SnapshotError::ReadOnly.into())
+    }
+
+    fn remove(&mut self, _ctx: Context, _key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Err(SnapshotError::ReadOnly.into())
+    }
+
+    fn prefetch_prefixes(&self, _ctx: Context, prefixes: &Vec<Prefix>, limit: u16) -> Result<()> {
+        self.prefetch(prefixes, Some(limit as usize))
+    }
+
+    /// Nodes are loaded one at a time, so the page size is not used.
+    fn prefetch_all_prefixes(
+        &self,
+        _ctx: Context,
+        prefixes: &Vec<Prefix>,
+        _page_size: u16,
+    ) -> Result<()> {
+        self.prefetch(prefixes, None)
+    }
+
+    fn iter(&self, _ctx: Context) -> Box<dyn MKVSIterator + '_> {
+        Box::new(self.iter())
+    }
+
+    fn commit(
+        &mut self,
+        _ctx: Context,
+        _namespace: Namespace,
+        _version: u64,
+    ) -> Result<(WriteLog, Hash)> {
+        Err(SnapshotError::ReadOnly.into())
+    }
+
+    /// Snapshots have no pending changes.
+    fn rollback(&mut self) {}
+}
+
+enum Pending {
+    /// A subtree which has not been visited yet, together with the path to
+    /// it.
+    Subtree {
+        hash: Hash,
+        path: Key,
+        bit_depth: Depth,
+    },
+    Leaf(Arc<SnapshotLeaf>),
+}
+
+/// An iterator over the entries of a snapshot in key order.
+///
+/// Subtrees which only contain keys before the key passed to `seek` are not
+/// loaded.
+pub struct SnapshotIterator<'a> {
+    snapshot: &'a TreeSnapshot,
+    pending: Vec<Pending>,
+    /// Entries with keys before this key are skipped.
+    start: Key,
+    key: Option<Key>,
+    value: Option<Value>,
+    error: Option<Error>,
+}
+
+impl<'a> SnapshotIterator<'a> {
+    /// Move the iterator to the next entry which is not before `start`.
+    fn advance(&mut self) {
+        self.key = None;
+        self.value = None;
+
+        while let Some(pending) = self.pending.pop() {
+            let (hash, path, bit_depth) = match pending {
+                Pending::Leaf(leaf) => {
+                    if leaf.key < self.start {
+                        continue;
+                    }
+                    self.key = Some(leaf.key.clone());
+                    self.value = Some(leaf.value.clone());
+                    return;
+                }
+                Pending::Subtree {
+                    hash,
+                    path,
+                    bit_depth,
+                } => (hash, path, bit_depth),
+            };
+
+            // All keys in the subtree start with the path and are therefore
+            // not before it. Unless the start key is also in the subtree, the
+            // whole subtree is either before or after it.
+            if self.start > path && !has_prefix(&self.start, &path, bit_depth) {
+                continue;
+            }
+
+            let node = match self.snapshot.load(&hash, &path, false) {
+                Ok(Some(node)) => node,
+                Ok(None) => continue,
+                Err(err) => {
+                    self.pending.clear();
+                    self.error = Some(err);
+                    return;
+                }
+            };
+
+            match *node {
+                SnapshotNode::Leaf(ref leaf) => self.pending.push(Pending::Leaf(leaf.clone())),
+                SnapshotNode::Internal(ref n) => {
+                    let bit_length = bit_depth + n.label_bit_length;
+                    let path = path.merge(bit_depth, &n.label, n.label_bit_length);
+
+                    // The leaf comes first as its key is a prefix of all keys
+                    // in the left and right subtrees.
+                    self.pending.push(Pending::Subtree {
+                        hash: n.right,
+                        path: path.append_bit(bit_length, true),
+                        bit_depth: bit_length + 1,
+                    });
+                    self.pending.push(Pending::Subtree {
+                        hash: n.left,
+                        path: path.append_bit(bit_length, false),
+                        bit_depth: bit_length + 1,
+                    });
+                    if let Some(ref leaf) = n.leaf {
+                        self.pending.push(Pending::Leaf(leaf.clone()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for SnapshotIterator<'a> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.key.take()?;
+        let value = self.value.take().expect("iterator is valid");
+        self.advance();
+
+        Some((key, value))
+    }
+}
+
+impl<'a> MKVSIterator for SnapshotIterator<'a> {
+    /// Nodes are loaded one at a time, so prefetching is not supported.
+    fn set_prefetch(&mut self, _prefetch: usize) {}
+
+    fn is_valid(&self) -> bool {
+        self.key.is_some()
+    }
+
+    fn error(&self) -> &Option<Error> {
+        &self.error
+    }
+
+    fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    fn rewind(&mut self) {
+        self.seek(&[])
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        self.start = key.to_vec();
+        self.pending = vec![Pending::Subtree {
+            hash: self.snapshot.root.hash,
+            path: Key::new(),
+            bit_depth: 0,
+        }];
+        self.advance();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+
+    use io_context::Context;
+
+    use super::*;
+    use crate::{
+        common::roothash::Namespace,
+        storage::mkvs::{
+            db::MemoryBackend,
+            sync::NoopReadSyncer,
+            tree::tree_test::{generate_key_value_pairs_ex, make_tree},
+            Tree,
+        },
+    };
+
+    fn collect(it: &mut SnapshotIterator) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let items: Vec<_> = it.by_ref().collect();
+        assert!(it.error().is_none(), "iterate: {:?}", it.error());
+        items
+    }
+
+    fn sorted_entries() -> Vec<(Vec<u8>, Vec<u8>)> {
+        let (keys, values) = generate_key_value_pairs_ex("".to_string(), 100);
+        let mut entries: Vec<_> = keys.into_iter().zip(values.into_iter()).collect();
+        entries.sort();
+        entries
+    }
+
+    fn check_concurrent_reads(snapshot: TreeSnapshot) {
+        let snapshot = Arc::new(snapshot);
+        let (keys, values) = generate_key_value_pairs_ex("".to_string(), 100);
+        let handles: Vec<_> = (0..4)
+            .map(|thread| {
+                let snapshot = snapshot.clone();
+                let (keys, values) = (keys.clone(), values.clone());
+                thread::spawn(move || {
+                    for idx in (thread..100).step_by(4) {
+                        assert_eq!(
+                            snapshot.get(&keys[idx]).expect("get"),
+                            Some(values[idx].clone())
+                        );
+                    }
+                    assert_eq!(snapshot.get(b"key").expect("get"), None);
+                    assert_eq!(snapshot.get(b"key 100").expect("get"), None);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("reader thread");
+        }
+
+        let mut it = snapshot.iter();
+        it.rewind();
+        assert_eq!(collect(&mut it), sorted_entries());
+    }
+
+    /// A node source which counts the nodes it serves.
+    struct CountingSource {
+        db: NodeDB,
+        count: AtomicUsize,
+    }
+
+    impl NodeSource for CountingSource {
+        fn get_node(&self, root: &Root, hash: &Hash, key: &[u8]) -> Result<NodeBox> {
+            self.count.fetch_add(1, Ordering::SeqCst);
+            NodeSource::get_node(&self.db, root, hash, key)
+        }
+    }
+
+    fn open_db() -> NodeDB {
+        let namespace = Namespace::from(Hash::digest_bytes(b"snapshot test").as_ref());
+        NodeDB::open(Arc::new(MemoryBackend::new()), namespace).expect("open")
+    }
+
+    fn counting_source() -> (Arc<CountingSource>, Root) {
+        let db = open_db();
+        let mut tree = make_tree(100);
+        let (_, root) = db
+            .commit(Context::background(), &mut tree, 0)
+            .expect("commit");
+        let source = Arc::new(CountingSource {
+            db,
+            count: AtomicUsize::new(0),
+        });
+        (source, root)
+    }
+
+    #[test]
+    fn test_snapshot_from_nodedb() {
+        let db = open_db();
+        let mut tree = make_tree(100);
+        let (_, root) = db
+            .commit(Context::background(), &mut tree, 0)
+            .expect("commit");
+
+        check_concurrent_reads(db.snapshot(root).expect("snapshot"));
+
+        let missing = Root { version: 1, ..root };
+        assert!(db.snapshot(missing).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_from_read_syncer() {
+        let (keys, values) = generate_key_value_pairs_ex("".to_string(), 100);
+        let mut tree = Tree::make()
+            .with_capacity(0, 0)
+            .new(Box::new(NoopReadSyncer));
+        for (key, value) in keys.iter().zip(values.iter()) {
+            tree.insert(Context::background(), key, value)
+                .expect("insert");
+        }
+        let (_, hash) =
+            Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+        let root = Root {
+            hash,
+            ..Default::default()
+        };
+
+        let source = Arc::new(ReadSyncSource::new(Box::new(tree)));
+        check_concurrent_reads(TreeSnapshot::new(root, source));
+    }
+
+    #[test]
+    fn test_snapshot_lazy() {
+        let (source, root) = counting_source();
+
+        // Only the nodes on the path to the key are loaded, once.
+        let snapshot = TreeSnapshot::new(root, source.clone());
+        assert_eq!(
+            snapshot.get(b"key 42").expect("get"),
+            Some(b"value 42".to_vec())
+        );
+        let loaded = source.count.load(Ordering::SeqCst);
+        assert!(loaded > 0 && loaded < 20, "loaded {} nodes", loaded);
+        snapshot.clone().get(b"key 42").expect("get");
+        assert_eq!(source.count.load(Ordering::SeqCst), loaded);
+
+        // Nodes which are not available are reported as errors.
+        let snapshot = TreeSnapshot::new(
+            Root {
+                hash: Hash::digest_bytes(b"missing"),
+                ..root
+            },
+            source,
+        );
+        let err = snapshot.get(b"key 42").unwrap_err();
+        assert!(err.downcast_ref::<SnapshotError>().is_some());
+        let mut it = snapshot.iter();
+        it.rewind();
+        assert!(!it.is_valid());
+        assert!(it.next().is_none());
+        assert!(it.take_error().is_some());
+    }
+
+    #[test]
+    fn test_snapshot_cache_bounded() {
+        let (source, root) = counting_source();
+
+        let snapshot = TreeSnapshot::with_capacity(root, source.clone(), 10);
+        let mut it = snapshot.iter();
+        it.rewind();
+        assert_eq!(collect(&mut it), sorted_entries());
+        let loaded = source.count.load(Ordering::SeqCst);
+        assert!(loaded > 10, "loaded {} nodes", loaded);
+        assert_eq!(snapshot.cache.lock().unwrap().nodes.len(), 10);
+
+        // Evicted nodes are loaded again.
+        assert_eq!(
+            snapshot.get(b"key 0").expect("get"),
+            Some(b"value 0".to_vec())
+        );
+        assert!(source.count.load(Ordering::SeqCst) > loaded);
+        assert_eq!(snapshot.cache.lock().unwrap().nodes.len(), 10);
+    }
+
+    #[test]
+    fn test_snapshot_seek() {
+        let (source, root) = counting_source();
+        let entries = sorted_entries();
+
+        let snapshot = TreeSnapshot::new(root, source.clone());
+        let mut it = snapshot.iter();
+        let seek_keys: [&[u8]; 10] = [
+            b"",
+            b"k",
+            b"key",
+            b"key ",
+            b"key 1",
+            b"key 42",
+            b"key 42\x00",
+            b"key 99",
+            b"kez",
+            b"\xff",
+        ];
+        for &seek_key in seek_keys.iter() {
+            it.seek(seek_key);
+            let expected: Vec<_> = entries
+                .iter()
+                .filter(|(key, _)| &key[..] >= seek_key)
+                .cloned()
+                .collect();
+            assert_eq!(it.is_valid(), !expected.is_empty());
+            assert_eq!(collect(&mut it), expected, "seek to {:?}", seek_key);
+        }
+
+        // Subtrees before the seek key are not loaded.
+        let (source, root) = counting_source();
+        let snapshot = TreeSnapshot::new(root, source.clone());
+        let mut it = snapshot.iter();
+        it.seek(b"key 99");
+        assert_eq!(it.next(), Some((b"key 99".to_vec(), b"value 99".to_vec())));
+        let loaded = source.count.load(Ordering::SeqCst);
+        assert!(loaded < 20, "loaded {} nodes", loaded);
+    }
+
+    #[test]
+    fn test_snapshot_mkvs() {
+        let (source, root) = counting_source();
+        let mut snapshot = TreeSnapshot::new(root, source);
+
+        {
+            let mkvs: &mut dyn MKVS = &mut snapshot;
+            assert!(!mkvs.cache_contains_key(Context::background(), b"key 42"));
+            assert_eq!(
+                mkvs.get(Context::background(), b"key 42").expect("get"),
+                Some(b"value 42".to_vec())
+            );
+            assert!(mkvs.cache_contains_key(Context::background(), b"key 42"));
+
+            mkvs.prefetch_prefixes(Context::background(), &vec![b"key 5".to_vec().into()], 100)
+                .expect("prefetch");
+            assert!(mkvs.cache_contains_key(Context::background(), b"key 57"));
+            assert!(!mkvs.cache_contains_key(Context::background(), b"key 70"));
+
+            let items: Vec<_> = mkvs
+                .prefix(Context::background(), b"key 1")
+                .map(|item| item.expect("iterate"))
+                .collect();
+            let expected: Vec<_> = sorted_entries()
+                .into_iter()
+                .filter(|(key, _)| key.starts_with(b"key 1"))
+                .collect();
+            assert_eq!(items, expected);
+
+            // Updates are rejected.
+            for err in vec![
+                mkvs.insert(Context::background(), b"key", b"value")
+                    .unwrap_err(),
+                mkvs.remove(Context::background(), b"key 42").unwrap_err(),
+                mkvs.commit(Context::background(), Default::default(), 1)
+                    .unwrap_err(),
+            ] {
+                assert!(matches!(
+                    err.downcast_ref::<SnapshotError>(),
+                    Some(SnapshotError::ReadOnly)
+                ));
+            }
+            mkvs.rollback();
+        }
+
+        assert_eq!(
+            snapshot.get(b"key 42").expect("get"),
+            Some(b"value 42".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_snapshot_empty() {
+        let db = open_db();
+        let mut tree = Tree::make().new(Box::new(NoopReadSyncer));
+        let (_, root) = db
+            .commit(Context::background(), &mut tree, 0)
+            .expect("commit");
+
+        let snapshot = db.snapshot(root).expect("snapshot");
+        assert_eq!(snapshot.get(b"key").expect("get"), None);
+        let mut it = snapshot.iter();
+        it.rewind();
+        assert!(!it.is_valid());
+        assert!(collect(&mut it).is_empty());
+    }
+}
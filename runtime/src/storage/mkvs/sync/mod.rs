@@ -4,6 +4,7 @@ mod host;
 mod merge;
 mod noop;
 mod proof;
+mod query;
 mod stats;
 mod sync;
 
@@ -12,6 +13,7 @@ pub use host::*;
 pub use merge::*;
 pub use noop::*;
 pub use proof::*;
+pub use query::*;
 pub use stats::*;
 pub use sync::*;
 
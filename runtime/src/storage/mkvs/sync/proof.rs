@@ -1,4 +1,7 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::{Deref, DerefMut},
+};
 
 use anyhow::{anyhow, Result};
 use arbitrary::Arbitrary;
@@ -15,6 +18,16 @@ use crate::{
 const PROOF_ENTRY_FULL: u8 = 0x01;
 /// Proof entry type for subtree hashes.
 const PROOF_ENTRY_HASH: u8 = 0x02;
+/// Proof entry type for nodes already known to the verifier.
+///
+/// The entry contains the node hash followed by a byte of child flags. Only
+/// the entries of the flagged children follow, all other children are taken
+/// from the known node.
+const PROOF_ENTRY_KNOWN: u8 = 0x03;
+/// Known node flag set when the entries of the left child follow.
+const KNOWN_FLAG_LEFT: u8 = 0x01;
+/// Known node flag set when the entries of the right child follow.
+const KNOWN_FLAG_RIGHT: u8 = 0x02;
 
 /// A raw proof entry.
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Arbitrary)]
@@ -63,6 +76,207 @@ pub struct Proof {
     pub entries: Vec<Option<RawProofEntry>>,
 }
 
+/// A node included in a proof.
+struct ProofNode {
+    serialized: Vec<u8>,
+    children: Vec<Hash>,
+}
+
+/// A proof builder enables generating proofs for the ReadSyncer API.
+///
+/// The generated proofs use the same pre-order format as proofs generated
+/// by the Go implementation and can be verified using `ProofVerifier`.
+pub struct ProofBuilder {
+    root: Hash,
+    subtree_root: Hash,
+    included: HashMap<Hash, ProofNode>,
+    known_nodes: HashSet<Hash>,
+}
+
+impl ProofBuilder {
+    /// Create a new proof builder for the given root hash.
+    ///
+    /// The subtree root is the hash of the node at the caller's position in
+    /// the tree. If the subtree root is included, the generated proof will
+    /// be for the subtree below it, the same as with the Go implementation.
+    /// Otherwise the proof will be for the root.
+    pub fn new(root: Hash, subtree_root: Hash) -> Self {
+        Self {
+            root,
+            subtree_root,
+            included: HashMap::new(),
+            known_nodes: HashSet::new(),
+        }
+    }
+
+    /// Set the hashes of nodes which the caller already holds.
+    ///
+    /// Such nodes are encoded as known node entries which only contain the
+    /// node hash. Their children are only encoded if they are included, so
+    /// subtrees below known nodes which are not part of the proof are
+    /// skipped. Proofs with known node entries can only be verified using
+    /// `verify_compact_proof`.
+    pub fn with_known_nodes(mut self, known_nodes: &[Hash]) -> Self {
+        self.known_nodes = known_nodes.iter().cloned().collect();
+        self
+    }
+
+    /// Include a node in the proof.
+    ///
+    /// The node must be clean. Children of included internal nodes which are
+    /// not themselves included are represented by their hashes.
+    pub fn include(&mut self, node: &NodeBox) -> Result<()> {
+        if !node.is_clean() {
+            return Err(anyhow!("proof builder: node is not clean"));
+        }
+
+        let hash = node.get_hash();
+        if self.included.contains_key(&hash) {
+            return Ok(());
+        }
+
+        let node = match node {
+            NodeBox::Internal(ref n) => ProofNode {
+                serialized: n.compact_marshal_binary()?,
+                children: vec![n.left.borrow().hash, n.right.borrow().hash],
+            },
+            NodeBox::Leaf(ref n) => ProofNode {
+                serialized: n.marshal_binary()?,
+                children: vec![],
+            },
+        };
+        self.included.insert(hash, node);
+
+        Ok(())
+    }
+
+    /// Check whether the subtree root node has been included.
+    pub fn has_subtree_root(&self) -> bool {
+        self.included.contains_key(&self.subtree_root)
+    }
+
+    /// Build the proof.
+    pub fn build(&self, _ctx: Context) -> Result<Proof> {
+        if self.included.contains_key(&self.subtree_root) {
+            // Nodes outside the subtree are omitted, as they may not be
+            // merged into a caller's tree which has uncommitted changes.
+            let mut proof = Proof {
+                untrusted_root: self.subtree_root,
+                entries: Vec::new(),
+            };
+            self._build(&mut proof, self.subtree_root);
+            return Ok(proof);
+        }
+
+        if !self.root.is_empty() && !self.included.contains_key(&self.root) {
+            return Err(anyhow!("proof builder: root node not included"));
+        }
+
+        let mut proof = Proof {
+            untrusted_root: self.root,
+            entries: Vec::new(),
+        };
+        self._build(&mut proof, self.root);
+
+        Ok(proof)
+    }
+
+    fn _build(&self, proof: &mut Proof, hash: Hash) {
+        if hash.is_empty() {
+            proof.entries.push(None);
+            return;
+        }
+
+        match self.included.get(&hash) {
+            Some(node) => {
+                // Pre-order traversal, add visited node followed by its children.
+                if self.known_nodes.contains(&hash) {
+                    // Only included children follow, the verifier takes the
+                    // others from the known node.
+                    let mut flags = 0;
+                    let mut children = Vec::new();
+                    for (child, flag) in node
+                        .children
+                        .iter()
+                        .zip(&[KNOWN_FLAG_LEFT, KNOWN_FLAG_RIGHT])
+                    {
+                        if self.included.contains_key(child) {
+                            flags |= flag;
+                            children.push(*child);
+                        }
+                    }
+
+                    let mut entry = Vec::with_capacity(2 + Hash::len());
+                    entry.push(PROOF_ENTRY_KNOWN);
+                    entry.extend_from_slice(hash.as_ref());
+                    entry.push(flags);
+                    proof.entries.push(Some(entry.into()));
+
+                    for child in children {
+                        self._build(proof, child);
+                    }
+                    return;
+                }
+
+                let mut entry = Vec::with_capacity(1 + node.serialized.len());
+                entry.push(PROOF_ENTRY_FULL);
+                entry.extend_from_slice(&node.serialized);
+                proof.entries.push(Some(entry.into()));
+
+                for child in &node.children {
+                    self._build(proof, *child);
+                }
+            }
+            None => {
+                // Node is not included in this proof, just add the subtree hash.
+                let mut entry = Vec::with_capacity(1 + Hash::len());
+                entry.push(PROOF_ENTRY_HASH);
+                entry.extend_from_slice(hash.as_ref());
+                proof.entries.push(Some(entry.into()));
+            }
+        }
+    }
+}
+
+/// A source of nodes already held by a verifier, used to verify proofs
+/// containing known node entries.
+pub trait KnownNodes {
+    /// Return the serialized node with the given hash, if it is known.
+    fn get_known_node(&self, hash: &Hash) -> Option<Vec<u8>>;
+}
+
+impl KnownNodes for HashMap<Hash, Vec<u8>> {
+    fn get_known_node(&self, hash: &Hash) -> Option<Vec<u8>> {
+        self.get(hash).cloned()
+    }
+}
+
+impl KnownNodes for HashMap<Hash, NodeRef> {
+    fn get_known_node(&self, hash: &Hash) -> Option<Vec<u8>> {
+        self.get(hash)
+            .and_then(|node| node.borrow().marshal_binary().ok())
+    }
+}
+
+/// Collect all nodes of a verified subtree, so they can be passed as known
+/// nodes when verifying later proofs.
+pub fn collect_known_nodes(ptr: &NodePtrRef, known: &mut HashMap<Hash, Vec<u8>>) -> Result<()> {
+    let ptr = ptr.borrow();
+    let node_ref = match ptr.node {
+        Some(ref node_ref) if !ptr.is_null() => node_ref.clone(),
+        _ => return Ok(()),
+    };
+
+    let node = node_ref.borrow();
+    known.insert(ptr.hash, node.marshal_binary()?);
+    if let NodeBox::Internal(ref n) = *node {
+        collect_known_nodes(&n.left, known)?;
+        collect_known_nodes(&n.right, known)?;
+    }
+
+    Ok(())
+}
+
 /// A proof verifier enables verifying proofs returned by the ReadSyncer API.
 pub struct ProofVerifier;
 
@@ -70,6 +284,28 @@ impl ProofVerifier {
     /// Verify a proof and generate an in-memory subtree representing the
     /// nodes which are included in the proof.
     pub fn verify_proof(&self, _ctx: Context, root: Hash, proof: &Proof) -> Result<NodePtrRef> {
+        self._verify_root(root, proof, None)
+    }
+
+    /// Verify a proof which may contain known node entries, resolving them
+    /// using the given known nodes, and generate an in-memory subtree
+    /// representing the nodes which are included in the proof.
+    pub fn verify_compact_proof(
+        &self,
+        _ctx: Context,
+        root: Hash,
+        proof: &Proof,
+        known: &dyn KnownNodes,
+    ) -> Result<NodePtrRef> {
+        self._verify_root(root, proof, Some(known))
+    }
+
+    fn _verify_root(
+        &self,
+        root: Hash,
+        proof: &Proof,
+        known: Option<&dyn KnownNodes>,
+    ) -> Result<NodePtrRef> {
         // Sanity check that the proof is for the correct root (as otherwise it
         // makes no sense to verify the proof).
         if proof.untrusted_root != root {
@@ -83,7 +319,7 @@ impl ProofVerifier {
             return Err(anyhow!("verifier: empty proof"));
         }
 
-        let (_, root_node) = self._verify_proof(proof, 0)?;
+        let (_, root_node) = self._verify_proof(proof, 0, known)?;
         let root_hash = root_node.borrow().hash;
         if root_hash != root {
             return Err(anyhow!(
@@ -96,7 +332,12 @@ impl ProofVerifier {
         Ok(root_node)
     }
 
-    fn _verify_proof(&self, proof: &Proof, idx: usize) -> Result<(usize, NodePtrRef)> {
+    fn _verify_proof(
+        &self,
+        proof: &Proof,
+        idx: usize,
+        known: Option<&dyn KnownNodes>,
+    ) -> Result<(usize, NodePtrRef)> {
         if idx >= proof.entries.len() {
             return Err(anyhow!("verifier: malformed proof"));
         }
@@ -118,11 +359,11 @@ impl ProofVerifier {
                 let mut pos = idx + 1;
                 if let NodeBox::Internal(ref mut nd) = node {
                     // Left.
-                    let result = self._verify_proof(&proof, pos)?;
+                    let result = self._verify_proof(&proof, pos, known)?;
                     pos = result.0;
                     nd.left = result.1;
                     // Right.
-                    let result = self._verify_proof(&proof, pos)?;
+                    let result = self._verify_proof(&proof, pos, known)?;
                     pos = result.0;
                     nd.right = result.1;
 
@@ -141,6 +382,51 @@ impl ProofVerifier {
 
                 Ok((idx + 1, NodePointer::hash_ptr(entry.into())))
             }
+            PROOF_ENTRY_KNOWN => {
+                // Node already known to the verifier.
+                let entry = &entry[1..];
+                if entry.len() != Hash::len() + 1 {
+                    return Err(anyhow!("verifier: malformed known node entry"));
+                }
+                let hash: Hash = entry[..Hash::len()].into();
+                let flags = entry[Hash::len()];
+                let serialized = known
+                    .and_then(|known| known.get_known_node(&hash))
+                    .ok_or_else(|| anyhow!("verifier: unknown node in proof ({:?})", hash))?;
+
+                // Known nodes use the full encoding, so children which do not
+                // follow are hash pointers.
+                let mut node = NodeBox::default();
+                node.unmarshal_binary(&serialized)?;
+                let mut pos = idx + 1;
+                match node {
+                    NodeBox::Internal(ref mut nd) => {
+                        if flags & !(KNOWN_FLAG_LEFT | KNOWN_FLAG_RIGHT) != 0 {
+                            return Err(anyhow!("verifier: malformed known node entry"));
+                        }
+                        if flags & KNOWN_FLAG_LEFT != 0 {
+                            let result = self._verify_proof(&proof, pos, known)?;
+                            pos = result.0;
+                            nd.left = result.1;
+                        }
+                        if flags & KNOWN_FLAG_RIGHT != 0 {
+                            let result = self._verify_proof(&proof, pos, known)?;
+                            pos = result.0;
+                            nd.right = result.1;
+                        }
+                        nd.update_hash();
+                    }
+                    NodeBox::Leaf(..) if flags != 0 => {
+                        return Err(anyhow!("verifier: malformed known node entry"));
+                    }
+                    NodeBox::Leaf(..) => {}
+                }
+                if node.get_hash() != hash {
+                    return Err(anyhow!("verifier: known node hash mismatch ({:?})", hash));
+                }
+
+                Ok((pos, NodePointer::from_node(node)))
+            }
             entry_type => Err(anyhow!(
                 "verifier: unexpected entry in proof ({:?})",
                 entry_type
@@ -0,0 +1,176 @@
+use anyhow::Result;
+use io_context::Context;
+use thiserror::Error;
+
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{range::prefix_end, sync::*, tree::*},
+};
+
+#[derive(Error, Debug)]
+pub enum QueryError {
+    #[error("verifier: proof is incomplete for the query")]
+    IncompleteProof,
+    #[error("verifier: key is present")]
+    KeyPresent,
+}
+
+/// A query that can be answered using a proof.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Query {
+    /// Look up the value of a single key, which may be absent.
+    Get(Vec<u8>),
+    /// Require a single key to be absent.
+    Absent(Vec<u8>),
+    /// Look up all entries with keys starting with the given prefix.
+    Prefix(Vec<u8>),
+    /// Look up all entries with keys in `[start, end)`.
+    Range {
+        start: Vec<u8>,
+        end: Option<Vec<u8>>,
+    },
+}
+
+impl Query {
+    /// Return the key range `[start, end)` covered by the query.
+    fn bounds(&self) -> (Vec<u8>, Option<Vec<u8>>) {
+        match self {
+            Query::Get(key) | Query::Absent(key) => {
+                // The only key in [key, key || 0x00) is the key itself.
+                let mut end = key.clone();
+                end.push(0x00);
+                (key.clone(), Some(end))
+            }
+            Query::Prefix(prefix) => (prefix.clone(), prefix_end(prefix)),
+            Query::Range { start, end } => (start.clone(), end.clone()),
+        }
+    }
+}
+
+impl ProofVerifier {
+    /// Verify a proof against a trusted root hash and answer the given query.
+    ///
+    /// Returns all entries matching the query in ascending key order, so a
+    /// `Get` query returns at most one entry and an `Absent` query returns no
+    /// entries. Fails if the proof is invalid or if it omits any part of the
+    /// tree which could contain matching entries, so the result is guaranteed
+    /// to be complete.
+    pub fn verify_query(
+        &self,
+        ctx: Context,
+        root: Hash,
+        proof: &Proof,
+        query: &Query,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let root_ptr = self.verify_proof(ctx, root, proof)?;
+        let (start, end) = query.bounds();
+        let bounds = Bounds {
+            start: &start,
+            end: end.as_ref(),
+        };
+        let mut entries = Vec::new();
+        bounds.collect(&root_ptr, &Key::new(), 0, &mut entries)?;
+
+        if let Query::Absent(..) = query {
+            if !entries.is_empty() {
+                return Err(QueryError::KeyPresent.into());
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+struct Bounds<'a> {
+    start: &'a Key,
+    end: Option<&'a Key>,
+}
+
+impl<'a> Bounds<'a> {
+    fn contains(&self, key: &Key) -> bool {
+        key >= self.start && self.end.map_or(true, |end| key < end)
+    }
+
+    /// Check whether all keys starting with the first `path_len` bits of
+    /// `path` are smaller than `bound`.
+    fn below(path: &Key, path_len: Depth, bound: &Key) -> bool {
+        let bound_len = bound.bit_length();
+        let cp_len = path.common_prefix_len(path_len, bound, bound_len);
+        if cp_len == path_len || cp_len == bound_len {
+            // Either the bound starts with the path or all keys starting with
+            // the path are longer than the bound and start with it.
+            return false;
+        }
+        !path.get_bit(cp_len)
+    }
+
+    /// Check whether all keys starting with the first `path_len` bits of
+    /// `path` are greater than or equal to `bound`.
+    fn at_or_above(path: &Key, path_len: Depth, bound: &Key) -> bool {
+        // The smallest such key is the path padded with zero bits.
+        let (smallest, _) = path.split(path_len, path_len);
+        &smallest >= bound
+    }
+
+    /// Check whether any key starting with the first `path_len` bits of
+    /// `path` can be within the bounds.
+    fn intersects(&self, path: &Key, path_len: Depth) -> bool {
+        !Self::below(path, path_len, self.start)
+            && self
+                .end
+                .map_or(true, |end| !Self::at_or_above(path, path_len, end))
+    }
+
+    fn collect(
+        &self,
+        ptr: &NodePtrRef,
+        path: &Key,
+        path_len: Depth,
+        entries: &mut Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<()> {
+        let ptr = ptr.borrow();
+        if ptr.is_null() || !self.intersects(path, path_len) {
+            return Ok(());
+        }
+        let node_ref = match ptr.node {
+            Some(ref node_ref) => node_ref.clone(),
+            None => return Err(QueryError::IncompleteProof.into()),
+        };
+
+        let node = node_ref.borrow();
+        match *node {
+            NodeBox::Leaf(ref n) => {
+                if self.contains(&n.key) {
+                    entries.push((n.key.clone(), n.value.clone()));
+                }
+            }
+            NodeBox::Internal(ref n) => {
+                let full_path = path.merge(path_len, &n.label, n.label_bit_length);
+                let full_len = path_len + n.label_bit_length;
+                if !self.intersects(&full_path, full_len) {
+                    return Ok(());
+                }
+
+                // Leaf keys end at this node so they sort before all children.
+                if let Some(ref leaf) = n.leaf_node.borrow().node {
+                    if let NodeBox::Leaf(ref leaf) = *leaf.borrow() {
+                        if self.contains(&leaf.key) {
+                            entries.push((leaf.key.clone(), leaf.value.clone()));
+                        }
+                    }
+                }
+
+                // Labels of child nodes include the bit used to select them,
+                // so only the parent path is passed down.
+                for (child, bit) in &[(&n.left, false), (&n.right, true)] {
+                    let child_path = full_path.append_bit(full_len, *bit);
+                    if self.intersects(&child_path, full_len + 1) {
+                        self.collect(child, &full_path, full_len, entries)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
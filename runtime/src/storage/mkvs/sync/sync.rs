@@ -30,6 +30,10 @@ pub struct GetRequest {
     pub key: Vec<u8>,
     #[serde(default)]
     pub include_siblings: bool,
+    /// Hashes of nodes which the caller already holds. Servers may replace
+    /// these nodes with known node entries in the returned proof.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub known_nodes: Vec<Hash>,
 }
 
 /// Request for the SyncGetPrefixes operation.
@@ -38,6 +42,14 @@ pub struct GetPrefixesRequest {
     pub tree: TreeID,
     pub prefixes: Vec<Prefix>,
     pub limit: u16,
+    /// Key to continue from, skipping all keys before it. Used to fetch the
+    /// following page after a response with `next_key` set.
+    #[serde(default, with = "serde_bytes", skip_serializing_if = "Option::is_none")]
+    pub start_key: Option<Vec<u8>>,
+    /// Hashes of nodes which the caller already holds. Servers may replace
+    /// these nodes with known node entries in the returned proof.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub known_nodes: Vec<Hash>,
 }
 
 /// Request for the SyncIterate operation.
@@ -47,12 +59,20 @@ pub struct IterateRequest {
     #[serde(with = "serde_bytes")]
     pub key: Vec<u8>,
     pub prefetch: u16,
+    /// Hashes of nodes which the caller already holds. Servers may replace
+    /// these nodes with known node entries in the returned proof.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub known_nodes: Vec<Hash>,
 }
 
 /// Response for requests that produce proofs.
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct ProofResponse {
     pub proof: Proof,
+    /// For SyncGetPrefixes, the first key which was not included because the
+    /// limit was reached. Set as `start_key` to fetch the following page.
+    #[serde(default, with = "serde_bytes", skip_serializing_if = "Option::is_none")]
+    pub next_key: Option<Vec<u8>>,
 }
 
 /// ReadSync is the interface for synchronizing the in-memory cache
@@ -1,10 +1,18 @@
+use std::collections::HashMap;
+
 use io_context::Context;
 
-use crate::storage::mkvs::{
-    interop::{Driver, ProtocolServer},
-    sync::*,
-    tree::*,
-    LogEntry,
+use crate::{
+    common::{cbor, crypto::hash::Hash},
+    storage::mkvs::{
+        interop::{Driver, ProtocolServer},
+        sync::*,
+        tree::{
+            tree_test::{generate_key_value_pairs_ex, make_tree},
+            *,
+        },
+        LogEntry,
+    },
 };
 
 #[test]
@@ -54,3 +62,246 @@ fn test_nil_pointers() {
         .insert(Context::background(), b"insert", b"key")
         .expect("insert");
 }
+
+#[test]
+fn test_verify_query() {
+    let mut tree = make_tree(100);
+    let (_, hash) =
+        Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+    let tree_id = TreeID {
+        root: Root {
+            hash,
+            ..Default::default()
+        },
+        position: hash,
+    };
+    let (keys, values) = generate_key_value_pairs_ex("".to_string(), 100);
+    let entry = |i: usize| (keys[i].clone(), values[i].clone());
+    let pv = ProofVerifier;
+
+    // Present key.
+    let proof = tree
+        .sync_get(
+            Context::background(),
+            GetRequest {
+                tree: tree_id.clone(),
+                key: b"key 42".to_vec(),
+                include_siblings: false,
+                known_nodes: Vec::new(),
+            },
+        )
+        .expect("sync get")
+        .proof;
+    let query = Query::Get(b"key 42".to_vec());
+    assert_eq!(
+        pv.verify_query(Context::background(), hash, &proof, &query)
+            .expect("verify get"),
+        vec![entry(42)]
+    );
+    let query = Query::Absent(b"key 42".to_vec());
+    assert!(
+        pv.verify_query(Context::background(), hash, &proof, &query)
+            .is_err(),
+        "present key should not be proven absent"
+    );
+    let bogus_hash = Hash::digest_bytes(b"i am a bogus hash");
+    let query = Query::Get(b"key 42".to_vec());
+    assert!(
+        pv.verify_query(Context::background(), bogus_hash, &proof, &query)
+            .is_err(),
+        "proof should not verify against a different root"
+    );
+    let query = Query::Get(b"key 99".to_vec());
+    assert!(
+        pv.verify_query(Context::background(), hash, &proof, &query)
+            .is_err(),
+        "proof for a different key should be incomplete"
+    );
+
+    // Absent key.
+    let proof = tree
+        .sync_get(
+            Context::background(),
+            GetRequest {
+                tree: tree_id.clone(),
+                key: b"key 100".to_vec(),
+                include_siblings: false,
+                known_nodes: Vec::new(),
+            },
+        )
+        .expect("sync get")
+        .proof;
+    let query = Query::Get(b"key 100".to_vec());
+    assert_eq!(
+        pv.verify_query(Context::background(), hash, &proof, &query)
+            .expect("verify get"),
+        vec![]
+    );
+    let query = Query::Absent(b"key 100".to_vec());
+    assert_eq!(
+        pv.verify_query(Context::background(), hash, &proof, &query)
+            .expect("verify absent"),
+        vec![]
+    );
+
+    // Prefix.
+    let proof = tree
+        .sync_get_prefixes(
+            Context::background(),
+            GetPrefixesRequest {
+                tree: tree_id.clone(),
+                prefixes: vec![b"key 5".to_vec().into()],
+                limit: 100,
+                start_key: None,
+                known_nodes: Vec::new(),
+            },
+        )
+        .expect("sync get prefixes")
+        .proof;
+    let query = Query::Prefix(b"key 5".to_vec());
+    let mut expected = vec![entry(5)];
+    expected.extend((50..60).map(entry));
+    assert_eq!(
+        pv.verify_query(Context::background(), hash, &proof, &query)
+            .expect("verify prefix"),
+        expected
+    );
+
+    // Range.
+    let query = Query::Range {
+        start: b"key 2".to_vec(),
+        end: Some(b"key 3".to_vec()),
+    };
+    let mut expected = vec![entry(2)];
+    expected.extend((20..30).map(entry));
+    let proof = tree
+        .sync_iterate(
+            Context::background(),
+            IterateRequest {
+                tree: tree_id.clone(),
+                key: b"key 2".to_vec(),
+                prefetch: 20,
+                known_nodes: Vec::new(),
+            },
+        )
+        .expect("sync iterate")
+        .proof;
+    assert_eq!(
+        pv.verify_query(Context::background(), hash, &proof, &query)
+            .expect("verify range"),
+        expected
+    );
+
+    // A proof which only covers part of the range is incomplete.
+    let proof = tree
+        .sync_iterate(
+            Context::background(),
+            IterateRequest {
+                tree: tree_id.clone(),
+                key: b"key 2".to_vec(),
+                prefetch: 3,
+                known_nodes: Vec::new(),
+            },
+        )
+        .expect("sync iterate")
+        .proof;
+    assert!(
+        pv.verify_query(Context::background(), hash, &proof, &query)
+            .is_err(),
+        "partial range proof should be rejected"
+    );
+}
+
+#[test]
+fn test_compact_proof() {
+    let mut tree = make_tree(100);
+    let (_, hash) =
+        Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+    let tree_id = TreeID {
+        root: Root {
+            hash,
+            ..Default::default()
+        },
+        position: hash,
+    };
+    let pv = ProofVerifier;
+
+    // Fetch a proof without any known nodes and remember its nodes.
+    let proof = tree
+        .sync_get_prefixes(
+            Context::background(),
+            GetPrefixesRequest {
+                tree: tree_id.clone(),
+                prefixes: vec![b"key 4".to_vec().into()],
+                limit: 100,
+                start_key: None,
+                known_nodes: Vec::new(),
+            },
+        )
+        .expect("sync get prefixes")
+        .proof;
+    let root_ptr = pv
+        .verify_proof(Context::background(), hash, &proof)
+        .expect("verify proof");
+    let mut known = HashMap::new();
+    collect_known_nodes(&root_ptr, &mut known).expect("collect known nodes");
+    assert!(!known.is_empty());
+
+    // An overlapping request only needs to include nodes not known yet.
+    let request = GetRequest {
+        tree: tree_id.clone(),
+        key: b"key 42".to_vec(),
+        include_siblings: false,
+        known_nodes: known.keys().cloned().collect(),
+    };
+    let full_proof = tree
+        .sync_get(
+            Context::background(),
+            GetRequest {
+                known_nodes: Vec::new(),
+                ..request.clone()
+            },
+        )
+        .expect("sync get")
+        .proof;
+    let compact_proof = tree
+        .sync_get(Context::background(), request)
+        .expect("sync get")
+        .proof;
+    // Known nodes are not resent and neither are subtrees below them which
+    // are not part of the proof.
+    assert!(compact_proof.entries.len() < full_proof.entries.len());
+    assert!(cbor::to_vec(&compact_proof).len() < cbor::to_vec(&full_proof).len());
+
+    pv.verify_compact_proof(Context::background(), hash, &compact_proof, &known)
+        .expect("verify compact proof");
+    let query = Query::Get(b"key 42".to_vec());
+    assert!(
+        pv.verify_query(Context::background(), hash, &compact_proof, &query)
+            .is_err(),
+        "compact proof should not verify without known nodes"
+    );
+    assert!(
+        pv.verify_compact_proof(
+            Context::background(),
+            hash,
+            &compact_proof,
+            &HashMap::<Hash, Vec<u8>>::new()
+        )
+        .is_err(),
+        "compact proof should not verify with missing known nodes"
+    );
+
+    // Known nodes which do not match their hashes are rejected.
+    let mut bogus = known.clone();
+    for node in bogus.values_mut() {
+        if node[0] == NodeKind::Leaf as u8 {
+            *node.last_mut().unwrap() ^= 0xff;
+        }
+    }
+    assert!(
+        pv.verify_compact_proof(Context::background(), hash, &compact_proof, &bogus)
+            .is_err(),
+        "compact proof should not verify with corrupted known nodes"
+    );
+}
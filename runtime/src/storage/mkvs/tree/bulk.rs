@@ -0,0 +1,104 @@
+use anyhow::{anyhow, Result};
+
+use crate::storage::mkvs::{cache::*, sync::*, tree::*};
+
+impl Options {
+    /// Construct a new tree instance containing the given key/value pairs.
+    ///
+    /// The entries must be sorted by key and must not contain duplicate keys.
+    /// Instead of inserting the entries one by one, the tree is built
+    /// bottom-up in a single pass over the entries. The resulting tree has
+    /// the same uncommitted changes as if all entries were inserted into an
+    /// empty tree, so committing it produces the same root hash and write log.
+    pub fn build<I>(self, read_syncer: Box<dyn ReadSync>, entries: I) -> Result<Tree>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    {
+        let entries: Vec<(Key, Value)> = entries.into_iter().collect();
+        if entries.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(TreeError::UnsortedKeys.into());
+        }
+
+        // A new tree without a root is empty, but its pending root is not a
+        // null pointer, so check the configured root instead.
+        if self.root.map_or(false, |root| !root.hash.is_empty()) {
+            return Err(anyhow!("mkvs: bulk load is only supported for empty trees"));
+        }
+        let mut tree = self.new(read_syncer);
+        if !entries.is_empty() {
+            let root = tree.build_subtree(&entries, 0);
+            let pending_root = tree.cache.borrow().get_pending_root();
+            tree.undo_log.save_root(&pending_root);
+            tree.cache.borrow_mut().set_pending_root(root);
+        }
+        for (key, value) in entries {
+            tree.pending_write_log.insert(
+                key.clone(),
+                PendingLogEntry {
+                    key,
+                    value: Some(value),
+                    existed: false,
+                },
+            );
+        }
+
+        Ok(tree)
+    }
+}
+
+impl Tree {
+    /// Build the subtree for the given non-empty sorted entries, all of which
+    /// share the first `bit_depth` bits of their keys.
+    fn build_subtree(&self, entries: &[(Key, Value)], bit_depth: Depth) -> NodePtrRef {
+        let (first_key, first_value) = &entries[0];
+        if entries.len() == 1 {
+            return self
+                .cache
+                .borrow_mut()
+                .new_leaf_node(first_key, first_value.clone());
+        }
+
+        // As entries are sorted, the prefix shared by the first and the last
+        // key is shared by all keys.
+        let last_key = &entries[entries.len() - 1].0;
+        let cp_len =
+            first_key.common_prefix_len(first_key.bit_length(), last_key, last_key.bit_length());
+        let (_, key_remainder) = first_key.split(bit_depth, first_key.bit_length());
+        let (label, _) = key_remainder.split(cp_len - bit_depth, key_remainder.bit_length());
+
+        // A key ending exactly at this node sorts before all others.
+        let (leaf_node, rest) = if first_key.bit_length() == cp_len {
+            let leaf_node = self
+                .cache
+                .borrow_mut()
+                .new_leaf_node(first_key, first_value.clone());
+            (leaf_node, &entries[1..])
+        } else {
+            (NodePointer::null_ptr(), entries)
+        };
+
+        let split = rest
+            .iter()
+            .position(|(key, _)| key.get_bit(cp_len))
+            .unwrap_or(rest.len());
+        let (left, right) = rest.split_at(split);
+        let left = if left.is_empty() {
+            NodePointer::null_ptr()
+        } else {
+            self.build_subtree(left, cp_len)
+        };
+        let right = if right.is_empty() {
+            NodePointer::null_ptr()
+        } else {
+            self.build_subtree(right, cp_len)
+        };
+
+        self.cache.borrow_mut().new_internal_node(
+            &label,
+            cp_len - bit_depth,
+            leaf_node,
+            left,
+            right,
+        )
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use io_context::Context;
+
+use crate::storage::mkvs::{
+    cache::*,
+    sync::*,
+    tree::{lookup::FetcherSyncGet, *},
+};
+
+impl Tree {
+    /// Visit all nodes reachable from the tree's current root in pre-order
+    /// and return the root.
+    ///
+    /// Nodes which are not available locally are fetched using the tree's
+    /// read syncer. The tree must not have any uncommitted changes.
+    pub(crate) fn visit_nodes<F>(&self, ctx: Context, mut visitor: F) -> Result<Root>
+    where
+        F: FnMut(&NodeBox) -> Result<()>,
+    {
+        let root = self.committed_root()?;
+        let pending_root = self.cache.borrow().get_pending_root();
+
+        let ctx = ctx.freeze();
+        self._visit_nodes(&ctx, pending_root, &mut visitor)?;
+
+        Ok(root)
+    }
+
+    fn _visit_nodes<F>(&self, ctx: &Arc<Context>, ptr: NodePtrRef, visitor: &mut F) -> Result<()>
+    where
+        F: FnMut(&NodeBox) -> Result<()>,
+    {
+        let key = Key::new();
+        let node_ref = self.cache.borrow_mut().deref_node_ptr(
+            ctx,
+            ptr,
+            Some(FetcherSyncGet::new(&key, false)),
+        )?;
+        let node_ref = match node_ref {
+            Some(node_ref) => node_ref,
+            None => return Ok(()),
+        };
+        visitor(&node_ref.borrow())?;
+
+        let children = match *node_ref.borrow() {
+            NodeBox::Internal(ref n) => Some((n.left.clone(), n.right.clone())),
+            NodeBox::Leaf(..) => None,
+        };
+        if let Some((left, right)) = children {
+            self._visit_nodes(ctx, left, visitor)?;
+            self._visit_nodes(ctx, right, visitor)?;
+        }
+
+        Ok(())
+    }
+
+    /// Construct a tree from a fully restored root pointer.
+    ///
+    /// The tree has an unlimited cache and no read syncer, so all nodes
+    /// reachable from the root pointer must be available in memory.
+    pub(crate) fn from_restored_root(root: Root, root_ptr: NodePtrRef) -> Tree {
+        let tree = Tree::make()
+            .with_capacity(0, 0)
+            .with_root(root)
+            .new(Box::new(NoopReadSyncer));
+
+        let mut cache = tree.cache.borrow_mut();
+        cache.set_pending_root(root_ptr.clone());
+        let mut pending = vec![root_ptr];
+        while let Some(ptr) = pending.pop() {
+            if let Some(ref node_ref) = ptr.borrow().node {
+                if let NodeBox::Internal(ref n) = *node_ref.borrow() {
+                    pending.push(n.left.clone());
+                    pending.push(n.right.clone());
+                }
+            }
+            cache.commit_node(ptr);
+        }
+        drop(cache);
+
+        tree
+    }
+}
@@ -5,7 +5,7 @@ use io_context::Context;
 
 use crate::{
     common::{crypto::hash::Hash, roothash::Namespace},
-    storage::mkvs::{cache::*, tree::*, LogEntry, WriteLog},
+    storage::mkvs::{cache::*, sync::*, tree::*, LogEntry, WriteLog},
 };
 
 impl Tree {
@@ -17,13 +17,28 @@ impl Tree {
         namespace: Namespace,
         version: u64,
     ) -> Result<(WriteLog, Hash)> {
+        self.commit_with(ctx, namespace, version, |_, _| Ok(()))
+    }
+
+    /// Commit tree updates, calling `persist` with the new root pointer and
+    /// write log before the committed nodes become eligible for eviction.
+    ///
+    /// If `persist` fails, the tree updates remain uncommitted.
+    pub(crate) fn commit_with<F>(
+        &mut self,
+        ctx: Context,
+        namespace: Namespace,
+        version: u64,
+        persist: F,
+    ) -> Result<(WriteLog, Hash)>
+    where
+        F: FnOnce(&NodePtrRef, &WriteLog) -> Result<()>,
+    {
         let ctx = ctx.freeze();
         let mut update_list: UpdateList<LRUCache> = UpdateList::new();
         let pending_root = self.cache.borrow().get_pending_root();
         let new_hash = _commit(&ctx, pending_root.clone(), &mut update_list, Some(version))?;
 
-        update_list.commit(&mut self.cache.borrow_mut());
-
         let mut log: WriteLog = Vec::new();
         for (_, entry) in self.pending_write_log.iter() {
             // Skip all entries that do not exist after all the updates and
@@ -36,7 +51,12 @@ impl Tree {
                 value: entry.value.clone(),
             });
         }
+
+        persist(&pending_root, &log)?;
+        update_list.commit(&mut self.cache.borrow_mut());
+
         self.pending_write_log.clear();
+        self.undo_log.clear();
         self.cache.borrow_mut().set_sync_root(Root {
             namespace,
             version,
@@ -45,6 +65,34 @@ impl Tree {
 
         Ok((log, new_hash))
     }
+
+    /// Apply the write log on top of the given root and return the hash of
+    /// the resulting root at the given version.
+    ///
+    /// All nodes are fetched using the read syncer and verified against the
+    /// given root, so the result can be trusted as much as the root itself.
+    pub fn apply_write_log(
+        ctx: Context,
+        read_syncer: Box<dyn ReadSync>,
+        root: Root,
+        write_log: &WriteLog,
+        version: u64,
+    ) -> Result<Hash> {
+        let ctx = ctx.freeze();
+        let mut tree = Tree::make()
+            .with_capacity(0, 0)
+            .with_root(root)
+            .new(read_syncer);
+        for entry in write_log {
+            match entry.value {
+                Some(ref value) => tree.insert(Context::create_child(&ctx), &entry.key, value)?,
+                None => tree.remove(Context::create_child(&ctx), &entry.key)?,
+            };
+        }
+        let (_, hash) = tree.commit(Context::create_child(&ctx), root.namespace, version)?;
+
+        Ok(hash)
+    }
 }
 
 pub fn _commit<C: Cache>(
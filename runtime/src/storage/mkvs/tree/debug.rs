@@ -0,0 +1,292 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Arc};
+
+use anyhow::Result;
+use io_context::Context;
+use rustc_hex::ToHex;
+use serde::Serialize;
+
+use crate::{
+    common::cbor,
+    storage::mkvs::{
+        cache::*,
+        sync::*,
+        tree::{lookup::FetcherSyncGet, *},
+    },
+};
+
+/// Statistics about the structure of a tree.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct TreeStats {
+    /// Number of internal nodes.
+    pub internal_nodes: usize,
+    /// Number of leaf nodes, including leaves embedded in internal nodes.
+    pub leaf_nodes: usize,
+    /// Maximum depth of any leaf node.
+    pub max_depth: usize,
+    /// Number of leaf nodes by depth, where the depth is the number of
+    /// internal nodes on the path from the root.
+    pub leaf_depths: BTreeMap<usize, usize>,
+    /// Number of values by size, where each value is counted in the bucket
+    /// of the smallest power of two not less than its size.
+    pub value_sizes: BTreeMap<usize, usize>,
+    /// Total size of all keys in bytes.
+    pub key_bytes: usize,
+    /// Total size of all values in bytes.
+    pub value_bytes: usize,
+}
+
+/// Size of a proof for a single key.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ProofSize {
+    /// Number of entries in the proof.
+    pub entries: usize,
+    /// Size of the CBOR-encoded proof in bytes.
+    pub bytes: usize,
+}
+
+/// A dump of a tree node and all of its descendants.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NodeDump {
+    Internal {
+        hash: String,
+        version: u64,
+        label: String,
+        label_bit_length: Depth,
+        leaf: Option<Box<NodeDump>>,
+        left: Option<Box<NodeDump>>,
+        right: Option<Box<NodeDump>>,
+    },
+    Leaf {
+        hash: String,
+        version: u64,
+        key: String,
+        value: String,
+    },
+}
+
+impl NodeDump {
+    fn hash(&self) -> &str {
+        match self {
+            NodeDump::Internal { hash, .. } => hash,
+            NodeDump::Leaf { hash, .. } => hash,
+        }
+    }
+
+    /// Export the dump as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Export the dump as a Graphviz DOT graph.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph mkvs {\n");
+        self.write_dot(&mut out).unwrap();
+        out.push_str("}\n");
+        out
+    }
+
+    fn write_dot(&self, out: &mut String) -> std::fmt::Result {
+        match self {
+            NodeDump::Internal {
+                hash,
+                version,
+                label,
+                label_bit_length,
+                leaf,
+                left,
+                right,
+            } => {
+                writeln!(
+                    out,
+                    "  \"{}\" [shape=ellipse, label=\"internal\\nhash: {}\\nversion: {}\\nlabel: {}\\nlabel_bit_length: {}\"];",
+                    hash,
+                    &hash[..8],
+                    version,
+                    label,
+                    label_bit_length
+                )?;
+                let children = [(leaf, "leaf"), (left, "0"), (right, "1")];
+                for (child, edge) in children.iter() {
+                    if let Some(child) = child {
+                        child.write_dot(out)?;
+                        writeln!(
+                            out,
+                            "  \"{}\" -> \"{}\" [label=\"{}\"];",
+                            hash,
+                            child.hash(),
+                            edge
+                        )?;
+                    }
+                }
+            }
+            NodeDump::Leaf {
+                hash,
+                version,
+                key,
+                value,
+            } => {
+                writeln!(
+                    out,
+                    "  \"{}\" [shape=box, label=\"leaf\\nhash: {}\\nversion: {}\\nkey: {}\\nvalue: {} bytes\"];",
+                    hash,
+                    &hash[..8],
+                    version,
+                    key,
+                    value.len() / 2
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Tree {
+    /// Return statistics about the structure of the tree at its committed
+    /// root.
+    ///
+    /// Nodes which are not available locally are fetched using the tree's
+    /// read syncer.
+    pub fn stats(&self, ctx: Context) -> Result<TreeStats> {
+        self.committed_root()?;
+
+        let mut stats = TreeStats::default();
+        let ctx = ctx.freeze();
+        let pending_root = self.cache.borrow().get_pending_root();
+        self._walk(&ctx, pending_root, 0, &mut |node, depth| {
+            match *node {
+                NodeBox::Internal(..) => stats.internal_nodes += 1,
+                NodeBox::Leaf(ref n) => {
+                    stats.leaf_nodes += 1;
+                    stats.max_depth = stats.max_depth.max(depth);
+                    *stats.leaf_depths.entry(depth).or_default() += 1;
+                    *stats
+                        .value_sizes
+                        .entry(n.value.len().next_power_of_two())
+                        .or_default() += 1;
+                    stats.key_bytes += n.key.len();
+                    stats.value_bytes += n.value.len();
+                }
+            }
+            Ok(())
+        })?;
+
+        Ok(stats)
+    }
+
+    /// Return the size of the proof for the given key at the tree's
+    /// committed root.
+    pub fn proof_size(&mut self, ctx: Context, key: &[u8]) -> Result<ProofSize> {
+        let root = self.committed_root()?;
+        let response = self.sync_get(
+            ctx,
+            GetRequest {
+                tree: TreeID {
+                    root,
+                    position: root.hash,
+                },
+                key: key.to_vec(),
+                include_siblings: false,
+                known_nodes: Vec::new(),
+            },
+        )?;
+
+        Ok(ProofSize {
+            entries: response.proof.entries.len(),
+            bytes: cbor::to_vec(&response.proof).len(),
+        })
+    }
+
+    /// Dump the structure of the tree at its committed root, including node
+    /// hashes and labels. Returns `None` for an empty tree.
+    pub fn dump(&self, ctx: Context) -> Result<Option<NodeDump>> {
+        self.committed_root()?;
+
+        let ctx = ctx.freeze();
+        let pending_root = self.cache.borrow().get_pending_root();
+        self._dump(&ctx, pending_root)
+    }
+
+    fn deref_debug_ptr(&self, ctx: &Arc<Context>, ptr: NodePtrRef) -> Result<Option<NodeRef>> {
+        let key = Key::new();
+        self.cache
+            .borrow_mut()
+            .deref_node_ptr(ctx, ptr, Some(FetcherSyncGet::new(&key, false)))
+    }
+
+    fn _walk<F>(
+        &self,
+        ctx: &Arc<Context>,
+        ptr: NodePtrRef,
+        depth: usize,
+        visitor: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(&NodeBox, usize) -> Result<()>,
+    {
+        let node_ref = match self.deref_debug_ptr(ctx, ptr)? {
+            Some(node_ref) => node_ref,
+            None => return Ok(()),
+        };
+        visitor(&node_ref.borrow(), depth)?;
+
+        let children = match *node_ref.borrow() {
+            NodeBox::Internal(ref n) => {
+                Some((n.leaf_node.clone(), n.left.clone(), n.right.clone()))
+            }
+            NodeBox::Leaf(..) => None,
+        };
+        if let Some((leaf_node, left, right)) = children {
+            // Leaves embedded in internal nodes are at the same depth.
+            self._walk(ctx, leaf_node, depth, visitor)?;
+            self._walk(ctx, left, depth + 1, visitor)?;
+            self._walk(ctx, right, depth + 1, visitor)?;
+        }
+
+        Ok(())
+    }
+
+    fn _dump(&self, ctx: &Arc<Context>, ptr: NodePtrRef) -> Result<Option<NodeDump>> {
+        let node_ref = match self.deref_debug_ptr(ctx, ptr)? {
+            Some(node_ref) => node_ref,
+            None => return Ok(None),
+        };
+
+        // Release the borrow on the node before dereferencing its children.
+        let children = match *node_ref.borrow() {
+            NodeBox::Internal(ref n) => {
+                Some((n.leaf_node.clone(), n.left.clone(), n.right.clone()))
+            }
+            NodeBox::Leaf(..) => None,
+        };
+        let children = match children {
+            Some((leaf_node, left, right)) => Some((
+                self._dump(ctx, leaf_node)?.map(Box::new),
+                self._dump(ctx, left)?.map(Box::new),
+                self._dump(ctx, right)?.map(Box::new),
+            )),
+            None => None,
+        };
+
+        let dump = match (&*node_ref.borrow(), children) {
+            (NodeBox::Internal(ref n), Some((leaf, left, right))) => NodeDump::Internal {
+                hash: format!("{:?}", n.hash),
+                version: n.version,
+                label: n.label.to_hex(),
+                label_bit_length: n.label_bit_length,
+                leaf,
+                left,
+                right,
+            },
+            (NodeBox::Leaf(ref n), _) => NodeDump::Leaf {
+                hash: format!("{:?}", n.hash),
+                version: n.version,
+                key: n.key.to_hex(),
+                value: n.value.to_hex(),
+            },
+            (NodeBox::Internal(..), None) => unreachable!("internal nodes have children"),
+        };
+
+        Ok(Some(dump))
+    }
+}
@@ -0,0 +1,340 @@
+use std::{cmp::Ordering, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use io_context::Context;
+
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{
+        cache::*,
+        sync::*,
+        tree::{lookup::FetcherSyncGet, *},
+        LogEntry, WriteLog,
+    },
+};
+
+/// A subtree which still needs to be compared.
+struct PendingSubtree {
+    ptr: NodePtrRef,
+    path: Key,
+    bit_depth: Depth,
+}
+
+/// The dereferenced root of the next subtree in key order.
+#[derive(Clone)]
+struct Subtree {
+    hash: Hash,
+    node: NodeRef,
+    /// Key prefix shared by all keys in the subtree (the key for leaves).
+    prefix: Key,
+    /// Length of the prefix in bits.
+    bit_length: Depth,
+}
+
+impl Subtree {
+    fn leaf(&self) -> Option<(Key, Value)> {
+        match *self.node.borrow() {
+            NodeBox::Leaf(ref n) => Some((n.key.clone(), n.value.clone())),
+            NodeBox::Internal(..) => None,
+        }
+    }
+}
+
+/// Walks over the subtrees of a tree in key order.
+struct DiffCursor<'a> {
+    tree: &'a Tree,
+    pending: Vec<PendingSubtree>,
+    current: Option<Subtree>,
+}
+
+impl<'a> DiffCursor<'a> {
+    fn new(tree: &'a Tree) -> Self {
+        Self {
+            tree,
+            pending: vec![PendingSubtree {
+                ptr: tree.cache.borrow().get_pending_root(),
+                path: Key::new(),
+                bit_depth: 0,
+            }],
+            current: None,
+        }
+    }
+
+    /// Return the next non-empty subtree without consuming it.
+    fn peek(&mut self, ctx: &Arc<Context>) -> Result<Option<Subtree>> {
+        while self.current.is_none() {
+            let pending = match self.pending.pop() {
+                Some(pending) => pending,
+                None => return Ok(None),
+            };
+            let node_ref = self.tree.cache.borrow_mut().deref_node_ptr(
+                ctx,
+                pending.ptr,
+                Some(FetcherSyncGet::new(&pending.path, false)),
+            )?;
+            let node_ref = match node_ref {
+                Some(node_ref) => node_ref,
+                None => continue,
+            };
+
+            let (prefix, bit_length) = match *node_ref.borrow() {
+                NodeBox::Internal(ref n) => (
+                    pending
+                        .path
+                        .merge(pending.bit_depth, &n.label, n.label_bit_length),
+                    pending.bit_depth + n.label_bit_length,
+                ),
+                NodeBox::Leaf(ref n) => (n.key.clone(), n.key.bit_length()),
+            };
+            let hash = node_ref.borrow().get_hash();
+            self.current = Some(Subtree {
+                hash,
+                node: node_ref,
+                prefix,
+                bit_length,
+            });
+        }
+
+        Ok(self.current.clone())
+    }
+
+    /// Consume the current subtree.
+    fn skip(&mut self) {
+        self.current = None;
+    }
+
+    /// Replace the current internal node with its leaf and children.
+    fn expand(&mut self) {
+        let current = self
+            .current
+            .take()
+            .expect("expand called without a subtree");
+        let node = current.node.borrow();
+        if let NodeBox::Internal(ref n) = *node {
+            // The leaf comes first as its key is a prefix of all keys in the children.
+            // The paths lead to each subtree so that they can be fetched by key.
+            let bit_length = current.bit_length;
+            for (ptr, path) in vec![
+                (n.right.clone(), current.prefix.append_bit(bit_length, true)),
+                (n.left.clone(), current.prefix.append_bit(bit_length, false)),
+                (n.leaf_node.clone(), current.prefix.clone()),
+            ] {
+                self.pending.push(PendingSubtree {
+                    ptr,
+                    path,
+                    bit_depth: bit_length,
+                });
+            }
+        }
+    }
+}
+
+/// Compare two bit strings, returning `None` if one is a prefix of the other.
+fn compare_bits(a: &Key, a_bits: Depth, b: &Key, b_bits: Depth) -> Option<Ordering> {
+    let common = a.common_prefix_len(a_bits, b, b_bits);
+    if common >= a_bits || common >= b_bits {
+        return None;
+    }
+    if a.get_bit(common) {
+        Some(Ordering::Greater)
+    } else {
+        Some(Ordering::Less)
+    }
+}
+
+impl Tree {
+    /// Compute the write log which transforms this tree into the other tree.
+    ///
+    /// Both trees must be in the same namespace and must not have any
+    /// uncommitted changes. Subtrees with equal hashes are skipped, so only
+    /// nodes in differing subtrees are fetched using the trees' read syncers.
+    pub fn diff(&self, ctx: Context, other: &Tree) -> Result<WriteLog> {
+        let start_root = self.committed_root()?;
+        let end_root = other.committed_root()?;
+        if start_root.namespace != end_root.namespace {
+            return Err(anyhow!("mkvs: cannot diff roots in different namespaces"));
+        }
+
+        let ctx = ctx.freeze();
+        let mut start = DiffCursor::new(self);
+        let mut end = DiffCursor::new(other);
+        let mut log = WriteLog::new();
+        loop {
+            let (advance_start, advance_end) = match (start.peek(&ctx)?, end.peek(&ctx)?) {
+                (None, None) => break,
+                (Some(_), None) => (true, false),
+                (None, Some(_)) => (false, true),
+                (Some(a), Some(b)) if a.hash == b.hash => {
+                    // Identical subtrees contain the same entries.
+                    start.skip();
+                    end.skip();
+                    continue;
+                }
+                (Some(a), Some(b)) => {
+                    match compare_bits(&a.prefix, a.bit_length, &b.prefix, b.bit_length) {
+                        Some(Ordering::Less) => (true, false),
+                        Some(Ordering::Greater) => (false, true),
+                        _ => match a.bit_length.cmp(&b.bit_length) {
+                            Ordering::Less => (true, false),
+                            Ordering::Greater => (false, true),
+                            Ordering::Equal => match (a.leaf(), b.leaf()) {
+                                (Some((key, old_value)), Some((_, new_value))) => {
+                                    // Same key, the value may have changed.
+                                    if old_value != new_value {
+                                        log.push(LogEntry::new(&key, &new_value));
+                                    }
+                                    start.skip();
+                                    end.skip();
+                                    continue;
+                                }
+                                (Some(_), None) => (false, true),
+                                (None, Some(_)) => (true, false),
+                                (None, None) => (true, true),
+                            },
+                        },
+                    }
+                }
+            };
+
+            if advance_start {
+                let a = start.peek(&ctx)?.unwrap();
+                match a.leaf() {
+                    Some((key, _)) => {
+                        log.push(LogEntry { key, value: None });
+                        start.skip();
+                    }
+                    None => start.expand(),
+                }
+            }
+            if advance_end {
+                let b = end.peek(&ctx)?.unwrap();
+                match b.leaf() {
+                    Some((key, value)) => {
+                        log.push(LogEntry::new(&key, &value));
+                        end.skip();
+                    }
+                    None => end.expand(),
+                }
+            }
+        }
+
+        Ok(log)
+    }
+
+    /// Compute the write log which transforms the `start` root into the `end`
+    /// root, fetching nodes using the given read syncer.
+    pub fn diff_roots<R>(ctx: Context, read_syncer: R, start: Root, end: Root) -> Result<WriteLog>
+    where
+        R: ReadSync + Clone + 'static,
+    {
+        let start = Tree::make()
+            .with_root(start)
+            .new(Box::new(read_syncer.clone()));
+        let end = Tree::make().with_root(end).new(Box::new(read_syncer));
+
+        start.diff(ctx, &end)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        common::roothash::Namespace,
+        storage::mkvs::db::{MemoryBackend, NodeDB},
+    };
+
+    fn key(idx: usize) -> Vec<u8> {
+        format!("key {}", idx).into_bytes()
+    }
+
+    fn value(idx: usize, round: usize) -> Vec<u8> {
+        format!("value {} {}", idx, round).into_bytes()
+    }
+
+    #[test]
+    fn test_diff() {
+        let db = NodeDB::open(Arc::new(MemoryBackend::new()), Default::default()).unwrap();
+
+        let mut tree = Tree::make().new(Box::new(NoopReadSyncer));
+        for idx in 0..200 {
+            tree.insert(Context::background(), &key(idx), &value(idx, 0))
+                .unwrap();
+        }
+        let (_, root_0) = db.commit(Context::background(), &mut tree, 0).unwrap();
+
+        // Update, remove and insert some keys, including keys that are prefixes
+        // of existing keys.
+        let mut tree = db.tree(root_0).unwrap();
+        for idx in (0..200).step_by(17) {
+            tree.insert(Context::background(), &key(idx), &value(idx, 1))
+                .unwrap();
+        }
+        for idx in (5..200).step_by(23) {
+            tree.remove(Context::background(), &key(idx)).unwrap();
+        }
+        tree.insert(Context::background(), b"key", b"prefix")
+            .unwrap();
+        tree.insert(Context::background(), b"key 1000", b"new")
+            .unwrap();
+        let (write_log, root_1) = db.commit(Context::background(), &mut tree, 1).unwrap();
+
+        let diff =
+            Tree::diff_roots(Context::background(), db.clone(), root_0, root_1).expect("diff");
+        assert_eq!(diff, write_log);
+
+        // Reverse diff restores the original values.
+        let reverse = Tree::diff_roots(Context::background(), db.clone(), root_1, root_0)
+            .expect("reverse diff");
+        assert_eq!(reverse.len(), write_log.len());
+        for entry in &reverse {
+            let original = db
+                .tree(root_0)
+                .unwrap()
+                .get(Context::background(), &entry.key)
+                .unwrap();
+            assert_eq!(entry.value, original, "key: {:?}", entry.key);
+        }
+
+        let same = Tree::diff_roots(Context::background(), db.clone(), root_1, root_1)
+            .expect("diff of the same root");
+        assert!(same.is_empty());
+
+        // Roots in different namespaces cannot be compared.
+        let other = Root {
+            namespace: Namespace::from(Hash::digest_bytes(b"other").as_ref()),
+            ..root_0
+        };
+        assert!(Tree::diff_roots(Context::background(), db, root_0, other).is_err());
+    }
+
+    #[test]
+    fn test_diff_empty() {
+        let db = NodeDB::open(Arc::new(MemoryBackend::new()), Default::default()).unwrap();
+
+        let mut tree = Tree::make().new(Box::new(NoopReadSyncer));
+        let (_, empty) = db.commit(Context::background(), &mut tree, 0).unwrap();
+        tree.insert(Context::background(), b"foo", b"bar").unwrap();
+        tree.insert(Context::background(), b"moo", b"goo").unwrap();
+        let (write_log, root) = db.commit(Context::background(), &mut tree, 1).unwrap();
+
+        let diff = Tree::diff_roots(Context::background(), db.clone(), empty, root).unwrap();
+        assert_eq!(diff, write_log);
+        let diff = Tree::diff_roots(Context::background(), db, root, empty).unwrap();
+        assert_eq!(
+            diff,
+            vec![
+                LogEntry {
+                    key: b"foo".to_vec(),
+                    value: None,
+                },
+                LogEntry {
+                    key: b"moo".to_vec(),
+                    value: None,
+                },
+            ]
+        );
+    }
+}
@@ -6,4 +6,6 @@ pub enum TreeError {
     MalformedNode,
     #[error("mkvs: malformed key")]
     MalformedKey,
+    #[error("mkvs: bulk load keys are not strictly increasing")]
+    UnsortedKeys,
 }
@@ -17,6 +17,7 @@ impl Tree {
 
         // Remember where the path from root to target node ends (will end).
         self.cache.borrow_mut().mark_position();
+        self.undo_log.save_root(&pending_root);
 
         let (new_root, old_val) =
             self._insert(&ctx, pending_root, 0, &boxed_key, boxed_val.clone(), 0)?;
@@ -64,6 +65,7 @@ impl Tree {
             }
             NodeKind::Internal => {
                 let node_ref = node_ref.unwrap();
+                self.snapshot_node(&ptr);
                 let (leaf_node, left, right): (NodePtrRef, NodePtrRef, NodePtrRef);
                 let cp_len: Depth;
                 let label_prefix: Key;
@@ -182,6 +184,9 @@ impl Tree {
             NodeKind::Leaf => {
                 // If the key matches, we can just update the value.
                 let node_ref = node_ref.unwrap();
+                if noderef_as!(node_ref, Leaf).key == *key {
+                    self.snapshot_node(&ptr);
+                }
                 let (leaf_node, left, right): (NodePtrRef, NodePtrRef, NodePtrRef);
                 let cp_len: Depth;
                 let label_prefix: Key;
@@ -1,10 +1,13 @@
 //! Tree iterator.
 use std::{collections::VecDeque, fmt, iter::Iterator, mem::replace, sync::Arc};
 
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
 use io_context::Context;
 
-use crate::storage::mkvs::{cache::*, sync::*, tree::*};
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{self, cache::*, sync::*, tree::*},
+};
 
 pub(super) struct FetcherSyncIterate<'a> {
     key: &'a Key,
@@ -23,6 +26,7 @@ impl<'a> ReadSyncFetcher for FetcherSyncIterate<'a> {
         ctx: Context,
         root: Root,
         ptr: NodePtrRef,
+        known_nodes: &[Hash],
         rs: &mut Box<dyn ReadSync>,
     ) -> Result<Proof> {
         let rsp = rs.sync_iterate(
@@ -34,6 +38,7 @@ impl<'a> ReadSyncFetcher for FetcherSyncIterate<'a> {
                 },
                 key: self.key.clone(),
                 prefetch: self.prefetch as u16,
+                known_nodes: known_nodes.to_vec(),
             },
         )?;
         Ok(rsp.proof)
@@ -77,6 +82,7 @@ pub struct TreeIterator<'tree> {
     key: Option<Key>,
     value: Option<Vec<u8>>,
     error: Option<Error>,
+    proof_builder: Option<ProofBuilder>,
 }
 
 impl<'tree> TreeIterator<'tree> {
@@ -90,6 +96,28 @@ impl<'tree> TreeIterator<'tree> {
             key: None,
             value: None,
             error: None,
+            proof_builder: None,
+        }
+    }
+
+    /// Include all nodes visited by the iterator in a proof, omitting the
+    /// contents of the given known nodes.
+    pub(super) fn with_proof(
+        mut self,
+        root: Hash,
+        subtree_root: Hash,
+        known_nodes: &[Hash],
+    ) -> Self {
+        self.proof_builder =
+            Some(ProofBuilder::new(root, subtree_root).with_known_nodes(known_nodes));
+        self
+    }
+
+    /// Build a proof for all nodes visited by the iterator so far.
+    pub(super) fn get_proof(&self) -> Result<Proof> {
+        match self.proof_builder {
+            Some(ref pb) => pb.build(Context::create_child(&self.ctx)),
+            None => Err(anyhow!("mkvs: iterator not configured to generate proofs")),
         }
     }
 
@@ -114,6 +142,11 @@ impl<'tree> TreeIterator<'tree> {
         &self.error
     }
 
+    /// Take the error that occurred during iteration if any.
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
     /// Move the iterator to the first key in the tree.
     pub fn rewind(&mut self) {
         self.seek(&[])
@@ -197,6 +230,9 @@ impl<'tree> TreeIterator<'tree> {
             ptr.clone(),
             Some(FetcherSyncIterate::new(&key, self.prefetch)),
         )?;
+        if let (Some(pb), Some(node_ref)) = (self.proof_builder.as_mut(), node_ref.as_ref()) {
+            pb.include(&node_ref.borrow())?;
+        }
 
         match classify_noderef!(?node_ref) {
             NodeKind::None => {
@@ -341,6 +377,32 @@ impl<'tree> Iterator for TreeIterator<'tree> {
     }
 }
 
+impl<'tree> mkvs::Iterator for TreeIterator<'tree> {
+    fn set_prefetch(&mut self, prefetch: usize) {
+        TreeIterator::set_prefetch(self, prefetch)
+    }
+
+    fn is_valid(&self) -> bool {
+        TreeIterator::is_valid(self)
+    }
+
+    fn error(&self) -> &Option<Error> {
+        TreeIterator::error(self)
+    }
+
+    fn take_error(&mut self) -> Option<Error> {
+        TreeIterator::take_error(self)
+    }
+
+    fn rewind(&mut self) {
+        TreeIterator::rewind(self)
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        TreeIterator::seek(self, key)
+    }
+}
+
 impl Tree {
     /// Returns an iterator over the tree.
     pub fn iter(&self, ctx: Context) -> TreeIterator {
@@ -3,7 +3,10 @@ use std::sync::Arc;
 use anyhow::Result;
 use io_context::Context;
 
-use crate::storage::mkvs::{cache::*, sync::*, tree::*};
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{cache::*, sync::*, tree::*},
+};
 
 pub(super) struct FetcherSyncGet<'a> {
     key: &'a Key,
@@ -25,6 +28,7 @@ impl<'a> ReadSyncFetcher for FetcherSyncGet<'a> {
         ctx: Context,
         root: Root,
         ptr: NodePtrRef,
+        known_nodes: &[Hash],
         rs: &mut Box<dyn ReadSync>,
     ) -> Result<Proof> {
         let rsp = rs.sync_get(
@@ -36,6 +40,7 @@ impl<'a> ReadSyncFetcher for FetcherSyncGet<'a> {
                 },
                 key: self.key.clone(),
                 include_siblings: self.include_siblings,
+                known_nodes: known_nodes.to_vec(),
             },
         )?;
         Ok(rsp.proof)
@@ -72,8 +72,12 @@ impl Marshal for NodeKind {
     }
 }
 
-impl Marshal for InternalNode {
-    fn marshal_binary(&self) -> Result<Vec<u8>> {
+impl InternalNode {
+    /// Serialize the node without the hashes of its children.
+    ///
+    /// This is the encoding used for internal nodes in proofs, where the
+    /// children follow the node itself.
+    pub fn compact_marshal_binary(&self) -> Result<Vec<u8>> {
         let leaf_node_binary: Vec<u8>;
         if self.leaf_node.borrow().is_null() {
             leaf_node_binary = vec![NodeKind::None as u8];
@@ -89,6 +93,14 @@ impl Marshal for InternalNode {
         result.append(&mut self.label_bit_length.marshal_binary()?);
         result.extend_from_slice(&self.label);
         result.extend_from_slice(leaf_node_binary.as_ref());
+
+        Ok(result)
+    }
+}
+
+impl Marshal for InternalNode {
+    fn marshal_binary(&self) -> Result<Vec<u8>> {
+        let mut result = self.compact_marshal_binary()?;
         result.extend_from_slice(self.left.borrow().hash.as_ref());
         result.extend_from_slice(self.right.borrow().hash.as_ref());
 
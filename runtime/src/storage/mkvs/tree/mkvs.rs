@@ -1,20 +1,63 @@
-use anyhow::Result;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context as AnyContext, Error, Result};
 use io_context::Context;
 
 use crate::{
     common::{crypto::hash::Hash, roothash::Namespace},
-    storage::mkvs::{tree::*, Prefix, WriteLog, MKVS},
+    storage::mkvs::{self, tree::*, Prefix, StorageError, WriteLog, MKVS},
 };
 
 unsafe impl Send for Tree {}
 unsafe impl Sync for Tree {}
 
-// TODO: We should likely change the MKVS interface to propagate errors instead of unwrapping.
+/// Tree iterator which holds the tree lock during each operation.
+struct LockedTreeIterator<'tree> {
+    inner: TreeIterator<'tree>,
+    lock: Arc<Mutex<isize>>,
+}
+
+impl<'tree> Iterator for LockedTreeIterator<'tree> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let _guard = self.lock.lock().unwrap();
+        Iterator::next(&mut self.inner)
+    }
+}
+
+impl<'tree> mkvs::Iterator for LockedTreeIterator<'tree> {
+    fn set_prefetch(&mut self, prefetch: usize) {
+        self.inner.set_prefetch(prefetch)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.inner.is_valid()
+    }
+
+    fn error(&self) -> &Option<Error> {
+        self.inner.error()
+    }
+
+    fn take_error(&mut self) -> Option<Error> {
+        self.inner.take_error()
+    }
+
+    fn rewind(&mut self) {
+        let _guard = self.lock.lock().unwrap();
+        self.inner.rewind()
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        let _guard = self.lock.lock().unwrap();
+        self.inner.seek(key)
+    }
+}
 
 impl MKVS for Tree {
-    fn get(&self, ctx: Context, key: &[u8]) -> Option<Vec<u8>> {
+    fn get(&self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let _lock = self.lock.lock().unwrap();
-        self.get(ctx, key).unwrap()
+        self.get(ctx, key).context(StorageError)
     }
 
     fn cache_contains_key(&self, ctx: Context, key: &[u8]) -> bool {
@@ -22,22 +65,42 @@ impl MKVS for Tree {
         self.cache_contains_key(ctx, key)
     }
 
-    fn insert(&mut self, ctx: Context, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+    fn insert(&mut self, ctx: Context, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
         let lock = self.lock.clone();
         let _guard = lock.lock().unwrap();
-        self.insert(ctx, key, value).unwrap()
+        self.insert(ctx, key, value).context(StorageError)
     }
 
-    fn remove(&mut self, ctx: Context, key: &[u8]) -> Option<Vec<u8>> {
+    fn remove(&mut self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let lock = self.lock.clone();
         let _guard = lock.lock().unwrap();
-        self.remove(ctx, key).unwrap()
+        self.remove(ctx, key).context(StorageError)
     }
 
-    fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16) {
+    fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16) -> Result<()> {
         let lock = self.lock.clone();
         let _guard = lock.lock().unwrap();
-        self.prefetch_prefixes(ctx, prefixes, limit).unwrap()
+        self.prefetch_prefixes(ctx, prefixes, limit)
+            .context(StorageError)
+    }
+
+    fn prefetch_all_prefixes(
+        &self,
+        ctx: Context,
+        prefixes: &Vec<Prefix>,
+        page_size: u16,
+    ) -> Result<()> {
+        let lock = self.lock.clone();
+        let _guard = lock.lock().unwrap();
+        self.prefetch_all_prefixes(ctx, prefixes, page_size)
+            .context(StorageError)
+    }
+
+    fn iter(&self, ctx: Context) -> Box<dyn mkvs::Iterator + '_> {
+        Box::new(LockedTreeIterator {
+            inner: Tree::iter(self, ctx),
+            lock: self.lock.clone(),
+        })
     }
 
     fn commit(
@@ -54,6 +117,6 @@ impl MKVS for Tree {
     fn rollback(&mut self) {
         let lock = self.lock.clone();
         let _guard = lock.lock().unwrap();
-        self.pending_write_log.clear();
+        Tree::rollback(self)
     }
 }
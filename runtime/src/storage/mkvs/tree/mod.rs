@@ -1,7 +1,11 @@
 #[macro_use]
 mod macros;
 
+mod bulk;
+mod checkpoint;
 mod commit;
+mod debug;
+mod diff;
 mod errors;
 mod insert;
 mod iterator;
@@ -11,19 +15,25 @@ mod mkvs;
 mod node;
 mod prefetch;
 mod remove;
+mod rollback;
+mod syncer;
 mod tree;
 
 pub use commit::*;
+pub use debug::*;
 pub use errors::*;
 pub use insert::*;
 pub use iterator::*;
 pub use node::*;
 pub use remove::*;
+pub(crate) use rollback::*;
 pub use tree::*;
 
+#[cfg(test)]
+mod model_test;
 #[cfg(test)]
 mod node_test;
 #[cfg(test)]
 mod tree_bench;
 #[cfg(test)]
-mod tree_test;
+pub(crate) mod tree_test;
@@ -0,0 +1,351 @@
+//! Differential tests which run random operation sequences against a tree
+//! and a `BTreeMap` model of its contents.
+use std::{collections::BTreeMap, sync::Arc};
+
+use io_context::Context;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    common::{crypto::hash::Hash, roothash::Namespace},
+    storage::mkvs::{
+        cache::*,
+        db::{MemoryBackend, NodeDB},
+        sync::*,
+        tree::*,
+        MKVS,
+    },
+};
+
+/// Number of operations executed in each run.
+const OPS_PER_RUN: usize = 400;
+/// Seeds used for the runs, so that failures are reproducible.
+const SEEDS: &[u64] = &[0, 1, 7, 42, 1337];
+/// Bytes used to generate keys, chosen so that keys often share prefixes.
+const KEY_ALPHABET: &[u8] = &[0x00, b'a', b'b', b'c', 0xff];
+/// Maximum length of generated keys.
+const MAX_KEY_LEN: usize = 4;
+/// Smallest node capacity which still fits the longest path in the tree, as
+/// the cache must hold all internal nodes on the path to the node being
+/// dereferenced.
+const TINY_NODE_CAPACITY: usize = 8 * MAX_KEY_LEN + 2;
+
+#[derive(Clone, Debug)]
+enum Op {
+    Insert(Vec<u8>, Vec<u8>),
+    Remove(Vec<u8>),
+    Get(Vec<u8>),
+    Commit,
+    Rollback,
+    Iterate,
+    Seek(Vec<u8>),
+    Prefetch(Vec<u8>),
+}
+
+struct Config {
+    node_capacity: usize,
+    value_capacity: usize,
+    /// Whether the tree is backed by a node database through a read syncer.
+    /// Otherwise prefetching is not supported.
+    remote: bool,
+    /// Whether each commit uses a new version. Otherwise all commits use
+    /// version zero and root hashes only depend on the tree contents.
+    versioned: bool,
+}
+
+fn random_key(rng: &mut StdRng) -> Vec<u8> {
+    let len = rng.gen_range(0, MAX_KEY_LEN + 1);
+    (0..len)
+        .map(|_| KEY_ALPHABET[rng.gen_range(0, KEY_ALPHABET.len())])
+        .collect()
+}
+
+fn random_value(rng: &mut StdRng) -> Vec<u8> {
+    let len = rng.gen_range(0, 40);
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+fn random_op(rng: &mut StdRng) -> Op {
+    match rng.gen_range(0, 100) {
+        0..=34 => Op::Insert(random_key(rng), random_value(rng)),
+        35..=49 => Op::Remove(random_key(rng)),
+        50..=69 => Op::Get(random_key(rng)),
+        70..=79 => Op::Commit,
+        80..=83 => Op::Rollback,
+        84..=87 => Op::Iterate,
+        88..=95 => Op::Seek(random_key(rng)),
+        _ => Op::Prefetch(random_key(rng)),
+    }
+}
+
+struct Harness {
+    config: Config,
+    db: NodeDB,
+    tree: Tree,
+    model: BTreeMap<Vec<u8>, Vec<u8>>,
+    committed: BTreeMap<Vec<u8>, Vec<u8>>,
+    version: u64,
+    /// Seed and index of the current operation, so that failures are easy
+    /// to reproduce.
+    step: String,
+}
+
+impl Harness {
+    fn new(config: Config) -> Self {
+        let namespace = Namespace::from(Hash::digest_bytes(b"mkvs model test").as_ref());
+        let db = NodeDB::open(Arc::new(MemoryBackend::new()), namespace).expect("open");
+        let read_syncer: Box<dyn ReadSync> = if config.remote {
+            Box::new(db.clone())
+        } else {
+            Box::new(NoopReadSyncer)
+        };
+        let tree = Tree::make()
+            .with_capacity(config.node_capacity, config.value_capacity)
+            .new(read_syncer);
+
+        Self {
+            config,
+            db,
+            tree,
+            model: BTreeMap::new(),
+            committed: BTreeMap::new(),
+            version: 0,
+            step: String::new(),
+        }
+    }
+
+    fn is_dirty(&self) -> bool {
+        !self.tree.cache.borrow().get_pending_root().borrow().clean
+    }
+
+    fn model_entries(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.model
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    fn apply(&mut self, op: &Op) {
+        let ctx = Context::background();
+        let step = &self.step;
+        match op {
+            Op::Insert(key, value) => {
+                let previous = self
+                    .tree
+                    .insert(ctx, key, value)
+                    .unwrap_or_else(|err| panic!("{}: insert: {}", step, err));
+                assert_eq!(
+                    previous,
+                    self.model.insert(key.clone(), value.clone()),
+                    "{}: insert",
+                    step
+                );
+            }
+            Op::Remove(key) => {
+                let previous = self
+                    .tree
+                    .remove(ctx, key)
+                    .unwrap_or_else(|err| panic!("{}: remove: {}", step, err));
+                assert_eq!(previous, self.model.remove(key), "{}: remove", step);
+            }
+            Op::Get(key) => {
+                let value = self
+                    .tree
+                    .get(ctx, key)
+                    .unwrap_or_else(|err| panic!("{}: get: {}", step, err));
+                assert_eq!(value.as_ref(), self.model.get(key), "{}: get", step);
+            }
+            Op::Commit => self.commit(),
+            Op::Rollback => {
+                MKVS::rollback(&mut self.tree);
+                self.model = self.committed.clone();
+                assert!(
+                    self.tree.pending_write_log.is_empty(),
+                    "{}: rollback should discard the write log",
+                    step
+                );
+            }
+            Op::Iterate => {
+                let mut it = self.tree.iter(ctx);
+                it.rewind();
+                let entries: Vec<_> = it.by_ref().collect();
+                assert!(
+                    it.error().is_none(),
+                    "{}: iteration should not fail: {:?}",
+                    step,
+                    it.error()
+                );
+                assert_eq!(entries, self.model_entries(), "{}: iterate", step);
+            }
+            Op::Seek(key) => {
+                let mut it = self.tree.iter(ctx);
+                it.seek(key);
+                let expected = self
+                    .model
+                    .range(key.clone()..)
+                    .next()
+                    .map(|(key, value)| (key.clone(), value.clone()));
+                let entry = it.next();
+                assert!(
+                    it.error().is_none(),
+                    "{}: seek should not fail: {:?}",
+                    step,
+                    it.error()
+                );
+                assert_eq!(entry, expected, "{}: seek", step);
+            }
+            Op::Prefetch(prefix) => {
+                // Prefetching is only supported for committed roots.
+                if self.is_dirty() || self.committed.is_empty() {
+                    return;
+                }
+                let result = self
+                    .tree
+                    .prefetch_prefixes(ctx, &vec![prefix.clone().into()], 10);
+                if self.config.remote {
+                    result.unwrap_or_else(|err| panic!("{}: prefetch prefixes: {}", step, err));
+                } else {
+                    // Prefetching must fail without changing the tree.
+                    let err = result.expect_err("prefetch without a read syncer");
+                    assert!(
+                        matches!(err.downcast_ref(), Some(SyncerError::Unsupported)),
+                        "{}: prefetch prefixes: {}",
+                        step,
+                        err
+                    );
+                }
+            }
+        }
+    }
+
+    fn commit(&mut self) {
+        let ctx = Context::background();
+        let step = &self.step;
+        let version = self.version;
+        let (write_log, hash) = if self.config.remote {
+            let (write_log, root) = self
+                .db
+                .commit(ctx, &mut self.tree, version)
+                .unwrap_or_else(|err| panic!("{}: commit: {}", step, err));
+            (write_log, root.hash)
+        } else {
+            Tree::commit(&mut self.tree, ctx, Default::default(), version)
+                .unwrap_or_else(|err| panic!("{}: commit: {}", step, err))
+        };
+
+        // The write log must contain all changes since the last commit.
+        for entry in &write_log {
+            assert_eq!(
+                entry.value.as_ref(),
+                self.model.get(&entry.key),
+                "{}: write log entry",
+                step
+            );
+        }
+        for (key, value) in &self.model {
+            if self.committed.get(key) != Some(value) {
+                assert!(
+                    write_log.iter().any(|entry| &entry.key == key),
+                    "{}: write log should contain all inserted keys",
+                    step
+                );
+            }
+        }
+        for key in self.committed.keys() {
+            if !self.model.contains_key(key) {
+                assert!(
+                    write_log.iter().any(|entry| &entry.key == key),
+                    "{}: write log should contain all removed keys",
+                    step
+                );
+            }
+        }
+        self.committed = self.model.clone();
+
+        if !self.config.versioned {
+            // Equivalent histories must produce the same root, so the root
+            // must match a tree built from the contents alone.
+            let mut expected = Tree::make()
+                .build(Box::new(NoopReadSyncer), self.model_entries())
+                .expect("bulk load");
+            let (_, expected_hash) =
+                Tree::commit(&mut expected, Context::background(), Default::default(), 0)
+                    .expect("commit");
+            assert_eq!(hash, expected_hash, "{}: root hash", step);
+        } else {
+            self.version += 1;
+        }
+
+        if self.config.remote {
+            // The committed root must be readable from the database alone.
+            let root = Root {
+                namespace: self.db.namespace(),
+                version,
+                hash,
+            };
+            let tree = self
+                .db
+                .tree(root)
+                .unwrap_or_else(|err| panic!("{}: tree: {}", step, err));
+            let mut it = tree.iter(Context::background());
+            it.rewind();
+            let entries: Vec<_> = it.collect();
+            assert_eq!(entries, self.model_entries(), "{}: committed root", step);
+        }
+    }
+}
+
+fn run(config_fn: impl Fn() -> Config) {
+    for seed in SEEDS {
+        let mut rng = StdRng::seed_from_u64(*seed);
+        let ops: Vec<_> = (0..OPS_PER_RUN).map(|_| random_op(&mut rng)).collect();
+
+        let mut harness = Harness::new(config_fn());
+        for (idx, op) in ops.iter().enumerate() {
+            harness.step = format!("seed {} op {} ({:?})", seed, idx, op);
+            harness.apply(op);
+        }
+        harness.step = format!("seed {} final", seed);
+        harness.apply(&Op::Commit);
+        harness.apply(&Op::Iterate);
+    }
+}
+
+#[test]
+fn test_model_local() {
+    run(|| Config {
+        node_capacity: 0,
+        value_capacity: 0,
+        remote: false,
+        versioned: false,
+    });
+}
+
+#[test]
+fn test_model_remote() {
+    run(|| Config {
+        node_capacity: 50_000,
+        value_capacity: 16 * 1024 * 1024,
+        remote: true,
+        versioned: true,
+    });
+}
+
+#[test]
+fn test_model_remote_tiny_cache() {
+    run(|| Config {
+        node_capacity: TINY_NODE_CAPACITY,
+        value_capacity: 128,
+        remote: true,
+        versioned: true,
+    });
+}
+
+#[test]
+fn test_model_remote_tiny_cache_unversioned() {
+    run(|| Config {
+        node_capacity: TINY_NODE_CAPACITY,
+        value_capacity: 128,
+        remote: true,
+        versioned: false,
+    });
+}
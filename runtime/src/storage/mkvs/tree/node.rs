@@ -1,4 +1,4 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, mem, rc::Rc};
 
 use serde::{Deserialize, Serialize};
 
@@ -32,7 +32,7 @@ pub struct Root {
 }
 
 /// A box type that can contain either internal or leaf nodes.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum NodeBox {
     Internal(InternalNode),
     Leaf(LeafNode),
@@ -200,6 +200,36 @@ impl CacheItem for NodePointer {
     fn get_cached_size(&self) -> usize {
         1
     }
+
+    /// Estimate the memory used by the pointer and the node it points to.
+    ///
+    /// This includes the reference counted allocations and the node's hash,
+    /// but not the child pointers of internal nodes, which are accounted for
+    /// by the children themselves once they are cached.
+    fn get_memory_size(&self) -> usize {
+        // Strong and weak counts of an Rc allocation.
+        const RC_OVERHEAD: usize = 2 * mem::size_of::<usize>();
+
+        let node_size = match self.node {
+            Some(ref node) => {
+                let data_size = match *node.borrow() {
+                    NodeBox::Internal(ref n) => n.label.len(),
+                    NodeBox::Leaf(ref n) => n.key.len() + n.value.len(),
+                };
+                RC_OVERHEAD + mem::size_of::<RefCell<NodeBox>>() + data_size
+            }
+            None => 0,
+        };
+        RC_OVERHEAD + mem::size_of::<RefCell<NodePointer>>() + node_size
+    }
+
+    /// Nodes are mutably borrowed while operations update them. Nodes which
+    /// are only being read may still be evicted as readers hold references.
+    fn is_in_use(&self) -> bool {
+        self.node
+            .as_ref()
+            .map_or(false, |node| node.try_borrow().is_err())
+    }
 }
 
 impl PartialEq for NodePointer {
@@ -215,7 +245,7 @@ impl PartialEq for NodePointer {
 impl Eq for NodePointer {}
 
 /// An internal tree node with two children and possibly a leaf.
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct InternalNode {
     pub clean: bool,
     pub version: u64,
@@ -285,7 +315,7 @@ impl PartialEq for InternalNode {
 impl Eq for InternalNode {}
 
 /// A leaf node containing a key/value pair.
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct LeafNode {
     pub clean: bool,
     pub version: u64,
@@ -1,16 +1,40 @@
+use std::cell::RefCell;
+
 use anyhow::Result;
 use io_context::Context;
 
-use crate::storage::mkvs::{cache::*, sync::*, tree::*, Prefix};
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{cache::*, sync::*, tree::*, Prefix},
+};
 
 pub(super) struct FetcherSyncGetPrefixes<'a> {
     prefixes: &'a Vec<Prefix>,
     limit: u16,
+    start_key: Option<Vec<u8>>,
+    next_key: Option<&'a RefCell<Option<Vec<u8>>>>,
 }
 
 impl<'a> FetcherSyncGetPrefixes<'a> {
     pub(super) fn new(prefixes: &'a Vec<Prefix>, limit: u16) -> Self {
-        Self { prefixes, limit }
+        Self {
+            prefixes,
+            limit,
+            start_key: None,
+            next_key: None,
+        }
+    }
+
+    /// Continue from the given key and store the key to continue from in the
+    /// following page (if any) in `next_key`.
+    pub(super) fn with_pagination(
+        mut self,
+        start_key: Option<Vec<u8>>,
+        next_key: &'a RefCell<Option<Vec<u8>>>,
+    ) -> Self {
+        self.start_key = start_key;
+        self.next_key = Some(next_key);
+        self
     }
 }
 
@@ -20,6 +44,7 @@ impl<'a> ReadSyncFetcher for FetcherSyncGetPrefixes<'a> {
         ctx: Context,
         root: Root,
         ptr: NodePtrRef,
+        known_nodes: &[Hash],
         rs: &mut Box<dyn ReadSync>,
     ) -> Result<Proof> {
         let rsp = rs.sync_get_prefixes(
@@ -31,14 +56,22 @@ impl<'a> ReadSyncFetcher for FetcherSyncGetPrefixes<'a> {
                 },
                 prefixes: self.prefixes.clone(),
                 limit: self.limit,
+                start_key: self.start_key.clone(),
+                known_nodes: known_nodes.to_vec(),
             },
         )?;
+        if let Some(next_key) = self.next_key {
+            *next_key.borrow_mut() = rsp.next_key;
+        }
         Ok(rsp.proof)
     }
 }
 
 impl Tree {
     /// Populate the in-memory tree with nodes for keys starting with given prefixes.
+    ///
+    /// At most `limit` keys are fetched, use `prefetch_all_prefixes` to fetch
+    /// all of them.
     pub fn prefetch_prefixes(
         &self,
         ctx: Context,
@@ -53,4 +86,38 @@ impl Tree {
             FetcherSyncGetPrefixes::new(prefixes, limit),
         )
     }
+
+    /// Populate the in-memory tree with nodes for all keys starting with given
+    /// prefixes, fetching at most `page_size` keys per request.
+    ///
+    /// Pagination is driven by the (untrusted) read syncer, so the result is
+    /// only a cache hint and any nodes it omits are fetched on demand.
+    pub fn prefetch_all_prefixes(
+        &self,
+        ctx: Context,
+        prefixes: &Vec<Prefix>,
+        page_size: u16,
+    ) -> Result<()> {
+        let ctx = ctx.freeze();
+        let next_key = RefCell::new(None);
+        let mut start_key: Option<Vec<u8>> = None;
+        loop {
+            let pending_root = self.cache.borrow().get_pending_root();
+            self.cache.borrow_mut().remote_sync(
+                &ctx,
+                pending_root,
+                FetcherSyncGetPrefixes::new(prefixes, page_size)
+                    .with_pagination(start_key.clone(), &next_key),
+            )?;
+
+            match next_key.borrow_mut().take() {
+                // Guard against a misbehaving read syncer which does not
+                // make progress.
+                Some(key) if page_size > 0 && Some(&key) > start_key.as_ref() => {
+                    start_key = Some(key)
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
 }
@@ -21,6 +21,7 @@ impl Tree {
 
         // Remember where the path from root to target node ends (will end).
         self.cache.borrow_mut().mark_position();
+        self.undo_log.save_root(&pending_root);
 
         let (new_root, changed, old_val) = self._remove(&ctx, pending_root, 0, &boxed_key, 0)?;
         match self.pending_write_log.get_mut(&boxed_key) {
@@ -65,6 +66,7 @@ impl Tree {
             NodeKind::Internal => {
                 // Remove from internal node and recursively collapse the path, if needed.
                 let node_ref = node_ref.unwrap();
+                self.snapshot_node(&ptr);
                 let (changed, old_val): (bool, Option<Value>);
                 let (remaining_leaf, remaining_left, remaining_right): (
                     Option<NodeRef>,
@@ -161,6 +163,7 @@ impl Tree {
 
                         if !both_children {
                             // If child is an internal node, also fix the label.
+                            self.snapshot_node(&node_ptr);
                             match nd_child {
                                 Some(_) => match classify_noderef!(?nd_child) {
                                     NodeKind::Internal => {
@@ -207,6 +210,7 @@ impl Tree {
                 // Remove from leaf node.
                 let node_ref = node_ref.unwrap();
                 if noderef_as!(node_ref, Leaf).key == *key {
+                    self.snapshot_node(&ptr);
                     let old_val = noderef_as!(node_ref, Leaf).value.clone();
                     self.cache.borrow_mut().remove_node(ptr.clone());
                     return Ok((NodePointer::null_ptr(), true, Some(old_val)));
@@ -0,0 +1,100 @@
+use std::{cell::RefCell, collections::HashSet, mem};
+
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{cache::*, tree::*},
+};
+
+/// State of a committed node pointer and its node before an update changed
+/// them.
+struct NodeSnapshot {
+    ptr: NodePtrRef,
+    hash: Hash,
+    node: Option<(NodeRef, NodeBox)>,
+}
+
+/// Committed tree state changed by updates since the last commit.
+///
+/// Updates modify committed nodes in place, so the state of each committed
+/// node is saved before it is first changed and restored on rollback. Nodes
+/// created since the last commit are not tracked as rollback drops them.
+#[derive(Default)]
+pub(crate) struct UndoLog {
+    /// Pending root at the last commit.
+    root: Option<NodePtrRef>,
+    /// Snapshots of changed nodes.
+    snapshots: Vec<NodeSnapshot>,
+    /// Pointers which already have a snapshot.
+    saved: HashSet<*const RefCell<NodePointer>>,
+}
+
+impl UndoLog {
+    /// Save the pending root, unless it was already saved since the last
+    /// commit.
+    pub(crate) fn save_root(&mut self, root: &NodePtrRef) {
+        if self.root.is_none() {
+            self.root = Some(root.clone());
+        }
+    }
+
+    /// Forget all saved state, making the current tree state the one which
+    /// rollback restores.
+    pub(crate) fn clear(&mut self) {
+        self.root = None;
+        self.snapshots.clear();
+        self.saved.clear();
+    }
+}
+
+impl Tree {
+    /// Save the state of a committed node before an update changes it.
+    pub(super) fn snapshot_node(&mut self, ptr: &NodePtrRef) {
+        let ptr_ref = ptr.borrow();
+        if !ptr_ref.clean || !self.undo_log.saved.insert(ptr.as_ptr()) {
+            return;
+        }
+
+        self.undo_log.snapshots.push(NodeSnapshot {
+            ptr: ptr.clone(),
+            hash: ptr_ref.hash,
+            node: ptr_ref
+                .node
+                .as_ref()
+                .map(|node_ref| (node_ref.clone(), node_ref.borrow().clone())),
+        });
+    }
+
+    /// Discard all uncommitted updates, restoring the tree to the last commit.
+    pub fn rollback(&mut self) {
+        self.pending_write_log.clear();
+        let root = match self.undo_log.root.take() {
+            Some(root) => root,
+            None => return,
+        };
+        let snapshots = mem::take(&mut self.undo_log.snapshots);
+        self.undo_log.clear();
+
+        let mut restored = Vec::with_capacity(snapshots.len());
+        for snapshot in snapshots {
+            let mut ptr = snapshot.ptr.borrow_mut();
+            ptr.clean = true;
+            ptr.hash = snapshot.hash;
+            ptr.node = snapshot.node.map(|(node_ref, node)| {
+                *node_ref.borrow_mut() = node;
+                node_ref
+            });
+            drop(ptr);
+            restored.push(snapshot.ptr);
+        }
+
+        // Changed nodes were removed from the cache as they became dirty, make
+        // them eligible for eviction again.
+        let mut cache = self.cache.borrow_mut();
+        for ptr in restored {
+            if ptr.borrow().node.is_some() && ptr.borrow().get_cache_extra().is_none() {
+                cache.commit_node(ptr);
+            }
+        }
+        cache.set_pending_root(root);
+    }
+}
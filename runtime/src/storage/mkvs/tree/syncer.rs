@@ -0,0 +1,198 @@
+use std::{any::Any, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use io_context::Context;
+
+use crate::storage::mkvs::{
+    cache::*,
+    range::prefix_end,
+    sync::*,
+    tree::{lookup::FetcherSyncGet, *},
+    Prefix,
+};
+
+/// Sort the prefixes and drop those covered by another prefix, so that all
+/// matching keys are visited exactly once and in ascending order.
+fn normalize_prefixes(prefixes: &[Prefix]) -> Vec<&[u8]> {
+    let mut sorted: Vec<&[u8]> = prefixes.iter().map(|prefix| prefix.as_ref()).collect();
+    sorted.sort();
+
+    let mut normalized: Vec<&[u8]> = Vec::with_capacity(sorted.len());
+    for prefix in sorted {
+        // A covering prefix sorts directly before the prefixes it covers.
+        match normalized.last() {
+            Some(last) if prefix.starts_with(last) => {}
+            _ => normalized.push(prefix),
+        }
+    }
+    normalized
+}
+
+impl Tree {
+    /// Make sure that the request is for the current (committed) root of the tree.
+    fn check_sync_root(&self, tree: &TreeID) -> Result<()> {
+        let pending_root = self.cache.borrow().get_pending_root();
+        let pending_root = pending_root.borrow();
+        if !pending_root.clean || pending_root.hash != tree.root.hash {
+            return Err(anyhow!(
+                "mkvs: requested root not available (expected: {:?})",
+                tree.root.hash
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn _sync_get(
+        &self,
+        ctx: &Arc<Context>,
+        ptr: NodePtrRef,
+        bit_depth: Depth,
+        request: &GetRequest,
+        pb: &mut ProofBuilder,
+    ) -> Result<()> {
+        let node_ref = self.cache.borrow_mut().deref_node_ptr(
+            ctx,
+            ptr,
+            Some(FetcherSyncGet::new(&request.key, request.include_siblings)),
+        )?;
+        let node_ref = match node_ref {
+            Some(node_ref) => node_ref,
+            None => return Ok(()),
+        };
+        pb.include(&node_ref.borrow())?;
+
+        let (bit_length, next, sibling) = match *node_ref.borrow() {
+            NodeBox::Internal(ref n) => {
+                let bit_length = bit_depth + n.label_bit_length;
+                if request.key.bit_length() <= bit_length {
+                    // The leaf node is included as part of the internal node.
+                    return Ok(());
+                }
+
+                if request.key.get_bit(bit_length) {
+                    (bit_length, n.right.clone(), n.left.clone())
+                } else {
+                    (bit_length, n.left.clone(), n.right.clone())
+                }
+            }
+            NodeBox::Leaf(..) => return Ok(()),
+        };
+
+        if request.include_siblings {
+            let sibling = self.cache.borrow_mut().deref_node_ptr(
+                ctx,
+                sibling,
+                Some(FetcherSyncGet::new(&request.key, request.include_siblings)),
+            )?;
+            if let Some(sibling) = sibling {
+                pb.include(&sibling.borrow())?;
+            }
+        }
+
+        self._sync_get(ctx, next, bit_length, request, pb)
+    }
+}
+
+/// The tree can serve as a read syncer for other trees.
+///
+/// Only requests for the tree's current root are supported, so the tree must
+/// not have any uncommitted changes. Nodes which are not available locally
+/// are fetched using the tree's own read syncer. Requests are serialized with
+/// other operations on the tree in the same way as `MKVS` methods.
+impl ReadSync for Tree {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn sync_get(&mut self, ctx: Context, request: GetRequest) -> Result<ProofResponse> {
+        let lock = self.lock.clone();
+        let _guard = lock.lock().unwrap();
+        self.check_sync_root(&request.tree)?;
+
+        let ctx = ctx.freeze();
+        let mut pb = ProofBuilder::new(request.tree.root.hash, request.tree.position)
+            .with_known_nodes(&request.known_nodes);
+        let pending_root = self.cache.borrow().get_pending_root();
+        self._sync_get(&ctx, pending_root, 0, &request, &mut pb)?;
+
+        Ok(ProofResponse {
+            proof: pb.build(Context::create_child(&ctx))?,
+            next_key: None,
+        })
+    }
+
+    fn sync_get_prefixes(
+        &mut self,
+        ctx: Context,
+        request: GetPrefixesRequest,
+    ) -> Result<ProofResponse> {
+        let lock = self.lock.clone();
+        let _guard = lock.lock().unwrap();
+        self.check_sync_root(&request.tree)?;
+
+        let mut it = self.iter(ctx).with_proof(
+            request.tree.root.hash,
+            request.tree.position,
+            &request.known_nodes,
+        );
+        let start_key = request.start_key.unwrap_or_default();
+        let mut remaining = request.limit;
+        let mut next_key = None;
+        'prefixes: for prefix in normalize_prefixes(&request.prefixes) {
+            if prefix_end(prefix).map_or(false, |end| end <= start_key) {
+                // All keys with this prefix were returned in earlier pages.
+                continue;
+            }
+
+            it.seek(std::cmp::max(prefix, &start_key[..]));
+            while let Some((key, _)) = Iterator::next(&mut it) {
+                if !key.starts_with(prefix) {
+                    break;
+                }
+                if remaining == 0 {
+                    // Report the first key which did not fit so that the
+                    // caller can continue from it.
+                    next_key = Some(key);
+                    break 'prefixes;
+                }
+                remaining -= 1;
+            }
+        }
+        if let Some(error) = it.error() {
+            return Err(anyhow!("mkvs: failed to iterate: {}", error));
+        }
+
+        Ok(ProofResponse {
+            proof: it.get_proof()?,
+            next_key,
+        })
+    }
+
+    fn sync_iterate(&mut self, ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+        let lock = self.lock.clone();
+        let _guard = lock.lock().unwrap();
+        self.check_sync_root(&request.tree)?;
+
+        let mut it = self.iter(ctx).with_proof(
+            request.tree.root.hash,
+            request.tree.position,
+            &request.known_nodes,
+        );
+        it.set_prefetch(request.prefetch as usize);
+        it.seek(&request.key);
+        for _ in 0..request.prefetch {
+            if Iterator::next(&mut it).is_none() {
+                break;
+            }
+        }
+        if let Some(error) = it.error() {
+            return Err(anyhow!("mkvs: failed to iterate: {}", error));
+        }
+
+        Ok(ProofResponse {
+            proof: it.get_proof()?,
+            next_key: None,
+        })
+    }
+}
@@ -18,7 +18,9 @@ pub struct PendingLogEntry {
 pub struct Options {
     node_capacity: usize,
     value_capacity: usize,
-    root: Option<Root>,
+    node_memory_capacity: usize,
+    eviction_policy: EvictionPolicy,
+    pub(super) root: Option<Root>,
 }
 
 impl Options {
@@ -37,6 +39,24 @@ impl Options {
         self
     }
 
+    /// Set the maximum memory, in bytes, used by internal nodes held by the
+    /// underlying in-memory cache before eviction.
+    ///
+    /// This applies in addition to the node capacity. If set to 0 (the
+    /// default), only the node capacity is used. The memory used by leaf
+    /// nodes is not included and is only bounded by the value capacity.
+    pub fn with_node_memory_capacity(mut self, node_memory_capacity: usize) -> Self {
+        self.node_memory_capacity = node_memory_capacity;
+        self
+    }
+
+    /// Set the policy used to select nodes for eviction from the underlying
+    /// in-memory cache. If left unspecified, LRU is used.
+    pub fn with_eviction_policy(mut self, eviction_policy: EvictionPolicy) -> Self {
+        self.eviction_policy = eviction_policy;
+        self
+    }
+
     /// Set an existing root as the root for the new tree.
     pub fn with_root(mut self, root: Root) -> Self {
         self.root = Some(root);
@@ -50,9 +70,13 @@ impl Options {
 }
 
 /// A patricia tree-based MKVS implementation.
+///
+/// All operations on a tree are serialized, including reads. Committed roots
+/// can be read concurrently through a `TreeSnapshot` instead.
 pub struct Tree {
     pub(crate) cache: RefCell<Box<LRUCache>>,
     pub(crate) pending_write_log: BTreeMap<Key, PendingLogEntry>,
+    pub(crate) undo_log: UndoLog,
     pub(crate) lock: Arc<Mutex<isize>>,
 }
 
@@ -66,8 +90,14 @@ impl Tree {
                 read_syncer,
             )),
             pending_write_log: BTreeMap::new(),
+            undo_log: UndoLog::default(),
             lock: Arc::new(Mutex::new(0)),
         };
+        {
+            let mut cache = tree.cache.borrow_mut();
+            cache.set_eviction_policy(opts.eviction_policy);
+            cache.set_node_memory_capacity(opts.node_memory_capacity);
+        }
 
         if let Some(root) = opts.root {
             tree.cache
@@ -88,9 +118,29 @@ impl Tree {
         Options {
             node_capacity: 50_000,
             value_capacity: 16 * 1024 * 1024,
+            node_memory_capacity: 0,
+            eviction_policy: EvictionPolicy::LRU,
             root: None,
         }
     }
+
+    /// Return statistics about the contents and usage of the tree's cache.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.borrow().stats()
+    }
+
+    /// Return the tree's current root, making sure that the tree does not
+    /// have any uncommitted changes.
+    pub(crate) fn committed_root(&self) -> anyhow::Result<Root> {
+        let sync_root = self.cache.borrow().get_sync_root();
+        let pending_root = self.cache.borrow().get_pending_root();
+        let pending_root = pending_root.borrow();
+        if !pending_root.clean || pending_root.hash != sync_root.hash {
+            return Err(anyhow::anyhow!("mkvs: tree has uncommitted changes"));
+        }
+
+        Ok(sync_root)
+    }
 }
 
 impl fmt::Debug for Tree {
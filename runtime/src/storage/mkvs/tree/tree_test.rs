@@ -1,16 +1,17 @@
+use anyhow::Result;
 use io_context::Context;
 use serde_json;
 use std::{collections::HashSet, fs::File, io::BufReader, iter::FromIterator, path::Path};
 
 use crate::{
-    common::crypto::hash::Hash,
+    common::{cbor, crypto::hash::Hash},
     storage::mkvs::{
         cache::*,
         interop::{Driver, ProtocolServer},
         sync::*,
         tests,
         tree::*,
-        LogEntry, LogEntryKind, WriteLog,
+        LogEntry, LogEntryKind, Prefix, WriteLog, MKVS,
     },
 };
 
@@ -37,6 +38,18 @@ fn generate_key_value_pairs() -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
     generate_key_value_pairs_ex("".to_string(), INSERT_ITEMS)
 }
 
+/// Construct a new tree with uncommitted inserts of `count` generated
+/// key/value pairs.
+pub fn make_tree(count: usize) -> Tree {
+    let (keys, values) = generate_key_value_pairs_ex("".to_string(), count);
+    let mut tree = Tree::make().new(Box::new(NoopReadSyncer));
+    for (key, value) in keys.iter().zip(values.iter()) {
+        tree.insert(Context::background(), key, value)
+            .expect("insert");
+    }
+    tree
+}
+
 fn generate_long_key_value_pairs() -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
     let mut keys: Vec<Vec<u8>> = Vec::with_capacity(LONG_KEY.len());
     let mut values: Vec<Vec<u8>> = Vec::with_capacity(LONG_KEY.len());
@@ -911,6 +924,768 @@ fn test_syncer_prefetch_prefixes() {
     assert_eq!(0, stats.sync_iterate_count, "sync_iterate count");
 }
 
+#[test]
+fn test_syncer_prefetch_all_prefixes() {
+    let server = ProtocolServer::new();
+
+    let mut tree = Tree::make()
+        .with_capacity(0, 0)
+        .new(Box::new(NoopReadSyncer));
+
+    let (keys, values) = generate_key_value_pairs();
+    for i in 0..keys.len() {
+        tree.insert(
+            Context::background(),
+            keys[i].as_slice(),
+            values[i].as_slice(),
+        )
+        .expect("insert");
+    }
+
+    let (write_log, hash) =
+        Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+    server.apply(&write_log, hash, Default::default(), 0);
+
+    let stats = StatsCollector::new(server.read_sync());
+    let remote_tree = Tree::make()
+        .with_capacity(0, 0)
+        .with_root(Root {
+            hash,
+            ..Default::default()
+        })
+        .new(Box::new(stats));
+
+    // Prefetch all keys starting with prefix "key" in pages of 300 keys.
+    remote_tree
+        .prefetch_all_prefixes(
+            Context::background(),
+            &vec![b"key 1".to_vec().into(), b"key".to_vec().into()],
+            300,
+        )
+        .expect("prefetch_all_prefixes");
+
+    for i in 0..keys.len() {
+        let value = remote_tree
+            .get(Context::background(), keys[i].as_slice())
+            .expect("get")
+            .expect("get_some");
+        assert_eq!(values[i], value.as_slice());
+    }
+
+    let cache = remote_tree.cache.borrow();
+    let stats = cache
+        .get_read_syncer()
+        .as_any()
+        .downcast_ref::<StatsCollector>()
+        .expect("stats");
+    assert_eq!(0, stats.sync_get_count, "sync_get count");
+    assert_eq!(4, stats.sync_get_prefixes_count, "sync_get_prefixes count");
+    assert_eq!(0, stats.sync_iterate_count, "sync_iterate count");
+}
+
+/// Create a committed tree containing the given items, for use as a read syncer.
+fn make_local_syncer(keys: &[Vec<u8>], values: &[Vec<u8>]) -> (Tree, Hash) {
+    let mut tree = Tree::make()
+        .with_capacity(0, 0)
+        .new(Box::new(NoopReadSyncer));
+    for i in 0..keys.len() {
+        tree.insert(
+            Context::background(),
+            keys[i].as_slice(),
+            values[i].as_slice(),
+        )
+        .expect("insert");
+    }
+
+    let (_, hash) =
+        Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+    (tree, hash)
+}
+
+/// A read syncer which records the known nodes sent with each request.
+struct KnownNodesRecorder {
+    inner: Box<dyn ReadSync>,
+    known_nodes: Vec<usize>,
+    known_entries: usize,
+}
+
+impl KnownNodesRecorder {
+    fn record(&mut self, known_nodes: &[Hash], response: &ProofResponse) {
+        self.known_nodes.push(known_nodes.len());
+        self.known_entries += response
+            .proof
+            .entries
+            .iter()
+            .flatten()
+            .filter(|entry| entry[0] == 0x03)
+            .count();
+    }
+}
+
+impl ReadSync for KnownNodesRecorder {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn sync_get(&mut self, ctx: Context, request: GetRequest) -> Result<ProofResponse> {
+        let known_nodes = request.known_nodes.clone();
+        let response = self.inner.sync_get(ctx, request)?;
+        self.record(&known_nodes, &response);
+        Ok(response)
+    }
+
+    fn sync_get_prefixes(
+        &mut self,
+        ctx: Context,
+        request: GetPrefixesRequest,
+    ) -> Result<ProofResponse> {
+        let known_nodes = request.known_nodes.clone();
+        let response = self.inner.sync_get_prefixes(ctx, request)?;
+        self.record(&known_nodes, &response);
+        Ok(response)
+    }
+
+    fn sync_iterate(&mut self, ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+        let known_nodes = request.known_nodes.clone();
+        let response = self.inner.sync_iterate(ctx, request)?;
+        self.record(&known_nodes, &response);
+        Ok(response)
+    }
+}
+
+#[test]
+fn test_local_syncer_known_nodes() {
+    let (keys, values) = generate_key_value_pairs();
+    let (tree, hash) = make_local_syncer(&keys, &values);
+
+    let recorder = KnownNodesRecorder {
+        inner: Box::new(tree),
+        known_nodes: Vec::new(),
+        known_entries: 0,
+    };
+    let remote_tree = Tree::make()
+        .with_root(Root {
+            hash,
+            ..Default::default()
+        })
+        .new(Box::new(recorder));
+
+    // The first request is sent without any known nodes.
+    let value = remote_tree
+        .get(Context::background(), keys[0].as_slice())
+        .expect("get")
+        .expect("get_some");
+    assert_eq!(values[0], value.as_slice());
+
+    // Proofs anchored at the root omit the nodes fetched before.
+    remote_tree
+        .prefetch_prefixes(Context::background(), &vec![b"key 5".to_vec().into()], 1000)
+        .expect("prefetch_prefixes");
+    for i in 0..keys.len() {
+        let value = remote_tree
+            .get(Context::background(), keys[i].as_slice())
+            .expect("get")
+            .expect("get_some");
+        assert_eq!(values[i], value.as_slice());
+    }
+
+    let cache = remote_tree.cache.borrow();
+    let recorder = cache
+        .get_read_syncer()
+        .as_any()
+        .downcast_ref::<KnownNodesRecorder>()
+        .expect("recorder");
+    assert_eq!(recorder.known_nodes[0], 0);
+    assert!(recorder.known_nodes[1] > 0);
+    assert!(recorder.known_entries > 0);
+}
+
+#[test]
+fn test_local_syncer_basic() {
+    let (keys, values) = generate_key_value_pairs();
+    let (tree, hash) = make_local_syncer(&keys, &values);
+
+    let stats = StatsCollector::new(Box::new(tree));
+    let remote_tree = Tree::make()
+        .with_capacity(0, 0)
+        .with_root(Root {
+            hash,
+            ..Default::default()
+        })
+        .new(Box::new(stats));
+
+    for i in 0..keys.len() {
+        let value = remote_tree
+            .get(Context::background(), keys[i].as_slice())
+            .expect("get")
+            .expect("get_some");
+        assert_eq!(values[i], value.as_slice());
+    }
+    assert_eq!(
+        None,
+        remote_tree
+            .get(Context::background(), b"missing key")
+            .expect("get")
+    );
+
+    let cache = remote_tree.cache.borrow();
+    let stats = cache
+        .get_read_syncer()
+        .as_any()
+        .downcast_ref::<StatsCollector>()
+        .expect("stats");
+    assert!(stats.sync_get_count > 0, "sync_get count");
+    assert!(stats.sync_get_count <= keys.len() + 1, "sync_get count");
+    assert_eq!(0, stats.sync_get_prefixes_count, "sync_get_prefixes count");
+    assert_eq!(0, stats.sync_iterate_count, "sync_iterate count");
+}
+
+#[test]
+fn test_local_syncer_prefetch_prefixes() {
+    let (keys, values) = generate_key_value_pairs();
+    let (tree, hash) = make_local_syncer(&keys, &values);
+
+    let stats = StatsCollector::new(Box::new(tree));
+    let remote_tree = Tree::make()
+        .with_capacity(0, 0)
+        .with_root(Root {
+            hash,
+            ..Default::default()
+        })
+        .new(Box::new(stats));
+
+    // Prefetch keys starting with prefix "key".
+    remote_tree
+        .prefetch_prefixes(Context::background(), &vec![b"key".to_vec().into()], 1000)
+        .expect("prefetch_prefixes");
+
+    for i in 0..keys.len() {
+        let value = remote_tree
+            .get(Context::background(), keys[i].as_slice())
+            .expect("get")
+            .expect("get_some");
+        assert_eq!(values[i], value.as_slice());
+    }
+
+    let cache = remote_tree.cache.borrow();
+    let stats = cache
+        .get_read_syncer()
+        .as_any()
+        .downcast_ref::<StatsCollector>()
+        .expect("stats");
+    assert_eq!(0, stats.sync_get_count, "sync_get count");
+    assert_eq!(1, stats.sync_get_prefixes_count, "sync_get_prefixes count");
+    assert_eq!(0, stats.sync_iterate_count, "sync_iterate count");
+}
+
+#[test]
+fn test_local_syncer_prefetch_all_prefixes() {
+    let (keys, values) = generate_key_value_pairs_ex("".to_owned(), 100);
+    let (mut tree, hash) = make_local_syncer(&keys, &values);
+    let mut sorted_keys = keys.clone();
+    sorted_keys.sort();
+
+    // Overlapping prefixes are only fetched once and a full page reports the
+    // key to continue from.
+    let root = Root {
+        hash,
+        ..Default::default()
+    };
+    let prefixes: Vec<Prefix> = vec![b"key 1".to_vec().into(), b"key".to_vec().into()];
+    let mut start_key = None;
+    let mut pages = 0;
+    loop {
+        let rsp = tree
+            .sync_get_prefixes(
+                Context::background(),
+                GetPrefixesRequest {
+                    tree: TreeID {
+                        root,
+                        position: hash,
+                    },
+                    prefixes: prefixes.clone(),
+                    limit: 30,
+                    start_key: start_key.clone(),
+                    known_nodes: Vec::new(),
+                },
+            )
+            .expect("sync_get_prefixes");
+        pages += 1;
+        match rsp.next_key {
+            Some(next_key) => {
+                assert_eq!(sorted_keys[pages * 30], next_key);
+                start_key = Some(next_key);
+            }
+            None => break,
+        }
+    }
+    assert_eq!(4, pages, "number of pages");
+
+    let stats = StatsCollector::new(Box::new(tree));
+    let remote_tree = Tree::make()
+        .with_capacity(0, 0)
+        .with_root(root)
+        .new(Box::new(stats));
+
+    // Prefetch all keys starting with prefix "key" in pages of 10 keys,
+    // through the MKVS interface used by runtimes.
+    MKVS::prefetch_all_prefixes(&remote_tree, Context::background(), &prefixes, 10)
+        .expect("prefetch_all_prefixes");
+
+    for i in 0..keys.len() {
+        let value = remote_tree
+            .get(Context::background(), keys[i].as_slice())
+            .expect("get")
+            .expect("get_some");
+        assert_eq!(values[i], value.as_slice());
+    }
+
+    let cache = remote_tree.cache.borrow();
+    let stats = cache
+        .get_read_syncer()
+        .as_any()
+        .downcast_ref::<StatsCollector>()
+        .expect("stats");
+    assert_eq!(0, stats.sync_get_count, "sync_get count");
+    assert_eq!(10, stats.sync_get_prefixes_count, "sync_get_prefixes count");
+    assert_eq!(0, stats.sync_iterate_count, "sync_iterate count");
+}
+
+#[test]
+fn test_local_syncer_iterate() {
+    let (keys, values) = generate_key_value_pairs_ex("".to_owned(), 100);
+    let mut items: Vec<(Vec<u8>, Vec<u8>)> =
+        keys.iter().cloned().zip(values.iter().cloned()).collect();
+    items.sort();
+
+    for prefetch in &[0, 10, 1000] {
+        let (tree, hash) = make_local_syncer(&keys, &values);
+        let stats = StatsCollector::new(Box::new(tree));
+        let remote_tree = Tree::make()
+            .with_capacity(0, 0)
+            .with_root(Root {
+                hash,
+                ..Default::default()
+            })
+            .new(Box::new(stats));
+
+        let mut it = remote_tree.iter(Context::background());
+        it.set_prefetch(*prefetch);
+        it.rewind();
+        let fetched: Vec<(Vec<u8>, Vec<u8>)> = it.by_ref().collect();
+        assert!(it.error().is_none(), "iterator should not error");
+        assert_eq!(items, fetched, "iterator should return all items");
+
+        let cache = remote_tree.cache.borrow();
+        let stats = cache
+            .get_read_syncer()
+            .as_any()
+            .downcast_ref::<StatsCollector>()
+            .expect("stats");
+        assert_eq!(0, stats.sync_get_count, "sync_get count");
+        if *prefetch == 1000 {
+            assert_eq!(1, stats.sync_iterate_count, "sync_iterate count");
+        }
+    }
+}
+
+#[test]
+fn test_local_syncer_root_mismatch() {
+    let (keys, values) = generate_key_value_pairs_ex("".to_owned(), 10);
+    let (mut tree, hash) = make_local_syncer(&keys, &values);
+
+    let request = GetRequest {
+        tree: TreeID {
+            root: Root {
+                hash: Hash::digest_bytes(b"bogus root"),
+                ..Default::default()
+            },
+            position: hash,
+        },
+        key: keys[0].clone(),
+        include_siblings: false,
+        known_nodes: Vec::new(),
+    };
+    assert!(
+        tree.sync_get(Context::background(), request).is_err(),
+        "sync_get should fail for an unknown root"
+    );
+}
+
+#[test]
+fn test_local_syncer_interop() {
+    let server = ProtocolServer::new();
+
+    let (keys, values) = generate_key_value_pairs_ex("".to_owned(), 100);
+    let mut tree = Tree::make()
+        .with_capacity(0, 0)
+        .new(Box::new(NoopReadSyncer));
+    for i in 0..keys.len() {
+        tree.insert(
+            Context::background(),
+            keys[i].as_slice(),
+            values[i].as_slice(),
+        )
+        .expect("insert");
+    }
+    let (write_log, hash) =
+        Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+    server.apply(&write_log, hash, Default::default(), 0);
+
+    // Proofs generated by the tree must be identical to those generated by
+    // the Go implementation for the same requests.
+    let tree_id = TreeID {
+        root: Root {
+            hash,
+            ..Default::default()
+        },
+        position: hash,
+    };
+    let mut remote = server.read_sync();
+    for key in vec![keys[0].clone(), keys[42].clone(), b"missing key".to_vec()] {
+        for include_siblings in vec![false, true] {
+            let request = GetRequest {
+                tree: tree_id.clone(),
+                key: key.clone(),
+                include_siblings,
+                known_nodes: Vec::new(),
+            };
+            let expected = remote
+                .sync_get(Context::background(), request.clone())
+                .expect("remote sync_get");
+            let proof = tree
+                .sync_get(Context::background(), request)
+                .expect("sync_get");
+            assert_eq!(
+                cbor::to_vec(&expected.proof),
+                cbor::to_vec(&proof.proof),
+                "sync_get proof for {:?} (include_siblings: {})",
+                key,
+                include_siblings,
+            );
+        }
+    }
+
+    let request = GetPrefixesRequest {
+        tree: tree_id.clone(),
+        prefixes: vec![b"key 1".to_vec().into(), b"key 5".to_vec().into()],
+        limit: 100,
+        start_key: None,
+        known_nodes: Vec::new(),
+    };
+    let expected = remote
+        .sync_get_prefixes(Context::background(), request.clone())
+        .expect("remote sync_get_prefixes");
+    let proof = tree
+        .sync_get_prefixes(Context::background(), request)
+        .expect("sync_get_prefixes");
+    assert_eq!(
+        cbor::to_vec(&expected.proof),
+        cbor::to_vec(&proof.proof),
+        "sync_get_prefixes proof"
+    );
+
+    for prefetch in vec![1, 10, 1000] {
+        let request = IterateRequest {
+            tree: tree_id.clone(),
+            key: b"key 3".to_vec(),
+            prefetch,
+            known_nodes: Vec::new(),
+        };
+        let expected = remote
+            .sync_iterate(Context::background(), request.clone())
+            .expect("remote sync_iterate");
+        let proof = tree
+            .sync_iterate(Context::background(), request)
+            .expect("sync_iterate");
+        assert_eq!(
+            cbor::to_vec(&expected.proof),
+            cbor::to_vec(&proof.proof),
+            "sync_iterate proof (prefetch: {})",
+            prefetch,
+        );
+    }
+}
+
+#[test]
+fn test_apply_write_log() {
+    let (keys, values) = generate_key_value_pairs_ex("".to_owned(), 100);
+    let (syncer, hash) = make_local_syncer(&keys, &values);
+    let root = Root {
+        hash,
+        ..Default::default()
+    };
+
+    let mut write_log = WriteLog::new();
+    for i in (0..keys.len()).step_by(7) {
+        write_log.push(LogEntry::new(&keys[i], b"updated"));
+    }
+    for i in (3..keys.len()).step_by(11) {
+        write_log.push(LogEntry {
+            key: keys[i].clone(),
+            value: None,
+        });
+    }
+    write_log.push(LogEntry::new(b"new key", b"new value"));
+
+    // Compute the expected root locally.
+    let (mut local, _) = make_local_syncer(&keys, &values);
+    for entry in &write_log {
+        match entry.value {
+            Some(ref value) => local
+                .insert(Context::background(), &entry.key, value)
+                .expect("insert"),
+            None => local
+                .remove(Context::background(), &entry.key)
+                .expect("remove"),
+        };
+    }
+    let (_, expected) =
+        Tree::commit(&mut local, Context::background(), Default::default(), 1).expect("commit");
+
+    let computed =
+        Tree::apply_write_log(Context::background(), Box::new(syncer), root, &write_log, 1)
+            .expect("apply write log");
+    assert_eq!(computed, expected);
+
+    // A read syncer which does not serve the given root is rejected.
+    let (other, _) = make_local_syncer(&keys[..50], &values[..50]);
+    assert!(
+        Tree::apply_write_log(Context::background(), Box::new(other), root, &write_log, 1).is_err(),
+        "apply should fail for an unverifiable root"
+    );
+}
+
+#[test]
+fn test_rollback() {
+    let (keys, values) = generate_key_value_pairs_ex("".to_owned(), 100);
+    let update = |tree: &mut Tree| {
+        for i in (0..keys.len()).step_by(7) {
+            tree.insert(Context::background(), &keys[i], b"updated")
+                .expect("insert");
+        }
+        for i in (3..keys.len()).step_by(11) {
+            tree.remove(Context::background(), &keys[i])
+                .expect("remove");
+        }
+        tree.insert(Context::background(), b"new key", b"new value")
+            .expect("insert");
+    };
+    let check = |tree: &Tree| {
+        for (key, value) in keys.iter().zip(values.iter()) {
+            let got = tree.get(Context::background(), key).expect("get");
+            assert_eq!(got.as_ref(), Some(value));
+        }
+        let got = tree.get(Context::background(), b"new key").expect("get");
+        assert_eq!(got, None);
+    };
+
+    let cached = |tree: &Tree| {
+        let stats = tree.cache_stats();
+        (stats.internal_node_count, stats.leaf_value_size)
+    };
+
+    // Without a read syncer, the committed nodes are restored in memory.
+    let (mut tree, hash) = make_local_syncer(&keys, &values);
+    let before = cached(&tree);
+    update(&mut tree);
+    MKVS::rollback(&mut tree);
+    check(&tree);
+    assert_eq!(tree.committed_root().expect("committed root").hash, hash);
+    assert_eq!(cached(&tree), before, "restored nodes should be cached");
+
+    // Committing after a rollback produces an empty write log.
+    let (write_log, new_hash) =
+        Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+    assert!(write_log.is_empty());
+    assert_eq!(new_hash, hash);
+
+    // With a read syncer, the fetched nodes are restored the same way.
+    let (syncer, hash) = make_local_syncer(&keys, &values);
+    let mut tree = Tree::make()
+        .with_root(Root {
+            hash,
+            ..Default::default()
+        })
+        .new(Box::new(syncer));
+    check(&tree);
+    let before = cached(&tree);
+    update(&mut tree);
+    MKVS::rollback(&mut tree);
+    check(&tree);
+    assert_eq!(tree.committed_root().expect("committed root").hash, hash);
+    assert_eq!(cached(&tree), before, "restored nodes should be cached");
+
+    // Rolling back a new tree makes it empty again.
+    let mut tree = Tree::make().new(Box::new(NoopReadSyncer));
+    update(&mut tree);
+    MKVS::rollback(&mut tree);
+    let got = tree.get(Context::background(), &keys[0]).expect("get");
+    assert_eq!(got, None);
+    let (write_log, _) =
+        Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+    assert!(write_log.is_empty());
+}
+
+#[test]
+fn test_bulk_load() {
+    let (keys, values) = generate_key_value_pairs();
+    let mut entries: Vec<_> = keys.into_iter().zip(values.into_iter()).collect();
+    entries.sort();
+
+    let mut tree = Tree::make()
+        .build(Box::new(NoopReadSyncer), entries.clone())
+        .expect("bulk load");
+    for (key, value) in &entries {
+        assert_eq!(
+            tree.get(Context::background(), key).expect("get"),
+            Some(value.clone())
+        );
+    }
+    let (write_log, hash) =
+        Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+    assert_eq!(format!("{:?}", hash), ALL_ITEMS_ROOT);
+    assert_eq!(write_log.len(), entries.len());
+
+    // Keys which are prefixes of other keys, including the empty key.
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = vec![
+        (b"".to_vec(), b"empty".to_vec()),
+        (b"a".to_vec(), b"1".to_vec()),
+        (b"ab".to_vec(), b"2".to_vec()),
+        (b"abc".to_vec(), b"3".to_vec()),
+        (b"abd".to_vec(), b"".to_vec()),
+        (b"b".to_vec(), b"4".to_vec()),
+    ];
+    let mut expected = Tree::make().new(Box::new(NoopReadSyncer));
+    for (key, value) in &entries {
+        expected
+            .insert(Context::background(), key, value)
+            .expect("insert");
+    }
+    let (_, expected_hash) =
+        Tree::commit(&mut expected, Context::background(), Default::default(), 0).expect("commit");
+
+    let mut tree = Tree::make()
+        .build(Box::new(NoopReadSyncer), entries.clone())
+        .expect("bulk load");
+    let (_, hash) =
+        Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+    assert_eq!(hash, expected_hash);
+
+    // A bulk loaded tree can be modified further.
+    tree.insert(Context::background(), b"abe", b"5")
+        .expect("insert");
+    expected
+        .insert(Context::background(), b"abe", b"5")
+        .expect("insert");
+    let (_, hash) =
+        Tree::commit(&mut tree, Context::background(), Default::default(), 1).expect("commit");
+    let (_, expected_hash) =
+        Tree::commit(&mut expected, Context::background(), Default::default(), 1).expect("commit");
+    assert_eq!(hash, expected_hash);
+
+    // An empty input produces an empty tree.
+    let mut tree = Tree::make()
+        .build(Box::new(NoopReadSyncer), vec![])
+        .expect("bulk load");
+    let (_, hash) =
+        Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+    assert_eq!(hash, Hash::empty_hash());
+
+    // Unsorted and duplicate keys are rejected.
+    let unsorted = vec![(b"b".to_vec(), vec![]), (b"a".to_vec(), vec![])];
+    assert!(Tree::make()
+        .build(Box::new(NoopReadSyncer), unsorted)
+        .is_err());
+    let duplicate = vec![(b"a".to_vec(), vec![]), (b"a".to_vec(), vec![])];
+    assert!(Tree::make()
+        .build(Box::new(NoopReadSyncer), duplicate)
+        .is_err());
+
+    // Only empty trees can be bulk loaded.
+    assert!(Tree::make()
+        .with_root(Root {
+            hash,
+            ..Default::default()
+        })
+        .build(Box::new(NoopReadSyncer), entries.clone())
+        .is_ok());
+    assert!(Tree::make()
+        .with_root(Root {
+            hash: expected_hash,
+            ..Default::default()
+        })
+        .build(Box::new(NoopReadSyncer), entries)
+        .is_err());
+}
+
+#[test]
+fn test_debug_stats_and_dump() {
+    let (keys, values) = generate_key_value_pairs();
+    let mut tree = Tree::make().new(Box::new(NoopReadSyncer));
+    for i in 0..keys.len() {
+        tree.insert(
+            Context::background(),
+            keys[i].as_slice(),
+            values[i].as_slice(),
+        )
+        .expect("insert");
+    }
+    assert!(
+        tree.stats(Context::background()).is_err(),
+        "stats should require a committed root"
+    );
+    let (_, hash) =
+        Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+
+    let stats = tree.stats(Context::background()).expect("stats");
+    assert_eq!(stats.leaf_nodes, keys.len());
+    assert!(stats.internal_nodes >= keys.len() / 2);
+    assert_eq!(stats.leaf_depths.values().sum::<usize>(), keys.len());
+    assert_eq!(stats.value_sizes.values().sum::<usize>(), keys.len());
+    assert_eq!(
+        stats.max_depth,
+        *stats.leaf_depths.keys().last().expect("leaf depths")
+    );
+    assert_eq!(
+        stats.key_bytes,
+        keys.iter().map(|key| key.len()).sum::<usize>()
+    );
+    assert_eq!(
+        stats.value_bytes,
+        values.iter().map(|value| value.len()).sum::<usize>()
+    );
+
+    let proof_size = tree
+        .proof_size(Context::background(), &keys[0])
+        .expect("proof size");
+    assert!(proof_size.entries > 0);
+    assert!(proof_size.bytes > 0);
+
+    let dump = tree
+        .dump(Context::background())
+        .expect("dump")
+        .expect("non-empty tree");
+    let json: serde_json::Value = serde_json::from_str(&dump.to_json()).expect("valid json");
+    assert_eq!(json["kind"], "internal");
+    assert_eq!(json["hash"], format!("{:?}", hash));
+    assert!(json["label_bit_length"].is_u64());
+
+    let dot = dump.to_dot();
+    assert!(dot.starts_with("digraph mkvs {"));
+    assert!(dot.contains(&format!("{:?}", hash)));
+    assert_eq!(dot.matches("shape=box").count(), keys.len());
+    assert_eq!(dot.matches("shape=ellipse").count(), stats.internal_nodes);
+
+    let mut empty = Tree::make().new(Box::new(NoopReadSyncer));
+    Tree::commit(&mut empty, Context::background(), Default::default(), 0).expect("commit");
+    assert_eq!(empty.dump(Context::background()).expect("dump"), None);
+    assert_eq!(
+        empty.stats(Context::background()).expect("stats"),
+        TreeStats::default()
+    );
+}
+
 #[test]
 fn test_value_eviction() {
     let mut tree = Tree::make()
@@ -982,6 +1757,74 @@ fn test_node_eviction() {
     );
 }
 
+#[test]
+fn test_node_memory_eviction() {
+    let mut tree = Tree::make()
+        .with_capacity(0, 0)
+        .with_node_memory_capacity(16 * 1024)
+        .new(Box::new(NoopReadSyncer));
+
+    let (keys, values) = generate_key_value_pairs();
+    for i in 0..keys.len() {
+        tree.insert(
+            Context::background(),
+            keys[i].as_slice(),
+            values[i].as_slice(),
+        )
+        .expect("insert");
+    }
+    Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+
+    let stats = tree.cache_stats();
+    assert!(stats.internal_node_bytes <= 16 * 1024);
+    assert!(stats.internal_node_count < 999);
+    assert!(stats.evictions > 0);
+    assert!(stats.leaf_node_bytes > stats.leaf_value_size);
+}
+
+#[test]
+fn test_cache_eviction_policies() {
+    let (keys, values) = generate_key_value_pairs();
+
+    for policy in vec![
+        EvictionPolicy::LRU,
+        EvictionPolicy::SecondChance,
+        EvictionPolicy::SizeAware,
+    ] {
+        let (tree, hash) = make_local_syncer(&keys, &values);
+        let remote_tree = Tree::make()
+            .with_capacity(64, 64)
+            .with_eviction_policy(policy)
+            .with_root(Root {
+                hash,
+                ..Default::default()
+            })
+            .new(Box::new(tree));
+
+        for _ in 0..2 {
+            for i in 0..keys.len() {
+                let value = remote_tree
+                    .get(Context::background(), keys[i].as_slice())
+                    .expect("get")
+                    .expect("get_some");
+                assert_eq!(values[i], value.as_slice(), "policy: {:?}", policy);
+            }
+        }
+
+        let stats = remote_tree.cache_stats();
+        assert!(stats.internal_node_count <= 64, "policy: {:?}", policy);
+        assert!(stats.leaf_value_size <= 64, "policy: {:?}", policy);
+        assert!(stats.evictions > 0, "policy: {:?}", policy);
+        assert!(stats.remote_syncs > 0, "policy: {:?}", policy);
+        assert!(stats.hits > 0 && stats.misses > 0, "policy: {:?}", policy);
+        assert!(
+            stats.hit_rate() > 0.0 && stats.hit_rate() < 1.0,
+            "policy: {:?}",
+            policy
+        );
+    }
+}
+
 /// Location of the test vectors directory (from Go).
 const TEST_VECTORS_DIR: &'static str = "../go/storage/mkvs/testdata";
 
@@ -3,6 +3,8 @@ use std::sync::Arc;
 
 use anyhow::Result;
 
+pub mod collections;
+pub mod confidential;
 pub mod context;
 pub mod mkvs;
 
@@ -16,7 +16,10 @@ use super::{
     tags::Tags,
     types::{TxnBatch, TxnCall, TxnCheckResult, TxnOutput},
 };
-use crate::common::{cbor, crypto::hash::Hash, roothash::Message as RoothashMessage};
+use crate::{
+    common::{cbor, crypto::hash::Hash, roothash::Message as RoothashMessage},
+    storage::mkvs::StorageError,
+};
 
 /// Dispatch error.
 #[derive(Error, Debug)]
@@ -277,16 +280,21 @@ impl MethodDispatcher {
     }
 
     /// Dispatches a raw runtime invocation request.
-    fn dispatch(&self, call: &Vec<u8>, ctx: &mut Context) -> Vec<u8> {
+    ///
+    /// Storage errors are not converted into a transaction output as they are
+    /// not caused by the call itself. Instead they are propagated so that the
+    /// whole batch fails.
+    fn dispatch(&self, call: &Vec<u8>, ctx: &mut Context) -> Result<Vec<u8>> {
         let rsp = match self.dispatch_fallible(call, ctx) {
             Ok(response) => TxnOutput::Success(response),
+            Err(error) if error.downcast_ref::<StorageError>().is_some() => return Err(error),
             Err(error) => match error.downcast::<CheckOnlySuccess>() {
                 Ok(check_result) => TxnOutput::Success(cbor::to_value(check_result.0)),
                 Err(error) => TxnOutput::Error(format!("{}", error)),
             },
         };
 
-        cbor::to_vec(&rsp)
+        Ok(cbor::to_vec(&rsp))
     }
 
     fn dispatch_fallible(&self, call: &Vec<u8>, ctx: &mut Context) -> Result<cbor::Value> {
@@ -329,7 +337,7 @@ impl Dispatcher for MethodDispatcher {
                 return Err(anyhow!("batch aborted"));
             }
             ctx.start_transaction();
-            vec.push(self.dispatch(call, &mut ctx));
+            vec.push(self.dispatch(call, &mut ctx)?);
         }
         let outputs = TxnBatch::new(vec);
 
@@ -411,7 +419,7 @@ mod tests {
         let mut ctx = Context::new(IoContext::background().freeze(), &header, false);
 
         // Call runtime.
-        let result = dispatcher.dispatch(&call_encoded, &mut ctx);
+        let result = dispatcher.dispatch(&call_encoded, &mut ctx).unwrap();
 
         // Decode result.
         let result_decoded: TxnOutput = cbor::from_slice(&result).unwrap();
@@ -430,4 +438,32 @@ mod tests {
             _ => panic!("txn call should return success"),
         }
     }
+
+    #[test]
+    fn test_dispatcher_storage_error() {
+        let mut dispatcher = MethodDispatcher::new();
+        dispatcher.add_method(Method::new(
+            MethodDescriptor {
+                name: "failing".to_owned(),
+            },
+            |_call: &(), _ctx: &mut Context| -> Result<()> {
+                Err(anyhow!("sync failed")).context(StorageError)
+            },
+        ));
+
+        let call = TxnCall {
+            method: "failing".to_owned(),
+            args: cbor::to_value(()),
+        };
+        let batch = TxnBatch::new(vec![cbor::to_vec(&call)]);
+        let header = Header {
+            timestamp: TEST_TIMESTAMP,
+            ..Default::default()
+        };
+        let ctx = Context::new(IoContext::background().freeze(), &header, false);
+
+        // A storage error should fail the whole batch.
+        let result = dispatcher.dispatch_batch(&batch, ctx);
+        assert!(result.is_err(), "batch should fail on storage errors");
+    }
 }
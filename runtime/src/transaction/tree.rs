@@ -4,10 +4,10 @@ use io_context::Context;
 use serde::{self, ser::SerializeSeq, Deserialize, Serializer};
 use serde_bytes::{self, Bytes};
 
-use super::tags::Tags;
+use super::tags::{Tag, Tags};
 use crate::{
     common::{cbor, crypto::hash::Hash, key_format::KeyFormat},
-    storage::mkvs::{self, sync::ReadSync, Root, WriteLog},
+    storage::mkvs::{self, sync::ReadSync, Root, WriteLog, MKVS},
 };
 
 // NOTE: This should be kept in sync with go/runtime/transaction/transaction.go.
@@ -19,13 +19,19 @@ enum ArtifactKind {
     Output = 2,
 }
 
+impl Default for ArtifactKind {
+    fn default() -> Self {
+        ArtifactKind::Input
+    }
+}
+
 // Workaround because rust doesn't support `as u8` inside match arms.
 // See https://github.com/rust-lang/rust/issues/44266
 const ARTIFACT_KIND_INPUT: u8 = ArtifactKind::Input as u8;
 const ARTIFACT_KIND_OUTPUT: u8 = ArtifactKind::Output as u8;
 
 /// Key format used for transaction artifacts.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 struct TxnKeyFormat {
     /// Transaction hash.
     tx_hash: Hash,
@@ -146,6 +152,19 @@ impl serde::Serialize for OutputArtifacts {
     }
 }
 
+/// A transaction together with its artifacts.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Transaction {
+    /// Transaction hash.
+    pub hash: Hash,
+    /// Transaction input.
+    pub input: Vec<u8>,
+    /// Transaction order within the batch.
+    pub batch_order: u32,
+    /// Transaction output, if one has been added.
+    pub output: Option<Vec<u8>>,
+}
+
 /// A Merkle tree containing transaction artifacts.
 pub struct Tree {
     io_root: Root,
@@ -218,6 +237,128 @@ impl Tree {
         Ok(())
     }
 
+    /// Fetch the input and batch order of the transaction with the given hash.
+    fn get_input_artifacts(&self, ctx: Context, tx_hash: Hash) -> Result<Option<InputArtifacts>> {
+        let key = TxnKeyFormat {
+            tx_hash,
+            kind: ArtifactKind::Input,
+        }
+        .encode();
+        match self.tree.get(ctx, &key)? {
+            Some(raw) => Ok(Some(cbor::from_slice(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Fetch the input of the transaction with the given hash.
+    pub fn get_input(&self, ctx: Context, tx_hash: Hash) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .get_input_artifacts(ctx, tx_hash)?
+            .map(|artifacts| artifacts.input))
+    }
+
+    /// Fetch the output of the transaction with the given hash.
+    pub fn get_output(&self, ctx: Context, tx_hash: Hash) -> Result<Option<Vec<u8>>> {
+        let key = TxnKeyFormat {
+            tx_hash,
+            kind: ArtifactKind::Output,
+        }
+        .encode();
+        match self.tree.get(ctx, &key)? {
+            Some(raw) => Ok(Some(cbor::from_slice::<OutputArtifacts>(&raw)?.output)),
+            None => Ok(None),
+        }
+    }
+
+    /// Fetch the transaction with the given hash together with its output.
+    pub fn get_transaction(&self, ctx: Context, tx_hash: Hash) -> Result<Option<Transaction>> {
+        let ctx = ctx.freeze();
+        let artifacts = match self.get_input_artifacts(Context::create_child(&ctx), tx_hash)? {
+            Some(artifacts) => artifacts,
+            None => return Ok(None),
+        };
+
+        Ok(Some(Transaction {
+            hash: tx_hash,
+            input: artifacts.input,
+            batch_order: artifacts.batch_order,
+            output: self.get_output(Context::create_child(&ctx), tx_hash)?,
+        }))
+    }
+
+    /// Fetch all transactions in the tree, sorted by batch order.
+    pub fn get_transactions(&self, ctx: Context) -> Result<Vec<Transaction>> {
+        let mut txns: Vec<Transaction> = Vec::new();
+        let prefix = TxnKeyFormat::default().encode_partial(0);
+        for item in self.tree.prefix(ctx, &prefix) {
+            let (key, value) = item?;
+            if key.len() != 1 + TxnKeyFormat::size() {
+                return Err(anyhow!("transaction: malformed artifact key"));
+            }
+            let tx_hash: Hash = key[1..1 + Hash::len()].into();
+
+            // Inputs sort before outputs of the same transaction.
+            match key[1 + Hash::len()] {
+                ARTIFACT_KIND_INPUT => {
+                    let artifacts: InputArtifacts = cbor::from_slice(&value)?;
+                    txns.push(Transaction {
+                        hash: tx_hash,
+                        input: artifacts.input,
+                        batch_order: artifacts.batch_order,
+                        output: None,
+                    });
+                }
+                ARTIFACT_KIND_OUTPUT => {
+                    let artifacts: OutputArtifacts = cbor::from_slice(&value)?;
+                    match txns.last_mut() {
+                        Some(tx) if tx.hash == tx_hash => tx.output = Some(artifacts.output),
+                        _ => {
+                            return Err(anyhow!(
+                                "transaction: output without input ({:?})",
+                                tx_hash
+                            ))
+                        }
+                    }
+                }
+                other => {
+                    return Err(anyhow!(
+                        "transaction: malformed artifact kind ({:?})",
+                        other
+                    ))
+                }
+            }
+        }
+        txns.sort_by_key(|tx| tx.batch_order);
+
+        Ok(txns)
+    }
+
+    /// Fetch all tags with the given key.
+    pub fn get_tags(&self, ctx: Context, key: &[u8]) -> Result<Tags> {
+        let prefix = TagKeyFormat {
+            key: key.to_vec(),
+            ..Default::default()
+        }
+        .encode_partial(1);
+
+        let mut tags = Vec::new();
+        for item in self.tree.prefix(ctx, &prefix) {
+            let (raw_key, value) = item?;
+            // Skip tags whose key merely starts with the requested key.
+            if raw_key.len() != prefix.len() + TagKeyFormat::size() {
+                continue;
+            }
+            let decoded = TagKeyFormat::decode(&raw_key).expect("prefix matches");
+            tags.push(Tag {
+                key: decoded.key,
+                value,
+                tx_hash: decoded.tx_hash,
+            });
+        }
+
+        Ok(tags)
+    }
+
     /// Commit updates to the underlying Merkle tree and return the write
     /// log and root hash.
     pub fn commit(&mut self, ctx: Context) -> Result<(WriteLog, Hash)> {
@@ -279,4 +420,106 @@ mod test {
             "c65f4e8bd5314c26f245337a859ad244f4b1544acf60ef334cf0d0eadb47363b",
         );
     }
+
+    #[test]
+    fn test_transaction_read() {
+        let mut tree = Tree::new(
+            Box::new(NoopReadSyncer),
+            Root {
+                hash: Hash::empty_hash(),
+                ..Default::default()
+            },
+        );
+
+        let mut hashes = Vec::new();
+        for i in 0..10 {
+            let input = format!("this goes in ({})", i).into_bytes();
+            let tx_hash = Hash::digest_bytes(&input);
+            hashes.push(tx_hash);
+
+            tree.add_input(Context::background(), input, i).unwrap();
+            if i % 2 == 0 {
+                tree.add_output(
+                    Context::background(),
+                    tx_hash,
+                    format!("and this comes out ({})", i).into_bytes(),
+                    vec![
+                        Tag::new(b"tag".to_vec(), format!("value {}", i).into_bytes()),
+                        Tag::new(b"tagA".to_vec(), b"valueA".to_vec()),
+                    ],
+                )
+                .unwrap();
+            }
+        }
+        let (_, root_hash) = tree.commit(Context::background()).unwrap();
+
+        // Read the artifacts back through a read syncer.
+        let remote = Tree::new(
+            Box::new(tree.tree),
+            Root {
+                hash: root_hash,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            remote.get_input(Context::background(), hashes[3]).unwrap(),
+            Some(b"this goes in (3)".to_vec())
+        );
+        assert_eq!(
+            remote.get_output(Context::background(), hashes[3]).unwrap(),
+            None
+        );
+        assert_eq!(
+            remote.get_output(Context::background(), hashes[4]).unwrap(),
+            Some(b"and this comes out (4)".to_vec())
+        );
+        let missing = Hash::digest_bytes(b"missing");
+        assert_eq!(
+            remote
+                .get_transaction(Context::background(), missing)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            remote
+                .get_transaction(Context::background(), hashes[6])
+                .unwrap(),
+            Some(Transaction {
+                hash: hashes[6],
+                input: b"this goes in (6)".to_vec(),
+                batch_order: 6,
+                output: Some(b"and this comes out (6)".to_vec()),
+            })
+        );
+
+        let txns = remote.get_transactions(Context::background()).unwrap();
+        assert_eq!(txns.len(), 10);
+        for (i, tx) in txns.iter().enumerate() {
+            assert_eq!(tx.batch_order, i as u32);
+            assert_eq!(tx.hash, hashes[i]);
+            assert_eq!(tx.output.is_some(), i % 2 == 0);
+        }
+
+        // Tags with keys that only share a prefix are not returned.
+        let mut tags = remote.get_tags(Context::background(), b"tag").unwrap();
+        tags.sort_by_key(|tag| tag.value.clone());
+        assert_eq!(tags.len(), 5);
+        for (i, tag) in tags.iter().enumerate() {
+            assert_eq!(tag.key, b"tag".to_vec());
+            assert_eq!(tag.value, format!("value {}", i * 2).into_bytes());
+            assert_eq!(tag.tx_hash, hashes[i * 2]);
+        }
+        assert_eq!(
+            remote
+                .get_tags(Context::background(), b"tagA")
+                .unwrap()
+                .len(),
+            5
+        );
+        assert!(remote
+            .get_tags(Context::background(), b"ta")
+            .unwrap()
+            .is_empty());
+    }
 }
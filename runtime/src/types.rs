@@ -1,4 +1,6 @@
 //! Types used by the worker-host protocol.
+use anyhow::{anyhow, Result};
+use io_context::Context;
 use serde::{self, Deserialize, Deserializer, Serialize, Serializer};
 use serde_bytes;
 
@@ -12,7 +14,7 @@ use crate::{
         runtime::RuntimeId,
         sgx::avr::AVR,
     },
-    storage::mkvs::{sync, WriteLog},
+    storage::mkvs::{sync, Root, Tree, WriteLog},
     transaction::types::TxnBatch,
 };
 
@@ -30,6 +32,77 @@ pub struct ComputedBatch {
     pub rak_sig: Signature,
 }
 
+impl ComputedBatch {
+    /// Verify that applying the state write log on top of the previous state
+    /// root results in the state root from the header.
+    ///
+    /// Nodes of the previous state are fetched using the read syncer and
+    /// verified against the previous state root.
+    pub fn verify_state_root(
+        &self,
+        ctx: Context,
+        read_syncer: Box<dyn sync::ReadSync>,
+        previous_state_root: Root,
+    ) -> Result<()> {
+        verify_root(
+            ctx,
+            read_syncer,
+            previous_state_root,
+            &self.state_write_log,
+            self.header.round,
+            self.header.state_root,
+            "state",
+        )
+    }
+
+    /// Verify that applying the I/O write log on top of the input I/O root
+    /// results in the I/O root from the header.
+    ///
+    /// The input I/O root is the root of the I/O tree which only contains
+    /// the batch inputs, i.e. the I/O root that was sent together with the
+    /// batch. It is at the same round as the computed batch, since the I/O
+    /// write log only adds the outputs and tags to the inputs.
+    pub fn verify_io_root(
+        &self,
+        ctx: Context,
+        read_syncer: Box<dyn sync::ReadSync>,
+        input_io_root: Root,
+    ) -> Result<()> {
+        verify_root(
+            ctx,
+            read_syncer,
+            input_io_root,
+            &self.io_write_log,
+            self.header.round,
+            self.header.io_root,
+            "I/O",
+        )
+    }
+}
+
+fn verify_root(
+    ctx: Context,
+    read_syncer: Box<dyn sync::ReadSync>,
+    root: Root,
+    write_log: &WriteLog,
+    version: u64,
+    expected: Option<Hash>,
+    kind: &str,
+) -> Result<()> {
+    let expected = expected.ok_or_else(|| anyhow!("computed batch: missing {} root", kind))?;
+    let computed = Tree::apply_write_log(ctx, read_syncer, root, write_log, version)?;
+    if computed != expected {
+        return Err(anyhow!(
+            "computed batch: {} root mismatch (expected: {:?} got: {:?})",
+            kind,
+            expected,
+            computed
+        ));
+    }
+
+    Ok(())
+}
+
 /// Storage sync request.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum StorageSyncRequest {
@@ -210,3 +283,107 @@ pub struct Message {
     #[serde(with = "serde_bytes")]
     pub span_context: Vec<u8>,
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::storage::mkvs::{
+        db::{MemoryBackend, NodeDB},
+        sync::NoopReadSyncer,
+        LogEntry,
+    };
+
+    fn commit(db: &NodeDB, root: Option<Root>, write_log: &WriteLog, version: u64) -> Root {
+        let mut tree = match root {
+            Some(root) => db.tree(root).unwrap(),
+            None => Tree::make().new(Box::new(NoopReadSyncer)),
+        };
+        for entry in write_log {
+            match entry.value {
+                Some(ref value) => tree.insert(Context::background(), &entry.key, value),
+                None => tree.remove(Context::background(), &entry.key),
+            }
+            .unwrap();
+        }
+        db.commit(Context::background(), &mut tree, version)
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn test_verify_roots() {
+        let db = NodeDB::open(Arc::new(MemoryBackend::new()), Default::default()).unwrap();
+
+        let previous_state_root = commit(
+            &db,
+            None,
+            &vec![LogEntry::new(b"foo", b"bar"), LogEntry::new(b"moo", b"goo")],
+            1,
+        );
+        let state_write_log = vec![
+            LogEntry::new(b"foo", b"baz"),
+            LogEntry {
+                key: b"moo".to_vec(),
+                value: None,
+            },
+        ];
+        let state_root = commit(&db, Some(previous_state_root), &state_write_log, 2);
+
+        // The inputs and outputs are committed at the same round.
+        let input_io_root = commit(&db, None, &vec![LogEntry::new(b"input", b"tx")], 2);
+        let io_write_log = vec![LogEntry::new(b"output", b"result")];
+        let io_root = commit(&db, Some(input_io_root), &io_write_log, 2);
+
+        let mut batch = ComputedBatch {
+            header: ComputeResultsHeader {
+                round: 2,
+                io_root: Some(io_root.hash),
+                state_root: Some(state_root.hash),
+                ..Default::default()
+            },
+            io_write_log,
+            state_write_log,
+            rak_sig: Default::default(),
+        };
+        batch
+            .verify_state_root(
+                Context::background(),
+                Box::new(db.clone()),
+                previous_state_root,
+            )
+            .expect("state root should verify");
+        batch
+            .verify_io_root(Context::background(), Box::new(db.clone()), input_io_root)
+            .expect("I/O root should verify");
+
+        // Roots which do not match the write logs are rejected.
+        batch.header.state_root = Some(io_root.hash);
+        batch.header.io_root = Some(state_root.hash);
+        assert!(batch
+            .verify_state_root(
+                Context::background(),
+                Box::new(db.clone()),
+                previous_state_root,
+            )
+            .is_err());
+        assert!(batch
+            .verify_io_root(Context::background(), Box::new(db.clone()), input_io_root)
+            .is_err());
+
+        // Missing roots are rejected.
+        batch.header.state_root = None;
+        batch.header.io_root = None;
+        assert!(batch
+            .verify_state_root(
+                Context::background(),
+                Box::new(db.clone()),
+                previous_state_root,
+            )
+            .is_err());
+        assert!(batch
+            .verify_io_root(Context::background(), Box::new(db), input_io_root)
+            .is_err());
+    }
+}
@@ -5,13 +5,9 @@ use grpcio::EnvBuilder;
 use rand::{rngs::StdRng, Rng, SeedableRng};
 use tokio::runtime::Runtime;
 
-use oasis_core_client::{create_txn_api_client, Node, TxnClient};
+use oasis_core_client::{Node, TxnClient};
 use oasis_core_runtime::common::{crypto::hash::Hash, runtime::RuntimeId};
-use simple_keyvalue_api::{with_api, Key, KeyValue};
-
-with_api! {
-    create_txn_api_client!(KeyValueOpsClient, api);
-}
+use simple_keyvalue_api::{Key, KeyValue, SimpleKeyValueClient};
 
 fn main() {
     let matches = App::new("Simple key/value operation client")
@@ -56,7 +52,7 @@ fn main() {
     let env = Arc::new(EnvBuilder::new().build());
     let node = Node::new(env, node_address);
     let txn_client = TxnClient::new(node.channel(), runtime_id, None);
-    let kv_client = KeyValueOpsClient::new(txn_client);
+    let kv_client = SimpleKeyValueClient::new(txn_client);
 
     if let Some(matches) = matches.subcommand_matches("set") {
         let kv = KeyValue {
@@ -17,7 +17,6 @@ use serde_bytes::ByteBuf;
 use tokio::runtime::Runtime;
 
 use oasis_core_client::{
-    create_txn_api_client,
     transaction::{Query, QueryCondition},
     Node, TxnClient,
 };
@@ -25,11 +24,7 @@ use oasis_core_runtime::{
     common::{crypto::hash::Hash, runtime::RuntimeId},
     storage::MKVS,
 };
-use simple_keyvalue_api::{with_api, Key, KeyValue};
-
-with_api! {
-    create_txn_api_client!(SimpleKeyValueClient, api);
-}
+use simple_keyvalue_api::{Key, KeyValue, SimpleKeyValueClient};
 
 fn main() {
     let matches = App::new("Simple key/value runtime test client")
@@ -66,7 +61,7 @@ fn main() {
     let kv_client = SimpleKeyValueClient::new(txn_client);
 
     // Check whether Runtime ID is also set remotely.
-    let r: Option<String> = rt.block_on(kv_client.get_runtime_id(())).unwrap();
+    let r: Option<String> = rt.block_on(kv_client.get_runtime_id()).unwrap();
     assert_eq!(runtime_id.to_string(), r.expect("runtime_id"));
 
     // Test simple [set,get] key calls
@@ -139,7 +134,8 @@ fn main() {
     println!("Accessing read-only state snapshot...");
     let r = snapshot
         .get(Context::background(), kv.key.as_bytes())
-        .expect("read-only state get");
+        .expect("read-only state get")
+        .expect("key must exist");
     println!(
         "Got \"{}\" ({:?})",
         String::from_utf8(r.clone()).unwrap(),
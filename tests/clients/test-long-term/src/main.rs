@@ -5,13 +5,9 @@ use grpcio::EnvBuilder;
 use rand::{rngs::StdRng, Rng, SeedableRng};
 use tokio::runtime::Runtime;
 
-use oasis_core_client::{create_txn_api_client, Node, TxnClient};
+use oasis_core_client::{Node, TxnClient};
 use oasis_core_runtime::common::{crypto::hash::Hash, runtime::RuntimeId};
-use simple_keyvalue_api::{with_api, Key, KeyValue};
-
-with_api! {
-    create_txn_api_client!(SimpleKeyValueClient, api);
-}
+use simple_keyvalue_api::{Key, KeyValue, SimpleKeyValueClient};
 
 fn main() {
     let matches = App::new("Simple key/value runtime test client")
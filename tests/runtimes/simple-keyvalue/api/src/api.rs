@@ -19,27 +19,31 @@ pub struct KeyValue {
     pub nonce: Option<u64>,
 }
 
-runtime_api! {
-    //  Gets runtime ID of the runtime.
-    pub fn get_runtime_id(()) -> Option<String>;
+#[runtime_api(txn)]
+pub trait SimpleKeyValue {
+    /// Gets runtime ID of the runtime.
+    #[read_only]
+    fn get_runtime_id() -> Option<String>;
 
-    // Inserts key and corresponding value and returns old value, if any.
-    // Both parameters are passed using a single serializable struct KeyValue.
-    pub fn insert(KeyValue) -> Option<String>;
+    /// Inserts key and corresponding value and returns old value, if any.
+    /// Both parameters are passed using a single serializable struct KeyValue.
+    fn insert(kv: KeyValue) -> Option<String>;
 
-    // Gets value associated with given key.
-    pub fn get(Key) -> Option<String>;
+    /// Gets value associated with given key.
+    #[read_only]
+    fn get(key: Key) -> Option<String>;
 
-    // Removes value associated with the given key and returns old value, if any.
-    pub fn remove(Key) -> Option<String>;
+    /// Removes value associated with the given key and returns old value, if any.
+    fn remove(key: Key) -> Option<String>;
 
-    // (encrypted) Inserts key and corresponding value and returns old value, if any.
-    // Both parameters are passed using a single serializable struct KeyValue.
-    pub fn enc_insert(KeyValue) -> Option<String>;
+    /// (encrypted) Inserts key and corresponding value and returns old value, if any.
+    /// Both parameters are passed using a single serializable struct KeyValue.
+    fn enc_insert(kv: KeyValue) -> Option<String>;
 
-    // (encrypted) Gets value associated with given key.
-    pub fn enc_get(Key) -> Option<String>;
+    /// (encrypted) Gets value associated with given key.
+    #[read_only]
+    fn enc_get(key: Key) -> Option<String>;
 
-    // (encrypted) Removes value associated with the given key and returns old value, if any.
-    pub fn enc_remove(Key) -> Option<String>;
+    /// (encrypted) Removes value associated with the given key and returns old value, if any.
+    fn enc_remove(key: Key) -> Option<String>;
 }
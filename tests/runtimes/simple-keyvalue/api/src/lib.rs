@@ -1,8 +1,10 @@
 extern crate serde;
 
+extern crate oasis_core_client;
 extern crate oasis_core_runtime;
 
-#[macro_use]
 mod api;
 
-pub use api::{Key, KeyValue};
+#[cfg(not(target_env = "sgx"))]
+pub use api::SimpleKeyValueClient;
+pub use api::{Key, KeyValue, SimpleKeyValue, SIMPLE_KEY_VALUE_METHODS};
@@ -6,86 +6,136 @@ use io_context::Context as IoContext;
 use oasis_core_keymanager_client::{KeyManagerClient, KeyPairId};
 use oasis_core_runtime::{
     common::{
-        crypto::{
-            hash::Hash,
-            mrae::deoxysii::{DeoxysII, KEY_SIZE, NONCE_SIZE, TAG_SIZE},
-        },
+        crypto::{hash::Hash, mrae::deoxysii::KEY_SIZE},
         runtime::RuntimeId,
         version::Version,
     },
     executor::Executor,
     rak::RAK,
-    register_runtime_txn_methods, runtime_context,
-    storage::{StorageContext, MKVS},
+    runtime_context,
+    storage::{confidential::ConfidentialStore, StorageContext, MKVS},
     transaction::{dispatcher::CheckOnlySuccess, Context as TxnContext},
     version_from_cargo, Protocol, RpcDemux, RpcDispatcher, TxnDispatcher, TxnMethDispatcher,
 };
 use simple_keymanager::trusted_policy_signers;
-use simple_keyvalue_api::{with_api, Key, KeyValue};
+use simple_keyvalue_api::{Key, KeyValue, SimpleKeyValue};
 
 struct Context {
     test_runtime_id: RuntimeId,
     km_client: Arc<dyn KeyManagerClient>,
+    /// Confidential store nonce counter, shared by all transactions in the batch.
+    nonce_counter: u64,
 }
 
-/// Return previously set runtime ID of this runtime.
-fn get_runtime_id(_args: &(), ctx: &mut TxnContext) -> Result<Option<String>> {
-    let rctx = runtime_context!(ctx, Context);
+/// Simple key/value runtime.
+struct Runtime;
 
-    Ok(Some(rctx.test_runtime_id.to_string()))
-}
+impl SimpleKeyValue for Runtime {
+    /// Return previously set runtime ID of this runtime.
+    fn get_runtime_id(&self, ctx: &mut TxnContext) -> Result<Option<String>> {
+        let rctx = runtime_context!(ctx, Context);
 
-/// Insert a key/value pair.
-fn insert(args: &KeyValue, ctx: &mut TxnContext) -> Result<Option<String>> {
-    if args.value.as_bytes().len() > 128 {
-        return Err(anyhow!("Value too big to be inserted."));
+        Ok(Some(rctx.test_runtime_id.to_string()))
     }
-    if ctx.check_only {
-        return Err(CheckOnlySuccess::default().into());
+
+    /// Insert a key/value pair.
+    fn insert(&self, args: &KeyValue, ctx: &mut TxnContext) -> Result<Option<String>> {
+        if args.value.as_bytes().len() > 128 {
+            return Err(anyhow!("Value too big to be inserted."));
+        }
+        if ctx.check_only {
+            return Err(CheckOnlySuccess::default().into());
+        }
+        ctx.emit_txn_tag(b"kv_op", b"insert");
+        ctx.emit_txn_tag(b"kv_key", args.key.as_bytes());
+
+        let existing = StorageContext::with_current(|mkvs, _untrusted_local| {
+            mkvs.insert(
+                IoContext::create_child(&ctx.io_ctx),
+                args.key.as_bytes(),
+                args.value.as_bytes(),
+            )
+        })?;
+        Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
     }
-    ctx.emit_txn_tag(b"kv_op", b"insert");
-    ctx.emit_txn_tag(b"kv_key", args.key.as_bytes());
 
-    let existing = StorageContext::with_current(|mkvs, _untrusted_local| {
-        mkvs.insert(
-            IoContext::create_child(&ctx.io_ctx),
-            args.key.as_bytes(),
-            args.value.as_bytes(),
-        )
-    });
-    Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
-}
+    /// Retrieve a key/value pair.
+    fn get(&self, args: &Key, ctx: &mut TxnContext) -> Result<Option<String>> {
+        if ctx.check_only {
+            return Err(CheckOnlySuccess::default().into());
+        }
+        ctx.emit_txn_tag(b"kv_op", b"get");
+        ctx.emit_txn_tag(b"kv_key", args.key.as_bytes());
 
-/// Retrieve a key/value pair.
-fn get(args: &Key, ctx: &mut TxnContext) -> Result<Option<String>> {
-    if ctx.check_only {
-        return Err(CheckOnlySuccess::default().into());
+        let existing = StorageContext::with_current(|mkvs, _untrusted_local| {
+            mkvs.get(IoContext::create_child(&ctx.io_ctx), args.key.as_bytes())
+        })?;
+        Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
     }
-    ctx.emit_txn_tag(b"kv_op", b"get");
-    ctx.emit_txn_tag(b"kv_key", args.key.as_bytes());
 
-    let existing = StorageContext::with_current(|mkvs, _untrusted_local| {
-        mkvs.get(IoContext::create_child(&ctx.io_ctx), args.key.as_bytes())
-    });
-    Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
-}
+    /// Remove a key/value pair.
+    fn remove(&self, args: &Key, ctx: &mut TxnContext) -> Result<Option<String>> {
+        if ctx.check_only {
+            return Err(CheckOnlySuccess::default().into());
+        }
+        ctx.emit_txn_tag(b"kv_op", b"remove");
+        ctx.emit_txn_tag(b"kv_key", args.key.as_bytes());
+
+        let existing = StorageContext::with_current(|mkvs, _untrusted_local| {
+            mkvs.remove(IoContext::create_child(&ctx.io_ctx), args.key.as_bytes())
+        })?;
+        Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
+    }
+
+    /// (encrypted) Insert a key/value pair.
+    fn enc_insert(&self, args: &KeyValue, ctx: &mut TxnContext) -> Result<Option<String>> {
+        let existing = with_confidential_store(ctx, args.key.as_bytes(), |store, io_ctx| {
+            store.insert(io_ctx, args.key.as_bytes(), args.value.as_bytes())
+        })?;
+        Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
+    }
 
-/// Remove a key/value pair.
-fn remove(args: &Key, ctx: &mut TxnContext) -> Result<Option<String>> {
-    if ctx.check_only {
-        return Err(CheckOnlySuccess::default().into());
+    /// (encrypted) Retrieve a key/value pair.
+    fn enc_get(&self, args: &Key, ctx: &mut TxnContext) -> Result<Option<String>> {
+        let existing = with_confidential_store(ctx, args.key.as_bytes(), |store, io_ctx| {
+            store.get(io_ctx, args.key.as_bytes())
+        })?;
+        Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
     }
-    ctx.emit_txn_tag(b"kv_op", b"remove");
-    ctx.emit_txn_tag(b"kv_key", args.key.as_bytes());
 
-    let existing = StorageContext::with_current(|mkvs, _untrusted_local| {
-        mkvs.remove(IoContext::create_child(&ctx.io_ctx), args.key.as_bytes())
+    /// (encrypted) Remove a key/value pair.
+    fn enc_remove(&self, args: &Key, ctx: &mut TxnContext) -> Result<Option<String>> {
+        let existing = with_confidential_store(ctx, args.key.as_bytes(), |store, io_ctx| {
+            store.remove(io_ctx, args.key.as_bytes())
+        })?;
+        Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
+    }
+}
+
+/// Run the given operation on a confidential store using the state key for
+/// the given key.
+///
+/// The nonce counter is carried over between calls, so nonces do not repeat
+/// within a batch.
+fn with_confidential_store<F, R>(ctx: &mut TxnContext, key: &[u8], f: F) -> Result<R>
+where
+    F: FnOnce(&mut ConfidentialStore<&mut dyn MKVS>, IoContext) -> Result<R>,
+{
+    let state_key = get_state_key(ctx, key)?;
+    let counter = runtime_context!(ctx, Context).nonce_counter;
+    // Updates are committed in the round following the one of the header.
+    let round = ctx.header.round + 1;
+    let (result, counter) = StorageContext::with_current(|mkvs, _untrusted_local| {
+        let mut store = ConfidentialStore::new(mkvs, &state_key, round).with_counter(counter);
+        let result = f(&mut store, IoContext::create_child(&ctx.io_ctx));
+        (result, store.counter())
     });
-    Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
+    runtime_context!(ctx, Context).nonce_counter = counter;
+    result
 }
 
-/// Helper for doing encrypted MKVS operations.
-fn get_encryption_context(ctx: &mut TxnContext, key: &[u8]) -> Result<EncryptionContext> {
+/// Fetch the state key used to encrypt the given key.
+fn get_state_key(ctx: &mut TxnContext, key: &[u8]) -> Result<[u8; KEY_SIZE]> {
     let rctx = runtime_context!(ctx, Context);
 
     // Derive key pair ID based on key.
@@ -96,152 +146,7 @@ fn get_encryption_context(ctx: &mut TxnContext, key: &[u8]) -> Result<Encryption
     let result = rctx.km_client.get_or_create_keys(io_ctx, key_pair_id);
     let key = Executor::with_current(|executor| executor.block_on(result))?;
 
-    Ok(EncryptionContext::new(key.state_key.as_ref()))
-}
-
-/// (encrypted) Insert a key/value pair.
-fn enc_insert(args: &KeyValue, ctx: &mut TxnContext) -> Result<Option<String>> {
-    // NOTE: This is only for example purposes, the correct way would be
-    //       to also generate a (deterministic) nonce.
-    let nonce = [0u8; NONCE_SIZE];
-
-    let enc_ctx = get_encryption_context(ctx, args.key.as_bytes())?;
-    let existing = StorageContext::with_current(|mkvs, _untrusted_local| {
-        enc_ctx.insert(
-            mkvs,
-            IoContext::create_child(&ctx.io_ctx),
-            args.key.as_bytes(),
-            args.value.as_bytes(),
-            &nonce,
-        )
-    });
-    Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
-}
-
-/// (encrypted) Retrieve a key/value pair.
-fn enc_get(args: &Key, ctx: &mut TxnContext) -> Result<Option<String>> {
-    let enc_ctx = get_encryption_context(ctx, args.key.as_bytes())?;
-    let existing = StorageContext::with_current(|mkvs, _untrusted_local| {
-        enc_ctx.get(
-            mkvs,
-            IoContext::create_child(&ctx.io_ctx),
-            args.key.as_bytes(),
-        )
-    });
-    Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
-}
-
-/// (encrypted) Remove a key/value pair.
-fn enc_remove(args: &Key, ctx: &mut TxnContext) -> Result<Option<String>> {
-    let enc_ctx = get_encryption_context(ctx, args.key.as_bytes())?;
-    let existing = StorageContext::with_current(|mkvs, _untrusted_local| {
-        enc_ctx.remove(
-            mkvs,
-            IoContext::create_child(&ctx.io_ctx),
-            args.key.as_bytes(),
-        )
-    });
-    Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
-}
-
-/// A keyed storage encryption context, for use with a MKVS instance.
-struct EncryptionContext {
-    d2: DeoxysII,
-}
-
-impl EncryptionContext {
-    /// Initialize a new EncryptionContext with the given MRAE key.
-    pub fn new(key: &[u8]) -> Self {
-        if key.len() != KEY_SIZE {
-            panic!("mkvs: invalid encryption key size {}", key.len());
-        }
-        let mut raw_key = [0u8; KEY_SIZE];
-        raw_key.copy_from_slice(&key[..KEY_SIZE]);
-
-        let d2 = DeoxysII::new(&raw_key);
-        //raw_key.zeroize();
-
-        Self { d2 }
-    }
-
-    /// Get encrypted MKVS entry.
-    pub fn get(&self, mkvs: &dyn MKVS, ctx: IoContext, key: &[u8]) -> Option<Vec<u8>> {
-        let key = self.derive_encrypted_key(key);
-        let ciphertext = match mkvs.get(ctx, &key) {
-            Some(ciphertext) => ciphertext,
-            None => return None,
-        };
-
-        self.open(&ciphertext)
-    }
-
-    /// Insert encrypted MKVS entry.
-    pub fn insert(
-        &self,
-        mkvs: &mut dyn MKVS,
-        ctx: IoContext,
-        key: &[u8],
-        value: &[u8],
-        nonce: &[u8],
-    ) -> Option<Vec<u8>> {
-        let nonce = Self::derive_nonce(&nonce);
-        let mut ciphertext = self.d2.seal(&nonce, value.to_vec(), vec![]);
-        ciphertext.extend_from_slice(&nonce);
-
-        let key = self.derive_encrypted_key(key);
-        let ciphertext = match mkvs.insert(ctx, &key, &ciphertext) {
-            Some(ciphertext) => ciphertext,
-            None => return None,
-        };
-
-        self.open(&ciphertext)
-    }
-
-    /// Remove encrypted MKVS entry.
-    pub fn remove(&self, mkvs: &mut dyn MKVS, ctx: IoContext, key: &[u8]) -> Option<Vec<u8>> {
-        let key = self.derive_encrypted_key(key);
-        let ciphertext = match mkvs.remove(ctx, &key) {
-            Some(ciphertext) => ciphertext,
-            None => return None,
-        };
-
-        self.open(&ciphertext)
-    }
-
-    fn open(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
-        // ciphertext || tag || nonce.
-        if ciphertext.len() < TAG_SIZE + NONCE_SIZE {
-            return None;
-        }
-
-        let nonce_offset = ciphertext.len() - NONCE_SIZE;
-        let mut nonce = [0u8; NONCE_SIZE];
-        nonce.copy_from_slice(&ciphertext[nonce_offset..]);
-        let ciphertext = &ciphertext[..nonce_offset];
-
-        let plaintext = self.d2.open(&nonce, ciphertext.to_vec(), vec![]);
-        plaintext.ok()
-    }
-
-    fn derive_encrypted_key(&self, key: &[u8]) -> Vec<u8> {
-        // XXX: The plan is eventually to use a lighter weight transform
-        // for the key instead of a full fledged MRAE algorithm.  For now
-        // approximate it with a Deoxys-II call with an all 0 nonce.
-
-        let nonce = [0u8; NONCE_SIZE];
-        self.d2.seal(&nonce, key.to_vec(), vec![])
-    }
-
-    fn derive_nonce(nonce: &[u8]) -> [u8; NONCE_SIZE] {
-        // Just a copy for type safety.
-        let mut n = [0u8; NONCE_SIZE];
-        if nonce.len() != NONCE_SIZE {
-            panic!("invalid nonce size: {}", nonce.len());
-        }
-        n.copy_from_slice(nonce);
-
-        n
-    }
+    Ok(key.state_key.0)
 }
 
 pub fn main() {
@@ -252,7 +157,7 @@ pub fn main() {
                 rpc: &mut RpcDispatcher|
      -> Option<Box<dyn TxnDispatcher>> {
         let mut txn = TxnMethDispatcher::new();
-        with_api! { register_runtime_txn_methods!(txn, api); }
+        Arc::new(Runtime).register_methods(&mut txn);
 
         // Create the key manager client.
         let rt_id = protocol.get_runtime_id();
@@ -278,6 +183,7 @@ pub fn main() {
             ctx.runtime = Box::new(Context {
                 test_runtime_id: rt_id.clone(),
                 km_client: initializer_km_client.clone(),
+                nonce_counter: 0,
             })
         });
 